@@ -1,33 +1,60 @@
 extern crate anyhow;
+#[cfg(feature = "ffi")]
 extern crate cbindgen;
 
 #[cfg(feature = "sqlite")]
 fn main() -> anyhow::Result<()> {
     let target_os = std::env::var("CARGO_CFG_TARGET_OS")?;
     if target_os != "macos" && target_os != "windows" && target_os != "linux" {
-        println!("cargo:rustc-link-lib=static=sqlite3");
-        if target_os.as_str() == "android" {
-            println!("cargo:rustc-link-search=native=./sqlite3/obj/local/arm64-v8a");
-        } else if target_os == "ios" {
-            println!("cargo:rustc-link-search=native=./sqlite3/obj/local/arm64-ios");
-        }
-
-        cbindgen::Builder::new()
-            .with_language(cbindgen::Language::C)
-            .with_src("./src/ffi.rs")
-            .generate()
-            .expect("Unable to generate bindings")
-            .write_to_file("target/include/ssi_man.h");
+        check_mobile_sqlite(&target_os)?;
+        generate_bindings();
     }
     Ok(())
 }
 
 #[cfg(not(feature = "sqlite"))]
 fn main() {
+    generate_bindings();
+}
+
+/// Fails the build early, rather than at link time, when mobile targets have no sqlite to
+/// link.
+///
+/// `bundled-sqlite` compiles sqlite for the target through libsqlite3-sys.
+/// `external-sqlite` links a prebuilt library from `SQLITE3_LIB_DIR`, which libsqlite3-sys
+/// reads along with `SQLITE3_STATIC`, e.g. the one `cargo make build-sqlite3` builds for
+/// Android.
+#[cfg(feature = "sqlite")]
+fn check_mobile_sqlite(target_os: &str) -> anyhow::Result<()> {
+    println!("cargo:rerun-if-env-changed=SQLITE3_LIB_DIR");
+    if cfg!(feature = "bundled-sqlite") || cfg!(feature = "sqlcipher") {
+        return Ok(());
+    }
+    if !cfg!(feature = "external-sqlite") {
+        anyhow::bail!(
+            "no sqlite for {target_os}: enable the `bundled-sqlite` feature, or \
+             `external-sqlite` with SQLITE3_LIB_DIR set"
+        );
+    }
+    let lib_dir = std::env::var("SQLITE3_LIB_DIR").map_err(|_| {
+        anyhow::anyhow!("the `external-sqlite` feature needs SQLITE3_LIB_DIR for {target_os}")
+    })?;
+    if !std::path::Path::new(&lib_dir).is_dir() {
+        anyhow::bail!("SQLITE3_LIB_DIR is not a directory: {lib_dir}");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "ffi")]
+fn generate_bindings() {
     cbindgen::Builder::new()
         .with_language(cbindgen::Language::C)
         .with_src("./src/ffi.rs")
+        .with_src("./src/lib.rs")
         .generate()
         .expect("Unable to generate bindings")
         .write_to_file("target/include/ssi_man.h");
 }
+
+#[cfg(not(feature = "ffi"))]
+fn generate_bindings() {}
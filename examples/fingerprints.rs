@@ -0,0 +1,52 @@
+//! Times listing the fingerprints of 5000 identities on a database, before and after
+//! warming the fingerprint cache:
+//!
+//! ```sh
+//! cargo run --release --example fingerprints --features sqlite -- /tmp/ssi-man-fingerprints.db
+//! ```
+//!
+//! Before warming every ssi is loaded and parsed, the way databases written by older
+//! versions are listed; after, the listing is a single query over the cached column.
+
+use std::time::Instant;
+
+use diesel::{connection::SimpleConnection, Connection, SqliteConnection};
+use ssi_man::{Error, SsiMan};
+
+const IDENTITIES: usize = 5000;
+
+fn main() -> Result<(), Error> {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "/tmp/ssi-man-fingerprints.db".to_string());
+    let _ = std::fs::remove_file(&path);
+
+    let mut ssi_man = SsiMan::with_sqlite(&path)?;
+    let start = Instant::now();
+    for n in 0..IDENTITIES {
+        ssi_man.new_ssi(&format!("id{n:05}"), &format!("id{n:05}@example.com"), None)?;
+    }
+    println!("seeded {IDENTITIES} identities in {:?}", start.elapsed());
+    let cached = ssi_man.fingerprints()?;
+
+    // Forget the cached fingerprints, as if the identities predated the cache.
+    SqliteConnection::establish(&path)?
+        .batch_execute("UPDATE ssi_secrets SET fingerprint = NULL")?;
+    let start = Instant::now();
+    let computed = ssi_man.fingerprints()?;
+    println!("listed without the cache in {:?}", start.elapsed());
+    assert_eq!(computed, cached);
+
+    let start = Instant::now();
+    let warmed = ssi_man.warm_fingerprints(500, |done, total| {
+        println!("  cached {done}/{total}");
+        true
+    })?;
+    println!("warmed {warmed} fingerprints in {:?}", start.elapsed());
+
+    let start = Instant::now();
+    let listed = ssi_man.fingerprints()?;
+    println!("listed from the cache in {:?}", start.elapsed());
+    assert_eq!(listed, cached);
+    Ok(())
+}
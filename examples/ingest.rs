@@ -0,0 +1,57 @@
+//! Compares ingesting identities into a database in a single transaction, one
+//! transaction per identity, and in chunks with and without relaxed syncing:
+//!
+//! ```sh
+//! cargo run --release --example ingest --features sqlite -- 20000 /tmp/ssi-man-ingest.db
+//! ```
+
+use std::time::Instant;
+
+use ssi_man::{Error, IngestOptions, IngestRecord, SsiMan};
+
+fn main() -> Result<(), Error> {
+    let mut args = std::env::args().skip(1);
+    let count = args
+        .next()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(20_000);
+    let path = args
+        .next()
+        .unwrap_or_else(|| "/tmp/ssi-man-ingest.db".to_string());
+
+    let mut source = SsiMan::with_memory();
+    for n in 0..count {
+        source.new_ssi(&format!("id{n:06}"), &format!("id{n:06}@example.com"), None)?;
+    }
+    let records = source
+        .export_all()?
+        .lines()
+        .map(|line| serde_json::from_str::<IngestRecord>(line).expect("exported record"))
+        .collect::<Vec<_>>();
+    println!("generated {count} records");
+
+    let modes = [
+        ("single transaction", usize::MAX, None),
+        ("per row", 1, None),
+        ("chunks of 1000", 1000, None),
+        ("chunks of 1000, sync every 10", 1000, Some(10)),
+    ];
+    for (name, chunk_size, fsync_every) in modes {
+        let _ = std::fs::remove_file(&path);
+        let mut ssi_man = SsiMan::with_sqlite(&path)?;
+        let options = IngestOptions {
+            chunk_size,
+            fsync_every,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let report = ssi_man.ingest(records.iter().cloned(), options)?;
+        println!(
+            "{name}: {} records in {} chunks, {:?}",
+            report.imported,
+            report.chunks,
+            start.elapsed()
+        );
+    }
+    Ok(())
+}
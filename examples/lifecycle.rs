@@ -0,0 +1,64 @@
+//! Walks an identity through its whole lifecycle on the memory store and, when built with
+//! the `sqlite` feature, on a database:
+//!
+//! ```sh
+//! cargo run --example lifecycle
+//! cargo run --example lifecycle --features sqlite -- /tmp/ssi-man-example.db
+//! ```
+
+use ssi_man::{ssi_cert_verify_text, ConflictPolicy, Error, SsiMan};
+
+const IDENTITY: &str = "Luna";
+const EMAIL: &str = "luna@bitlightlabs.com";
+
+fn lifecycle(name: &str, mut ssi_man: SsiMan, mut other: SsiMan) -> Result<(), Error> {
+    println!("== {name}");
+    let ssi = ssi_man.new_ssi(IDENTITY, EMAIL, Some("moon"))?;
+    println!("created {IDENTITY}: {ssi}");
+
+    let ssi = ssi_man.add_uid(IDENTITY, "Luna <mailto:luna@example.com>", Some("moon"))?;
+    println!("added a uid: {ssi}");
+
+    let message = "have a good day!";
+    let ssi_cert = ssi_man.sign(IDENTITY, message, Some("moon"))?;
+    println!("signed {message:?}: {ssi_cert}");
+    ssi_cert_verify_text(&ssi_cert, message)?;
+    println!(
+        "verified, signed by {}",
+        ssi_man.verify_from_known(&ssi_cert, message)?
+    );
+
+    ssi_man.change_password(IDENTITY, Some("moon"), Some("full moon"))?;
+    println!("changed the password");
+    let ssi = ssi_man.new_ssi_overwrite(IDENTITY, EMAIL, Some("new moon"))?;
+    println!("rotated the key: {ssi}");
+    ssi_cert_verify_text(&ssi_cert, message)?;
+    println!("the first certificate still verifies on its own");
+
+    let snapshot = ssi_man.export_all()?;
+    let imported = other.import_all(&snapshot, ConflictPolicy::Error)?;
+    println!("moved {imported} identity to another store");
+    let ssi_cert = other.sign(IDENTITY, message, Some("new moon"))?;
+    println!(
+        "signed again there, verified as {}",
+        ssi_man.verify_from_known(&ssi_cert, message)?
+    );
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    lifecycle("memory", SsiMan::with_memory(), SsiMan::with_memory())?;
+
+    #[cfg(feature = "sqlite")]
+    {
+        let path = std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "ssi-man-example.db".to_string());
+        let mut ssi_man = SsiMan::with_sqlite(&path)?;
+        // Starts over if the example already ran on this database.
+        ssi_man.remove(IDENTITY)?;
+        lifecycle("sqlite", ssi_man, SsiMan::with_memory())?;
+        println!("database left at {path}");
+    }
+    Ok(())
+}
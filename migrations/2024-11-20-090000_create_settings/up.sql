@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS settings
+(
+    key   TEXT NOT NULL PRIMARY KEY,
+    value TEXT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE ssi_secrets DROP COLUMN sign_count;
+ALTER TABLE ssi_secrets DROP COLUMN last_used_at;
+ALTER TABLE ssi_secrets DROP COLUMN created_at;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE ssi_secrets ADD COLUMN created_at TEXT NOT NULL DEFAULT '1970-01-01T00:00:00.000Z';
+-- Identities stored before this migration count as created by it.
+UPDATE ssi_secrets SET created_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+ALTER TABLE ssi_secrets ADD COLUMN last_used_at TEXT;
+ALTER TABLE ssi_secrets ADD COLUMN sign_count BIGINT NOT NULL DEFAULT 0;
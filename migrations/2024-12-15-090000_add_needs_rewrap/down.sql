@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE ssi_secrets DROP COLUMN needs_rewrap;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE ssi_secrets ADD COLUMN needs_rewrap BOOLEAN NOT NULL DEFAULT 0;
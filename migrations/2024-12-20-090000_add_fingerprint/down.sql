@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX ssi_secrets_fingerprint;
+ALTER TABLE ssi_secrets DROP COLUMN fingerprint;
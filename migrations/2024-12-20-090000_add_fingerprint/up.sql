@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE ssi_secrets ADD COLUMN fingerprint TEXT;
+CREATE INDEX ssi_secrets_fingerprint ON ssi_secrets (fingerprint);
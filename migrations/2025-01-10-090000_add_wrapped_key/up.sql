@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE ssi_secrets ADD COLUMN wrapped_key BLOB;
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+-- The tables match the sqlite schema, so both backends share `schema.rs`; timestamps
+-- are RFC 3339 UTC text with milliseconds, and ids sort byte-wise under the binary
+-- collation. Identities are at most 255 bytes, so 255 characters always fit.
+CREATE TABLE IF NOT EXISTS ssi_secrets
+(
+    id           VARCHAR(255) CHARACTER SET utf8mb4 COLLATE utf8mb4_bin NOT NULL PRIMARY KEY,
+    ssi          TEXT CHARACTER SET utf8mb4 NOT NULL,
+    secret       TEXT CHARACTER SET utf8mb4 NOT NULL,
+    created_at   VARCHAR(32) NOT NULL,
+    last_used_at VARCHAR(32),
+    sign_count   BIGINT NOT NULL DEFAULT 0,
+    needs_rewrap BOOLEAN NOT NULL DEFAULT FALSE,
+    fingerprint  VARCHAR(255),
+    wrapped_key  BLOB,
+    INDEX ssi_secrets_fingerprint (fingerprint)
+);
+
+CREATE TABLE IF NOT EXISTS settings
+(
+    `key`   VARCHAR(255) NOT NULL PRIMARY KEY,
+    `value` TEXT NOT NULL
+);
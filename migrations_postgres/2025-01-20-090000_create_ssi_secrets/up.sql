@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- The tables match the sqlite schema, so both backends share `schema.rs`; timestamps
+-- are RFC 3339 UTC text with milliseconds, and ids sort byte-wise under the C collation.
+CREATE TABLE IF NOT EXISTS ssi_secrets
+(
+    id           TEXT COLLATE "C" NOT NULL PRIMARY KEY,
+    ssi          TEXT    NOT NULL UNIQUE,
+    secret       TEXT    NOT NULL,
+    created_at   TEXT    NOT NULL,
+    last_used_at TEXT,
+    sign_count   BIGINT  NOT NULL DEFAULT 0,
+    needs_rewrap BOOLEAN NOT NULL DEFAULT FALSE,
+    fingerprint  TEXT,
+    wrapped_key  BYTEA
+);
+CREATE INDEX IF NOT EXISTS ssi_secrets_fingerprint ON ssi_secrets (fingerprint);
+
+CREATE TABLE IF NOT EXISTS settings
+(
+    key   TEXT NOT NULL PRIMARY KEY,
+    value TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- NULL until the record is updated, as for identities stored before this migration.
+ALTER TABLE ssi_secrets ADD COLUMN updated_at TEXT;
@@ -0,0 +1,166 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use ssi::{Algo, Chain, EncryptedSecret, Ssi, SsiSecret, Uid};
+
+use crate::{
+    identity::Identity, reveal_secret, revealed::RevealedSecret, Error, SsiStore,
+    DEFAULT_EMPTY_PASSWORD,
+};
+
+/// The async counterpart of [`SsiStore`], for stores talking to a server through an
+/// async driver.
+///
+/// Only the methods [`AsyncSsiMan`] needs are here; [`SsiStore`] remains the trait of
+/// embedded stores and of everything else.
+#[async_trait]
+pub trait AsyncSsiStore: Send {
+    /// Adds a new identity, failing with [`Error::IdentityExists`] if it is already present.
+    async fn insert(
+        &mut self,
+        identity: String,
+        ssi: Ssi,
+        secret: EncryptedSecret,
+    ) -> Result<(), Error>;
+    async fn get(&mut self, identity: &str) -> Result<(Ssi, EncryptedSecret), Error>;
+    async fn remove(&mut self, identity: &str) -> Result<bool, Error>;
+    async fn contains(&mut self, identity: &str) -> Result<bool, Error> {
+        match self.get(identity).await {
+            Ok(_) => Ok(true),
+            Err(Error::UnknownIdentity(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+    /// Returns every identity sorted by identity, as [`SsiStore::for_each_identity`]
+    /// walks them.
+    async fn identities(&mut self) -> Result<Vec<String>, Error>;
+}
+
+/// Exposes a [`SsiStore`] as an [`AsyncSsiStore`], calling it on the polling task.
+///
+/// Meant for stores that never block, such as the memory store; a store doing I/O would
+/// hold up the executor's thread meanwhile.
+pub struct SyncStoreAdapter<S>(pub S);
+
+#[async_trait]
+impl<S: SsiStore> AsyncSsiStore for SyncStoreAdapter<S> {
+    async fn insert(
+        &mut self,
+        identity: String,
+        ssi: Ssi,
+        secret: EncryptedSecret,
+    ) -> Result<(), Error> {
+        self.0.insert(identity, ssi, secret)
+    }
+
+    async fn get(&mut self, identity: &str) -> Result<(Ssi, EncryptedSecret), Error> {
+        self.0.get(identity).map(|record| record.into_owned())
+    }
+
+    async fn remove(&mut self, identity: &str) -> Result<bool, Error> {
+        self.0.remove(identity)
+    }
+
+    async fn contains(&mut self, identity: &str) -> Result<bool, Error> {
+        self.0.contains(identity)
+    }
+
+    async fn identities(&mut self) -> Result<Vec<String>, Error> {
+        let mut identities = Vec::new();
+        self.0.for_each_identity(&mut |identity| {
+            identities.push(identity.to_string());
+            Ok(())
+        })?;
+        Ok(identities)
+    }
+}
+
+/// The async counterpart of [`SsiMan`](crate::SsiMan) for [`AsyncSsiStore`]s, creating,
+/// using and removing identities as it does.
+///
+/// Only the core operations are mirrored; certificates and ssis are in the native
+/// [`OutputFormat`](crate::OutputFormat).
+pub struct AsyncSsiMan {
+    store: Box<dyn AsyncSsiStore>,
+}
+
+impl AsyncSsiMan {
+    pub fn with_store(store: Box<dyn AsyncSsiStore>) -> Self {
+        Self { store }
+    }
+
+    /// Same as [`SsiMan::new_ssi`](crate::SsiMan::new_ssi).
+    pub async fn new_ssi(
+        &mut self,
+        identity: impl ToString,
+        email: impl AsRef<str>,
+        optional_passwd: Option<&str>,
+    ) -> Result<String, Error> {
+        let identity: String = Identity::try_from(identity.to_string())?.into();
+        let uid = Uid::from_str(&format!("{identity} <mailto:{}>", email.as_ref()))?;
+        let (ssi, secret) = {
+            let secret = RevealedSecret::new(SsiSecret::new(Algo::Ed25519, Chain::Bitcoin));
+            let ssi = secret.to_ssi([uid], None);
+            let concealed = secret.conceal(optional_passwd.unwrap_or(DEFAULT_EMPTY_PASSWORD));
+            (ssi, concealed)
+        };
+        let ssi_string = ssi.to_string();
+        self.store.insert(identity, ssi, secret).await?;
+        Ok(ssi_string)
+    }
+
+    /// Same as [`SsiMan::sign`](crate::SsiMan::sign), without recording usage.
+    pub async fn sign(
+        &mut self,
+        identity: &str,
+        message: impl AsRef<[u8]>,
+        passwd: Option<&str>,
+    ) -> Result<String, Error> {
+        let (ssi, encrypted) = self.store.get(identity).await?;
+        if ssi.expiry.is_some_and(|expiry| expiry <= Utc::now()) {
+            return Err(Error::IdentityExpired(identity.to_string()));
+        }
+        let secret = reveal_secret(&ssi, &encrypted, passwd.unwrap_or(DEFAULT_EMPTY_PASSWORD))?;
+        let ssi_cert = secret.sign_all(ssi, &[message.as_ref()]).remove(0);
+        Ok(format!("{ssi_cert:#}"))
+    }
+
+    pub async fn remove(&mut self, identity: &str) -> Result<bool, Error> {
+        self.store.remove(identity).await
+    }
+
+    /// Returns every identity sorted by identity.
+    pub async fn all_identities(&mut self) -> Result<Vec<String>, Error> {
+        self.store.identities().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMemoryStore};
+
+    #[tokio::test]
+    async fn async_ssi_man_should_sign_with_new_identities() {
+        let store = SyncStoreAdapter(SsiMemoryStore::default());
+        let mut ssi_man = AsyncSsiMan::with_store(Box::new(store));
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", Some("secret"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            ssi_man.new_ssi("Luna", "luna@bitlightlabs.com", None).await,
+            Err(Error::IdentityExists(_))
+        ));
+        let ssi_cert = ssi_man
+            .sign("Luna", "have a good day!", Some("secret"))
+            .await
+            .unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert!(ssi_man.sign("Luna", "hi", None).await.is_err());
+        assert_eq!(ssi_man.all_identities().await, Ok(vec!["Luna".to_string()]));
+        assert_eq!(ssi_man.remove("Luna").await, Ok(true));
+        assert_eq!(ssi_man.remove("Luna").await, Ok(false));
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use aws_config::BehaviorVersion;
+use aws_sdk_secretsmanager::{error::DisplayErrorContext, Client};
+use ssi::{EncryptedSecret, Ssi};
+use tokio::runtime::{Builder, Runtime};
+use zeroize::Zeroizing;
+
+use crate::{Error, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability};
+
+/// Name prefix of the secrets of [`SsiAwsSecretsStore::new`].
+const DEFAULT_PREFIX: &str = "ssi-man";
+
+/// A store keeping concealed secrets in AWS Secrets Manager, and only the public ssis in
+/// a local index file, e.g. for signing services running on EC2 or ECS.
+///
+/// Each secret is named `<prefix>/<identity>`, with bytes Secrets Manager doesn't allow
+/// in names written as `=XX`, and the index holds `identity<TAB>ssi` lines. Secrets are
+/// encrypted by Secrets Manager with the account's default KMS key, or with the one of
+/// [`SsiAwsSecretsStore::kms_key_id`]. Credentials and region come from the usual AWS
+/// environment, profile or instance role. Secrets are written before the index and
+/// deleted after it, without a recovery window, so an interrupted write leaves at worst
+/// an unused secret behind.
+pub struct SsiAwsSecretsStore {
+    runtime: Runtime,
+    client: Client,
+    prefix: String,
+    kms_key_id: Option<String>,
+    index_path: PathBuf,
+    index: BTreeMap<String, Ssi>,
+}
+
+impl SsiAwsSecretsStore {
+    /// Opens the index file at `index_path`, creating it if missing, with secrets under
+    /// `ssi-man/`.
+    pub fn new(index_path: impl AsRef<Path>) -> Result<Self, Error> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let config = runtime.block_on(aws_config::load_defaults(BehaviorVersion::latest()));
+        Self::with_client(index_path, Client::new(&config), runtime)
+    }
+
+    /// Opens the index file at `index_path` with secrets read and written by `client`,
+    /// which `runtime` drives.
+    pub fn with_client(
+        index_path: impl AsRef<Path>,
+        client: Client,
+        runtime: Runtime,
+    ) -> Result<Self, Error> {
+        let index_path = index_path.as_ref().to_path_buf();
+        let text = match fs::read_to_string(&index_path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut index = BTreeMap::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (identity, ssi) = line
+                .split_once('\t')
+                .ok_or_else(|| Error::AwsSecretsIndex("line without an ssi".to_string()))?;
+            index.insert(identity.to_string(), Ssi::from_str(ssi)?);
+        }
+        let store = Self {
+            runtime,
+            client,
+            prefix: DEFAULT_PREFIX.to_string(),
+            kms_key_id: None,
+            index_path,
+            index,
+        };
+        store.save(&store.index)?;
+        Ok(store)
+    }
+
+    /// Names secrets `<prefix>/<identity>` rather than `ssi-man/<identity>`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Encrypts the secrets created from now on with the KMS key `kms_key_id`, an id,
+    /// ARN or alias, rather than the account's `aws/secretsmanager` key.
+    pub fn kms_key_id(mut self, kms_key_id: &str) -> Self {
+        self.kms_key_id = Some(kms_key_id.to_string());
+        self
+    }
+
+    /// Writes `index` through a temporary file renamed over the index file.
+    fn save(&self, index: &BTreeMap<String, Ssi>) -> Result<(), Error> {
+        let mut temporary = self.index_path.clone().into_os_string();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        for (identity, ssi) in index {
+            writeln!(file, "{identity}\t{ssi}")?;
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, &self.index_path)?;
+        Ok(())
+    }
+
+    fn secret_name(&self, id: &str) -> String {
+        format!("{}/{}", self.prefix, secret_name(id))
+    }
+
+    fn secret(&self, id: &str) -> Result<EncryptedSecret, Error> {
+        let output = self
+            .runtime
+            .block_on(
+                self.client
+                    .get_secret_value()
+                    .secret_id(self.secret_name(id))
+                    .send(),
+            )
+            .map_err(|err| Error::AwsSecrets(DisplayErrorContext(err).to_string()))?;
+        let text = Zeroizing::new(
+            output
+                .secret_string()
+                .ok_or_else(|| Error::AwsSecretsIndex(format!("secret of {id} is not text")))?
+                .to_string(),
+        );
+        EncryptedSecret::from_str(&text).map_err(|err| Error::SecretParse(err.to_string()))
+    }
+
+    /// Creates the secret of `id`, or sets a new value of it if it's left over.
+    fn set_secret(&self, id: &str, secret: &EncryptedSecret) -> Result<(), Error> {
+        let name = self.secret_name(id);
+        let secret = Zeroizing::new(secret.to_string());
+        let created = self.runtime.block_on(
+            self.client
+                .create_secret()
+                .name(&name)
+                .secret_string(secret.as_str())
+                .set_kms_key_id(self.kms_key_id.clone())
+                .send(),
+        );
+        match created {
+            Ok(_) => return Ok(()),
+            Err(err)
+                if !err
+                    .as_service_error()
+                    .is_some_and(|err| err.is_resource_exists_exception()) =>
+            {
+                return Err(Error::AwsSecrets(DisplayErrorContext(err).to_string()))
+            }
+            Err(_) => {}
+        }
+        self.runtime
+            .block_on(
+                self.client
+                    .put_secret_value()
+                    .secret_id(name)
+                    .secret_string(secret.as_str())
+                    .send(),
+            )
+            .map_err(|err| Error::AwsSecrets(DisplayErrorContext(err).to_string()))?;
+        Ok(())
+    }
+
+    fn delete_secret(&self, id: &str) -> Result<(), Error> {
+        let deleted = self.runtime.block_on(
+            self.client
+                .delete_secret()
+                .secret_id(self.secret_name(id))
+                .force_delete_without_recovery(true)
+                .send(),
+        );
+        match deleted {
+            Err(err)
+                if !err
+                    .as_service_error()
+                    .is_some_and(|err| err.is_resource_not_found_exception()) =>
+            {
+                Err(Error::AwsSecrets(DisplayErrorContext(err).to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the index with `id` set to `ssi`, and its secret before it.
+    fn put(&mut self, id: String, ssi: Ssi, secret: &EncryptedSecret) -> Result<(), Error> {
+        self.set_secret(&id, secret)?;
+        let mut index = self.index.clone();
+        index.insert(id, ssi);
+        self.save(&index)?;
+        self.index = index;
+        Ok(())
+    }
+}
+
+/// Escapes `id` into the characters allowed in secret names, but `/`.
+fn secret_name(id: &str) -> String {
+    let mut name = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || b"_+.@-".contains(&byte) {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("={byte:02X}"));
+        }
+    }
+    name
+}
+
+impl SsiStore for SsiAwsSecretsStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if self.index.contains_key(&id) {
+            return Err(Error::IdentityExists(id));
+        }
+        let added = id.clone();
+        let result = self.put(id, ssi, &secret);
+        if result.is_err() {
+            // Not indexed, so not in use: don't leave it behind.
+            let _ = self.delete_secret(&added);
+        }
+        result
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let ssi = self
+            .index
+            .get(id)
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?
+            .clone();
+        Ok(Cow::Owned((ssi, self.secret(id)?)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        if !self.index.contains_key(id) {
+            return Ok(false);
+        }
+        let mut index = self.index.clone();
+        index.remove(id);
+        self.save(&index)?;
+        self.index = index;
+        self.delete_secret(id)?;
+        Ok(true)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.index.contains_key(id))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        for identity in self.index.keys() {
+            f(identity)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default().with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiAwsSecretsStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.put(id, ssi, &secret)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.index.contains_key(id) {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        self.put(id.to_string(), ssi, &secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    #[test]
+    fn secret_names_should_escape_identities() {
+        assert_eq!(secret_name("Luna"), "Luna");
+        assert_eq!(secret_name("luna@bitlight.com"), "luna@bitlight.com");
+        assert_eq!(secret_name("a/b c"), "a=2Fb=20c");
+        assert_eq!(secret_name("a=b"), "a=3Db");
+        assert_eq!(secret_name("ü"), "=C3=BC");
+    }
+
+    /// Writes to the Secrets Manager of the AWS environment, so only runs with
+    /// `SSI_MAN_AWS_TESTS` set, and passes otherwise.
+    #[test]
+    fn aws_secrets_store_should_keep_secrets_out_of_the_index() {
+        if env::var_os("SSI_MAN_AWS_TESTS").is_none() {
+            return;
+        }
+        let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        let index_path = env::temp_dir().join(format!("ssi_man_{nanos}.index"));
+        let prefix = format!("ssi-man-test-{nanos}");
+        let store = SsiAwsSecretsStore::new(&index_path)
+            .unwrap()
+            .prefix(&prefix);
+        let mut ssi_man = SsiMan::with_store(Box::new(store));
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let index = fs::read_to_string(&index_path).unwrap();
+        assert_eq!(index, format!("Luna\t{ssi}\n"));
+        drop(ssi_man);
+
+        let store = SsiAwsSecretsStore::new(&index_path)
+            .unwrap()
+            .prefix(&prefix);
+        let mut ssi_man = SsiMan::with_store(Box::new(store));
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        fs::remove_file(index_path).unwrap();
+    }
+}
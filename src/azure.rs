@@ -0,0 +1,364 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::json;
+use ssi::{EncryptedSecret, Ssi};
+use ureq::{Agent, AgentBuilder, Request};
+use zeroize::Zeroizing;
+
+use crate::{Error, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability};
+
+const API_VERSION: &str = "7.4";
+/// Token endpoint of the instance metadata service, serving the managed identity.
+const IMDS_TOKEN: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const VAULT_RESOURCE: &str = "https://vault.azure.net";
+/// Secret name prefix of the secrets of [`SsiAzureStore::new`].
+const DEFAULT_PREFIX: &str = "ssi-man";
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Margin before expiry after which a managed identity token is renewed.
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct ImdsToken {
+    access_token: String,
+    /// Seconds, as a string.
+    expires_in: String,
+}
+
+#[derive(Deserialize)]
+struct SecretBundle {
+    value: String,
+}
+
+/// Where requests get their Microsoft Entra access token.
+enum Credentials {
+    /// The managed identity, with the token cached until shortly before it expires.
+    ManagedIdentity(Mutex<Option<(Zeroizing<String>, Instant)>>),
+    /// A fixed token, e.g. from `az account get-access-token --resource
+    /// https://vault.azure.net`.
+    Token(Zeroizing<String>),
+}
+
+/// A store keeping concealed secrets in Azure Key Vault, and only the public ssis in a
+/// local index file, so that no secret is ever written to local disk.
+///
+/// Requests are authenticated as the managed identity of the VM or container through
+/// the instance metadata service, unless given a token with
+/// [`SsiAzureStore::access_token`]. Each secret is named `<prefix>-<identity>`, with
+/// bytes Key Vault doesn't allow in names, and `-`, written as `-XX`, and each write is
+/// a new version. Removal deletes the secret and then purges it if the vault allows it;
+/// with purge protection on, a removed identity can't be created again until the vault's
+/// retention period is over. The index holds `identity<TAB>ssi` lines, and secrets are
+/// written before it and deleted after it, so an interrupted write leaves at worst an
+/// unused secret behind.
+pub struct SsiAzureStore {
+    agent: Agent,
+    vault_url: String,
+    prefix: String,
+    credentials: Credentials,
+    index_path: PathBuf,
+    index: BTreeMap<String, Ssi>,
+}
+
+impl SsiAzureStore {
+    /// Opens the index file at `index_path`, creating it if missing, with secrets in the
+    /// Key Vault at `vault_url`, e.g. `https://my-vault.vault.azure.net`.
+    pub fn new(index_path: impl AsRef<Path>, vault_url: &str) -> Result<Self, Error> {
+        let index_path = index_path.as_ref().to_path_buf();
+        let text = match fs::read_to_string(&index_path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut index = BTreeMap::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (identity, ssi) = line
+                .split_once('\t')
+                .ok_or_else(|| Error::AzureIndex("line without an ssi".to_string()))?;
+            index.insert(identity.to_string(), Ssi::from_str(ssi)?);
+        }
+        let store = Self {
+            agent: AgentBuilder::new().timeout(TIMEOUT).build(),
+            vault_url: vault_url.trim_end_matches('/').to_string(),
+            prefix: DEFAULT_PREFIX.to_string(),
+            credentials: Credentials::ManagedIdentity(Mutex::new(None)),
+            index_path,
+            index,
+        };
+        store.save(&store.index)?;
+        Ok(store)
+    }
+
+    /// Names secrets `<prefix>-<identity>` rather than `ssi-man-<identity>`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Authenticates with `token` rather than as the managed identity, e.g. outside of
+    /// Azure.
+    pub fn access_token(mut self, token: &str) -> Self {
+        self.credentials = Credentials::Token(Zeroizing::new(token.to_string()));
+        self
+    }
+
+    /// Writes `index` through a temporary file renamed over the index file.
+    fn save(&self, index: &BTreeMap<String, Ssi>) -> Result<(), Error> {
+        let mut temporary = self.index_path.clone().into_os_string();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        for (identity, ssi) in index {
+            writeln!(file, "{identity}\t{ssi}")?;
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, &self.index_path)?;
+        Ok(())
+    }
+
+    fn token(&self) -> Result<Zeroizing<String>, Error> {
+        let cached = match &self.credentials {
+            Credentials::Token(token) => return Ok(token.clone()),
+            Credentials::ManagedIdentity(cached) => cached,
+        };
+        // Held while fetching, so concurrent reads wait for one token.
+        let mut cached = cached.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((token, expiry)) = &*cached {
+            if Instant::now() + TOKEN_MARGIN < *expiry {
+                return Ok(token.clone());
+            }
+        }
+        let token: ImdsToken = self
+            .agent
+            .get(IMDS_TOKEN)
+            .query("api-version", "2018-02-01")
+            .query("resource", VAULT_RESOURCE)
+            .set("Metadata", "true")
+            .call()
+            .map_err(azure_error)?
+            .into_json()
+            .map_err(|err| Error::Azure(err.to_string()))?;
+        let expires_in = token
+            .expires_in
+            .parse()
+            .map_err(|_| Error::Azure(format!("bad token lifetime {}", token.expires_in)))?;
+        let access_token = Zeroizing::new(token.access_token);
+        let expiry = Instant::now() + Duration::from_secs(expires_in);
+        *cached = Some((access_token.clone(), expiry));
+        Ok(access_token)
+    }
+
+    fn request(&self, method: &str, path: &str) -> Result<Request, Error> {
+        let token = self.token()?;
+        Ok(self
+            .agent
+            .request(method, &format!("{}{path}", self.vault_url))
+            .query("api-version", API_VERSION)
+            .set("Authorization", &format!("Bearer {}", token.as_str())))
+    }
+
+    fn secret(&self, id: &str) -> Result<EncryptedSecret, Error> {
+        let path = format!("/secrets/{}", self.secret_name(id));
+        let bundle: SecretBundle = self
+            .request("GET", &path)?
+            .call()
+            .map_err(azure_error)?
+            .into_json()
+            .map_err(|err| Error::Azure(err.to_string()))?;
+        let text = Zeroizing::new(bundle.value);
+        EncryptedSecret::from_str(&text).map_err(|err| Error::SecretParse(err.to_string()))
+    }
+
+    fn set_secret(&mut self, id: &str, secret: &EncryptedSecret) -> Result<(), Error> {
+        let path = format!("/secrets/{}", self.secret_name(id));
+        let value = Zeroizing::new(secret.to_string());
+        self.request("PUT", &path)?
+            .send_json(json!({ "value": value.as_str(), "contentType": "text/plain" }))
+            .map_err(azure_error)?;
+        Ok(())
+    }
+
+    fn delete_secret(&mut self, id: &str) -> Result<(), Error> {
+        let name = self.secret_name(id);
+        match self.request("DELETE", &format!("/secrets/{name}"))?.call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => {}
+            Err(err) => return Err(azure_error(err)),
+        }
+        // Fails with purge protection on, or until the deletion is done; the secret
+        // is deleted either way.
+        let _ = self
+            .request("DELETE", &format!("/deletedsecrets/{name}"))?
+            .call();
+        Ok(())
+    }
+
+    fn secret_name(&self, id: &str) -> String {
+        format!("{}-{}", self.prefix, secret_name(id))
+    }
+
+    /// Writes the index with `id` set to `ssi`, and its secret before it.
+    fn put(&mut self, id: String, ssi: Ssi, secret: &EncryptedSecret) -> Result<(), Error> {
+        self.set_secret(&id, secret)?;
+        let mut index = self.index.clone();
+        index.insert(id, ssi);
+        self.save(&index)?;
+        self.index = index;
+        Ok(())
+    }
+}
+
+/// Escapes `id` into the characters allowed in secret names, using `-` for escapes.
+fn secret_name(id: &str) -> String {
+    let mut name = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("-{byte:02X}"));
+        }
+    }
+    name
+}
+
+fn azure_error(err: ureq::Error) -> Error {
+    match err {
+        ureq::Error::Status(status, response) => Error::Azure(format!(
+            "status {status}: {}",
+            response.into_string().unwrap_or_default()
+        )),
+        ureq::Error::Transport(transport) => Error::Azure(transport.to_string()),
+    }
+}
+
+impl SsiStore for SsiAzureStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if self.index.contains_key(&id) {
+            return Err(Error::IdentityExists(id));
+        }
+        let added = id.clone();
+        let result = self.put(id, ssi, &secret);
+        if result.is_err() {
+            // Not indexed, so not in use: don't leave it behind.
+            let _ = self.delete_secret(&added);
+        }
+        result
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let ssi = self
+            .index
+            .get(id)
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?
+            .clone();
+        Ok(Cow::Owned((ssi, self.secret(id)?)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        if !self.index.contains_key(id) {
+            return Ok(false);
+        }
+        let mut index = self.index.clone();
+        index.remove(id);
+        self.save(&index)?;
+        self.index = index;
+        self.delete_secret(id)?;
+        Ok(true)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.index.contains_key(id))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        for identity in self.index.keys() {
+            f(identity)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default().with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiAzureStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.put(id, ssi, &secret)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.index.contains_key(id) {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        self.put(id.to_string(), ssi, &secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    #[test]
+    fn secret_names_should_escape_identities() {
+        assert_eq!(secret_name("Luna"), "Luna");
+        assert_eq!(secret_name("luna-2"), "luna-2D2");
+        assert_eq!(secret_name("a b/c"), "a-20b-2Fc");
+        assert_eq!(secret_name("ü"), "-C3-BC");
+    }
+
+    /// Writes to the Key Vault at `SSI_MAN_AZURE_VAULT`, authenticated by
+    /// `SSI_MAN_AZURE_TOKEN` or the managed identity, and passes without a vault.
+    #[test]
+    fn azure_store_should_keep_secrets_out_of_the_index() {
+        let Some(vault_url) = env::var_os("SSI_MAN_AZURE_VAULT") else {
+            return;
+        };
+        let vault_url = vault_url.to_string_lossy();
+        let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        let index_path = env::temp_dir().join(format!("ssi_man_{nanos}.index"));
+        let prefix = format!("ssi-man-test-{nanos}");
+        let store = || {
+            let store = SsiAzureStore::new(&index_path, &vault_url)
+                .unwrap()
+                .prefix(&prefix);
+            match env::var("SSI_MAN_AZURE_TOKEN") {
+                Ok(token) => store.access_token(&token),
+                Err(_) => store,
+            }
+        };
+        let mut ssi_man = SsiMan::with_store(Box::new(store()));
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let index = fs::read_to_string(&index_path).unwrap();
+        assert_eq!(index, format!("Luna\t{ssi}\n"));
+        drop(ssi_man);
+
+        let mut ssi_man = SsiMan::with_store(Box::new(store()));
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        fs::remove_file(index_path).unwrap();
+    }
+}
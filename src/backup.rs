@@ -0,0 +1,76 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use ssi::{EncryptedSecret, Ssi};
+
+use crate::Error;
+
+const BACKUP_HEADER: &str = "ssi-man backup v";
+const BACKUP_VERSION: u32 = 1;
+
+/// Self-contained backup of a single identity.
+///
+/// The secret stays concealed with the password it was created with, so a backup
+/// can only be used for signing by someone who also knows that password.
+pub(crate) struct SsiBackup {
+    pub identity: String,
+    pub ssi: Ssi,
+    pub secret: EncryptedSecret,
+}
+
+impl SsiBackup {
+    /// Checks that the concealed secret belongs to the public key of the ssi.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.secret.fp != self.ssi.pk.fingerprint() {
+            return Err(Error::BackupKeyMismatch(self.identity.clone()));
+        }
+        Ok(())
+    }
+}
+
+impl Display for SsiBackup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{BACKUP_HEADER}{BACKUP_VERSION}")?;
+        writeln!(f, "identity: {}", self.identity)?;
+        writeln!(f, "ssi: {}", self.ssi)?;
+        write!(f, "secret: {}", self.secret)
+    }
+}
+
+impl FromStr for SsiBackup {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.trim().lines();
+        let version = lines
+            .next()
+            .and_then(|line| line.trim().strip_prefix(BACKUP_HEADER))
+            .and_then(|version| version.parse::<u32>().ok())
+            .ok_or_else(|| Error::BackupParse("missing backup header".to_string()))?;
+        if version > BACKUP_VERSION {
+            return Err(Error::FormatTooNew {
+                found: version,
+                supported: BACKUP_VERSION,
+            });
+        }
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name))
+                .and_then(|line| line.strip_prefix(": "))
+                .map(str::trim)
+                .ok_or_else(|| Error::BackupParse(format!("missing `{name}` field")))
+        };
+        let identity = field("identity")?.to_string();
+        let ssi = Ssi::from_str(field("ssi")?)?;
+        let secret = EncryptedSecret::from_str(field("secret")?)
+            .map_err(|err| Error::BackupParse(err.to_string()))?;
+        Ok(Self {
+            identity,
+            ssi,
+            secret,
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt::{self, Display, Formatter};
+
+/// An optional feature of a store, that [`SsiMan`](crate::SsiMan) checks for before
+/// using it, see [`SsiStore::capabilities`](crate::SsiStore::capabilities).
+///
+/// Operations needing a capability the store doesn't report fail with
+/// [`Error::Unsupported`](crate::Error::Unsupported) instead of falling back to a weaker
+/// version of themselves.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum StoreCapability {
+    /// Batches written all or nothing, see [`JournalCapable`](crate::JournalCapable).
+    AtomicWrites,
+    /// Creation and usage times, signature counts and rewrap flags of identities, see
+    /// [`IdentityMetadata`](crate::IdentityMetadata).
+    Metadata,
+    /// Secrets concealed with a key wrapped by the platform, see
+    /// [`Protection::Platform`](crate::Protection::Platform).
+    PlatformProtection,
+    /// Records replaced in a single write, see [`SsiStoreExt`](crate::SsiStoreExt).
+    Replace,
+    /// Writes grouped so that they're undone together, see
+    /// [`SsiMan::transaction`](crate::SsiMan::transaction).
+    Transactions,
+}
+
+impl StoreCapability {
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl Display for StoreCapability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StoreCapability::AtomicWrites => "atomic batch writes",
+            StoreCapability::Metadata => "identity metadata",
+            StoreCapability::PlatformProtection => "platform-protected secrets",
+            StoreCapability::Replace => "replacing identities",
+            StoreCapability::Transactions => "transactions",
+        })
+    }
+}
+
+/// The set of [`StoreCapability`]s of a store, empty by default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StoreCapabilities(u32);
+
+impl StoreCapabilities {
+    pub fn with(self, capability: StoreCapability) -> Self {
+        Self(self.0 | capability.bit())
+    }
+
+    pub fn contains(self, capability: StoreCapability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+
+    /// Leaves out `capability`, e.g. one a store can't offer over the stores it wraps.
+    pub fn without(self, capability: StoreCapability) -> Self {
+        Self(self.0 & !capability.bit())
+    }
+
+    /// Keeps the capabilities found in both sets, e.g. those of two stores used together.
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
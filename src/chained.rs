@@ -0,0 +1,358 @@
+use std::{borrow::Cow, collections::BTreeSet, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use ssi::{EncryptedSecret, Ssi};
+
+use crate::{
+    store_ext, store_ext_mut, store_journal, Clock, ConflictPolicy, Error, IdentityMetadata,
+    IntegrityFindings, IntegrityRepair, JournalCapable, RepairPolicy, SnapshotCapable, SsiStore,
+    SsiStoreExt, StoreCapabilities, StoreCapability, StoredIdentity, FORMAT_VERSION,
+};
+
+/// Looks identities up in several stores in turn, e.g. a local sqlite database then a
+/// shared network one, and writes to a single primary store.
+///
+/// The first store holding an identity answers for it, so listings merge the stores and
+/// an identity held by several is the first one's. Reads skip stores failing with an
+/// error classified as transient by [`Error::is_transient`], so a client offline still
+/// reads the stores at hand. Writing an identity removes it from the stores consulted
+/// before the primary, which would shadow it otherwise; stores consulted after it are
+/// never written, so identities they hold can't be removed through this store.
+pub struct ChainedStore {
+    stores: Vec<Box<dyn SsiStore>>,
+    primary: usize,
+}
+
+impl ChainedStore {
+    /// Starts a chain holding only `primary`, which takes every write.
+    pub fn new(primary: Box<dyn SsiStore>) -> Self {
+        Self {
+            stores: vec![primary],
+            primary: 0,
+        }
+    }
+
+    /// Consults `store` before the stores already in the chain.
+    pub fn before(mut self, store: Box<dyn SsiStore>) -> Self {
+        self.stores.insert(0, store);
+        self.primary += 1;
+        self
+    }
+
+    /// Consults `store` after the stores already in the chain.
+    pub fn after(mut self, store: Box<dyn SsiStore>) -> Self {
+        self.stores.push(store);
+        self
+    }
+
+    fn primary(&mut self) -> &mut dyn SsiStore {
+        &mut *self.stores[self.primary]
+    }
+
+    /// Removes `identity` from the stores consulted before the primary.
+    fn unshadow(&mut self, identity: &str) -> Result<(), Error> {
+        for store in &mut self.stores[..self.primary] {
+            store.remove(identity)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the first store holding `identity`.
+    fn holder(&self, identity: &str) -> Result<usize, Error> {
+        let mut skipped = None;
+        for (index, store) in self.stores.iter().enumerate() {
+            match store.contains(identity) {
+                Ok(true) => return Ok(index),
+                Ok(false) => {}
+                Err(err) if err.is_transient() => {
+                    skipped.get_or_insert(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(skipped.unwrap_or_else(|| Error::UnknownIdentity(identity.to_string())))
+    }
+}
+
+/// Merges the identities listed by every store, sorted by identity, skipping the stores
+/// failing with a transient error unless they all do.
+///
+/// `lists` is consumed in order, and no further once a store fails otherwise.
+fn merge(lists: impl Iterator<Item = Result<Vec<String>, Error>>) -> Result<Vec<String>, Error> {
+    let mut identities = BTreeSet::new();
+    let mut listed = false;
+    let mut skipped = None;
+    for list in lists {
+        match list {
+            Ok(list) => {
+                identities.extend(list);
+                listed = true;
+            }
+            Err(err) if err.is_transient() => {
+                skipped.get_or_insert(err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    match skipped {
+        Some(err) if !listed => Err(err),
+        _ => Ok(identities.into_iter().collect()),
+    }
+}
+
+impl SsiStore for ChainedStore {
+    fn insert(&mut self, identity: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        for store in &mut self.stores[..self.primary] {
+            if store.contains(&identity)? {
+                return Err(Error::IdentityExists(identity));
+            }
+        }
+        self.primary().insert(identity, ssi, secret)
+    }
+
+    fn get(&self, identity: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let holder = self.holder(identity)?;
+        self.stores[holder].get(identity)
+    }
+
+    fn get_shared(&self, identity: &str) -> Result<Arc<(Ssi, EncryptedSecret)>, Error> {
+        let holder = self.holder(identity)?;
+        self.stores[holder].get_shared(identity)
+    }
+
+    fn remove(&mut self, identity: &str) -> Result<bool, Error> {
+        let mut removed = false;
+        for store in &mut self.stores[..=self.primary] {
+            removed |= store.remove(identity)?;
+        }
+        Ok(removed)
+    }
+
+    fn contains(&self, identity: &str) -> Result<bool, Error> {
+        match self.holder(identity) {
+            Ok(_) => Ok(true),
+            Err(Error::UnknownIdentity(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        let identities = merge(self.stores.iter().map(|store| {
+            let mut identities = Vec::new();
+            store.for_each_identity(&mut |identity| {
+                identities.push(identity.to_string());
+                Ok(())
+            })?;
+            Ok(identities)
+        }))?;
+        identities.iter().try_for_each(|identity| f(identity))
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        self.stores
+            .iter()
+            .map(|store| store.capabilities())
+            .reduce(StoreCapabilities::intersection)
+            .unwrap_or_default()
+            // A write may span several stores, which can't commit together.
+            .without(StoreCapability::Transactions)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+
+    fn as_snapshot(&mut self) -> Option<&mut dyn SnapshotCapable> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for ChainedStore {
+    fn replace(
+        &mut self,
+        identity: String,
+        ssi: Ssi,
+        secret: EncryptedSecret,
+    ) -> Result<(), Error> {
+        self.unshadow(&identity)?;
+        store_ext_mut(self.primary(), StoreCapability::Replace)?.replace(identity, ssi, secret)
+    }
+
+    fn update(&mut self, identity: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.unshadow(identity)?;
+        store_ext_mut(self.primary(), StoreCapability::Replace)?.update(identity, ssi, secret)
+    }
+
+    fn metadata(&self, identity: &str) -> Result<IdentityMetadata, Error> {
+        let holder = self.holder(identity)?;
+        store_ext(&*self.stores[holder], StoreCapability::Metadata)?.metadata(identity)
+    }
+
+    fn record_signatures(
+        &mut self,
+        identity: &str,
+        count: u64,
+        at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let holder = self.holder(identity)?;
+        store_ext_mut(&mut *self.stores[holder], StoreCapability::Metadata)?
+            .record_signatures(identity, count, at)
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        merge(
+            self.stores.iter().map(|store| {
+                store_ext(&**store, StoreCapability::Metadata)?.stale_identities(cutoff)
+            }),
+        )
+    }
+
+    fn set_needs_rewrap(&mut self, identity: &str, needs_rewrap: bool) -> Result<(), Error> {
+        let holder = self.holder(identity)?;
+        store_ext_mut(&mut *self.stores[holder], StoreCapability::Metadata)?
+            .set_needs_rewrap(identity, needs_rewrap)
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        merge(self.stores.iter().map(|store| {
+            store_ext(&**store, StoreCapability::Metadata)?.identities_needing_rewrap()
+        }))
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        for store in &mut self.stores {
+            if let Some(store) = store.as_ext_mut() {
+                store.set_clock(clock.clone());
+            }
+        }
+    }
+
+    fn check_integrity(&self) -> Result<IntegrityFindings, Error> {
+        match self.stores[self.primary].as_ext() {
+            Some(primary) => primary.check_integrity(),
+            None => Ok(IntegrityFindings::default()),
+        }
+    }
+
+    fn repair_integrity(&mut self, policy: &RepairPolicy) -> Result<IntegrityRepair, Error> {
+        match self.primary().as_ext_mut() {
+            Some(primary) => primary.repair_integrity(policy),
+            None => Ok(IntegrityRepair::default()),
+        }
+    }
+
+    fn wrapped_key(&self, identity: &str) -> Result<Option<Vec<u8>>, Error> {
+        let holder = self.holder(identity)?;
+        match self.stores[holder].as_ext() {
+            Some(holder) => holder.wrapped_key(identity),
+            None => Ok(None),
+        }
+    }
+
+    fn set_wrapped_key(&mut self, identity: &str, wrapped_key: Vec<u8>) -> Result<(), Error> {
+        store_ext_mut(self.primary(), StoreCapability::PlatformProtection)?
+            .set_wrapped_key(identity, wrapped_key)
+    }
+
+    fn format_version(&self) -> Result<u32, Error> {
+        match self.stores[self.primary].as_ext() {
+            Some(primary) => primary.format_version(),
+            None => Ok(FORMAT_VERSION),
+        }
+    }
+
+    fn upgrade_format(&mut self) -> Result<(), Error> {
+        match self.primary().as_ext_mut() {
+            Some(primary) => primary.upgrade_format(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl JournalCapable for ChainedStore {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        // Nothing is unshadowed for a primary that can't import.
+        store_journal(self.primary())?;
+        for record in &records {
+            self.unshadow(&record.identity)?;
+        }
+        store_journal(self.primary())?.import_batch(records, on_conflict)
+    }
+
+    fn ingest_chunk(&mut self, records: Vec<StoredIdentity>, ingested: usize) -> Result<(), Error> {
+        store_journal(self.primary())?;
+        for record in &records {
+            self.unshadow(&record.identity)?;
+        }
+        store_journal(self.primary())?.ingest_chunk(records, ingested)
+    }
+
+    fn ingest_progress(&mut self) -> Result<Option<usize>, Error> {
+        store_journal(self.primary())?.ingest_progress()
+    }
+
+    fn finish_ingest(&mut self) -> Result<(), Error> {
+        store_journal(self.primary())?.finish_ingest()
+    }
+}
+
+/// Stores without [`SnapshotCapable`] are read as they are.
+impl SnapshotCapable for ChainedStore {
+    fn begin_read_snapshot(&mut self) -> Result<(), Error> {
+        self.stores
+            .iter_mut()
+            .filter_map(|store| store.as_snapshot())
+            .try_for_each(|store| store.begin_read_snapshot())
+    }
+
+    fn end_read_snapshot(&mut self) -> Result<(), Error> {
+        // Every view is ended, even after a failure.
+        self.stores
+            .iter_mut()
+            .filter_map(|store| store.as_snapshot())
+            .map(|store| store.end_read_snapshot())
+            .fold(Ok(()), Result::and)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan, SsiMemoryStore};
+
+    #[test]
+    fn chained_store_should_read_every_store_and_write_the_primary() {
+        let mut shared = SsiMan::with_memory();
+        shared
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let shared = std::mem::take(shared.memory_store().unwrap());
+        let store = ChainedStore::new(Box::new(SsiMemoryStore::default())).after(Box::new(shared));
+        let mut ssi_man = SsiMan::with_store(Box::new(store));
+
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        ssi_man
+            .new_ssi("Sol", "sol@bitlightlabs.com", None)
+            .unwrap();
+        assert_eq!(
+            ssi_man.paginated_identities(1, 10).unwrap().identities,
+            vec!["Luna".to_string(), "Sol".to_string()]
+        );
+        assert_eq!(ssi_man.remove("Sol"), Ok(true));
+        // Only the primary is written.
+        assert_eq!(ssi_man.remove("Luna"), Ok(false));
+        assert!(ssi_man.store.contains("Luna").unwrap());
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Source of the current time for everything time-dependent: expiries, creation and
+/// usage timestamps, and staleness.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock, used unless [`SsiMan::with_clock`](crate::SsiMan::with_clock) sets
+/// another one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to, for tests.
+///
+/// Clones share the same time, so a test can keep one to advance the clock given to a
+/// manager.
+#[derive(Clone, Debug)]
+pub struct ManualClock(Arc<Mutex<DateTime<Utc>>>);
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.0.lock().unwrap_or_else(|err| err.into_inner()) = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap_or_else(|err| err.into_inner()) += by;
+    }
+}
+
+impl Default for ManualClock {
+    /// Starts at the current system time.
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+pub(crate) fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sources of the modules whose time-dependent code must go through a [`Clock`].
+    const CLOCKED_MODULES: [(&str, &str); 5] = [
+        ("lib.rs", include_str!("lib.rs")),
+        ("failover.rs", include_str!("failover.rs")),
+        ("memory.rs", include_str!("memory.rs")),
+        ("rewrap.rs", include_str!("rewrap.rs")),
+        ("sqlite.rs", include_str!("sqlite.rs")),
+    ];
+
+    #[test]
+    fn clocked_modules_should_not_read_system_time() {
+        for (name, source) in CLOCKED_MODULES {
+            let code = source.split("#[cfg(test)]\nmod tests").next().unwrap();
+            for call in ["Utc::now()", "now_utc()", "SystemTime::now()"] {
+                assert!(!code.contains(call), "{name} calls {call}");
+            }
+        }
+    }
+
+    #[test]
+    fn manual_clock_should_move_only_when_told() {
+        let start = DateTime::<Utc>::UNIX_EPOCH;
+        let clock = ManualClock::new(start);
+        let shared = clock.clone();
+        assert_eq!(shared.now(), start);
+        clock.advance(Duration::days(1));
+        assert_eq!(shared.now(), start + Duration::days(1));
+        clock.set(start);
+        assert_eq!(shared.now(), start);
+    }
+}
@@ -0,0 +1,108 @@
+#[cfg(feature = "compression")]
+use std::io::Write;
+
+use crate::Error;
+
+/// Codec ids, written as the first byte of compressed artifacts.
+const CODEC_NONE: u8 = 0;
+#[cfg(feature = "compression")]
+const CODEC_ZSTD: u8 = 1;
+/// zstd level of [`Compression::Zstd`], its default.
+#[cfg(feature = "compression")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// How [`SsiMan::export_all_compressed`](crate::SsiMan::export_all_compressed) encodes
+/// its output.
+///
+/// Exported secrets stay concealed one by one and the export isn't encrypted as a
+/// whole, so compressing it reveals nothing a compression oracle could exploit; for
+/// artifacts that get encrypted later, compress before encrypting, or stick to
+/// [`Compression::None`] where the size of ciphertexts must not depend on their content.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// zstd, with the `compression` feature.
+    #[cfg(feature = "compression")]
+    Zstd,
+}
+
+impl Compression {
+    /// Returns `payload` behind a one-byte header naming the codec.
+    pub(crate) fn encode(self, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::None => Ok([&[CODEC_NONE], payload].concat()),
+            #[cfg(feature = "compression")]
+            Compression::Zstd => {
+                // The checksum makes corrupted payloads fail to decode, rather than decode
+                // to garbage.
+                let mut encoder = zstd::stream::Encoder::new(vec![CODEC_ZSTD], ZSTD_LEVEL)?;
+                encoder.include_checksum(true)?;
+                encoder.write_all(payload)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+
+    /// Reverses [`Compression::encode`], passing through text written before artifacts
+    /// had a header, recognized by its leading `{` or whitespace.
+    pub(crate) fn decode(encoded: &[u8]) -> Result<Vec<u8>, Error> {
+        match encoded.first() {
+            None => Ok(Vec::new()),
+            Some(b'{') => Ok(encoded.to_vec()),
+            Some(byte) if byte.is_ascii_whitespace() => Ok(encoded.to_vec()),
+            Some(&CODEC_NONE) => Ok(encoded[1..].to_vec()),
+            #[cfg(feature = "compression")]
+            Some(&CODEC_ZSTD) => zstd::stream::decode_all(&encoded[1..])
+                .map_err(|err| Error::Decompression(err.to_string())),
+            Some(codec) => Err(Error::Decompression(format!("unknown codec {codec}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codecs_should_round_trip() {
+        let payload = "{\"identity\":\"Luna\"}\n".repeat(100);
+        let codecs = [
+            Compression::None,
+            #[cfg(feature = "compression")]
+            Compression::Zstd,
+        ];
+        for codec in codecs {
+            let encoded = codec.encode(payload.as_bytes()).unwrap();
+            assert_eq!(
+                Compression::decode(&encoded),
+                Ok(payload.clone().into_bytes())
+            );
+        }
+        assert_eq!(
+            Compression::decode(payload.as_bytes()),
+            Ok(payload.clone().into_bytes())
+        );
+        assert_eq!(Compression::decode(b""), Ok(vec![]));
+        assert!(matches!(
+            Compression::decode(&[0xff, 1, 2]),
+            Err(Error::Decompression(_))
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn corrupted_zstd_payload_should_fail() {
+        let payload = "{\"identity\":\"Luna\"}\n".repeat(100);
+        let mut encoded = Compression::Zstd.encode(payload.as_bytes()).unwrap();
+        encoded.truncate(encoded.len() / 2);
+        assert!(matches!(
+            Compression::decode(&encoded),
+            Err(Error::Decompression(_))
+        ));
+        assert!(matches!(
+            Compression::decode(&[CODEC_ZSTD, 0xde, 0xad, 0xbe, 0xef]),
+            Err(Error::Decompression(_))
+        ));
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    cell::{Cell, RefCell},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::Error;
+
+thread_local! {
+    /// Context of the operation running on this thread, see [`OpContext::interrupting`].
+    static CURRENT: RefCell<Option<OpContext>> = const { RefCell::new(None) };
+    /// Whether a statement of that operation was interrupted.
+    static INTERRUPTED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Limits on a long operation, given to the `_with_ctx` variants of listings, exports,
+/// ingestion and batch signing.
+///
+/// They are checked between items, so an operation stops before its next identity, record,
+/// chunk or signature once `cancel` is set or `deadline` is past, failing with
+/// [`Error::Cancelled`] or [`Error::DeadlineExceeded`] and the number of items done. The
+/// sqlite store also interrupts the read it is running, e.g. a listing stuck on a network
+/// volume, from a progress handler.
+#[derive(Clone, Debug, Default)]
+pub struct OpContext {
+    pub deadline: Option<Instant>,
+    /// Set from another thread to cancel the operation.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl OpContext {
+    /// A context expiring `timeout` from now.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            deadline: Some(Instant::now() + timeout),
+            cancel: None,
+        }
+    }
+
+    /// A context cancelled by setting `cancel`.
+    pub fn with_cancel(cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            deadline: None,
+            cancel: Some(cancel),
+        }
+    }
+
+    /// Fails if the operation must stop, after `done` items.
+    pub(crate) fn check(&self, done: usize) -> Result<(), Error> {
+        if self
+            .cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+        {
+            return Err(Error::Cancelled { done });
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(Error::DeadlineExceeded { done });
+        }
+        Ok(())
+    }
+
+    /// Runs `f` with this context interrupting the statements of stores that check
+    /// [`stop_requested`] on this thread. If one is interrupted, `f` fails like
+    /// [`OpContext::check`] after `done()` items instead of with the error of the store.
+    pub(crate) fn interrupting<T>(
+        &self,
+        f: impl FnOnce() -> Result<T, Error>,
+        done: impl FnOnce() -> usize,
+    ) -> Result<T, Error> {
+        let scope = Scope {
+            previous: CURRENT.replace(Some(self.clone())),
+            interrupted: INTERRUPTED.replace(false),
+        };
+        let result = f();
+        let interrupted = INTERRUPTED.get();
+        drop(scope);
+        match result {
+            Err(err) if interrupted => Err(self.check(done()).err().unwrap_or(err)),
+            result => result,
+        }
+    }
+}
+
+/// Restores the context of the enclosing operation, even if the inner one panics.
+struct Scope {
+    previous: Option<OpContext>,
+    interrupted: bool,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CURRENT.set(self.previous.take());
+        INTERRUPTED.set(self.interrupted);
+    }
+}
+
+/// Tells whether the operation running on this thread must stop.
+#[cfg(feature = "sqlite")]
+pub(crate) fn stop_requested() -> bool {
+    CURRENT.with_borrow(|ctx| ctx.as_ref().is_some_and(|ctx| ctx.check(0).is_err()))
+}
+
+/// Notes that a statement of the operation running on this thread is being interrupted.
+#[cfg(feature = "sqlite")]
+pub(crate) fn note_interrupted() {
+    INTERRUPTED.set(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, sync::atomic::AtomicUsize};
+
+    use ssi::{EncryptedSecret, Ssi};
+
+    use super::*;
+    use crate::{IngestOptions, IngestRecord, SsiMan, SsiMemoryStore, SsiStore};
+
+    /// A memory store setting `cancel` once `after` records were read, as a user giving up
+    /// half way would.
+    struct CancellingStore {
+        inner: SsiMemoryStore,
+        cancel: Arc<AtomicBool>,
+        after: usize,
+        reads: AtomicUsize,
+    }
+
+    impl SsiStore for CancellingStore {
+        fn insert(
+            &mut self,
+            identity: String,
+            ssi: Ssi,
+            secret: EncryptedSecret,
+        ) -> Result<(), Error> {
+            self.inner.insert(identity, ssi, secret)
+        }
+
+        fn get(&self, identity: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+            if self.reads.fetch_add(1, Ordering::Relaxed) + 1 == self.after {
+                self.cancel.store(true, Ordering::Relaxed);
+            }
+            self.inner.get(identity)
+        }
+
+        fn remove(&mut self, identity: &str) -> Result<bool, Error> {
+            self.inner.remove(identity)
+        }
+
+        fn for_each_identity(
+            &self,
+            f: &mut dyn FnMut(&str) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            self.inner.for_each_identity(f)
+        }
+    }
+
+    /// A manager holding `count` identities, and its export.
+    fn populated(count: usize) -> (SsiMan, String) {
+        let mut ssi_man = SsiMan::with_memory();
+        for n in 0..count {
+            let identity = format!("id{n:03}");
+            ssi_man
+                .new_ssi(&identity, &format!("{identity}@bitlightlabs.com"), None)
+                .unwrap();
+        }
+        let export = ssi_man.export_all().unwrap();
+        (ssi_man, export)
+    }
+
+    #[test]
+    fn export_should_stop_when_cancelled() {
+        let (source, export) = populated(100);
+        let mut inner = SsiMemoryStore::default();
+        for identity in source
+            .all_identities_with_ctx(&OpContext::default())
+            .unwrap()
+        {
+            let (ssi, secret) = source.store.get(&identity).unwrap().into_owned();
+            inner.insert(identity, ssi, secret).unwrap();
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        let ssi_man = SsiMan::with_store(Box::new(CancellingStore {
+            inner,
+            cancel: cancel.clone(),
+            after: 40,
+            reads: AtomicUsize::new(0),
+        }));
+
+        let ctx = OpContext::with_cancel(cancel.clone());
+        assert_eq!(
+            ssi_man.export_all_with_ctx(&ctx),
+            Err(Error::Cancelled { done: 40 })
+        );
+        assert_eq!(
+            ssi_man.export_all_with_ctx(&ctx),
+            Err(Error::Cancelled { done: 0 })
+        );
+        assert_eq!(ssi_man.export_all(), Ok(export));
+
+        cancel.store(false, Ordering::Relaxed);
+        assert_eq!(
+            ssi_man.export_all_with_ctx(&OpContext::with_timeout(Duration::ZERO)),
+            Err(Error::DeadlineExceeded { done: 0 })
+        );
+    }
+
+    #[test]
+    fn listing_and_signing_should_stop_when_cancelled() {
+        let (mut ssi_man, _) = populated(10);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let ctx = OpContext::with_cancel(cancel.clone());
+        let mut listed = vec![];
+        let result = ssi_man.for_each_identity_with_ctx(&ctx, |identity| {
+            listed.push(identity.to_string());
+            if listed.len() == 3 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            Ok(())
+        });
+        assert_eq!(result, Err(Error::Cancelled { done: 3 }));
+        assert_eq!(listed, ["id000", "id001", "id002"]);
+        assert_eq!(
+            ssi_man.all_identities_with_ctx(&ctx),
+            Err(Error::Cancelled { done: 0 })
+        );
+        assert_eq!(
+            ssi_man.sign_batch_with_ctx("id000", &[b"have a good day!".as_slice()], None, &ctx),
+            Err(Error::Cancelled { done: 0 })
+        );
+        assert_eq!(ssi_man.identity_info("id000").unwrap().sign_count, 0);
+
+        let ctx = OpContext::with_timeout(Duration::from_secs(60));
+        assert_eq!(ssi_man.all_identities_with_ctx(&ctx).unwrap().len(), 10);
+        let certs = ssi_man
+            .sign_batch_with_ctx("id000", &[b"have a".as_slice(), b"good day!"], None, &ctx)
+            .unwrap();
+        assert_eq!(certs.len(), 2);
+    }
+
+    #[test]
+    fn ingest_should_resume_after_cancel() {
+        let (source, _) = populated(10);
+        let records = source
+            .all_identities_with_ctx(&OpContext::default())
+            .unwrap()
+            .into_iter()
+            .map(|identity| {
+                let (ssi, secret) = source.store.get(&identity).unwrap().into_owned();
+                IngestRecord {
+                    identity,
+                    ssi: ssi.to_string(),
+                    encrypted_secret: secret.to_string(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let options = IngestOptions {
+            chunk_size: 4,
+            ..Default::default()
+        };
+
+        let mut ssi_man = SsiMan::with_memory();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let ctx = OpContext::with_cancel(cancel.clone());
+        let result = ssi_man.ingest_with_ctx(records.clone(), options, &ctx, |_| {
+            cancel.store(true, Ordering::Relaxed)
+        });
+        assert_eq!(result, Err(Error::Cancelled { done: 4 }));
+        assert_eq!(
+            ssi_man
+                .all_identities_with_ctx(&Default::default())
+                .unwrap()
+                .len(),
+            4
+        );
+
+        let report = ssi_man.ingest(records, options).unwrap();
+        assert_eq!(report.imported, 6);
+        assert_eq!(ssi_man.export_all(), source.export_all());
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt::{self, Debug, Formatter};
+
+#[cfg(feature = "identity-validation")]
+use regex::Regex;
+use ssi::{Algo, Chain, Ssi};
+
+use crate::redact::{redact, RedactedList};
+
+/// Checks an identity about to be created or imported, returning the reason to reject it.
+pub type CreationHook = Box<dyn Fn(&CreationRequest) -> Result<(), String> + Send + Sync>;
+
+/// What is known about an identity before it gets a key or reaches the store.
+#[derive(Clone)]
+pub struct CreationRequest {
+    pub identity: String,
+    pub emails: Vec<String>,
+    pub algo: Algo,
+    pub chain: Chain,
+}
+
+impl Debug for CreationRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreationRequest")
+            .field("identity", &redact(&self.identity))
+            .field("emails", &RedactedList(&self.emails))
+            .field("algo", &self.algo)
+            .field("chain", &self.chain)
+            .finish()
+    }
+}
+
+impl CreationRequest {
+    /// Describes an existing ssi, e.g. one being imported, taking the emails from its
+    /// `mailto:` uids.
+    pub(crate) fn from_ssi(identity: String, ssi: &Ssi) -> Self {
+        let emails = ssi
+            .uids
+            .iter()
+            .filter_map(|uid| {
+                uid.to_string()
+                    .split_once("<mailto:")
+                    .and_then(|(_, email)| email.strip_suffix('>'))
+                    .map(str::to_string)
+            })
+            .collect();
+        Self {
+            identity,
+            emails,
+            algo: ssi.pk.algo(),
+            chain: ssi.pk.chain(),
+        }
+    }
+}
+
+/// Common naming rules, usable as a [`CreationHook`] through [`CreationRules::into_hook`].
+#[derive(Clone, Debug, Default)]
+pub struct CreationRules {
+    #[cfg(feature = "identity-validation")]
+    identity_regex: Option<Regex>,
+    email_domains: Vec<String>,
+}
+
+impl CreationRules {
+    pub fn builder() -> CreationRulesBuilder {
+        CreationRulesBuilder::default()
+    }
+
+    pub fn check(&self, request: &CreationRequest) -> Result<(), String> {
+        #[cfg(feature = "identity-validation")]
+        if let Some(regex) = &self.identity_regex {
+            if !regex.is_match(&request.identity) {
+                return Err(format!(
+                    "identity `{}` does not match `{regex}`",
+                    redact(&request.identity)
+                ));
+            }
+        }
+        if !self.email_domains.is_empty() {
+            for email in &request.emails {
+                let domain = email.rsplit_once('@').map(|(_, domain)| domain);
+                let allowed = domain.is_some_and(|domain| {
+                    self.email_domains
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+                });
+                if !allowed {
+                    return Err(format!(
+                        "email `{}` is not in an allowed domain",
+                        redact(email)
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_hook(self) -> CreationHook {
+        Box::new(move |request| self.check(request))
+    }
+}
+
+#[derive(Default)]
+pub struct CreationRulesBuilder {
+    rules: CreationRules,
+}
+
+impl CreationRulesBuilder {
+    /// Requires identities to match `regex`; anchor it to constrain the whole identity.
+    #[cfg(feature = "identity-validation")]
+    pub fn identity_regex(mut self, regex: Regex) -> Self {
+        self.rules.identity_regex = Some(regex);
+        self
+    }
+
+    /// Requires every email to belong to one of `domains`, compared case-insensitively.
+    pub fn email_domains(mut self, domains: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.rules.email_domains = domains.into_iter().map(|d| d.to_string()).collect();
+        self
+    }
+
+    pub fn build(self) -> CreationRules {
+        self.rules
+    }
+}
+
+#[cfg(all(test, feature = "identity-validation"))]
+mod tests {
+    use super::*;
+
+    fn request(identity: &str, email: &str) -> CreationRequest {
+        CreationRequest {
+            identity: identity.to_string(),
+            emails: vec![email.to_string()],
+            algo: Algo::Ed25519,
+            chain: Chain::Bitcoin,
+        }
+    }
+
+    #[test]
+    fn creation_rules_should_check_identity_and_email() {
+        let rules = CreationRules::builder()
+            .identity_regex(Regex::new(r"^[a-z]+/[a-z]+$").unwrap())
+            .email_domains(["bitlightlabs.com"])
+            .build();
+
+        assert_eq!(
+            rules.check(&request("ops/luna", "luna@BitlightLabs.com")),
+            Ok(())
+        );
+        assert!(rules
+            .check(&request("luna", "luna@bitlightlabs.com"))
+            .is_err());
+        assert!(rules
+            .check(&request("ops/luna", "luna@example.com"))
+            .is_err());
+        assert!(rules.check(&request("ops/luna", "not an email")).is_err());
+        assert_eq!(
+            CreationRules::default().check(&request("anything", "x@y")),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    env,
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use ssi::{EncryptedSecret, Ssi};
+
+use crate::{
+    fingerprint, ConflictPolicy, Error, JournalCapable, SsiStore, SsiStoreExt, StoreCapabilities,
+    StoreCapability, StoredIdentity,
+};
+
+/// File of the upstream tool with one ssi per line.
+const IDENTITIES_FILE: &str = "identities";
+/// File of the upstream tool with one concealed secret per line.
+const SECRETS_FILE: &str = "secrets";
+/// Our own file naming identities, as `identity<TAB>fingerprint` lines; the upstream tool
+/// doesn't name them and ignores it.
+const NAMES_FILE: &str = "ssi-man.names";
+
+/// Reads the lines of the file at `path`, none if it is missing.
+fn read_lines(path: &Path) -> Result<Vec<String>, Error> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes `lines` to the file at `path` through a temporary one renamed over it.
+fn write_lines(path: &Path, lines: impl IntoIterator<Item = String>) -> Result<(), Error> {
+    let mut temporary = path.to_path_buf().into_os_string();
+    temporary.push(".tmp");
+    let mut file = File::create(&temporary)?;
+    for line in lines {
+        writeln!(file, "{line}")?;
+    }
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// The name the upstream tool shows for an ssi: that of its first uid, before the email.
+fn uid_name(ssi: &Ssi) -> Option<String> {
+    let uid = ssi.uids.iter().next()?.to_string();
+    let name = uid.split(" <").next().unwrap_or_default().trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Everything in the directory.
+#[derive(Default)]
+struct DirContents {
+    records: BTreeMap<String, (Ssi, EncryptedSecret)>,
+    /// Lines of the upstream files not making a record of ours, e.g. ssis without their
+    /// secret, kept as they are.
+    other_ssis: Vec<String>,
+    other_secrets: Vec<String>,
+}
+
+impl DirContents {
+    fn load(dir: &Path) -> Result<Self, Error> {
+        let mut secrets = BTreeMap::new();
+        let mut contents = Self::default();
+        for line in read_lines(&dir.join(SECRETS_FILE))? {
+            match EncryptedSecret::from_str(&line) {
+                Ok(secret) => {
+                    secrets.insert(secret.fp.to_string(), (secret, line));
+                }
+                Err(_) => contents.other_secrets.push(line),
+            }
+        }
+        let names = read_lines(&dir.join(NAMES_FILE))?
+            .into_iter()
+            .filter_map(|line| {
+                let (identity, fp) = line.split_once('\t')?;
+                Some((fp.to_string(), identity.to_string()))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        for line in read_lines(&dir.join(IDENTITIES_FILE))? {
+            let Ok(ssi) = Ssi::from_str(&line) else {
+                contents.other_ssis.push(line);
+                continue;
+            };
+            let fp = fingerprint(&ssi);
+            let Some((secret, _)) = secrets.remove(&fp) else {
+                contents.other_ssis.push(line);
+                continue;
+            };
+            // Identities the upstream tool created have no name of ours yet.
+            let identity = names
+                .get(&fp)
+                .cloned()
+                .or_else(|| uid_name(&ssi))
+                .filter(|identity| !contents.records.contains_key(identity))
+                .unwrap_or(fp);
+            contents.records.insert(identity, (ssi, secret));
+        }
+        contents
+            .other_secrets
+            .extend(secrets.into_values().map(|(_, line)| line));
+        Ok(contents)
+    }
+
+    fn save(&self, dir: &Path) -> Result<(), Error> {
+        let records = self.records.values();
+        write_lines(
+            &dir.join(SECRETS_FILE),
+            records
+                .clone()
+                .map(|(_, secret)| secret.to_string())
+                .chain(self.other_secrets.iter().cloned()),
+        )?;
+        write_lines(
+            &dir.join(IDENTITIES_FILE),
+            records
+                .map(|(ssi, _)| ssi.to_string())
+                .chain(self.other_ssis.iter().cloned()),
+        )?;
+        write_lines(
+            &dir.join(NAMES_FILE),
+            self.records
+                .iter()
+                .map(|(identity, (ssi, _))| format!("{identity}\t{}", fingerprint(ssi))),
+        )
+    }
+}
+
+/// A store in the directory layout of the upstream `ssi` tool, `~/.ssi` by default, so
+/// both can share the same identities.
+///
+/// The upstream files list ssis in `identities` and concealed secrets in `secrets`, one
+/// per line in their text forms, matched by the fingerprint of their key. The upstream
+/// tool doesn't name identities, so names are kept in `ssi-man.names` next to them;
+/// identities it created are named after their first uid, or their fingerprint if that
+/// name is taken. Lines making no identity, e.g. ssis of which we don't have the secret,
+/// are kept as they are.
+///
+/// Every write reads the directory again before writing it back, so it keeps what the
+/// upstream tool changed meanwhile; reads see the directory as of the last write or
+/// [`SsiDirStore::reload`]. There is no metadata, which the upstream layout can't hold.
+pub struct SsiDirStore {
+    dir: PathBuf,
+    contents: DirContents,
+}
+
+impl SsiDirStore {
+    /// Opens the directory at `dir`, creating it if missing.
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            contents: DirContents::load(&dir)?,
+            dir,
+        })
+    }
+
+    /// Opens the directory of the upstream tool, `~/.ssi`.
+    pub fn upstream() -> Result<Self, Error> {
+        let home = env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no home directory"))?;
+        Self::new(PathBuf::from(home).join(".ssi"))
+    }
+
+    /// Reads the directory again, e.g. after the upstream tool changed it.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        self.contents = DirContents::load(&self.dir)?;
+        Ok(())
+    }
+
+    /// Applies `change` to the directory as it is now and writes it back, keeping the
+    /// result only if both succeed.
+    fn commit<T>(
+        &mut self,
+        change: impl FnOnce(&mut DirContents) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut contents = DirContents::load(&self.dir)?;
+        let value = change(&mut contents)?;
+        contents.save(&self.dir)?;
+        self.contents = contents;
+        Ok(value)
+    }
+}
+
+impl SsiStore for SsiDirStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.commit(|contents| {
+            if contents.records.contains_key(&id) {
+                return Err(Error::IdentityExists(id));
+            }
+            contents.records.insert(id, (ssi, secret));
+            Ok(())
+        })
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        self.contents
+            .records
+            .get(id)
+            .map(Cow::Borrowed)
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        self.commit(|contents| Ok(contents.records.remove(id).is_some()))
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.contents.records.contains_key(id))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        for identity in self.contents.records.keys() {
+            f(identity)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default()
+            .with(StoreCapability::AtomicWrites)
+            .with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiDirStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.commit(|contents| {
+            contents.records.insert(id, (ssi, secret));
+            Ok(())
+        })
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.commit(|contents| {
+            let record = contents
+                .records
+                .get_mut(id)
+                .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+            *record = (ssi, secret);
+            Ok(())
+        })
+    }
+}
+
+impl JournalCapable for SsiDirStore {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        self.commit(|contents| {
+            let mut imported = 0;
+            for record in records {
+                if contents.records.contains_key(&record.identity) {
+                    match on_conflict {
+                        ConflictPolicy::Skip => continue,
+                        ConflictPolicy::Overwrite => {}
+                        ConflictPolicy::Error => {
+                            return Err(Error::IdentityExists(record.identity))
+                        }
+                    }
+                }
+                contents
+                    .records
+                    .insert(record.identity, (record.ssi, record.encrypted_secret));
+                imported += 1;
+            }
+            Ok(imported)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ssi_man_{name}_{}_ssi",
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ))
+    }
+
+    #[test]
+    fn dir_store_should_write_the_upstream_layout() {
+        let dir = temp_dir("layout");
+        let mut ssi_man = SsiMan::with_dir(&dir).unwrap();
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let (_, secret) = ssi_man.store.get("Luna").unwrap().into_owned();
+        assert_eq!(
+            read_lines(&dir.join(IDENTITIES_FILE)).unwrap(),
+            [ssi.clone()]
+        );
+        assert_eq!(
+            read_lines(&dir.join(SECRETS_FILE)).unwrap(),
+            [secret.to_string()]
+        );
+        drop(ssi_man);
+
+        let mut ssi_man = SsiMan::with_dir(&dir).unwrap();
+        assert_eq!(ssi_man.get_ssi("Luna"), Ok(ssi));
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        assert!(read_lines(&dir.join(IDENTITIES_FILE)).unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dir_store_should_share_identities_with_the_upstream_tool() {
+        let mut source = SsiMan::with_memory();
+        source.new_ssi("Sol", "sol@bitlightlabs.com", None).unwrap();
+        let (ssi, secret) = source.store.get("Sol").unwrap().into_owned();
+
+        // As left by the upstream tool: no names, and an ssi we have no secret of.
+        let dir = temp_dir("upstream");
+        fs::create_dir_all(&dir).unwrap();
+        let foreign = "not an ssi of ours";
+        fs::write(dir.join(IDENTITIES_FILE), format!("{ssi}\n{foreign}\n")).unwrap();
+        fs::write(dir.join(SECRETS_FILE), format!("{secret}\n")).unwrap();
+
+        let mut ssi_man = SsiMan::with_dir(&dir).unwrap();
+        assert_eq!(
+            ssi_man.paginated_identities(1, 10).unwrap().identities,
+            ["Sol"]
+        );
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let identities = read_lines(&dir.join(IDENTITIES_FILE)).unwrap();
+        assert_eq!(identities.len(), 3);
+        assert!(identities.contains(&foreign.to_string()));
+
+        // The upstream tool adds another Sol while we have the directory open.
+        source
+            .new_ssi_overwrite("Sol", "sol@example.com", None)
+            .unwrap();
+        let (ssi, secret) = source.store.get("Sol").unwrap().into_owned();
+        for (file, line) in [
+            (IDENTITIES_FILE, ssi.to_string()),
+            (SECRETS_FILE, secret.to_string()),
+        ] {
+            let mut lines = read_lines(&dir.join(file)).unwrap();
+            lines.push(line);
+            fs::write(dir.join(file), lines.join("\n")).unwrap();
+        }
+
+        ssi_man.remove("Luna").unwrap();
+        let mut identities = ssi_man.paginated_identities(1, 10).unwrap().identities;
+        identities.sort();
+        let mut expected = vec!["Sol".to_string(), fingerprint(&ssi)];
+        expected.sort();
+        assert_eq!(identities, expected);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    ptr, slice,
+    str::FromStr,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ssi::{EncryptedSecret, Ssi};
+use windows_sys::Win32::{
+    Foundation::LocalFree,
+    Security::Cryptography::{
+        CryptProtectData, CryptUnprotectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPT_INTEGER_BLOB,
+    },
+};
+use zeroize::Zeroizing;
+
+use crate::{Error, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability};
+
+/// A store keeping secrets in a local file encrypted with DPAPI for the current Windows
+/// user, so that the file is of no use to other users or off the machine.
+///
+/// The file holds `identity<TAB>ssi<TAB>base64 DPAPI blob` lines; the blobs are only
+/// decrypted on use and are written through a temporary file renamed over the store.
+/// DPAPI ties them to the user's logon credentials: a password reset by an administrator
+/// loses them, while a password change by the user keeps them.
+pub struct SsiDpapiStore {
+    path: PathBuf,
+    records: BTreeMap<String, (Ssi, Vec<u8>)>,
+}
+
+impl SsiDpapiStore {
+    /// Opens the store file at `path`, creating it if missing.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut records = BTreeMap::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let mut fields = line.splitn(3, '\t');
+            let (Some(identity), Some(ssi), Some(blob)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(Error::DpapiFile("line without a secret".to_string()));
+            };
+            let blob = STANDARD
+                .decode(blob)
+                .map_err(|err| Error::DpapiFile(format!("secret of {identity}: {err}")))?;
+            records.insert(identity.to_string(), (Ssi::from_str(ssi)?, blob));
+        }
+        let store = Self { path, records };
+        store.save(&store.records)?;
+        Ok(store)
+    }
+
+    /// Writes `records` through a temporary file renamed over the store file.
+    fn save(&self, records: &BTreeMap<String, (Ssi, Vec<u8>)>) -> Result<(), Error> {
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        for (identity, (ssi, blob)) in records {
+            writeln!(file, "{identity}\t{ssi}\t{}", STANDARD.encode(blob))?;
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+
+    /// Writes the store with `id` set to `ssi` and its protected secret.
+    fn put(&mut self, id: String, ssi: Ssi, secret: &EncryptedSecret) -> Result<(), Error> {
+        let blob = protect(Zeroizing::new(secret.to_string()).as_bytes())?;
+        let mut records = self.records.clone();
+        records.insert(id, (ssi, blob));
+        self.save(&records)?;
+        self.records = records;
+        Ok(())
+    }
+}
+
+/// Encrypts `data` for the current user, without ever prompting.
+fn protect(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: data.len() as u32,
+        pbData: data.as_ptr() as *mut u8,
+    };
+    let mut output = CRYPT_INTEGER_BLOB {
+        cbData: 0,
+        pbData: ptr::null_mut(),
+    };
+    // SAFETY: `input` points to `data` for the whole call, and DPAPI only reads it.
+    let ok = unsafe {
+        CryptProtectData(
+            &input,
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+            CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        )
+    };
+    if ok == 0 {
+        return Err(Error::Dpapi(io::Error::last_os_error().to_string()));
+    }
+    Ok(take_blob(output).to_vec())
+}
+
+/// Decrypts a blob of [`protect`], failing for other users or machines.
+fn unprotect(blob: &[u8]) -> Result<Zeroizing<Vec<u8>>, Error> {
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: blob.len() as u32,
+        pbData: blob.as_ptr() as *mut u8,
+    };
+    let mut output = CRYPT_INTEGER_BLOB {
+        cbData: 0,
+        pbData: ptr::null_mut(),
+    };
+    // SAFETY: as in `protect`.
+    let ok = unsafe {
+        CryptUnprotectData(
+            &input,
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+            CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        )
+    };
+    if ok == 0 {
+        return Err(Error::Dpapi(io::Error::last_os_error().to_string()));
+    }
+    Ok(take_blob(output))
+}
+
+/// Copies out and frees a blob allocated by DPAPI, wiping it first.
+fn take_blob(blob: CRYPT_INTEGER_BLOB) -> Zeroizing<Vec<u8>> {
+    // SAFETY: DPAPI returned `cbData` bytes at `pbData`, ours to free with `LocalFree`.
+    unsafe {
+        let bytes = slice::from_raw_parts_mut(blob.pbData, blob.cbData as usize);
+        let copy = Zeroizing::new(bytes.to_vec());
+        zeroize::Zeroize::zeroize(bytes);
+        LocalFree(blob.pbData as _);
+        copy
+    }
+}
+
+impl SsiStore for SsiDpapiStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if self.records.contains_key(&id) {
+            return Err(Error::IdentityExists(id));
+        }
+        self.put(id, ssi, &secret)
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let (ssi, blob) = self
+            .records
+            .get(id)
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+        let bytes = unprotect(blob)?;
+        let text = std::str::from_utf8(&bytes)
+            .map_err(|err| Error::DpapiFile(format!("secret of {id}: {err}")))?;
+        let secret =
+            EncryptedSecret::from_str(text).map_err(|err| Error::SecretParse(err.to_string()))?;
+        Ok(Cow::Owned((ssi.clone(), secret)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        if !self.records.contains_key(id) {
+            return Ok(false);
+        }
+        let mut records = self.records.clone();
+        records.remove(id);
+        self.save(&records)?;
+        self.records = records;
+        Ok(true)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.records.contains_key(id))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        for identity in self.records.keys() {
+            f(identity)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default().with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiDpapiStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.put(id, ssi, &secret)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.records.contains_key(id) {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        self.put(id.to_string(), ssi, &secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    #[test]
+    fn dpapi_store_should_keep_secrets_protected() {
+        let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        let path = env::temp_dir().join(format!("ssi_man_{nanos}.dpapi"));
+        let mut ssi_man = SsiMan::with_dpapi(&path).unwrap();
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let (_, secret) = ssi_man.store.get("Luna").unwrap().into_owned();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with(&format!("Luna\t{ssi}\t")));
+        assert!(!text.contains(&secret.to_string()));
+        drop(ssi_man);
+
+        let mut ssi_man = SsiMan::with_dpapi(&path).unwrap();
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unprotect_should_reject_tampered_blobs() {
+        let mut blob = protect(b"it's the sun").unwrap();
+        assert_eq!(&*unprotect(&blob).unwrap(), b"it's the sun");
+        let last = blob.len() - 1;
+        blob[last] ^= 1;
+        assert!(matches!(unprotect(&blob), Err(Error::Dpapi(_))));
+    }
+}
@@ -0,0 +1,587 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use ssi::{EncryptedSecret, Ssi};
+use zeroize::Zeroizing;
+
+use crate::{
+    clock::system_clock, fingerprint, Clock, ConflictPolicy, Error, IdentityFingerprint,
+    IdentityMetadata, JournalCapable, Page, SsiStore, SsiStoreExt, StoreCapabilities,
+    StoreCapability, StoredIdentity, FORMAT_VERSION,
+};
+
+/// First bytes of every file, naming the layout below and the key derivation.
+const MAGIC: &[u8; 8] = b"SSIMANE1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+/// The magic, the salt of the key and the nonce of the contents, authenticated with them.
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN;
+
+/// An identity as stored: the ssi and secret in their text forms, with the metadata the
+/// sqlite store keeps in its columns.
+#[derive(Clone, Deserialize, Serialize)]
+struct FileRecord {
+    ssi: String,
+    secret: String,
+    created_at: DateTime<Utc>,
+    /// `None` until the ssi or secret is first changed, as in records written before it
+    /// was kept.
+    updated_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    sign_count: u64,
+    needs_rewrap: bool,
+    wrapped_key: Option<Vec<u8>>,
+}
+
+impl FileRecord {
+    fn new(ssi: &Ssi, secret: &EncryptedSecret, created_at: DateTime<Utc>) -> Self {
+        Self {
+            ssi: ssi.to_string(),
+            secret: secret.to_string(),
+            created_at,
+            updated_at: None,
+            last_used_at: None,
+            sign_count: 0,
+            needs_rewrap: false,
+            wrapped_key: None,
+        }
+    }
+
+    fn metadata(&self) -> IdentityMetadata {
+        IdentityMetadata {
+            created_at: self.created_at,
+            updated_at: self.updated_at.unwrap_or(self.created_at),
+            last_used_at: self.last_used_at,
+            sign_count: self.sign_count,
+            needs_rewrap: self.needs_rewrap,
+        }
+    }
+}
+
+/// Everything in the file, in JSON once decrypted.
+#[derive(Clone, Deserialize, Serialize)]
+struct FileContents {
+    format_version: u32,
+    records: BTreeMap<String, FileRecord>,
+    ingest_progress: Option<usize>,
+}
+
+impl Default for FileContents {
+    fn default() -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            records: BTreeMap::new(),
+            ingest_progress: None,
+        }
+    }
+}
+
+fn random<const N: usize>() -> Result<[u8; N], Error> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).map_err(|err| Error::EncryptedFile(err.to_string()))?;
+    Ok(bytes)
+}
+
+/// Derives the key of the contents from the passphrase with Argon2id.
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, Error> {
+    let mut key = Zeroizing::new([0; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+        .map_err(|err| Error::EncryptedFile(err.to_string()))?;
+    Ok(key)
+}
+
+/// Seals `plaintext` under `key` behind the header, with `salt` and a fresh nonce.
+fn seal(key: &[u8; 32], salt: &[u8; SALT_LEN], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(salt);
+    sealed.extend_from_slice(&random::<NONCE_LEN>()?);
+    let ciphertext = XChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(
+            XNonce::from_slice(&sealed[MAGIC.len() + SALT_LEN..]),
+            Payload {
+                msg: plaintext,
+                aad: &sealed,
+            },
+        )
+        .map_err(|err| Error::EncryptedFile(err.to_string()))?;
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Opens what [`seal`] sealed with the key of `passphrase`, returning the salt and key
+/// along with the plaintext.
+fn unseal(
+    passphrase: &str,
+    sealed: &[u8],
+) -> Result<([u8; SALT_LEN], Zeroizing<[u8; 32]>, Zeroizing<Vec<u8>>), Error> {
+    if sealed.len() < HEADER_LEN || !sealed.starts_with(MAGIC) {
+        return Err(Error::EncryptedFile(
+            "not an encrypted ssi file".to_string(),
+        ));
+    }
+    let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+    let mut salt = [0; SALT_LEN];
+    salt.copy_from_slice(&header[MAGIC.len()..MAGIC.len() + SALT_LEN]);
+    let key = derive_key(passphrase, &salt)?;
+    let plaintext = Zeroizing::new(
+        XChaCha20Poly1305::new(Key::from_slice(&*key))
+            .decrypt(
+                XNonce::from_slice(&header[MAGIC.len() + SALT_LEN..]),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| Error::EncryptedFilePassphrase)?,
+    );
+    Ok((salt, key, plaintext))
+}
+
+/// Seals `plaintext` with `passphrase` the way encrypted files are, e.g. for memory
+/// store snapshots.
+pub(crate) fn seal_with_passphrase(passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    let salt = random()?;
+    seal(&derive_key(passphrase, &salt)?, &salt, plaintext)
+}
+
+/// Opens what [`seal_with_passphrase`] sealed.
+pub(crate) fn unseal_with_passphrase(
+    passphrase: &str,
+    sealed: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, Error> {
+    Ok(unseal(passphrase, sealed)?.2)
+}
+
+/// A store keeping every identity in one file encrypted with a passphrase, e.g. for a
+/// portable wallet backed up as a single blob.
+///
+/// The file holds the records in JSON, sealed with XChaCha20-Poly1305 under a key derived
+/// from the passphrase with Argon2id. Records live in memory once opened; every write
+/// seals them all again, with a fresh nonce, into a temporary file renamed over the
+/// previous one, so the file is always either the old contents or the new. A wrong
+/// passphrase and a damaged file both fail with [`Error::EncryptedFilePassphrase`], the
+/// two being indistinguishable.
+pub struct SsiEncryptedFileStore {
+    path: PathBuf,
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<[u8; 32]>,
+    contents: FileContents,
+    clock: Arc<dyn Clock>,
+}
+
+impl SsiEncryptedFileStore {
+    /// Opens the file at `path` with `passphrase`, creating it empty if missing.
+    pub fn open(path: impl AsRef<Path>, passphrase: &str) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let sealed = match fs::read(&path) {
+            Ok(sealed) => sealed,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let salt = random()?;
+                let store = Self {
+                    key: derive_key(passphrase, &salt)?,
+                    path,
+                    salt,
+                    contents: FileContents::default(),
+                    clock: system_clock(),
+                };
+                store.save(&store.contents)?;
+                return Ok(store);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let (salt, key, plaintext) = unseal(passphrase, &sealed)?;
+        let contents: FileContents = serde_json::from_slice(&plaintext)
+            .map_err(|err| Error::EncryptedFile(err.to_string()))?;
+        if contents.format_version > FORMAT_VERSION {
+            return Err(Error::FormatTooNew {
+                found: contents.format_version,
+                supported: FORMAT_VERSION,
+            });
+        }
+        Ok(Self {
+            path,
+            salt,
+            key,
+            contents,
+            clock: system_clock(),
+        })
+    }
+
+    /// Seals the file again under `new_passphrase`, with a new salt.
+    pub fn rekey(&mut self, new_passphrase: &str) -> Result<(), Error> {
+        let (salt, key) = (self.salt, std::mem::take(&mut self.key));
+        self.salt = random()?;
+        self.key = derive_key(new_passphrase, &self.salt)?;
+        if let Err(err) = self.save(&self.contents) {
+            (self.salt, self.key) = (salt, key);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Seals `contents` into the file through a temporary one, renamed over it once
+    /// synced.
+    fn save(&self, contents: &FileContents) -> Result<(), Error> {
+        let plaintext = Zeroizing::new(
+            serde_json::to_vec(contents).map_err(|err| Error::EncryptedFile(err.to_string()))?,
+        );
+        let sealed = seal(&self.key, &self.salt, &plaintext)?;
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&sealed)?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+
+    /// Applies `change` to a copy of the contents and saves it, keeping it only if both
+    /// succeed.
+    fn commit<T>(
+        &mut self,
+        change: impl FnOnce(&mut FileContents) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut contents = self.contents.clone();
+        let value = change(&mut contents)?;
+        self.save(&contents)?;
+        self.contents = contents;
+        Ok(value)
+    }
+
+    fn record(&self, id: &str) -> Result<&FileRecord, Error> {
+        self.contents
+            .records
+            .get(id)
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))
+    }
+
+    /// Applies `change` to the record of `id`.
+    fn modify(&mut self, id: &str, change: impl FnOnce(&mut FileRecord)) -> Result<(), Error> {
+        self.commit(|contents| {
+            let record = contents
+                .records
+                .get_mut(id)
+                .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+            change(record);
+            Ok(())
+        })
+    }
+
+    /// Returns the identities whose records match `filter`, in identity order.
+    fn identities_where(&self, filter: impl Fn(&FileRecord) -> bool) -> Vec<String> {
+        self.contents
+            .records
+            .iter()
+            .filter(|(_, record)| filter(record))
+            .map(|(identity, _)| identity.clone())
+            .collect()
+    }
+}
+
+impl SsiStore for SsiEncryptedFileStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if self.contents.records.contains_key(&id) {
+            return Err(Error::IdentityExists(id));
+        }
+        self.replace(id, ssi, secret)
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let record = self.record(id)?;
+        let secret = EncryptedSecret::from_str(&record.secret)
+            .map_err(|err| Error::SecretParse(err.to_string()))?;
+        Ok(Cow::Owned((Ssi::from_str(&record.ssi)?, secret)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        if !self.contents.records.contains_key(id) {
+            return Ok(false);
+        }
+        self.commit(|contents| Ok(contents.records.remove(id).is_some()))
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.contents.records.contains_key(id))
+    }
+
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error> {
+        let offset = Page::offset(page, per_page)?;
+        let identities = self
+            .contents
+            .records
+            .keys()
+            .skip(offset)
+            .take(per_page)
+            .cloned()
+            .collect();
+        Ok(Page::new(identities, self.contents.records.len(), per_page))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        for identity in self.contents.records.keys() {
+            f(identity)?;
+        }
+        Ok(())
+    }
+
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error> {
+        self.contents
+            .records
+            .iter()
+            .map(|(identity, record)| {
+                Ok(IdentityFingerprint {
+                    identity: identity.clone(),
+                    fingerprint: fingerprint(&Ssi::from_str(&record.ssi)?),
+                })
+            })
+            .collect()
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default()
+            .with(StoreCapability::AtomicWrites)
+            .with(StoreCapability::Metadata)
+            .with(StoreCapability::PlatformProtection)
+            .with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiEncryptedFileStore {
+    fn format_version(&self) -> Result<u32, Error> {
+        Ok(self.contents.format_version)
+    }
+
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let record = FileRecord::new(&ssi, &secret, self.clock.now());
+        self.commit(|contents| {
+            contents.records.insert(id, record);
+            Ok(())
+        })
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let now = self.clock.now();
+        self.modify(id, |record| {
+            record.ssi = ssi.to_string();
+            record.secret = secret.to_string();
+            record.updated_at = Some(now);
+        })
+    }
+
+    fn metadata(&self, id: &str) -> Result<IdentityMetadata, Error> {
+        Ok(self.record(id)?.metadata())
+    }
+
+    fn record_signatures(&mut self, id: &str, count: u64, at: DateTime<Utc>) -> Result<(), Error> {
+        self.modify(id, |record| {
+            record.last_used_at = Some(at);
+            record.sign_count += count;
+        })
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        Ok(self.identities_where(|record| record.metadata().last_activity() < cutoff))
+    }
+
+    fn set_needs_rewrap(&mut self, id: &str, needs_rewrap: bool) -> Result<(), Error> {
+        self.modify(id, |record| record.needs_rewrap = needs_rewrap)
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        Ok(self.identities_where(|record| record.needs_rewrap))
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn wrapped_key(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self
+            .contents
+            .records
+            .get(id)
+            .and_then(|record| record.wrapped_key.clone()))
+    }
+
+    fn set_wrapped_key(&mut self, id: &str, wrapped_key: Vec<u8>) -> Result<(), Error> {
+        self.modify(id, |record| record.wrapped_key = Some(wrapped_key))
+    }
+}
+
+impl JournalCapable for SsiEncryptedFileStore {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        let created_at = self.clock.now();
+        self.commit(|contents| {
+            let mut imported = 0;
+            for record in records {
+                if contents.records.contains_key(&record.identity) {
+                    match on_conflict {
+                        ConflictPolicy::Skip => continue,
+                        ConflictPolicy::Overwrite => {}
+                        ConflictPolicy::Error => {
+                            return Err(Error::IdentityExists(record.identity))
+                        }
+                    }
+                }
+                let value = FileRecord::new(&record.ssi, &record.encrypted_secret, created_at);
+                contents.records.insert(record.identity, value);
+                imported += 1;
+            }
+            Ok(imported)
+        })
+    }
+
+    fn ingest_chunk(&mut self, records: Vec<StoredIdentity>, ingested: usize) -> Result<(), Error> {
+        let created_at = self.clock.now();
+        self.commit(|contents| {
+            for record in records {
+                let value = FileRecord::new(&record.ssi, &record.encrypted_secret, created_at);
+                contents.records.insert(record.identity, value);
+            }
+            contents.ingest_progress = Some(ingested);
+            Ok(())
+        })
+    }
+
+    fn ingest_progress(&mut self) -> Result<Option<usize>, Error> {
+        Ok(self.contents.ingest_progress)
+    }
+
+    fn finish_ingest(&mut self) -> Result<(), Error> {
+        self.commit(|contents| {
+            contents.ingest_progress = None;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SsiMan;
+
+    fn temp_file_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ssi_man_{name}_{}.ssi",
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ))
+    }
+
+    #[test]
+    fn encrypted_file_should_reopen_with_its_passphrase_only() {
+        let path = temp_file_path("reopen");
+        let mut ssi_man = SsiMan::with_encrypted_file(&path, "correct horse").unwrap();
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        drop(ssi_man);
+
+        let sealed = fs::read(&path).unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        assert!(!String::from_utf8_lossy(&sealed).contains("Luna"));
+        assert_eq!(
+            SsiEncryptedFileStore::open(&path, "wrong horse").err(),
+            Some(Error::EncryptedFilePassphrase)
+        );
+
+        let ssi_man = SsiMan::with_encrypted_file(&path, "correct horse").unwrap();
+        assert_eq!(ssi_man.get_ssi("Luna"), Ok(ssi));
+        assert_eq!(ssi_man.identity_info("Luna").unwrap().sign_count, 1);
+        drop(ssi_man);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn encrypted_file_should_detect_tampering() {
+        let path = temp_file_path("tampering");
+        let mut ssi_man = SsiMan::with_encrypted_file(&path, "correct horse").unwrap();
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        drop(ssi_man);
+
+        let mut sealed = fs::read(&path).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        fs::write(&path, &sealed).unwrap();
+        assert_eq!(
+            SsiEncryptedFileStore::open(&path, "correct horse").err(),
+            Some(Error::EncryptedFilePassphrase)
+        );
+        fs::write(&path, b"SQLite format 3\0").unwrap();
+        assert!(matches!(
+            SsiEncryptedFileStore::open(&path, "correct horse"),
+            Err(Error::EncryptedFile(_))
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn encrypted_file_should_rekey_and_keep_failed_batches_out() {
+        let path = temp_file_path("rekey");
+        let mut store = SsiEncryptedFileStore::open(&path, "correct horse").unwrap();
+        let mut source = SsiMan::with_memory();
+        source
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let (ssi, secret) = source.store.get("Luna").unwrap().into_owned();
+        store
+            .insert("Luna".to_string(), ssi.clone(), secret.clone())
+            .unwrap();
+        let records = ["Sol", "Luna"].map(|identity| StoredIdentity {
+            identity: identity.to_string(),
+            ssi: ssi.clone(),
+            encrypted_secret: secret.clone(),
+        });
+        assert_eq!(
+            store.import_batch(records.to_vec(), ConflictPolicy::Error),
+            Err(Error::IdentityExists("Luna".to_string()))
+        );
+        assert_eq!(store.contains("Sol"), Ok(false));
+
+        store.rekey("battery staple").unwrap();
+        drop(store);
+        assert!(SsiEncryptedFileStore::open(&path, "correct horse").is_err());
+        let store = SsiEncryptedFileStore::open(&path, "battery staple").unwrap();
+        assert_eq!(
+            store.paginated_identities(1, 10).unwrap().identities,
+            ["Luna"]
+        );
+        drop(store);
+        fs::remove_file(path).unwrap();
+    }
+}
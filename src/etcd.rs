@@ -0,0 +1,310 @@
+use std::{borrow::Cow, collections::VecDeque, str::FromStr, sync::Arc};
+
+use etcd_client::{
+    Client, Compare, CompareOp, EventType, GetOptions, Txn, TxnOp, WatchOptions, WatchStream,
+    Watcher,
+};
+use serde::{Deserialize, Serialize};
+use ssi::{EncryptedSecret, Ssi};
+use tokio::runtime::{Builder, Runtime};
+use zeroize::Zeroizing;
+
+use crate::{Error, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability};
+
+/// Key prefix of [`SsiEtcdStore::new`].
+const DEFAULT_PREFIX: &str = "ssi-man";
+
+/// An identity as stored, in JSON: the ssi and the concealed secret in their text forms.
+#[derive(Deserialize, Serialize)]
+struct EtcdRecord {
+    ssi: String,
+    secret: String,
+}
+
+/// A store keeping identities in an etcd cluster, shared by clustered signers.
+///
+/// Each identity is a key `<prefix>/identity/<identity>` holding the ssi and the
+/// concealed secret in JSON. Inserts and updates check and write the key in one
+/// transaction, so two signers can't create the same identity, and every read goes to
+/// the cluster, so writes are seen at once. Signers caching identities, e.g. behind a
+/// [`TieredStore`](crate::TieredStore), learn of changes through
+/// [`SsiEtcdStore::watch`].
+pub struct SsiEtcdStore {
+    runtime: Arc<Runtime>,
+    client: Client,
+    prefix: String,
+}
+
+/// A change of an identity of a [`SsiEtcdStore`], see [`SsiEtcdStore::watch`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum IdentityChange {
+    /// The identity was created or its record replaced.
+    Put(String),
+    Removed(String),
+}
+
+/// The changes of the identities of a [`SsiEtcdStore`], in the order the cluster made
+/// them; iterating blocks until the next one.
+pub struct SsiEtcdWatcher {
+    runtime: Arc<Runtime>,
+    // Cancels the watch once dropped.
+    _watcher: Watcher,
+    stream: WatchStream,
+    start: String,
+    pending: VecDeque<IdentityChange>,
+}
+
+impl SsiEtcdStore {
+    /// Connects to the etcd cluster at `endpoints`, e.g. `["localhost:2379"]`, with keys
+    /// under `ssi-man/`.
+    pub fn new(endpoints: &[&str]) -> Result<Self, Error> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        let client = runtime.block_on(Client::connect(endpoints, None))?;
+        Ok(Self::with_client(client, runtime))
+    }
+
+    /// Keeps identities through `client`, e.g. one set up with TLS or credentials, which
+    /// `runtime` drives.
+    pub fn with_client(client: Client, runtime: Runtime) -> Self {
+        Self {
+            runtime: Arc::new(runtime),
+            client,
+            prefix: DEFAULT_PREFIX.to_string(),
+        }
+    }
+
+    /// Keeps identities under `<prefix>/` rather than `ssi-man/`, e.g. to share a cluster.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Starts watching the identities, for the changes made from now on by any signer.
+    pub fn watch(&mut self) -> Result<SsiEtcdWatcher, Error> {
+        let start = self.key("");
+        let options = WatchOptions::new().with_prefix();
+        let (watcher, stream) = self
+            .runtime
+            .block_on(self.client.watch(start.as_str(), Some(options)))?;
+        Ok(SsiEtcdWatcher {
+            runtime: Arc::clone(&self.runtime),
+            _watcher: watcher,
+            stream,
+            start,
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}/identity/{id}", self.prefix)
+    }
+
+    /// Returns a handle on the client for reads, which only borrow the store; handles
+    /// share the connection.
+    fn reader(&self) -> Client {
+        self.client.clone()
+    }
+
+    /// Writes the record of `id`, first checking that it `exists` unless that's `None`,
+    /// and returns whether it was written.
+    fn write(
+        &mut self,
+        id: &str,
+        ssi: &Ssi,
+        secret: &EncryptedSecret,
+        exists: Option<bool>,
+    ) -> Result<bool, Error> {
+        let key = self.key(id);
+        let record = EtcdRecord {
+            ssi: ssi.to_string(),
+            secret: secret.to_string(),
+        };
+        let json =
+            serde_json::to_string(&record).map_err(|err| Error::EtcdRecord(err.to_string()))?;
+        // A key that never existed, or was deleted, has no create revision.
+        let checks: Vec<_> = exists
+            .map(|exists| {
+                let op = if exists {
+                    CompareOp::Greater
+                } else {
+                    CompareOp::Equal
+                };
+                Compare::create_revision(key.as_str(), op, 0)
+            })
+            .into_iter()
+            .collect();
+        let txn = Txn::new()
+            .when(checks)
+            .and_then([TxnOp::put(key.as_str(), json, None)]);
+        Ok(self.runtime.block_on(self.client.txn(txn))?.succeeded())
+    }
+}
+
+impl Iterator for SsiEtcdWatcher {
+    type Item = Result<IdentityChange, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let response = match self.runtime.block_on(self.stream.message()) {
+                Ok(Some(response)) => response,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err.into())),
+            };
+            if response.canceled() {
+                let reason = response.cancel_reason().to_string();
+                return Some(Err(Error::EtcdWatchCancelled(reason)));
+            }
+            for event in response.events() {
+                let Some(id) = event
+                    .kv()
+                    .and_then(|kv| kv.key_str().ok())
+                    .and_then(|key| key.strip_prefix(&self.start))
+                else {
+                    continue;
+                };
+                self.pending.push_back(match event.event_type() {
+                    EventType::Put => IdentityChange::Put(id.to_string()),
+                    EventType::Delete => IdentityChange::Removed(id.to_string()),
+                });
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+impl SsiStore for SsiEtcdStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.write(&id, &ssi, &secret, Some(false))? {
+            return Err(Error::IdentityExists(id));
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let response = self
+            .runtime
+            .block_on(self.reader().get(self.key(id), None))?;
+        let kv = response
+            .kvs()
+            .first()
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+        let record: EtcdRecord = serde_json::from_slice(kv.value())
+            .map_err(|err| Error::EtcdRecord(format!("record of {id}: {err}")))?;
+        let secret = Zeroizing::new(record.secret);
+        Ok(Cow::Owned((
+            Ssi::from_str(&record.ssi)?,
+            EncryptedSecret::from_str(&secret)
+                .map_err(|err| Error::SecretParse(err.to_string()))?,
+        )))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        let response = self
+            .runtime
+            .block_on(self.client.delete(self.key(id), None))?;
+        Ok(response.deleted() > 0)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        let options = GetOptions::new().with_count_only();
+        let response = self
+            .runtime
+            .block_on(self.reader().get(self.key(id), Some(options)))?;
+        Ok(response.count() > 0)
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        let start = self.key("");
+        // Sorted by key, so by identity after the shared prefix.
+        let options = GetOptions::new().with_prefix().with_keys_only();
+        let response = self
+            .runtime
+            .block_on(self.reader().get(start.as_str(), Some(options)))?;
+        for kv in response.kvs() {
+            if let Some(identity) = kv.key_str()?.strip_prefix(&start) {
+                f(identity)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default().with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiEtcdStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.write(&id, &ssi, &secret, None).map(drop)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.write(id, &ssi, &secret, Some(true))? {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    /// Needs an etcd cluster at `SSI_MAN_ETCD_ENDPOINT`, and passes otherwise.
+    #[test]
+    fn etcd_store_should_share_identities() {
+        let Ok(endpoint) = env::var("SSI_MAN_ETCD_ENDPOINT") else {
+            return;
+        };
+        let prefix = format!("ssi-man-test-{}", Utc::now().timestamp_nanos_opt().unwrap());
+        let store = || {
+            SsiEtcdStore::new(&[endpoint.as_str()])
+                .unwrap()
+                .prefix(&prefix)
+        };
+        let mut watched = store();
+        let mut changes = watched.watch().unwrap();
+        let mut ssi_man = SsiMan::with_store(Box::new(store()));
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let mut other = SsiMan::with_store(Box::new(store()));
+        assert!(matches!(
+            other.new_ssi("Luna", "luna@bitlightlabs.com", None),
+            Err(Error::IdentityExists(_))
+        ));
+        let ssi_cert = other.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(
+            ssi_man.paginated_identities(1, 10).unwrap().identities,
+            vec!["Luna".to_string()]
+        );
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        assert_eq!(other.remove("Luna"), Ok(false));
+
+        assert_eq!(
+            changes.next(),
+            Some(Ok(IdentityChange::Put("Luna".to_string())))
+        );
+        assert_eq!(
+            changes.next(),
+            Some(Ok(IdentityChange::Removed("Luna".to_string())))
+        );
+    }
+}
@@ -0,0 +1,641 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use chrono::{DateTime, Utc};
+use ssi::{EncryptedSecret, Ssi, SsiPub};
+
+use crate::{
+    store_ext, store_ext_mut, Clock, Error, IdentityFingerprint, IdentityMetadata,
+    IntegrityFindings, IntegrityRepair, Page, RepairPolicy, SsiStore, SsiStoreExt,
+    StoreCapabilities, StoreCapability, FORMAT_VERSION,
+};
+
+/// How [`FailoverStore`] handles writes while the primary store is unreachable.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FailoverPolicy {
+    /// Writes fail with the primary's error.
+    FailFast,
+    /// Writes go to the fallback and are queued, up to `capacity`, to be replayed on the
+    /// primary once it is reachable again.
+    QueueUntilRecovered { capacity: usize },
+}
+
+#[derive(Clone)]
+enum QueuedWrite {
+    Insert(String, Ssi, EncryptedSecret),
+    Replace(String, Ssi, EncryptedSecret),
+    Update(String, Ssi, EncryptedSecret),
+    Remove(String),
+}
+
+impl QueuedWrite {
+    fn identity(&self) -> &str {
+        match self {
+            QueuedWrite::Insert(identity, ..)
+            | QueuedWrite::Replace(identity, ..)
+            | QueuedWrite::Update(identity, ..)
+            | QueuedWrite::Remove(identity) => identity,
+        }
+    }
+
+    fn apply(self, store: &mut dyn SsiStore) -> Result<(), Error> {
+        match self {
+            QueuedWrite::Insert(identity, ssi, secret) => store.insert(identity, ssi, secret),
+            QueuedWrite::Replace(identity, ssi, secret) => {
+                store_ext_mut(store, StoreCapability::Replace)?.replace(identity, ssi, secret)
+            }
+            QueuedWrite::Update(identity, ssi, secret) => {
+                store_ext_mut(store, StoreCapability::Replace)?.update(&identity, ssi, secret)
+            }
+            QueuedWrite::Remove(identity) => store.remove(&identity).map(drop),
+        }
+    }
+}
+
+/// Serves reads from a fallback store while the primary is unreachable.
+///
+/// Successful writes on the primary are mirrored to the fallback, so it can take over
+/// reads after an error classified as transient by [`Error::is_transient`]. Writes
+/// during an outage follow the [`FailoverPolicy`].
+///
+/// Queued writes are replayed in order before the next operation once the primary is
+/// back. On conflict the primary wins: a queued write the primary rejects, such as an
+/// insert of an identity created there meanwhile, is dropped and the fallback record
+/// is overwritten with the primary's, so the fallback needs [`SsiStoreExt`]. As reads may
+/// replay writes, calls take turns, reads included.
+pub struct FailoverStore {
+    state: Mutex<FailoverState>,
+}
+
+struct FailoverState {
+    primary: Box<dyn SsiStore>,
+    fallback: Box<dyn SsiStore>,
+    policy: FailoverPolicy,
+    queue: VecDeque<QueuedWrite>,
+}
+
+impl FailoverStore {
+    pub fn new(
+        primary: Box<dyn SsiStore>,
+        fallback: Box<dyn SsiStore>,
+        policy: FailoverPolicy,
+    ) -> Self {
+        Self {
+            state: Mutex::new(FailoverState {
+                primary,
+                fallback,
+                policy,
+                queue: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Returns the number of writes waiting for the primary to come back.
+    pub fn pending_writes(&self) -> usize {
+        self.state().queue.len()
+    }
+
+    fn state(&self) -> MutexGuard<'_, FailoverState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl FailoverState {
+    /// Replays queued writes on the primary, stopping at the first transient error.
+    fn replay(&mut self) -> Result<(), Error> {
+        while let Some(write) = self.queue.pop_front() {
+            let identity = write.identity().to_string();
+            match write.clone().apply(&mut *self.primary) {
+                Ok(()) => {}
+                Err(err) if err.is_transient() => {
+                    self.queue.push_front(write);
+                    return Err(err);
+                }
+                Err(_) => self.resync(&identity)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the primary's record of an identity to the fallback.
+    fn resync(&mut self, identity: &str) -> Result<(), Error> {
+        match self.primary.get(identity) {
+            Ok(record) => {
+                let (ssi, secret) = record.into_owned();
+                store_ext_mut(&mut *self.fallback, StoreCapability::Replace)?.replace(
+                    identity.to_string(),
+                    ssi,
+                    secret,
+                )
+            }
+            Err(Error::UnknownIdentity(_)) => self.fallback.remove(identity).map(drop),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Runs a read on the primary, or on the fallback if the primary is unreachable.
+    fn read<T>(
+        &mut self,
+        read: impl Fn(&mut dyn SsiStore) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let result = self.replay().and_then(|_| read(&mut *self.primary));
+        match result {
+            Err(err) if err.is_transient() => read(&mut *self.fallback),
+            result => result,
+        }
+    }
+
+    /// Runs a write on the primary and mirrors it to the fallback, or applies the policy
+    /// if the primary is unreachable.
+    fn write(&mut self, write: QueuedWrite) -> Result<(), Error> {
+        let identity = write.identity().to_string();
+        let result = self
+            .replay()
+            .and_then(|_| write.clone().apply(&mut *self.primary));
+        match result {
+            Ok(()) => self.resync(&identity),
+            Err(err) if err.is_transient() => match self.policy {
+                FailoverPolicy::FailFast => Err(err),
+                FailoverPolicy::QueueUntilRecovered { capacity } => {
+                    if self.queue.len() >= capacity {
+                        return Err(Error::FailoverQueueFull(self.queue.len()));
+                    }
+                    write.clone().apply(&mut *self.fallback)?;
+                    self.queue.push_back(write);
+                    Ok(())
+                }
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Runs a write of identity metadata on the primary and mirrors it to the fallback.
+    ///
+    /// Metadata is not worth a queue slot: while the primary is unreachable, only the
+    /// fallback gets the write.
+    fn write_metadata(
+        &mut self,
+        identity: &str,
+        write: impl Fn(&mut dyn SsiStore) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let result = self.replay().and_then(|_| write(&mut *self.primary));
+        match result {
+            Ok(()) => write(&mut *self.fallback).or_else(|_| self.resync(identity)),
+            Err(err) if err.is_transient() => write(&mut *self.fallback),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl SsiStore for FailoverStore {
+    fn insert(&mut self, identity: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.state()
+            .write(QueuedWrite::Insert(identity, ssi, secret))
+    }
+
+    fn get(&self, identity: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        self.state()
+            .read(|store| store.get(identity).map(Cow::into_owned))
+            .map(Cow::Owned)
+    }
+
+    fn get_shared(&self, identity: &str) -> Result<Arc<(Ssi, EncryptedSecret)>, Error> {
+        self.state().read(|store| store.get_shared(identity))
+    }
+
+    fn remove(&mut self, identity: &str) -> Result<bool, Error> {
+        let existed = self.contains(identity)?;
+        self.state()
+            .write(QueuedWrite::Remove(identity.to_string()))?;
+        Ok(existed)
+    }
+
+    fn contains(&self, identity: &str) -> Result<bool, Error> {
+        self.state().read(|store| store.contains(identity))
+    }
+
+    fn find_by_pubkey(&self, pk: &SsiPub) -> Result<Vec<String>, Error> {
+        self.state().read(|store| store.find_by_pubkey(pk))
+    }
+
+    fn find_identities(&self, query: &str) -> Result<Vec<String>, Error> {
+        self.state().read(|store| store.find_identities(query))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        // Collected first, so falling back halfway can't repeat identities.
+        let identities = self.state().read(|store| {
+            let mut identities = Vec::new();
+            store.for_each_identity(&mut |identity| {
+                identities.push(identity.to_string());
+                Ok(())
+            })?;
+            Ok(identities)
+        })?;
+        identities.iter().try_for_each(|identity| f(identity))
+    }
+
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error> {
+        self.state()
+            .read(|store| store.paginated_identities(page, per_page))
+    }
+
+    fn all_identities(&self) -> Result<Vec<Cow<'_, String>>, Error> {
+        self.state()
+            .read(|store| {
+                store.all_identities().map(|identities| {
+                    identities
+                        .into_iter()
+                        .map(Cow::into_owned)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .map(|identities| identities.into_iter().map(Cow::Owned).collect())
+    }
+
+    fn active_identities(&self, now: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        self.state().read(|store| store.active_identities(now))
+    }
+
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error> {
+        self.state().read(|store| store.fingerprints())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        let state = self.state();
+        state
+            .primary
+            .capabilities()
+            .intersection(state.fallback.capabilities())
+            // Writes queued for the primary are replayed one by one, outside any transaction.
+            .without(StoreCapability::AtomicWrites)
+            .without(StoreCapability::Transactions)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for FailoverStore {
+    fn replace(
+        &mut self,
+        identity: String,
+        ssi: Ssi,
+        secret: EncryptedSecret,
+    ) -> Result<(), Error> {
+        self.state()
+            .write(QueuedWrite::Replace(identity, ssi, secret))
+    }
+
+    fn update(&mut self, identity: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.state()
+            .write(QueuedWrite::Update(identity.to_string(), ssi, secret))
+    }
+
+    fn warm_fingerprints(&mut self, batch_size: usize) -> Result<(usize, usize), Error> {
+        self.state().read(|store| match store.as_ext_mut() {
+            Some(store) => store.warm_fingerprints(batch_size),
+            None => Ok((0, 0)),
+        })
+    }
+
+    fn metadata(&self, identity: &str) -> Result<IdentityMetadata, Error> {
+        self.state()
+            .read(|store| store_ext(store, StoreCapability::Metadata)?.metadata(identity))
+    }
+
+    fn record_signatures(
+        &mut self,
+        identity: &str,
+        count: u64,
+        at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.state().write_metadata(identity, |store| {
+            store_ext_mut(store, StoreCapability::Metadata)?.record_signatures(identity, count, at)
+        })
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        self.state()
+            .read(|store| store_ext(store, StoreCapability::Metadata)?.stale_identities(cutoff))
+    }
+
+    fn set_needs_rewrap(&mut self, identity: &str, needs_rewrap: bool) -> Result<(), Error> {
+        self.state().write_metadata(identity, |store| {
+            store_ext_mut(store, StoreCapability::Metadata)?
+                .set_needs_rewrap(identity, needs_rewrap)
+        })
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        self.state()
+            .read(|store| store_ext(store, StoreCapability::Metadata)?.identities_needing_rewrap())
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        let mut state = self.state();
+        let FailoverState {
+            primary, fallback, ..
+        } = &mut *state;
+        for store in [primary, fallback] {
+            if let Some(store) = store.as_ext_mut() {
+                store.set_clock(clock.clone());
+            }
+        }
+    }
+
+    fn wrapped_key(&self, identity: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.state().read(|store| match store.as_ext() {
+            Some(store) => store.wrapped_key(identity),
+            None => Ok(None),
+        })
+    }
+
+    fn set_wrapped_key(&mut self, identity: &str, wrapped_key: Vec<u8>) -> Result<(), Error> {
+        self.state().write_metadata(identity, |store| {
+            store_ext_mut(store, StoreCapability::PlatformProtection)?
+                .set_wrapped_key(identity, wrapped_key.clone())
+        })
+    }
+
+    fn check_integrity(&self) -> Result<IntegrityFindings, Error> {
+        self.state().read(|store| match store.as_ext() {
+            Some(store) => store.check_integrity(),
+            None => Ok(IntegrityFindings::default()),
+        })
+    }
+
+    fn repair_integrity(&mut self, policy: &RepairPolicy) -> Result<IntegrityRepair, Error> {
+        let mut state = self.state();
+        let repair = match state.primary.as_ext_mut() {
+            Some(primary) => primary.repair_integrity(policy)?,
+            None => IntegrityRepair::default(),
+        };
+        if let Some(fallback) = state.fallback.as_ext_mut() {
+            fallback.repair_integrity(policy)?;
+        }
+        Ok(repair)
+    }
+
+    fn format_version(&self) -> Result<u32, Error> {
+        self.state().read(|store| match store.as_ext() {
+            Some(store) => store.format_version(),
+            None => Ok(FORMAT_VERSION),
+        })
+    }
+
+    fn upgrade_format(&mut self) -> Result<(), Error> {
+        let mut state = self.state();
+        if let Some(primary) = state.primary.as_ext_mut() {
+            primary.upgrade_format()?;
+        }
+        match state.fallback.as_ext_mut() {
+            Some(fallback) => fallback.upgrade_format(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan, SsiMemoryStore};
+
+    /// Memory store that fails every call with a transient error while `down` is set.
+    #[derive(Default)]
+    struct FlakyStore {
+        inner: SsiMemoryStore,
+        down: Arc<AtomicBool>,
+    }
+
+    impl FlakyStore {
+        fn check(&self) -> Result<(), Error> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(io::Error::from(io::ErrorKind::NotConnected).into());
+            }
+            Ok(())
+        }
+    }
+
+    impl SsiStore for FlakyStore {
+        fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+            self.check()?;
+            self.inner.insert(id, ssi, secret)
+        }
+
+        fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+            self.check()?;
+            self.inner.get(id)
+        }
+
+        fn remove(&mut self, id: &str) -> Result<bool, Error> {
+            self.check()?;
+            self.inner.remove(id)
+        }
+
+        fn contains(&self, id: &str) -> Result<bool, Error> {
+            self.check()?;
+            self.inner.contains(id)
+        }
+
+        fn find_by_pubkey(&self, pk: &SsiPub) -> Result<Vec<String>, Error> {
+            self.check()?;
+            self.inner.find_by_pubkey(pk)
+        }
+
+        fn find_identities(&self, query: &str) -> Result<Vec<String>, Error> {
+            self.check()?;
+            self.inner.find_identities(query)
+        }
+
+        fn for_each_identity(
+            &self,
+            f: &mut dyn FnMut(&str) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            self.check()?;
+            self.inner.for_each_identity(f)
+        }
+
+        fn active_identities(&self, now: DateTime<Utc>) -> Result<Vec<String>, Error> {
+            self.check()?;
+            self.inner.active_identities(now)
+        }
+
+        fn capabilities(&self) -> StoreCapabilities {
+            self.inner.capabilities()
+        }
+
+        fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+            Some(self)
+        }
+
+        fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+            Some(self)
+        }
+    }
+
+    impl SsiStoreExt for FlakyStore {
+        fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+            self.check()?;
+            self.inner.replace(id, ssi, secret)
+        }
+
+        fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+            self.check()?;
+            self.inner.update(id, ssi, secret)
+        }
+
+        fn metadata(&self, identity: &str) -> Result<IdentityMetadata, Error> {
+            self.check()?;
+            self.inner.metadata(identity)
+        }
+
+        fn record_signatures(
+            &mut self,
+            identity: &str,
+            count: u64,
+            at: DateTime<Utc>,
+        ) -> Result<(), Error> {
+            self.check()?;
+            self.inner.record_signatures(identity, count, at)
+        }
+
+        fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+            self.check()?;
+            self.inner.stale_identities(cutoff)
+        }
+
+        fn set_needs_rewrap(&mut self, identity: &str, needs_rewrap: bool) -> Result<(), Error> {
+            self.check()?;
+            self.inner.set_needs_rewrap(identity, needs_rewrap)
+        }
+
+        fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+            self.check()?;
+            self.inner.identities_needing_rewrap()
+        }
+
+        fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+            self.inner.set_clock(clock)
+        }
+    }
+
+    fn failover_ssi_man(policy: FailoverPolicy) -> (SsiMan, Arc<AtomicBool>) {
+        let primary = FlakyStore::default();
+        let down = primary.down.clone();
+        let store = FailoverStore::new(
+            Box::new(primary),
+            Box::new(SsiMemoryStore::default()),
+            policy,
+        );
+        (SsiMan::with_store(Box::new(store)), down)
+    }
+
+    #[test]
+    fn failover_should_queue_and_replay_writes() {
+        let message = "have a good day!";
+        let (mut ssi_man, down) =
+            failover_ssi_man(FailoverPolicy::QueueUntilRecovered { capacity: 1 });
+        ssi_man
+            .new_ssi("alice", "alice@bitlightlabs.com", None)
+            .unwrap();
+
+        down.store(true, Ordering::SeqCst);
+        let ssi_cert = ssi_man.sign("alice", message, None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, message).unwrap();
+        ssi_man
+            .new_ssi("bob", "bob@bitlightlabs.com", None)
+            .unwrap();
+        assert_eq!(
+            ssi_man.new_ssi("carol", "carol@bitlightlabs.com", None),
+            Err(Error::FailoverQueueFull(1))
+        );
+        assert_eq!(
+            ssi_man.paginated_identities(1, 10).unwrap().identities,
+            vec!["alice".to_string(), "bob".to_string()]
+        );
+
+        down.store(false, Ordering::SeqCst);
+        assert!(ssi_man.sign("bob", message, None).is_ok());
+        ssi_man
+            .new_ssi("carol", "carol@bitlightlabs.com", None)
+            .unwrap();
+        down.store(true, Ordering::SeqCst);
+        assert_eq!(
+            ssi_man.paginated_identities(1, 10).unwrap().identities,
+            vec!["alice".to_string(), "bob".to_string(), "carol".to_string()]
+        );
+    }
+
+    #[test]
+    fn failover_should_resolve_conflicts_for_primary() {
+        let mut primary = FlakyStore::default();
+        let down = primary.down.clone();
+        let mut source = SsiMan::with_memory();
+        source
+            .new_ssi("alice", "alice@bitlightlabs.com", None)
+            .unwrap();
+        let (ssi, secret) = source.store.get("alice").unwrap().into_owned();
+        primary
+            .inner
+            .insert("alice".to_string(), ssi.clone(), secret)
+            .unwrap();
+
+        let mut store = FailoverStore::new(
+            Box::new(primary),
+            Box::new(SsiMemoryStore::default()),
+            FailoverPolicy::QueueUntilRecovered { capacity: 8 },
+        );
+        down.store(true, Ordering::SeqCst);
+        let mut ssi_man = SsiMan::with_memory();
+        ssi_man
+            .new_ssi("alice", "other@bitlightlabs.com", None)
+            .unwrap();
+        let (other_ssi, other_secret) = ssi_man.store.get("alice").unwrap().into_owned();
+        store
+            .insert("alice".to_string(), other_ssi, other_secret)
+            .unwrap();
+        assert_eq!(store.pending_writes(), 1);
+
+        down.store(false, Ordering::SeqCst);
+        assert_eq!(store.get("alice").unwrap().0.to_string(), ssi.to_string());
+        assert_eq!(store.pending_writes(), 0);
+        down.store(true, Ordering::SeqCst);
+        assert_eq!(store.get("alice").unwrap().0.to_string(), ssi.to_string());
+    }
+
+    #[test]
+    fn failover_fail_fast_should_reject_writes() {
+        let (mut ssi_man, down) = failover_ssi_man(FailoverPolicy::FailFast);
+        ssi_man
+            .new_ssi("alice", "alice@bitlightlabs.com", None)
+            .unwrap();
+
+        down.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            ssi_man.new_ssi("bob", "bob@bitlightlabs.com", None),
+            Err(Error::Io(_))
+        ));
+        assert_eq!(
+            ssi_man.all_identities().unwrap(),
+            vec![Cow::Owned("alice".to_string())]
+        );
+        assert!(matches!(ssi_man.remove("alice"), Err(Error::Io(_))));
+    }
+}
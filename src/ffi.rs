@@ -1,117 +1,1332 @@
 use std::{
-    ffi::{c_char, CStr, CString},
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr, CString, NulError},
+    fmt::{Display, Formatter},
     ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
 };
 
 use libc::size_t;
 
-use crate::{Error, SsiMan};
+use ssi::{Algo, Chain};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{
+    ssi_cert_verify_text, Error, OpContext, Redaction, SecretWrapper, SsiMan, VerifyContext,
+};
+#[cfg(all(feature = "keychain", any(target_os = "macos", target_os = "ios")))]
+use crate::{KeychainAccessibility, SsiKeychainStore};
 
 macro_rules! c_char_to_string {
     ($chars: ident) => {
-        unsafe {
-            if $chars.is_null() {
-                panic!("{} cannot be null", stringify!($chars));
-            }
-            CStr::from_ptr($chars).to_string_lossy().into_owned()
+        if $chars.is_null() {
+            Err(FfiError::NullArgument(stringify!($chars)))
+        } else {
+            Ok(unsafe { CStr::from_ptr($chars) }
+                .to_string_lossy()
+                .into_owned())
         }
     };
 }
 
-fn to_c_char(string: String) -> *mut c_char {
-    let c_str_content = CString::new(string).unwrap();
-    c_str_content.into_raw()
+/// Stable numeric codes for the errors reported through [`ssi_man_last_error_code`].
+///
+/// Values are part of the C API and must never be renumbered; new kinds get new values.
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SsiManErrorCode {
+    Ok = 0,
+    NullArgument = 1,
+    UnknownIdentity = 2,
+    WrongPassword = 3,
+    IdentityExists = 4,
+    DuplicateKey = 5,
+    InvalidInput = 6,
+    VerificationFailed = 7,
+    StorageBusy = 8,
+    Storage = 9,
+    PasswordPromptCancelled = 10,
+    IdentityExpired = 11,
+    FormatTooNew = 12,
+    Io = 13,
+    BadDatabaseKey = 14,
+    DatabaseParentMissing = 15,
+    DatabaseIsDirectory = 16,
+    DatabasePermissionDenied = 17,
+    Cancelled = 18,
+    DeadlineExceeded = 19,
+    NoSecretWrapper = 20,
+    PlatformProtected = 21,
+    SecretWrapperFailed = 22,
+    ReadOnly = 23,
+    Internal = 99,
+}
+
+impl From<&Error> for SsiManErrorCode {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::AuthenticationFailed => Self::WrongPassword,
+            #[cfg(feature = "aws-secrets")]
+            Error::AwsSecrets(_) => Self::Storage,
+            #[cfg(feature = "aws-secrets")]
+            Error::AwsSecretsIndex(_) => Self::Storage,
+            #[cfg(feature = "azure")]
+            Error::Azure(_) => Self::Storage,
+            #[cfg(feature = "azure")]
+            Error::AzureIndex(_) => Self::Storage,
+            Error::BackupKeyMismatch(_) => Self::InvalidInput,
+            Error::BackupParse(_) => Self::InvalidInput,
+            #[cfg(feature = "sqlcipher")]
+            Error::BadDatabaseKey => Self::BadDatabaseKey,
+            #[cfg(any(feature = "sqlite", feature = "postgres", feature = "mysql"))]
+            Error::Diesel(diesel::result::Error::DatabaseError(_, info))
+                if info.message().contains("locked") || info.message().contains("busy") =>
+            {
+                Self::StorageBusy
+            }
+            #[cfg(any(feature = "sqlite", feature = "postgres", feature = "mysql"))]
+            Error::Diesel(_) => Self::Storage,
+            #[cfg(any(feature = "sqlite", feature = "postgres", feature = "mysql"))]
+            Error::DieselMigration(_) => Self::Storage,
+            Error::Cancelled { .. } => Self::Cancelled,
+            Error::CreationRejected(_) => Self::InvalidInput,
+            #[cfg(feature = "sqlite")]
+            Error::DatabaseIsDirectory(_) => Self::DatabaseIsDirectory,
+            #[cfg(feature = "sqlite")]
+            Error::DatabaseParentMissing(_) => Self::DatabaseParentMissing,
+            #[cfg(feature = "sqlite")]
+            Error::DatabasePermissionDenied(_) => Self::DatabasePermissionDenied,
+            Error::DeadlineExceeded { .. } => Self::DeadlineExceeded,
+            Error::Decompression(_) => Self::InvalidInput,
+            #[cfg(all(feature = "dpapi", windows))]
+            Error::Dpapi(_) => Self::Storage,
+            #[cfg(all(feature = "dpapi", windows))]
+            Error::DpapiFile(_) => Self::Storage,
+            Error::DumpPlatformProtected(_) => Self::PlatformProtected,
+            Error::DuplicateKey { .. } => Self::DuplicateKey,
+            #[cfg(feature = "encrypted-file")]
+            Error::EncryptedFile(_) => Self::Storage,
+            #[cfg(feature = "encrypted-file")]
+            Error::EncryptedFilePassphrase => Self::WrongPassword,
+            #[cfg(feature = "etcd")]
+            Error::Etcd(_) => Self::Storage,
+            #[cfg(feature = "etcd")]
+            Error::EtcdRecord(_) => Self::Storage,
+            #[cfg(feature = "etcd")]
+            Error::EtcdWatchCancelled(_) => Self::Storage,
+            Error::FailoverQueueFull(_) => Self::StorageBusy,
+            Error::FormatTooNew { .. } => Self::FormatTooNew,
+            #[cfg(feature = "gcp")]
+            Error::Gcp(_) => Self::Storage,
+            #[cfg(feature = "gcp")]
+            Error::GcpIndex(_) => Self::Storage,
+            Error::IdentityExists(_) => Self::IdentityExists,
+            Error::IdentityExpired(_) => Self::IdentityExpired,
+            #[cfg(all(feature = "indexeddb", target_arch = "wasm32"))]
+            Error::IndexedDb(_) => Self::Storage,
+            Error::InvalidIdentity(_) => Self::InvalidInput,
+            Error::InvalidPagination { .. } => Self::InvalidInput,
+            Error::Io(_) => Self::Io,
+            #[cfg(all(feature = "keychain", any(target_os = "macos", target_os = "ios")))]
+            Error::Keychain(_) => Self::Storage,
+            #[cfg(all(feature = "keychain", any(target_os = "macos", target_os = "ios")))]
+            Error::KeychainIndex(_) => Self::Storage,
+            Error::LastUid(_) => Self::InvalidInput,
+            #[cfg(feature = "lmdb")]
+            Error::Lmdb(_) => Self::Storage,
+            #[cfg(feature = "lmdb")]
+            Error::LmdbRecord(_) => Self::Storage,
+            #[cfg(feature = "sqlite")]
+            Error::MigrationLockTimeout => Self::StorageBusy,
+            Error::MissingPassword(_) => Self::InvalidInput,
+            #[cfg(feature = "mysql")]
+            Error::MysqlPool(_) => Self::StorageBusy,
+            Error::NoSecretWrapper => Self::NoSecretWrapper,
+            Error::PasswordPromptCancelled => Self::PasswordPromptCancelled,
+            Error::PlatformProtected(_) => Self::PlatformProtected,
+            #[cfg(feature = "postgres")]
+            Error::PostgresPool(_) => Self::StorageBusy,
+            Error::PubkeyParse(_) => Self::InvalidInput,
+            Error::ReadOnly => Self::ReadOnly,
+            #[cfg(feature = "sqlite")]
+            Error::ReadOnlyQueryViolation => Self::InvalidInput,
+            #[cfg(feature = "redb")]
+            Error::Redb(_) => Self::Storage,
+            #[cfg(feature = "redb")]
+            Error::RedbRecord(_) => Self::Storage,
+            #[cfg(feature = "redis")]
+            Error::Redis(_) => Self::Storage,
+            #[cfg(feature = "redis")]
+            Error::RedisRecord(_) => Self::Storage,
+            Error::RestoreTargetNotEmpty => Self::InvalidInput,
+            #[cfg(feature = "rocksdb")]
+            Error::Rocks(_) => Self::Storage,
+            #[cfg(feature = "rocksdb")]
+            Error::RocksRecord(_) => Self::Storage,
+            #[cfg(feature = "s3")]
+            Error::S3(_) => Self::Storage,
+            #[cfg(feature = "s3")]
+            Error::S3Record(_) => Self::Storage,
+            Error::SecretParse(_) => Self::InvalidInput,
+            Error::SecretReveal(_) => Self::WrongPassword,
+            #[cfg(all(feature = "secret-service", target_os = "linux"))]
+            Error::SecretService(_) => Self::Storage,
+            #[cfg(all(feature = "secret-service", target_os = "linux"))]
+            Error::SecretServiceIndex(_) => Self::Storage,
+            Error::SecretWrapper(_) => Self::SecretWrapperFailed,
+            Error::Signer(ssi::SignerError::WrongPassword) => Self::WrongPassword,
+            Error::Signer(_) => Self::Internal,
+            #[cfg(feature = "sled")]
+            Error::Sled(_) => Self::Storage,
+            #[cfg(feature = "sled")]
+            Error::SledRecord(_) => Self::Storage,
+            Error::SnapshotParse { .. } => Self::InvalidInput,
+            #[cfg(feature = "sqlite")]
+            Error::SqlDump(_) => Self::InvalidInput,
+            #[cfg(feature = "sqlite")]
+            Error::SqliteConnection(_) => Self::Storage,
+            #[cfg(feature = "sqlite")]
+            Error::SqlitePool(_) => Self::StorageBusy,
+            #[cfg(feature = "sqlite")]
+            Error::SqliteTooOld { .. } => Self::Storage,
+            Error::SsiCertParse(_) => Self::InvalidInput,
+            Error::SsiParse(_) => Self::InvalidInput,
+            Error::StableFormatParse(_) => Self::InvalidInput,
+            Error::VerifyText(_) => Self::VerificationFailed,
+            Error::UidParse(_) => Self::InvalidInput,
+            Error::UnknownAlgo(_) => Self::InvalidInput,
+            Error::UnknownIdentity(_) => Self::UnknownIdentity,
+            #[cfg(all(feature = "keychain", any(target_os = "macos", target_os = "ios")))]
+            Error::UnknownKeychainAccessibility(_) => Self::InvalidInput,
+            Error::UnknownSigner => Self::VerificationFailed,
+            Error::UnknownUid(_) => Self::InvalidInput,
+            Error::Unsupported(_) => Self::Storage,
+            #[cfg(feature = "vault")]
+            Error::Vault(_) => Self::Storage,
+            #[cfg(feature = "vault")]
+            Error::VaultUnreachable(_) => Self::StorageBusy,
+        }
+    }
+}
+
+impl SsiManErrorCode {
+    /// Every code, in value order.
+    const ALL: [Self; 25] = [
+        Self::Ok,
+        Self::NullArgument,
+        Self::UnknownIdentity,
+        Self::WrongPassword,
+        Self::IdentityExists,
+        Self::DuplicateKey,
+        Self::InvalidInput,
+        Self::VerificationFailed,
+        Self::StorageBusy,
+        Self::Storage,
+        Self::PasswordPromptCancelled,
+        Self::IdentityExpired,
+        Self::FormatTooNew,
+        Self::Io,
+        Self::BadDatabaseKey,
+        Self::DatabaseParentMissing,
+        Self::DatabaseIsDirectory,
+        Self::DatabasePermissionDenied,
+        Self::Cancelled,
+        Self::DeadlineExceeded,
+        Self::NoSecretWrapper,
+        Self::PlatformProtected,
+        Self::SecretWrapperFailed,
+        Self::ReadOnly,
+        Self::Internal,
+    ];
+
+    fn from_code(code: i32) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| *kind as i32 == code)
+    }
+
+    /// Returns the stable name and the description of the code, the table behind
+    /// [`ssi_man_error_name`] and [`ssi_man_error_description`].
+    fn strings(self) -> (&'static CStr, &'static CStr) {
+        match self {
+            Self::Ok => (c"OK", c"The call succeeded."),
+            Self::NullArgument => (c"NULL_ARGUMENT", c"A required argument was null."),
+            Self::UnknownIdentity => (c"UNKNOWN_IDENTITY", c"No identity has this name."),
+            Self::WrongPassword => (c"WRONG_PASSWORD", c"The password is wrong."),
+            Self::IdentityExists => (
+                c"IDENTITY_EXISTS",
+                c"An identity with this name already exists.",
+            ),
+            Self::DuplicateKey => (
+                c"DUPLICATE_KEY",
+                c"Another identity already holds this key.",
+            ),
+            Self::InvalidInput => (c"INVALID_INPUT", c"An argument is malformed or invalid."),
+            Self::VerificationFailed => (
+                c"VERIFICATION_FAILED",
+                c"The signature does not match the message or its signer.",
+            ),
+            Self::StorageBusy => (
+                c"STORAGE_BUSY",
+                c"The storage is busy or unreachable; retrying may succeed.",
+            ),
+            Self::Storage => (c"STORAGE", c"The storage failed."),
+            Self::PasswordPromptCancelled => (
+                c"PASSWORD_PROMPT_CANCELLED",
+                c"The password prompt was cancelled.",
+            ),
+            Self::IdentityExpired => (c"IDENTITY_EXPIRED", c"The identity has expired."),
+            Self::FormatTooNew => (
+                c"FORMAT_TOO_NEW",
+                c"The data was written by a newer version of this library.",
+            ),
+            Self::Io => (c"IO", c"A file could not be read or written."),
+            Self::BadDatabaseKey => (
+                c"BAD_DATABASE_KEY",
+                c"The database key is wrong or the file is not a database.",
+            ),
+            Self::DatabaseParentMissing => (
+                c"DATABASE_PARENT_MISSING",
+                c"The directory of the database does not exist.",
+            ),
+            Self::DatabaseIsDirectory => (
+                c"DATABASE_IS_DIRECTORY",
+                c"The database path is a directory.",
+            ),
+            Self::DatabasePermissionDenied => (
+                c"DATABASE_PERMISSION_DENIED",
+                c"The database path is not writable.",
+            ),
+            Self::Cancelled => (c"CANCELLED", c"The operation was cancelled."),
+            Self::DeadlineExceeded => (
+                c"DEADLINE_EXCEEDED",
+                c"The operation ran past its deadline.",
+            ),
+            Self::NoSecretWrapper => (
+                c"NO_SECRET_WRAPPER",
+                c"No secret wrapper is set for platform-protected identities.",
+            ),
+            Self::PlatformProtected => (
+                c"PLATFORM_PROTECTED",
+                c"The identity is protected by the platform, not a password.",
+            ),
+            Self::SecretWrapperFailed => (
+                c"SECRET_WRAPPER_FAILED",
+                c"The secret wrapper failed, e.g. the unlock was cancelled.",
+            ),
+            Self::ReadOnly => (c"READ_ONLY", c"The store was opened read-only."),
+            Self::Internal => (c"INTERNAL", c"An unexpected internal error occurred."),
+        }
+    }
+}
+
+/// Strings of codes unknown to this version of the library.
+const UNKNOWN_ERROR_CODE: (&CStr, &CStr) = (c"UNKNOWN", c"Unknown error code.");
+
+fn error_strings(code: i32) -> (&'static CStr, &'static CStr) {
+    SsiManErrorCode::from_code(code).map_or(UNKNOWN_ERROR_CODE, SsiManErrorCode::strings)
+}
+
+enum FfiError {
+    NullArgument(&'static str),
+    Json(serde_json::Error),
+    /// A string handed back to C holds a NUL byte, which would cut it short.
+    Nul(NulError),
+    Ssi(Error),
+}
+
+impl FfiError {
+    fn code(&self) -> SsiManErrorCode {
+        match self {
+            FfiError::NullArgument(_) => SsiManErrorCode::NullArgument,
+            FfiError::Json(_) | FfiError::Nul(_) => SsiManErrorCode::Internal,
+            FfiError::Ssi(err) => err.into(),
+        }
+    }
+}
+
+impl From<NulError> for FfiError {
+    fn from(err: NulError) -> Self {
+        FfiError::Nul(err)
+    }
+}
+
+impl From<Error> for FfiError {
+    fn from(err: Error) -> Self {
+        FfiError::Ssi(err)
+    }
+}
+
+impl Display for FfiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FfiError::NullArgument(name) => write!(f, "{name} cannot be null"),
+            FfiError::Json(err) => write!(f, "json error: {err}"),
+            FfiError::Nul(err) => write!(f, "string holds a NUL byte at {}", err.nul_position()),
+            FfiError::Ssi(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<(SsiManErrorCode, String)>> = const { RefCell::new(None) };
+}
+
+/// Records the outcome of an FFI call for [`ssi_man_last_error_code`] and returns the
+/// value to hand back to C.
+fn report<T>(result: Result<T, FfiError>, on_error: T) -> T {
+    match result {
+        Ok(value) => {
+            LAST_ERROR.with(|last| last.borrow_mut().take());
+            value
+        }
+        Err(err) => {
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some((err.code(), err.to_string())));
+            on_error
+        }
+    }
+}
+
+/// Reads an optional C string argument, where null means "not given".
+fn c_char_to_option(chars: *const c_char) -> Option<String> {
+    if chars.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { CStr::from_ptr(chars) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// Same as [`c_char_to_option`], for passwords, wiped from memory when dropped.
+fn c_char_to_password(chars: *const c_char) -> Option<Zeroizing<String>> {
+    c_char_to_option(chars).map(Zeroizing::new)
+}
+
+fn to_c_char(string: String) -> Result<*mut c_char, FfiError> {
+    Ok(CString::new(string)?.into_raw())
+}
+
+/// Host callback asked for the password of `identity` on the given 1-based `attempt`.
+///
+/// Returns the password, which only needs to stay valid until the callback returns, or
+/// null to cancel the operation.
+pub type SsiManPasswordPrompt =
+    extern "C" fn(identity: *const c_char, attempt: u32, user_data: *mut c_void) -> *const c_char;
+
+#[derive(Clone, Copy)]
+struct HostPasswordPrompt {
+    callback: SsiManPasswordPrompt,
+    user_data: *mut c_void,
+}
+
+// The host is responsible for `user_data` being usable from the threads it calls us on.
+unsafe impl Send for HostPasswordPrompt {}
+unsafe impl Sync for HostPasswordPrompt {}
+
+impl HostPasswordPrompt {
+    fn ask(&self, identity: &str, attempt: u32) -> Option<String> {
+        let identity = CString::new(identity).ok()?;
+        c_char_to_option((self.callback)(identity.as_ptr(), attempt, self.user_data))
+    }
+}
+
+static PASSWORD_PROMPT: Mutex<Option<HostPasswordPrompt>> = Mutex::new(None);
+
+/// Host callback wrapping, or unwrapping, the `len` bytes at `data` with a key held by the
+/// platform, e.g. after FaceID.
+///
+/// Returns the result in a buffer allocated with `malloc`, which the library wipes and
+/// frees, with its length in `out_len`, or null on failure.
+pub type SsiManSecretWrapCallback = extern "C" fn(
+    data: *const u8,
+    len: size_t,
+    out_len: *mut size_t,
+    user_data: *mut c_void,
+) -> *mut u8;
+
+#[derive(Clone, Copy)]
+struct HostSecretWrapper {
+    wrap: SsiManSecretWrapCallback,
+    unwrap: SsiManSecretWrapCallback,
+    user_data: *mut c_void,
+}
+
+// The host is responsible for `user_data` being usable from the threads it calls us on.
+unsafe impl Send for HostSecretWrapper {}
+unsafe impl Sync for HostSecretWrapper {}
+
+impl HostSecretWrapper {
+    fn call(
+        &self,
+        callback: SsiManSecretWrapCallback,
+        data: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, String> {
+        let mut len: size_t = 0;
+        let out = callback(data.as_ptr(), data.len(), &mut len, self.user_data);
+        if out.is_null() {
+            return Err("the host secret wrapper failed".to_string());
+        }
+        let bytes = unsafe { std::slice::from_raw_parts_mut(out, len) };
+        let result = Zeroizing::new(bytes.to_vec());
+        bytes.zeroize();
+        unsafe { libc::free(out.cast()) };
+        Ok(result)
+    }
+}
+
+impl SecretWrapper for HostSecretWrapper {
+    fn wrap(&self, key: &[u8]) -> Result<Vec<u8>, String> {
+        self.call(self.wrap, key).map(|wrapped| wrapped.to_vec())
+    }
+
+    fn unwrap(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+        self.call(self.unwrap, wrapped)
+    }
+}
+
+static SECRET_WRAPPER: Mutex<Option<HostSecretWrapper>> = Mutex::new(None);
+
+/// Installs the host callbacks set when `ssi_man` is opened.
+fn with_host_callbacks(mut ssi_man: SsiMan) -> SsiMan {
+    let prompt = *PASSWORD_PROMPT
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(prompt) = prompt {
+        ssi_man.set_password_prompt(Box::new(move |identity, attempt| {
+            prompt.ask(identity, attempt)
+        }));
+    }
+    let wrapper = *SECRET_WRAPPER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(wrapper) = wrapper {
+        ssi_man.set_secret_wrapper(Some(Box::new(wrapper)));
+    }
+    ssi_man
 }
 
 #[cfg(feature = "sqlite")]
-fn ssi_man_new(db_path: *const c_char) -> Result<SsiMan, Error> {
+fn open_ssi_man(db_path: *const c_char) -> Result<SsiMan, FfiError> {
     if !db_path.is_null() {
-        SsiMan::with_sqlite(c_char_to_string!(db_path))
+        let db_path = c_char_to_string!(db_path)?;
+        Ok(with_host_callbacks(SsiMan::with_sqlite(db_path)?))
     } else {
-        Ok(SsiMan::with_memory())
+        Ok(with_host_callbacks(SsiMan::with_memory()))
     }
 }
 
+#[cfg(feature = "sqlcipher")]
+fn open_encrypted(db_path: *const c_char, key: *const c_char) -> Result<SsiMan, FfiError> {
+    let db_path = c_char_to_string!(db_path)?;
+    let key = c_char_to_password(key).ok_or(FfiError::NullArgument("key"))?;
+    Ok(with_host_callbacks(SsiMan::with_sqlite_encrypted(
+        db_path, &key,
+    )?))
+}
+
+#[cfg(all(feature = "keychain", any(target_os = "macos", target_os = "ios")))]
+fn open_keychain(
+    index_path: *const c_char,
+    accessibility: *const c_char,
+) -> Result<SsiMan, FfiError> {
+    let index_path = c_char_to_string!(index_path)?;
+    let accessibility = match c_char_to_option(accessibility) {
+        Some(accessibility) => accessibility.parse()?,
+        None => KeychainAccessibility::default(),
+    };
+    let store = SsiKeychainStore::new(index_path)?.accessibility(accessibility);
+    Ok(with_host_callbacks(SsiMan::with_store(Box::new(store))))
+}
+
+#[cfg(feature = "sqlcipher")]
+fn open_platform_keyed(db_path: *const c_char) -> Result<SsiMan, FfiError> {
+    let db_path = c_char_to_string!(db_path)?;
+    let wrapper = *SECRET_WRAPPER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let wrapper = wrapper.ok_or(Error::NoSecretWrapper)?;
+    Ok(with_host_callbacks(SsiMan::with_sqlite_platform_keyed(
+        db_path,
+        Box::new(wrapper),
+    )?))
+}
+
 #[cfg(not(feature = "sqlite"))]
-fn ssi_man_new(_db_path: *const c_char) -> Result<SsiMan, Error> {
-    Ok(SsiMan::with_memory())
+fn open_ssi_man(_db_path: *const c_char) -> Result<SsiMan, FfiError> {
+    Ok(with_host_callbacks(SsiMan::with_memory()))
+}
+
+fn last_error_status() -> i32 {
+    ssi_man_last_error_code() as i32
+}
+
+fn handle_mut<'a>(handle: *mut SsiMan) -> Result<&'a mut SsiMan, FfiError> {
+    unsafe { handle.as_mut() }.ok_or(FfiError::NullArgument("handle"))
+}
+
+/// Runs a handle-based call against a handle opened on `db_path` for just that call.
+fn with_opened<T>(
+    db_path: *const c_char,
+    on_error: impl FnOnce() -> T,
+    f: impl FnOnce(*mut SsiMan) -> T,
+) -> T {
+    let handle = ssi_man_open(db_path);
+    if handle.is_null() {
+        return on_error();
+    }
+    let result = f(handle);
+    ssi_man_free(handle);
+    result
+}
+
+/// Parses the algorithm names accepted over FFI.
+fn algo_from_name(name: &str) -> Result<Algo, Error> {
+    match name.to_ascii_lowercase().as_str() {
+        "ed25519" => Ok(Algo::Ed25519),
+        "bip340" => Ok(Algo::Bip340),
+        _ => Err(Error::UnknownAlgo(name.to_string())),
+    }
+}
+
+fn new_ssi(
+    handle: *mut SsiMan,
+    name: *const c_char,
+    email: *const c_char,
+    passwd: *const c_char,
+    algo: *const c_char,
+) -> Result<String, FfiError> {
+    let ssi_man = handle_mut(handle)?;
+    let name = c_char_to_string!(name)?;
+    let email = c_char_to_string!(email)?;
+    let passwd = c_char_to_password(passwd);
+    let algo = match c_char_to_option(algo) {
+        Some(algo) => algo_from_name(&algo)?,
+        None => Algo::Ed25519,
+    };
+    Ok(ssi_man.new_ssi_with(
+        name,
+        email,
+        passwd.as_deref().map(String::as_str),
+        algo,
+        Chain::Bitcoin,
+    )?)
+}
+
+fn new_ssi_platform(
+    handle: *mut SsiMan,
+    name: *const c_char,
+    email: *const c_char,
+) -> Result<String, FfiError> {
+    let ssi_man = handle_mut(handle)?;
+    let name = c_char_to_string!(name)?;
+    let email = c_char_to_string!(email)?;
+    Ok(ssi_man.new_ssi_platform(name, email)?)
+}
+
+fn sign(
+    handle: *mut SsiMan,
+    ssi: *const c_char,
+    message: *const c_char,
+    passwd: *const c_char,
+) -> Result<String, FfiError> {
+    let ssi_man = handle_mut(handle)?;
+    let ssi = c_char_to_string!(ssi)?;
+    let message = c_char_to_string!(message)?;
+    let passwd = c_char_to_password(passwd);
+    Ok(ssi_man.sign(
+        ssi,
+        message.as_bytes(),
+        passwd.as_deref().map(String::as_str),
+    )?)
 }
 
+fn sign_bytes(
+    handle: *mut SsiMan,
+    ssi: *const c_char,
+    data: *const u8,
+    len: size_t,
+    passwd: *const c_char,
+) -> Result<String, FfiError> {
+    let ssi_man = handle_mut(handle)?;
+    let ssi = c_char_to_string!(ssi)?;
+    if data.is_null() && len > 0 {
+        return Err(FfiError::NullArgument("data"));
+    }
+    let data = if len == 0 {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len) }
+    };
+    let passwd = c_char_to_password(passwd);
+    Ok(ssi_man.sign(ssi, data, passwd.as_deref().map(String::as_str))?)
+}
+
+fn cert_verify(cert: *const c_char, text: *const c_char) -> Result<(), FfiError> {
+    let cert = c_char_to_string!(cert)?;
+    let text = c_char_to_string!(text)?;
+    Ok(ssi_cert_verify_text(&cert, &text)?)
+}
+
+fn ctx_mut<'a>(ctx: *mut SsiVerifyCtx) -> Result<&'a mut SsiVerifyCtx, FfiError> {
+    unsafe { ctx.as_mut() }.ok_or(FfiError::NullArgument("ctx"))
+}
+
+fn ctx_add_contact(
+    ctx: *mut SsiVerifyCtx,
+    name: *const c_char,
+    ssi: *const c_char,
+) -> Result<(), FfiError> {
+    let ctx = ctx_mut(ctx)?;
+    let name = c_char_to_string!(name)?;
+    let ssi = c_char_to_string!(ssi)?;
+    Ok(ctx.contacts.add_contact(name, &ssi)?)
+}
+
+fn ctx_verify(
+    ctx: *mut SsiVerifyCtx,
+    cert: *const c_char,
+    text: *const c_char,
+    out_contact_name: *mut *mut c_char,
+) -> Result<(), FfiError> {
+    let ctx = ctx_mut(ctx)?;
+    let cert = c_char_to_string!(cert)?;
+    let text = c_char_to_string!(text)?;
+    let name = ctx.contacts.verify(&cert, &text)?;
+    if !out_contact_name.is_null() {
+        let name = to_c_char(name.to_string())?;
+        unsafe { *out_contact_name = name };
+    }
+    Ok(())
+}
+
+fn remove(handle: *mut SsiMan, identity: *const c_char) -> Result<(), FfiError> {
+    let ssi_man = handle_mut(handle)?;
+    let identity = c_char_to_string!(identity)?;
+    if !ssi_man.remove(&identity)? {
+        return Err(Error::UnknownIdentity(identity).into());
+    }
+    Ok(())
+}
+
+fn get(handle: *mut SsiMan, identity: *const c_char) -> Result<String, FfiError> {
+    let ssi_man = handle_mut(handle)?;
+    let identity = c_char_to_string!(identity)?;
+    let details = ssi_man.get_ssi_details(&identity)?;
+    serde_json::to_string(&details).map_err(FfiError::Json)
+}
+
+fn list(
+    handle: *mut SsiMan,
+    ctx: &OpContext,
+    out_ssis: &mut *mut *const c_char,
+    out_len: *mut size_t,
+) -> Result<(), FfiError> {
+    let ssi_man = handle_mut(handle)?;
+    if out_len.is_null() {
+        return Err(FfiError::NullArgument("out_len"));
+    }
+    let mut names = Vec::<CString>::new();
+    let mut nul = None;
+    ssi_man.for_each_identity_with_ctx(ctx, |identity| {
+        match CString::new(identity) {
+            Ok(name) => names.push(name),
+            Err(err) => {
+                nul.get_or_insert(err);
+            }
+        }
+        Ok(())
+    })?;
+    // Leaving the name out would hand back a short list.
+    if let Some(err) = nul {
+        return Err(err.into());
+    }
+    let c_ptrs = names
+        .into_iter()
+        .map(|name| name.into_raw() as *const c_char)
+        .collect();
+    write_c_char_array(c_ptrs, out_ssis, out_len);
+    Ok(())
+}
+
+fn sign_batch(
+    handle: *mut SsiMan,
+    ssi: *const c_char,
+    messages: *const *const c_char,
+    count: size_t,
+    passwd: *const c_char,
+    out_certs: &mut *mut *const c_char,
+    out_len: *mut size_t,
+) -> Result<(), FfiError> {
+    let ssi_man = handle_mut(handle)?;
+    let ssi = c_char_to_string!(ssi)?;
+    if out_len.is_null() {
+        return Err(FfiError::NullArgument("out_len"));
+    }
+    if messages.is_null() && count > 0 {
+        return Err(FfiError::NullArgument("messages"));
+    }
+    let messages = if count == 0 {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(messages, count) }
+    };
+    let messages = messages
+        .iter()
+        .map(|&message| {
+            if message.is_null() {
+                return Err(FfiError::NullArgument("messages"));
+            }
+            Ok(unsafe { CStr::from_ptr(message) }.to_bytes())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let passwd = c_char_to_password(passwd);
+    let certs = ssi_man.sign_batch(&ssi, &messages, passwd.as_deref().map(String::as_str))?;
+    let certs = certs
+        .into_iter()
+        .map(CString::new)
+        .collect::<Result<Vec<_>, _>>()?;
+    let c_ptrs = certs
+        .into_iter()
+        .map(|cert| cert.into_raw() as *const c_char)
+        .collect();
+    write_c_char_array(c_ptrs, out_certs, out_len);
+    Ok(())
+}
+
+/// Hands `c_ptrs` over to C as an array to release with [`ssi_man_free_string_array`].
+fn write_c_char_array(
+    c_ptrs: Vec<*const c_char>,
+    out_array: &mut *mut *const c_char,
+    out_len: *mut size_t,
+) {
+    let leaked_array = Box::leak(c_ptrs.into_boxed_slice());
+    unsafe {
+        *out_len = leaked_array.len() as size_t;
+    }
+    *out_array = leaked_array.as_mut_ptr();
+}
+
+/// Opens a long-lived handle on the sqlite database at `db_path`, or on an in-memory
+/// store when `db_path` is null. Returns null on error.
+///
+/// The handle keeps its connection open across calls and must be released with
+/// [`ssi_man_free`].
 #[no_mangle]
-pub extern "C" fn ssi_new(
+pub extern "C" fn ssi_man_open(db_path: *const c_char) -> *mut SsiMan {
+    report(
+        open_ssi_man(db_path).map(|ssi_man| Box::into_raw(Box::new(ssi_man))),
+        ptr::null_mut(),
+    )
+}
+
+/// Same as [`ssi_man_open`] on a database encrypted at rest under `key`, failing with
+/// [`SsiManErrorCode::BadDatabaseKey`] if `key` doesn't decrypt it.
+#[cfg(feature = "sqlcipher")]
+#[no_mangle]
+pub extern "C" fn ssi_man_open_encrypted(
+    db_path: *const c_char,
+    key: *const c_char,
+) -> *mut SsiMan {
+    report(
+        open_encrypted(db_path, key).map(|ssi_man| Box::into_raw(Box::new(ssi_man))),
+        ptr::null_mut(),
+    )
+}
+
+/// Opens a long-lived handle keeping secrets in the iOS or macOS Keychain, readable as
+/// `accessibility` allows, and ssis in the index file at `index_path`. `accessibility`
+/// is e.g. "when-unlocked-this-device-only", which null means, or
+/// "after-first-unlock-this-device-only" for background use. Returns null on error.
+#[cfg(all(feature = "keychain", any(target_os = "macos", target_os = "ios")))]
+#[no_mangle]
+pub extern "C" fn ssi_man_open_keychain(
+    index_path: *const c_char,
+    accessibility: *const c_char,
+) -> *mut SsiMan {
+    report(
+        open_keychain(index_path, accessibility).map(|ssi_man| Box::into_raw(Box::new(ssi_man))),
+        ptr::null_mut(),
+    )
+}
+
+/// Same as [`ssi_man_open`] on a database encrypted under a random key wrapped by the
+/// host, e.g. with a key generated in the Android Keystore, through the wrapper set with
+/// [`ssi_man_set_secret_wrapper`] beforehand. The wrapped key is kept in
+/// `<db_path>.key`, so the database file alone is of no use. Fails with
+/// [`SsiManErrorCode::NoSecretWrapper`] without a wrapper.
+#[cfg(feature = "sqlcipher")]
+#[no_mangle]
+pub extern "C" fn ssi_man_open_platform_keyed(db_path: *const c_char) -> *mut SsiMan {
+    report(
+        open_platform_keyed(db_path).map(|ssi_man| Box::into_raw(Box::new(ssi_man))),
+        ptr::null_mut(),
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn ssi_man_free(handle: *mut SsiMan) {
+    if handle.is_null() {
+        return;
+    }
+    unsafe { drop(Box::from_raw(handle)) }
+}
+
+/// Creates a new identity through `handle`, protected by `passwd` (null meaning the empty
+/// password), and returns its ssi, or null on error.
+#[no_mangle]
+pub extern "C" fn ssi_man_handle_new(
+    handle: *mut SsiMan,
+    name: *const c_char,
+    email: *const c_char,
+    passwd: *const c_char,
+) -> *mut c_char {
+    ssi_man_handle_new_with_algo(handle, name, email, passwd, ptr::null())
+}
+
+/// Same as [`ssi_man_handle_new`], with the key algorithm given by name: "ed25519" or
+/// "bip340" (null meaning "ed25519").
+#[no_mangle]
+pub extern "C" fn ssi_man_handle_new_with_algo(
+    handle: *mut SsiMan,
+    name: *const c_char,
+    email: *const c_char,
+    passwd: *const c_char,
+    algo: *const c_char,
+) -> *mut c_char {
+    report(
+        new_ssi(handle, name, email, passwd, algo).and_then(to_c_char),
+        ptr::null_mut(),
+    )
+}
+
+/// Creates a new identity through `handle`, protected by the platform through the wrapper
+/// set with [`ssi_man_set_secret_wrapper`] before opening `handle`, and returns its ssi, or
+/// null on error.
+///
+/// The identity is used with a null `passwd`; passing one fails with
+/// [`SsiManErrorCode::PlatformProtected`].
+#[no_mangle]
+pub extern "C" fn ssi_man_handle_new_platform(
+    handle: *mut SsiMan,
+    name: *const c_char,
+    email: *const c_char,
+) -> *mut c_char {
+    report(
+        new_ssi_platform(handle, name, email).and_then(to_c_char),
+        ptr::null_mut(),
+    )
+}
+
+/// Signs `message` with `ssi` through `handle`, unlocked by `passwd` (null meaning the
+/// empty password), and returns the certificate, or null on error.
+#[no_mangle]
+pub extern "C" fn ssi_man_handle_sign(
+    handle: *mut SsiMan,
+    ssi: *const c_char,
+    message: *const c_char,
+    passwd: *const c_char,
+) -> *mut c_char {
+    report(
+        sign(handle, ssi, message, passwd).and_then(to_c_char),
+        ptr::null_mut(),
+    )
+}
+
+/// Signs the `len` bytes at `data` with `ssi` through `handle`, unlocked by `passwd`
+/// (null meaning the empty password), and returns the certificate, or null on error.
+///
+/// Unlike [`ssi_man_handle_sign`], the payload may contain NUL bytes.
+#[no_mangle]
+pub extern "C" fn ssi_man_handle_sign_bytes(
+    handle: *mut SsiMan,
+    ssi: *const c_char,
+    data: *const u8,
+    len: size_t,
+    passwd: *const c_char,
+) -> *mut c_char {
+    report(
+        sign_bytes(handle, ssi, data, len, passwd).and_then(to_c_char),
+        ptr::null_mut(),
+    )
+}
+
+/// Signs each of the `count` C strings at `messages` with `ssi` through `handle`,
+/// unlocked once by `passwd` (null meaning the empty password), returning an
+/// [`SsiManErrorCode`] (0 on success).
+///
+/// On success the certificates are stored in `out_certs` in the order of `messages`, to
+/// release with [`ssi_man_free_string_array`]; on error nothing is signed.
+#[no_mangle]
+pub extern "C" fn ssi_man_handle_sign_batch(
+    handle: *mut SsiMan,
+    ssi: *const c_char,
+    messages: *const *const c_char,
+    count: size_t,
+    passwd: *const c_char,
+    out_certs: &mut *mut *const c_char,
+    out_len: *mut size_t,
+) -> i32 {
+    report(
+        sign_batch(handle, ssi, messages, count, passwd, out_certs, out_len),
+        (),
+    );
+    last_error_status()
+}
+
+/// Removes an identity through `handle`, returning an [`SsiManErrorCode`] (0 when it was
+/// removed).
+#[no_mangle]
+pub extern "C" fn ssi_man_handle_remove(handle: *mut SsiMan, identity: *const c_char) -> i32 {
+    report(remove(handle, identity), ());
+    last_error_status()
+}
+
+/// Returns the public details of an identity through `handle` as a JSON object, or null
+/// on error.
+#[no_mangle]
+pub extern "C" fn ssi_man_handle_get(handle: *mut SsiMan, identity: *const c_char) -> *mut c_char {
+    report(get(handle, identity).and_then(to_c_char), ptr::null_mut())
+}
+
+/// Lists identities of `handle` into `out_ssis`, returning an [`SsiManErrorCode`] (0 on
+/// success).
+#[no_mangle]
+pub extern "C" fn ssi_man_handle_list(
+    handle: *mut SsiMan,
+    out_ssis: &mut *mut *const c_char,
+    out_len: *mut size_t,
+) -> i32 {
+    report(list(handle, &OpContext::default(), out_ssis, out_len), ());
+    last_error_status()
+}
+
+/// Same as [`ssi_man_handle_list`], failing with [`SsiManErrorCode::Cancelled`] once
+/// `token` is cancelled, from any thread. A null `token` never cancels.
+#[no_mangle]
+pub extern "C" fn ssi_man_handle_list_cancellable(
+    handle: *mut SsiMan,
+    token: *const SsiCancelToken,
+    out_ssis: &mut *mut *const c_char,
+    out_len: *mut size_t,
+) -> i32 {
+    let ctx = match unsafe { token.as_ref() } {
+        Some(token) => OpContext::with_cancel(token.0.clone()),
+        None => OpContext::default(),
+    };
+    report(list(handle, &ctx, out_ssis, out_len), ());
+    last_error_status()
+}
+
+/// Creates a new identity and returns its ssi, or null on error.
+#[no_mangle]
+pub extern "C" fn ssi_man_new(
     name: *const c_char,
     email: *const c_char,
     db_path: *const c_char,
 ) -> *mut c_char {
-    ssi_man_new(db_path)
-        .and_then(|mut ssi_man| {
-            ssi_man.new_ssi(c_char_to_string!(name), c_char_to_string!(email), None)
-        })
-        .map(to_c_char)
-        .unwrap_or(ptr::null_mut())
+    ssi_man_new_with_password(name, email, ptr::null(), db_path)
+}
+
+/// Creates a new identity whose secret is protected by `passwd` (null meaning the empty
+/// password) and returns its ssi, or null on error.
+#[no_mangle]
+pub extern "C" fn ssi_man_new_with_password(
+    name: *const c_char,
+    email: *const c_char,
+    passwd: *const c_char,
+    db_path: *const c_char,
+) -> *mut c_char {
+    with_opened(db_path, ptr::null_mut, |handle| {
+        ssi_man_handle_new(handle, name, email, passwd)
+    })
 }
 
+/// Signs `message` with `ssi` and returns the certificate, or null on error.
 #[no_mangle]
-pub extern "C" fn ssi_sign(
+pub extern "C" fn ssi_man_sign(
     ssi: *mut c_char,
     message: *const c_char,
     db_path: *const c_char,
 ) -> *mut c_char {
-    ssi_man_new(db_path)
-        .and_then(|mut ssi_man| {
-            ssi_man.sign(
-                c_char_to_string!(ssi),
-                c_char_to_string!(message).as_bytes(),
-                None,
-            )
-        })
-        .map(to_c_char)
-        .unwrap_or(ptr::null_mut())
+    ssi_man_sign_with_password(ssi, message, ptr::null(), db_path)
+}
+
+/// Signs `message` with `ssi` unlocked by `passwd` (null meaning the empty password) and
+/// returns the certificate, or null on error.
+#[no_mangle]
+pub extern "C" fn ssi_man_sign_with_password(
+    ssi: *mut c_char,
+    message: *const c_char,
+    passwd: *const c_char,
+    db_path: *const c_char,
+) -> *mut c_char {
+    with_opened(db_path, ptr::null_mut, |handle| {
+        ssi_man_handle_sign(handle, ssi, message, passwd)
+    })
+}
+
+/// Signs the `len` bytes at `data` with `ssi` unlocked by `passwd` (null meaning the
+/// empty password) and returns the certificate, or null on error.
+#[no_mangle]
+pub extern "C" fn ssi_man_sign_bytes(
+    ssi: *const c_char,
+    data: *const u8,
+    len: size_t,
+    passwd: *const c_char,
+    db_path: *const c_char,
+) -> *mut c_char {
+    with_opened(db_path, ptr::null_mut, |handle| {
+        ssi_man_handle_sign_bytes(handle, ssi, data, len, passwd)
+    })
 }
 
+/// Signs each of the `count` C strings at `messages` with `ssi`, unlocked once by
+/// `passwd`; see [`ssi_man_handle_sign_batch`].
 #[no_mangle]
-pub extern "C" fn ssi_list(
+pub extern "C" fn ssi_man_sign_batch(
+    ssi: *const c_char,
+    messages: *const *const c_char,
+    count: size_t,
+    passwd: *const c_char,
+    db_path: *const c_char,
+    out_certs: &mut *mut *const c_char,
+    out_len: *mut size_t,
+) -> i32 {
+    with_opened(db_path, last_error_status, |handle| {
+        ssi_man_handle_sign_batch(handle, ssi, messages, count, passwd, out_certs, out_len)
+    })
+}
+
+/// Verifies that `cert` signs `text`, returning an [`SsiManErrorCode`] (0 when valid).
+#[no_mangle]
+pub extern "C" fn ssi_man_cert_verify(cert: *const c_char, text: *const c_char) -> i32 {
+    report(cert_verify(cert, text), ());
+    last_error_status()
+}
+
+/// Contacts to verify certificates against, kept in memory only.
+pub struct SsiVerifyCtx {
+    contacts: VerifyContext,
+}
+
+/// Creates an empty verification context, which never touches storage. It must be
+/// released with [`ssi_man_ctx_free`].
+#[no_mangle]
+pub extern "C" fn ssi_man_ctx_new() -> *mut SsiVerifyCtx {
+    Box::into_raw(Box::new(SsiVerifyCtx {
+        contacts: VerifyContext::new(),
+    }))
+}
+
+/// Adds the contact `name` with its `ssi` to `ctx`, returning an [`SsiManErrorCode`]
+/// (0 on success).
+#[no_mangle]
+pub extern "C" fn ssi_man_ctx_add_contact(
+    ctx: *mut SsiVerifyCtx,
+    name: *const c_char,
+    ssi: *const c_char,
+) -> i32 {
+    report(ctx_add_contact(ctx, name, ssi), ());
+    last_error_status()
+}
+
+/// Verifies that `cert` signs `text` with the key of a contact of `ctx`, returning an
+/// [`SsiManErrorCode`] (0 when valid).
+///
+/// On success the contact name is stored in `out_contact_name`, if not null, and must be
+/// released with [`ssi_man_free_string`].
+#[no_mangle]
+pub extern "C" fn ssi_man_ctx_verify(
+    ctx: *mut SsiVerifyCtx,
+    cert: *const c_char,
+    text: *const c_char,
+    out_contact_name: *mut *mut c_char,
+) -> i32 {
+    report(ctx_verify(ctx, cert, text, out_contact_name), ());
+    last_error_status()
+}
+
+#[no_mangle]
+pub extern "C" fn ssi_man_ctx_free(ctx: *mut SsiVerifyCtx) {
+    if ctx.is_null() {
+        return;
+    }
+    unsafe { drop(Box::from_raw(ctx)) }
+}
+
+/// Cancels the calls it is given to, once [`ssi_man_cancel_token_cancel`] is called.
+pub struct SsiCancelToken(Arc<AtomicBool>);
+
+/// Creates a token that isn't cancelled. It must be released with
+/// [`ssi_man_cancel_token_free`], once no call is using it.
+#[no_mangle]
+pub extern "C" fn ssi_man_cancel_token_new() -> *mut SsiCancelToken {
+    Box::into_raw(Box::new(SsiCancelToken(Arc::new(AtomicBool::new(false)))))
+}
+
+/// Cancels `token`, stopping the calls using it, which may run on other threads.
+#[no_mangle]
+pub extern "C" fn ssi_man_cancel_token_cancel(token: *const SsiCancelToken) {
+    if let Some(token) = unsafe { token.as_ref() } {
+        token.0.store(true, Ordering::Relaxed);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ssi_man_cancel_token_free(token: *mut SsiCancelToken) {
+    if token.is_null() {
+        return;
+    }
+    unsafe { drop(Box::from_raw(token)) }
+}
+
+/// Removes an identity, returning an [`SsiManErrorCode`] (0 when it was removed).
+#[no_mangle]
+pub extern "C" fn ssi_man_remove(identity: *const c_char, db_path: *const c_char) -> i32 {
+    with_opened(db_path, last_error_status, |handle| {
+        ssi_man_handle_remove(handle, identity)
+    })
+}
+
+/// Returns the public details of an identity as a JSON object, or null on error.
+#[no_mangle]
+pub extern "C" fn ssi_man_get(identity: *const c_char, db_path: *const c_char) -> *mut c_char {
+    with_opened(db_path, ptr::null_mut, |handle| {
+        ssi_man_handle_get(handle, identity)
+    })
+}
+
+/// Lists identities into `out_ssis`, returning an [`SsiManErrorCode`] (0 on success).
+#[no_mangle]
+pub extern "C" fn ssi_man_list(
     db_path: *const c_char,
     out_ssis: &mut *mut *const c_char,
     out_len: *mut size_t,
 ) -> i32 {
-    ssi_man_new(db_path)
-        .and_then(|mut ssi_man| {
-            ssi_man.all_identities().map(|identities| {
-                let c_strings = identities
-                    .into_iter()
-                    .flat_map(|identity| CString::new(identity.into_owned()))
-                    .collect::<Vec<_>>();
-
-                let c_ptrs = c_strings
-                    .iter()
-                    .map(|s| s.as_ptr())
-                    .collect::<Vec<*const c_char>>();
-
-                let boxed_array = c_ptrs.into_boxed_slice();
-                let leaked_array = Box::leak(boxed_array);
-
-                unsafe {
-                    *out_len = leaked_array.len() as size_t;
-                    *out_ssis = leaked_array.as_mut_ptr();
-                }
-
-                Box::leak(Box::new(c_strings));
-                0
-            })
-        })
-        .unwrap_or(-1)
+    with_opened(db_path, last_error_status, |handle| {
+        ssi_man_handle_list(handle, out_ssis, out_len)
+    })
+}
+
+/// Installs the password prompt used by every following call, or removes it when
+/// `callback` is null.
+///
+/// With a prompt installed, a null `passwd` asks the prompt instead of using the empty
+/// password.
+#[no_mangle]
+pub extern "C" fn ssi_man_set_password_prompt(
+    callback: Option<SsiManPasswordPrompt>,
+    user_data: *mut c_void,
+) {
+    let prompt = callback.map(|callback| HostPasswordPrompt {
+        callback,
+        user_data,
+    });
+    *PASSWORD_PROMPT
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = prompt;
+}
+
+/// Installs the secret wrapper of platform-protected identities in handles opened from
+/// then on, or removes it when either callback is null.
+///
+/// Without one, such identities can neither be created nor used.
+#[no_mangle]
+pub extern "C" fn ssi_man_set_secret_wrapper(
+    wrap: Option<SsiManSecretWrapCallback>,
+    unwrap: Option<SsiManSecretWrapCallback>,
+    user_data: *mut c_void,
+) {
+    let wrapper = wrap.zip(unwrap).map(|(wrap, unwrap)| HostSecretWrapper {
+        wrap,
+        unwrap,
+        user_data,
+    });
+    *SECRET_WRAPPER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = wrapper;
+}
+
+/// How identities appear in error messages, see [`ssi_man_set_redaction`].
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SsiManRedaction {
+    Off = 0,
+    Hashed = 1,
+    Truncated = 2,
+}
+
+/// Sets how identity names, ssi strings and emails appear in every following error
+/// message, including [`ssi_man_last_error_message`].
+#[no_mangle]
+pub extern "C" fn ssi_man_set_redaction(redaction: SsiManRedaction) {
+    SsiMan::set_redaction(match redaction {
+        SsiManRedaction::Off => Redaction::Off,
+        SsiManRedaction::Hashed => Redaction::Hashed,
+        SsiManRedaction::Truncated => Redaction::Truncated,
+    });
+}
+
+/// Returns the code of the last error raised on this thread, or `Ok` if the last call
+/// succeeded.
+#[no_mangle]
+pub extern "C" fn ssi_man_last_error_code() -> SsiManErrorCode {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map(|(code, _)| *code)
+            .unwrap_or(SsiManErrorCode::Ok)
+    })
+}
+
+/// Returns the message of the last error raised on this thread, or null if the last call
+/// succeeded, with NUL bytes written as `\0`. The string must be released with
+/// [`ssi_man_free_string`].
+#[no_mangle]
+pub extern "C" fn ssi_man_last_error_message() -> *mut c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .and_then(|(_, message)| CString::new(message.replace('\0', "\\0")).ok())
+            .map_or(ptr::null_mut(), CString::into_raw)
+    })
 }
 
+/// Returns the stable name of an error code, e.g. `UNKNOWN_IDENTITY`, or `UNKNOWN` for
+/// codes this version doesn't know. The string is static: never free it.
 #[no_mangle]
-pub extern "C" fn free_string_array(array: *mut *const c_char, len: size_t) {
+pub extern "C" fn ssi_man_error_name(code: i32) -> *const c_char {
+    error_strings(code).0.as_ptr()
+}
+
+/// Returns an English description of an error code, fit to show users, with a generic
+/// one for codes this version doesn't know. The string is static: never free it.
+#[no_mangle]
+pub extern "C" fn ssi_man_error_description(code: i32) -> *const c_char {
+    error_strings(code).1.as_ptr()
+}
+
+#[no_mangle]
+pub extern "C" fn ssi_man_free_string(string: *mut c_char) {
+    if string.is_null() {
+        return;
+    }
+    unsafe { drop(CString::from_raw(string)) }
+}
+
+#[no_mangle]
+pub extern "C" fn ssi_man_free_string_array(array: *mut *const c_char, len: size_t) {
     if array.is_null() {
         return;
     }
 
     unsafe {
-        let slice = std::slice::from_raw_parts(array, len);
-        for &s in slice {
+        let slice = Box::from_raw(std::ptr::slice_from_raw_parts_mut(array, len));
+        for &s in slice.iter() {
             if !s.is_null() {
                 drop(CString::from_raw(s as *mut c_char))
             }
@@ -119,6 +1334,48 @@ pub extern "C" fn free_string_array(array: *mut *const c_char, len: size_t) {
     }
 }
 
+/// Unprefixed symbol names from before the `ssi_man_` prefix was introduced.
+///
+/// Kept for one release so existing host apps keep linking; they will be removed afterwards.
+#[cfg(feature = "ffi-compat")]
+mod compat {
+    use std::ffi::c_char;
+
+    use libc::size_t;
+
+    #[no_mangle]
+    pub extern "C" fn ssi_new(
+        name: *const c_char,
+        email: *const c_char,
+        db_path: *const c_char,
+    ) -> *mut c_char {
+        super::ssi_man_new(name, email, db_path)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn ssi_sign(
+        ssi: *mut c_char,
+        message: *const c_char,
+        db_path: *const c_char,
+    ) -> *mut c_char {
+        super::ssi_man_sign(ssi, message, db_path)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn ssi_list(
+        db_path: *const c_char,
+        out_ssis: &mut *mut *const c_char,
+        out_len: *mut size_t,
+    ) -> i32 {
+        super::ssi_man_list(db_path, out_ssis, out_len)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn free_string_array(array: *mut *const c_char, len: size_t) {
+        super::ssi_man_free_string_array(array, len)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::env;
@@ -126,7 +1383,9 @@ mod tests {
     use time::OffsetDateTime;
 
     use super::*;
+    use crate::{SsiMemoryStore, SsiStore};
 
+    #[cfg(feature = "sqlite")]
     #[test]
     fn ssi_ffi_methods_should_success() {
         let db_path = to_c_char(
@@ -137,21 +1396,404 @@ mod tests {
                 ))
                 .display()
                 .to_string(),
-        );
+        )
+        .unwrap();
 
-        let ssi = ssi_new(
-            to_c_char("luna".into()),
-            to_c_char("luna@bitlightlabs.com".into()),
+        let ssi = ssi_man_new(
+            to_c_char("luna".into()).unwrap(),
+            to_c_char("luna@bitlightlabs.com".into()).unwrap(),
             db_path,
         );
         assert!(!ssi.is_null());
 
         let mut out_ssi: *mut *const c_char = ptr::null_mut();
         let mut out_len: size_t = 0;
-        ssi_list(db_path, &mut out_ssi, &mut out_len);
+        ssi_man_list(db_path, &mut out_ssi, &mut out_len);
         assert_eq!(out_len, 1);
         let name = unsafe { *out_ssi.offset(0isize) };
-        assert_eq!(c_char_to_string!(name).as_str(), "luna");
-        free_string_array(out_ssi, out_len);
+        assert_eq!(c_char_to_string!(name).unwrap().as_str(), "luna");
+        ssi_man_free_string_array(out_ssi, out_len);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn ssi_ffi_password_sign_and_verify_should_success() {
+        let db_path = to_c_char(
+            env::temp_dir()
+                .join(format!(
+                    "ssi_test_passwd_{}.db",
+                    OffsetDateTime::now_utc().unix_timestamp_nanos()
+                ))
+                .display()
+                .to_string(),
+        )
+        .unwrap();
+        let identity = to_c_char("luna".into()).unwrap();
+        let passwd = to_c_char("secret".into()).unwrap();
+        let message = to_c_char("have a good day!".into()).unwrap();
+
+        let ssi = ssi_man_new_with_password(
+            identity,
+            to_c_char("luna@bitlightlabs.com".into()).unwrap(),
+            passwd,
+            db_path,
+        );
+        assert!(!ssi.is_null());
+
+        assert!(ssi_man_sign(identity, message, db_path).is_null());
+        assert_eq!(ssi_man_last_error_code(), SsiManErrorCode::WrongPassword);
+
+        let cert = ssi_man_sign_with_password(identity, message, passwd, db_path);
+        assert!(!cert.is_null());
+        assert_eq!(ssi_man_cert_verify(cert, message), 0);
+        assert_eq!(
+            ssi_man_cert_verify(cert, to_c_char("have a bad day!".into()).unwrap()),
+            SsiManErrorCode::VerificationFailed as i32
+        );
+        ssi_man_free_string(cert);
+
+        assert_eq!(ssi_man_remove(identity, db_path), 0);
+        assert_eq!(
+            ssi_man_remove(identity, db_path),
+            SsiManErrorCode::UnknownIdentity as i32
+        );
+    }
+
+    #[test]
+    fn ssi_ffi_handle_should_survive_multiple_operations() {
+        let handle = ssi_man_open(ptr::null());
+        assert!(!handle.is_null());
+        let identity = to_c_char("luna".into()).unwrap();
+        let passwd = to_c_char("secret".into()).unwrap();
+        let message = to_c_char("have a good day!".into()).unwrap();
+
+        let ssi = ssi_man_handle_new(
+            handle,
+            identity,
+            to_c_char("luna@bitlightlabs.com".into()).unwrap(),
+            passwd,
+        );
+        assert!(!ssi.is_null());
+        let details = ssi_man_handle_get(handle, identity);
+        let json: serde_json::Value =
+            serde_json::from_str(&c_char_to_string!(details).unwrap()).unwrap();
+        assert_eq!(json["identity"], "luna");
+        assert_eq!(
+            json["ssi"].as_str(),
+            Some(c_char_to_string!(ssi).unwrap().as_str())
+        );
+        ssi_man_free_string(details);
+        let payload = b"have a\0good\0day!";
+        let cert =
+            ssi_man_handle_sign_bytes(handle, identity, payload.as_ptr(), payload.len(), passwd);
+        assert!(!cert.is_null());
+        crate::ssi_cert_verify_text(
+            &c_char_to_string!(cert).unwrap(),
+            std::str::from_utf8(payload).unwrap(),
+        )
+        .unwrap();
+        assert!(crate::ssi_cert_verify_text(&c_char_to_string!(cert).unwrap(), "have a").is_err());
+        ssi_man_free_string(cert);
+        assert!(ssi_man_handle_get(handle, to_c_char("nobody".into()).unwrap()).is_null());
+        assert_eq!(ssi_man_last_error_code(), SsiManErrorCode::UnknownIdentity);
+        for _ in 0..2 {
+            let cert = ssi_man_handle_sign(handle, identity, message, passwd);
+            assert!(!cert.is_null());
+            assert_eq!(ssi_man_cert_verify(cert, message), 0);
+            ssi_man_free_string(cert);
+        }
+
+        let mut out_ssi: *mut *const c_char = ptr::null_mut();
+        let mut out_len: size_t = 0;
+        assert_eq!(ssi_man_handle_list(handle, &mut out_ssi, &mut out_len), 0);
+        assert_eq!(out_len, 1);
+        ssi_man_free_string_array(out_ssi, out_len);
+        let token = ssi_man_cancel_token_new();
+        assert_eq!(
+            ssi_man_handle_list_cancellable(handle, token, &mut out_ssi, &mut out_len),
+            0
+        );
+        assert_eq!(out_len, 1);
+        ssi_man_free_string_array(out_ssi, out_len);
+        ssi_man_cancel_token_cancel(token);
+        assert_eq!(
+            ssi_man_handle_list_cancellable(handle, token, &mut out_ssi, &mut out_len),
+            SsiManErrorCode::Cancelled as i32
+        );
+        ssi_man_cancel_token_free(token);
+
+        assert_eq!(ssi_man_handle_remove(handle, identity), 0);
+        assert_eq!(
+            ssi_man_handle_remove(handle, identity),
+            SsiManErrorCode::UnknownIdentity as i32
+        );
+        ssi_man_free(handle);
+
+        assert!(ssi_man_handle_sign(ptr::null_mut(), identity, message, passwd).is_null());
+        assert_eq!(ssi_man_last_error_code(), SsiManErrorCode::NullArgument);
+    }
+
+    #[test]
+    fn error_codes_should_have_unique_names_and_descriptions() {
+        let c_str = |ptr: *const c_char| unsafe { CStr::from_ptr(ptr) }.to_str().unwrap();
+        let mut names = std::collections::HashSet::new();
+        let mut previous = None;
+        for kind in SsiManErrorCode::ALL {
+            let code = kind as i32;
+            assert!(previous < Some(code), "{kind:?} is out of order");
+            previous = Some(code);
+            assert_eq!(SsiManErrorCode::from_code(code), Some(kind));
+
+            let name = c_str(ssi_man_error_name(code));
+            let description = c_str(ssi_man_error_description(code));
+            assert!(!name.is_empty() && !description.is_empty());
+            assert_ne!(name, "UNKNOWN");
+            assert!(names.insert(name), "{name} is used twice");
+        }
+        let known = SsiManErrorCode::ALL.map(|kind| kind as i32);
+        let unknown = (-1..=100).filter(|code| !known.contains(code));
+        for code in unknown.chain([i32::MAX]) {
+            assert_eq!(SsiManErrorCode::from_code(code), None);
+            assert_eq!(c_str(ssi_man_error_name(code)), "UNKNOWN");
+            assert_eq!(
+                c_str(ssi_man_error_description(code)),
+                "Unknown error code."
+            );
+        }
+    }
+
+    #[test]
+    fn ssi_ffi_errors_should_be_reported() {
+        let ssi = ssi_man_new(
+            ptr::null(),
+            to_c_char("luna@bitlightlabs.com".into()).unwrap(),
+            ptr::null(),
+        );
+        assert!(ssi.is_null());
+        assert_eq!(ssi_man_last_error_code(), SsiManErrorCode::NullArgument);
+        let message = ssi_man_last_error_message();
+        assert_eq!(
+            c_char_to_string!(message).unwrap().as_str(),
+            "name cannot be null"
+        );
+        ssi_man_free_string(message);
+
+        let cert = ssi_man_sign(
+            to_c_char("nobody".into()).unwrap(),
+            to_c_char("have a good day!".into()).unwrap(),
+            ptr::null(),
+        );
+        assert!(cert.is_null());
+        assert_eq!(ssi_man_last_error_code(), SsiManErrorCode::UnknownIdentity);
+
+        let handle = ssi_man_open(ptr::null());
+        let ssi = ssi_man_handle_new_with_algo(
+            handle,
+            to_c_char("luna".into()).unwrap(),
+            to_c_char("luna@bitlightlabs.com".into()).unwrap(),
+            ptr::null(),
+            to_c_char("rsa".into()).unwrap(),
+        );
+        assert!(ssi.is_null());
+        assert_eq!(ssi_man_last_error_code(), SsiManErrorCode::InvalidInput);
+        let ssi = ssi_man_handle_new_with_algo(
+            handle,
+            to_c_char("luna".into()).unwrap(),
+            to_c_char("luna@bitlightlabs.com".into()).unwrap(),
+            ptr::null(),
+            to_c_char("bip340".into()).unwrap(),
+        );
+        assert!(!ssi.is_null());
+        ssi_man_free_string(ssi);
+        ssi_man_free(handle);
+
+        let mut out_ssi: *mut *const c_char = ptr::null_mut();
+        let mut out_len: size_t = 0;
+        assert_eq!(ssi_man_list(ptr::null(), &mut out_ssi, &mut out_len), 0);
+        assert_eq!(ssi_man_last_error_code(), SsiManErrorCode::Ok);
+        assert!(ssi_man_last_error_message().is_null());
+    }
+
+    #[test]
+    fn ssi_ffi_nul_bytes_should_be_reported() {
+        report::<()>(Err(Error::UnknownIdentity("lu\0na".into()).into()), ());
+        let message = ssi_man_last_error_message();
+        assert!(c_char_to_string!(message).unwrap().contains("lu\\0na"));
+        ssi_man_free_string(message);
+
+        let mut store = SsiMemoryStore::default();
+        let secret = ssi::SsiSecret::new(Algo::Ed25519, Chain::Bitcoin);
+        let uid = "Luna <mailto:luna@bitlightlabs.com>".parse().unwrap();
+        let ssi = ssi::Ssi::new(vec![uid], None, &secret);
+        store
+            .insert("lu\0na".to_string(), ssi, secret.conceal(""))
+            .unwrap();
+        let handle = Box::into_raw(Box::new(SsiMan::with_store(Box::new(store))));
+        let mut out_ssi: *mut *const c_char = ptr::null_mut();
+        let mut out_len: size_t = 0;
+        assert_eq!(
+            ssi_man_handle_list(handle, &mut out_ssi, &mut out_len),
+            SsiManErrorCode::Internal as i32
+        );
+        assert!(out_ssi.is_null());
+        ssi_man_free(handle);
+    }
+
+    #[test]
+    fn ssi_ffi_verify_ctx_should_not_touch_storage() {
+        let mut ssi_man = SsiMan::with_memory();
+        let ssi = ssi_man
+            .new_ssi("luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let cert = ssi_man.sign("luna", "have a good day!", None).unwrap();
+        let entries = || {
+            let mut entries = std::fs::read_dir(".")
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect::<Vec<_>>();
+            entries.sort();
+            entries
+        };
+        let before = entries();
+
+        let name = to_c_char("Luna".into()).unwrap();
+        let cert = to_c_char(cert).unwrap();
+        let message = to_c_char("have a good day!".into()).unwrap();
+        assert_eq!(
+            ssi_man_ctx_add_contact(ptr::null_mut(), name, to_c_char(ssi.clone()).unwrap()),
+            SsiManErrorCode::NullArgument as i32
+        );
+        let ctx = ssi_man_ctx_new();
+        let mut out_name: *mut c_char = ptr::null_mut();
+        assert_eq!(
+            ssi_man_ctx_verify(ctx, cert, message, &mut out_name),
+            SsiManErrorCode::VerificationFailed as i32
+        );
+        assert_eq!(
+            ssi_man_ctx_add_contact(ctx, name, to_c_char("not an ssi".into()).unwrap()),
+            SsiManErrorCode::InvalidInput as i32
+        );
+        assert_eq!(
+            ssi_man_ctx_add_contact(ctx, name, to_c_char(ssi).unwrap()),
+            0
+        );
+        assert_eq!(ssi_man_ctx_verify(ctx, cert, message, &mut out_name), 0);
+        assert_eq!(c_char_to_string!(out_name).unwrap(), "Luna");
+        ssi_man_free_string(out_name);
+        assert_eq!(
+            ssi_man_ctx_verify(
+                ctx,
+                cert,
+                to_c_char("have a bad day!".into()).unwrap(),
+                ptr::null_mut()
+            ),
+            SsiManErrorCode::VerificationFailed as i32
+        );
+        ssi_man_ctx_free(ctx);
+
+        assert_eq!(entries(), before);
+    }
+
+    #[test]
+    fn ssi_ffi_sign_batch_should_sign_in_order() {
+        let handle = ssi_man_open(ptr::null());
+        let identity = to_c_char("luna".into()).unwrap();
+        let passwd = to_c_char("secret".into()).unwrap();
+        let ssi = ssi_man_handle_new_with_algo(
+            handle,
+            identity,
+            to_c_char("luna@bitlightlabs.com".into()).unwrap(),
+            passwd,
+            ptr::null(),
+        );
+        assert!(!ssi.is_null());
+
+        let texts = ["line 1", "line 2", "line 3"];
+        let messages = texts
+            .iter()
+            .map(|text| to_c_char(text.to_string()).unwrap() as *const c_char)
+            .collect::<Vec<_>>();
+        let mut out_certs: *mut *const c_char = ptr::null_mut();
+        let mut out_len: size_t = 0;
+        assert_eq!(
+            ssi_man_handle_sign_batch(
+                handle,
+                identity,
+                messages.as_ptr(),
+                messages.len(),
+                to_c_char("wrong".into()).unwrap(),
+                &mut out_certs,
+                &mut out_len,
+            ),
+            SsiManErrorCode::WrongPassword as i32
+        );
+        assert!(out_certs.is_null());
+
+        assert_eq!(
+            ssi_man_handle_sign_batch(
+                handle,
+                identity,
+                messages.as_ptr(),
+                messages.len(),
+                passwd,
+                &mut out_certs,
+                &mut out_len,
+            ),
+            0
+        );
+        assert_eq!(out_len, texts.len());
+        let certs = unsafe { std::slice::from_raw_parts(out_certs, out_len) };
+        for (&cert, text) in certs.iter().zip(texts) {
+            let cert = c_char_to_string!(cert).unwrap();
+            crate::ssi_cert_verify_text(&cert, text).unwrap();
+        }
+        ssi_man_free_string_array(out_certs, out_len);
+        ssi_man_free(handle);
+    }
+
+    extern "C" fn xor_pad(
+        data: *const u8,
+        len: size_t,
+        out_len: *mut size_t,
+        _user_data: *mut c_void,
+    ) -> *mut u8 {
+        let data = unsafe { std::slice::from_raw_parts(data, len) };
+        let out = unsafe { libc::malloc(len) }.cast::<u8>();
+        for (n, byte) in data.iter().enumerate() {
+            unsafe { *out.add(n) = byte ^ 0x5c };
+        }
+        unsafe { *out_len = len };
+        out
+    }
+
+    #[test]
+    fn ssi_ffi_platform_identity_should_unlock_through_the_host() {
+        let identity = to_c_char("luna".into()).unwrap();
+        let email = to_c_char("luna@bitlightlabs.com".into()).unwrap();
+        let message = to_c_char("have a good day!".into()).unwrap();
+
+        let unwrapped = ssi_man_open(ptr::null());
+        assert!(ssi_man_handle_new_platform(unwrapped, identity, email).is_null());
+        assert_eq!(ssi_man_last_error_code(), SsiManErrorCode::NoSecretWrapper);
+        ssi_man_free(unwrapped);
+
+        ssi_man_set_secret_wrapper(Some(xor_pad), Some(xor_pad), ptr::null_mut());
+        let handle = ssi_man_open(ptr::null());
+        ssi_man_set_secret_wrapper(None, None, ptr::null_mut());
+        let ssi = ssi_man_handle_new_platform(handle, identity, email);
+        assert!(!ssi.is_null());
+        ssi_man_free_string(ssi);
+
+        let cert = ssi_man_handle_sign(handle, identity, message, ptr::null());
+        assert!(!cert.is_null());
+        crate::ssi_cert_verify_text(&c_char_to_string!(cert).unwrap(), "have a good day!").unwrap();
+        ssi_man_free_string(cert);
+        let passwd = to_c_char("secret".into()).unwrap();
+        assert!(ssi_man_handle_sign(handle, identity, message, passwd).is_null());
+        assert_eq!(
+            ssi_man_last_error_code(),
+            SsiManErrorCode::PlatformProtected
+        );
+        ssi_man_free(handle);
     }
 }
@@ -0,0 +1,371 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+use serde_json::json;
+use ssi::{EncryptedSecret, Ssi};
+use ureq::{Agent, AgentBuilder, Request};
+use zeroize::Zeroizing;
+
+use crate::{Error, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability};
+
+const API: &str = "https://secretmanager.googleapis.com/v1";
+/// Token endpoint of the metadata server, serving the workload's service account.
+const METADATA_TOKEN: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+/// Secret id prefix of the secrets of [`SsiGcpStore::new`].
+const DEFAULT_PREFIX: &str = "ssi-man";
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Margin before expiry after which a token from the metadata server is renewed.
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct MetadataToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct AccessResponse {
+    payload: Payload,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    data: String,
+}
+
+/// Where requests get their OAuth access token.
+enum Credentials {
+    /// The metadata server, with the token cached until shortly before it expires.
+    Metadata(Mutex<Option<(Zeroizing<String>, Instant)>>),
+    /// A fixed token, e.g. from `gcloud auth print-access-token`.
+    Token(Zeroizing<String>),
+}
+
+/// A store keeping concealed secrets in Google Cloud Secret Manager, and only the public
+/// ssis in a local index file, e.g. for signing services running on GKE or Cloud Run.
+///
+/// Requests are authenticated as the workload's service account through the metadata
+/// server, i.e. with workload identity, unless given a token with
+/// [`SsiGcpStore::access_token`]. Each secret has the id `<prefix>-<identity>`, with
+/// bytes Secret Manager doesn't allow in ids, and `_`, written as `_XX`, automatic
+/// replication and one version per write; earlier versions are left to the secret's
+/// own version policy. The index holds `identity<TAB>ssi` lines. Secrets are written
+/// before the index and deleted after it, so an interrupted write leaves at worst an
+/// unused secret behind.
+pub struct SsiGcpStore {
+    agent: Agent,
+    project: String,
+    prefix: String,
+    credentials: Credentials,
+    index_path: PathBuf,
+    index: BTreeMap<String, Ssi>,
+}
+
+impl SsiGcpStore {
+    /// Opens the index file at `index_path`, creating it if missing, with secrets in the
+    /// Google Cloud project `project`.
+    pub fn new(index_path: impl AsRef<Path>, project: &str) -> Result<Self, Error> {
+        let index_path = index_path.as_ref().to_path_buf();
+        let text = match fs::read_to_string(&index_path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut index = BTreeMap::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (identity, ssi) = line
+                .split_once('\t')
+                .ok_or_else(|| Error::GcpIndex("line without an ssi".to_string()))?;
+            index.insert(identity.to_string(), Ssi::from_str(ssi)?);
+        }
+        let store = Self {
+            agent: AgentBuilder::new().timeout(TIMEOUT).build(),
+            project: project.to_string(),
+            prefix: DEFAULT_PREFIX.to_string(),
+            credentials: Credentials::Metadata(Mutex::new(None)),
+            index_path,
+            index,
+        };
+        store.save(&store.index)?;
+        Ok(store)
+    }
+
+    /// Names secrets `<prefix>-<identity>` rather than `ssi-man-<identity>`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Authenticates with `token` rather than through the metadata server, e.g. outside
+    /// of Google Cloud.
+    pub fn access_token(mut self, token: &str) -> Self {
+        self.credentials = Credentials::Token(Zeroizing::new(token.to_string()));
+        self
+    }
+
+    /// Writes `index` through a temporary file renamed over the index file.
+    fn save(&self, index: &BTreeMap<String, Ssi>) -> Result<(), Error> {
+        let mut temporary = self.index_path.clone().into_os_string();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        for (identity, ssi) in index {
+            writeln!(file, "{identity}\t{ssi}")?;
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, &self.index_path)?;
+        Ok(())
+    }
+
+    fn token(&self) -> Result<Zeroizing<String>, Error> {
+        let cached = match &self.credentials {
+            Credentials::Token(token) => return Ok(token.clone()),
+            Credentials::Metadata(cached) => cached,
+        };
+        // Held while fetching, so concurrent reads wait for one token.
+        let mut cached = cached.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((token, expiry)) = &*cached {
+            if Instant::now() + TOKEN_MARGIN < *expiry {
+                return Ok(token.clone());
+            }
+        }
+        let token: MetadataToken = self
+            .agent
+            .get(METADATA_TOKEN)
+            .set("Metadata-Flavor", "Google")
+            .call()
+            .map_err(gcp_error)?
+            .into_json()
+            .map_err(|err| Error::Gcp(err.to_string()))?;
+        let access_token = Zeroizing::new(token.access_token);
+        let expiry = Instant::now() + Duration::from_secs(token.expires_in);
+        *cached = Some((access_token.clone(), expiry));
+        Ok(access_token)
+    }
+
+    fn request(&self, method: &str, path: &str) -> Result<Request, Error> {
+        let token = self.token()?;
+        let url = format!("{API}/projects/{}/secrets{path}", self.project);
+        Ok(self
+            .agent
+            .request(method, &url)
+            .set("Authorization", &format!("Bearer {}", token.as_str())))
+    }
+
+    fn secret(&self, id: &str) -> Result<EncryptedSecret, Error> {
+        let path = format!("/{}/versions/latest:access", self.secret_id(id));
+        let response: AccessResponse = self
+            .request("GET", &path)?
+            .call()
+            .map_err(gcp_error)?
+            .into_json()
+            .map_err(|err| Error::Gcp(err.to_string()))?;
+        let bytes = Zeroizing::new(
+            STANDARD
+                .decode(response.payload.data)
+                .map_err(|err| Error::GcpIndex(format!("secret of {id}: {err}")))?,
+        );
+        let text = std::str::from_utf8(&bytes)
+            .map_err(|err| Error::GcpIndex(format!("secret of {id}: {err}")))?;
+        EncryptedSecret::from_str(text).map_err(|err| Error::SecretParse(err.to_string()))
+    }
+
+    /// Creates the secret of `id` unless it's left over, then adds `secret` as its
+    /// latest version.
+    fn set_secret(&mut self, id: &str, secret: &EncryptedSecret) -> Result<(), Error> {
+        let secret_id = self.secret_id(id);
+        let created = self
+            .request("POST", "")?
+            .query("secretId", &secret_id)
+            .send_json(json!({ "replication": { "automatic": {} } }));
+        match created {
+            Ok(_) | Err(ureq::Error::Status(409, _)) => {}
+            Err(err) => return Err(gcp_error(err)),
+        }
+        let data = Zeroizing::new(STANDARD.encode(Zeroizing::new(secret.to_string()).as_bytes()));
+        self.request("POST", &format!("/{secret_id}:addVersion"))?
+            .send_json(json!({ "payload": { "data": data.as_str() } }))
+            .map_err(gcp_error)?;
+        Ok(())
+    }
+
+    fn delete_secret(&mut self, id: &str) -> Result<(), Error> {
+        let path = format!("/{}", self.secret_id(id));
+        match self.request("DELETE", &path)?.call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(()),
+            Err(err) => Err(gcp_error(err)),
+        }
+    }
+
+    fn secret_id(&self, id: &str) -> String {
+        format!("{}-{}", self.prefix, secret_id(id))
+    }
+
+    /// Writes the index with `id` set to `ssi`, and its secret before it.
+    fn put(&mut self, id: String, ssi: Ssi, secret: &EncryptedSecret) -> Result<(), Error> {
+        self.set_secret(&id, secret)?;
+        let mut index = self.index.clone();
+        index.insert(id, ssi);
+        self.save(&index)?;
+        self.index = index;
+        Ok(())
+    }
+}
+
+/// Escapes `id` into the characters allowed in secret ids, using `_` for escapes.
+fn secret_id(id: &str) -> String {
+    let mut secret_id = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' {
+            secret_id.push(byte as char);
+        } else {
+            secret_id.push_str(&format!("_{byte:02X}"));
+        }
+    }
+    secret_id
+}
+
+fn gcp_error(err: ureq::Error) -> Error {
+    match err {
+        ureq::Error::Status(status, response) => Error::Gcp(format!(
+            "status {status}: {}",
+            response.into_string().unwrap_or_default()
+        )),
+        ureq::Error::Transport(transport) => Error::Gcp(transport.to_string()),
+    }
+}
+
+impl SsiStore for SsiGcpStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if self.index.contains_key(&id) {
+            return Err(Error::IdentityExists(id));
+        }
+        let added = id.clone();
+        let result = self.put(id, ssi, &secret);
+        if result.is_err() {
+            // Not indexed, so not in use: don't leave it behind.
+            let _ = self.delete_secret(&added);
+        }
+        result
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let ssi = self
+            .index
+            .get(id)
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?
+            .clone();
+        Ok(Cow::Owned((ssi, self.secret(id)?)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        if !self.index.contains_key(id) {
+            return Ok(false);
+        }
+        let mut index = self.index.clone();
+        index.remove(id);
+        self.save(&index)?;
+        self.index = index;
+        self.delete_secret(id)?;
+        Ok(true)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.index.contains_key(id))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        for identity in self.index.keys() {
+            f(identity)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default().with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiGcpStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.put(id, ssi, &secret)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.index.contains_key(id) {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        self.put(id.to_string(), ssi, &secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    #[test]
+    fn secret_ids_should_escape_identities() {
+        assert_eq!(secret_id("Luna"), "Luna");
+        assert_eq!(secret_id("luna-2"), "luna-2");
+        assert_eq!(secret_id("a_b/c"), "a_5Fb_2Fc");
+        assert_eq!(secret_id("ü"), "_C3_BC");
+    }
+
+    /// Writes to Secret Manager in the project `SSI_MAN_GCP_PROJECT`, authenticated by
+    /// `SSI_MAN_GCP_TOKEN` or the metadata server, and passes without a project.
+    #[test]
+    fn gcp_store_should_keep_secrets_out_of_the_index() {
+        let Some(project) = env::var_os("SSI_MAN_GCP_PROJECT") else {
+            return;
+        };
+        let project = project.to_string_lossy();
+        let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        let index_path = env::temp_dir().join(format!("ssi_man_{nanos}.index"));
+        let prefix = format!("ssi-man-test-{nanos}");
+        let store = || {
+            let store = SsiGcpStore::new(&index_path, &project)
+                .unwrap()
+                .prefix(&prefix);
+            match env::var("SSI_MAN_GCP_TOKEN") {
+                Ok(token) => store.access_token(&token),
+                Err(_) => store,
+            }
+        };
+        let mut ssi_man = SsiMan::with_store(Box::new(store()));
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let index = fs::read_to_string(&index_path).unwrap();
+        assert_eq!(index, format!("Luna\t{ssi}\n"));
+        drop(ssi_man);
+
+        let mut ssi_man = SsiMan::with_store(Box::new(store()));
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        fs::remove_file(index_path).unwrap();
+    }
+}
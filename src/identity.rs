@@ -0,0 +1,298 @@
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug, Display, Formatter},
+    ops::Deref,
+    str::FromStr,
+    sync::Arc,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+#[cfg(feature = "identity-validation")]
+use unicode_normalization::UnicodeNormalization;
+
+use crate::redact::redact;
+
+/// Longest identity in bytes, once normalized.
+pub const MAX_IDENTITY_LEN: usize = 255;
+
+/// Characters that don't render but change how a name reads or compares: zero-width
+/// characters, bidi controls and the byte order mark.
+const INVISIBLE: [char; 14] = [
+    '\u{200B}', '\u{200C}', '\u{200D}', '\u{200E}', '\u{200F}', '\u{202A}', '\u{202B}', '\u{202C}',
+    '\u{202D}', '\u{202E}', '\u{2066}', '\u{2067}', '\u{2068}', '\u{FEFF}',
+];
+
+/// Characters delimiting the email of the `name <mailto:email>` uids built from identities.
+const RESERVED: [char; 2] = ['<', '>'];
+
+/// Why a name is not a valid [`Identity`].
+///
+/// Messages name the rule and the offending character, never the name itself.
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub enum IdentityError {
+    #[error("identity is empty")]
+    Empty,
+    #[error("identity contains the control character {}", .0.escape_unicode())]
+    ControlCharacter(char),
+    #[error("identity contains the invisible character {}", .0.escape_unicode())]
+    InvisibleCharacter(char),
+    #[error("identity contains the reserved character `{0}`")]
+    ReservedCharacter(char),
+    #[error("identity starts or ends with whitespace")]
+    SurroundingWhitespace,
+    #[error("identity is {0} bytes long, more than {MAX_IDENTITY_LEN}")]
+    TooLong(usize),
+}
+
+/// The name of an identity, validated and in Unicode normalization form C.
+///
+/// Every naming rule lives in [`Identity::try_from`], so code holding an `Identity` never
+/// checks names again. Names that look the same but are encoded differently, e.g. `é` as
+/// one code point or as `e` and a combining accent, make the same `Identity`. Cloning is
+/// cheap, and it serializes as the plain name. Without the `identity-validation` feature
+/// names aren't normalized, so differently encoded names make different identities.
+///
+/// Identities are created through it, whether given as an `Identity` or a string; lookups
+/// still take the stored name as `&str`, which an `Identity` derefs to.
+#[derive(Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(into = "String", try_from = "String")
+)]
+pub struct Identity(Arc<str>);
+
+impl Identity {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<&str> for Identity {
+    type Error = IdentityError;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        #[cfg(feature = "identity-validation")]
+        let name = name.nfc().collect::<String>();
+        #[cfg(not(feature = "identity-validation"))]
+        let name = name.to_string();
+        if name.is_empty() {
+            return Err(IdentityError::Empty);
+        }
+        if let Some(c) = name.chars().find(|c| c.is_control()) {
+            return Err(IdentityError::ControlCharacter(c));
+        }
+        if let Some(c) = name.chars().find(|c| INVISIBLE.contains(c)) {
+            return Err(IdentityError::InvisibleCharacter(c));
+        }
+        if let Some(c) = name.chars().find(|c| RESERVED.contains(c)) {
+            return Err(IdentityError::ReservedCharacter(c));
+        }
+        if name.starts_with(char::is_whitespace) || name.ends_with(char::is_whitespace) {
+            return Err(IdentityError::SurroundingWhitespace);
+        }
+        if name.len() > MAX_IDENTITY_LEN {
+            return Err(IdentityError::TooLong(name.len()));
+        }
+        Ok(Self(name.into()))
+    }
+}
+
+impl TryFrom<String> for Identity {
+    type Error = IdentityError;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Self::try_from(name.as_str())
+    }
+}
+
+impl TryFrom<&String> for Identity {
+    type Error = IdentityError;
+
+    fn try_from(name: &String) -> Result<Self, Self::Error> {
+        Self::try_from(name.as_str())
+    }
+}
+
+impl FromStr for Identity {
+    type Err = IdentityError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::try_from(name)
+    }
+}
+
+impl From<Identity> for String {
+    fn from(identity: Identity) -> Self {
+        identity.0.to_string()
+    }
+}
+
+impl Deref for Identity {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Identity {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Identity {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for Identity {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Identity {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Debug for Identity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Identity").field(&redact(&self.0)).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn identity_should_reject_every_rule() {
+        let cases = [
+            ("", IdentityError::Empty),
+            ("Lu\nna", IdentityError::ControlCharacter('\n')),
+            ("Lu\u{7f}na", IdentityError::ControlCharacter('\u{7f}')),
+            (
+                "Lu\u{200B}na",
+                IdentityError::InvisibleCharacter('\u{200B}'),
+            ),
+            (
+                "anuL\u{202E}",
+                IdentityError::InvisibleCharacter('\u{202E}'),
+            ),
+            (
+                "\u{FEFF}Luna",
+                IdentityError::InvisibleCharacter('\u{FEFF}'),
+            ),
+            ("Luna <luna>", IdentityError::ReservedCharacter('<')),
+            ("Luna>", IdentityError::ReservedCharacter('>')),
+            (" Luna", IdentityError::SurroundingWhitespace),
+            ("Luna\u{3000}", IdentityError::SurroundingWhitespace),
+        ];
+        for (name, err) in cases {
+            assert_eq!(Identity::try_from(name), Err(err), "{name:?}");
+        }
+        let long = "é".repeat(128);
+        assert_eq!(
+            Identity::try_from(long.as_str()),
+            Err(IdentityError::TooLong(256))
+        );
+        assert!(Identity::try_from(&long[2..]).is_ok());
+    }
+
+    #[cfg(feature = "identity-validation")]
+    #[test]
+    fn identity_should_be_normalized() {
+        let composed = Identity::try_from("Am\u{e9}lie").unwrap();
+        let decomposed = Identity::try_from("Ame\u{301}lie").unwrap();
+        assert_eq!(composed, decomposed);
+        assert_eq!(decomposed.as_str(), "Am\u{e9}lie");
+        assert_eq!(decomposed.to_string(), "Am\u{e9}lie");
+        assert_eq!(
+            Identity::from_str("Luna Lovegood").unwrap(),
+            "Luna Lovegood"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn identity_should_serialize_as_a_string() {
+        let identity = Identity::try_from("Luna").unwrap();
+        assert_eq!(serde_json::to_string(&identity).unwrap(), "\"Luna\"");
+        assert_eq!(
+            serde_json::from_str::<Identity>("\"Luna\"").unwrap(),
+            identity
+        );
+        assert!(serde_json::from_str::<Identity>("\" Luna\"").is_err());
+    }
+
+    /// Names mixing the characters validation cares about with arbitrary ones.
+    fn adversarial_name() -> impl Strategy<Value = String> {
+        let tricky = prop::sample::select(vec![
+            ' ',
+            '\t',
+            '\n',
+            '\u{0}',
+            '\u{85}',
+            '\u{a0}',
+            '\u{3000}',
+            '<',
+            '>',
+            '\u{200B}',
+            '\u{202E}',
+            '\u{FEFF}',
+            'e',
+            '\u{301}',
+            '\u{e9}',
+            '\u{1100}',
+            '\u{1161}',
+            '\u{1F600}',
+        ]);
+        prop::collection::vec(prop_oneof![tricky, any::<char>()], 0..80)
+            .prop_map(|chars| chars.into_iter().collect())
+    }
+
+    proptest! {
+        #[test]
+        fn valid_identities_should_keep_every_rule(name in adversarial_name()) {
+            if let Ok(identity) = Identity::try_from(name.as_str()) {
+                let normalized = identity.as_str();
+                prop_assert!(!normalized.is_empty());
+                prop_assert!(normalized.len() <= MAX_IDENTITY_LEN);
+                prop_assert!(!normalized.chars().any(|c| c.is_control()
+                    || INVISIBLE.contains(&c)
+                    || RESERVED.contains(&c)));
+                prop_assert!(!normalized.starts_with(char::is_whitespace));
+                prop_assert!(!normalized.ends_with(char::is_whitespace));
+                #[cfg(feature = "identity-validation")]
+                prop_assert_eq!(normalized.nfc().collect::<String>(), normalized);
+                prop_assert_eq!(Identity::try_from(normalized), Ok(identity.clone()));
+            }
+        }
+
+        #[cfg(feature = "identity-validation")]
+        #[test]
+        fn equivalent_names_should_make_the_same_identity(name in adversarial_name()) {
+            let composed = name.nfc().collect::<String>();
+            let decomposed = name.nfd().collect::<String>();
+            prop_assert_eq!(
+                Identity::try_from(composed.as_str()),
+                Identity::try_from(decomposed.as_str())
+            );
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet, VecDeque},
+    future::poll_fn,
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Poll, Waker},
+};
+
+use rexie::{ObjectStore, Rexie, TransactionMode};
+use serde::{Deserialize, Serialize};
+use ssi::{EncryptedSecret, Ssi};
+use wasm_bindgen::JsValue;
+
+use crate::{
+    ConflictPolicy, Error, JournalCapable, SsiStore, SsiStoreExt, StoreCapabilities,
+    StoreCapability, StoredIdentity,
+};
+
+/// Object store of the records, keyed by identity.
+const RECORDS_STORE: &str = "ssi_secrets";
+const DB_VERSION: u32 = 1;
+
+/// An identity as stored, in JSON: the ssi and secret in their text forms.
+#[derive(Clone, Deserialize, Serialize)]
+struct IndexedDbRecord {
+    ssi: String,
+    secret: String,
+}
+
+enum PendingWrite {
+    /// Records written together, in a single IndexedDB transaction.
+    Put(Vec<(String, IndexedDbRecord)>),
+    Delete(String),
+}
+
+/// Writes waiting for the background writer, which runs while `writing` is set.
+#[derive(Default)]
+struct WriteQueue {
+    writes: VecDeque<PendingWrite>,
+    writing: bool,
+    /// Kept once set, as memory and IndexedDB no longer agree.
+    failure: Option<String>,
+    /// Woken once the writer stops, see [`SsiIndexedDbStore::flush`].
+    idle: Vec<Waker>,
+}
+
+/// A store keeping identities in the IndexedDB database of a browser, for wasm32 builds
+/// such as web wallets.
+///
+/// IndexedDB only has asynchronous calls, so records are all read when opening, with
+/// [`SsiIndexedDbStore::open`], and served from memory. Writes apply to memory at once
+/// and are persisted in the background, in order, each import in a single transaction;
+/// [`SsiIndexedDbStore::flush`] waits for them. Once a write fails every call fails with
+/// [`Error::IndexedDb`], as records may have been lost, until the store is opened again
+/// from what IndexedDB holds.
+pub struct SsiIndexedDbStore {
+    db_name: String,
+    records: BTreeMap<String, IndexedDbRecord>,
+    queue: Arc<Mutex<WriteQueue>>,
+}
+
+impl SsiIndexedDbStore {
+    /// Opens the IndexedDB database `db_name`, creating it if missing, and reads its
+    /// records.
+    pub async fn open(db_name: &str) -> Result<Self, Error> {
+        let db = open_db(db_name).await?;
+        let transaction = db
+            .transaction(&[RECORDS_STORE], TransactionMode::ReadOnly)
+            .map_err(indexeddb_error)?;
+        let store = transaction.store(RECORDS_STORE).map_err(indexeddb_error)?;
+        let mut records = BTreeMap::new();
+        for (key, value) in store
+            .scan(None, None, None, None)
+            .await
+            .map_err(indexeddb_error)?
+        {
+            let (Some(identity), Some(json)) = (key.as_string(), value.as_string()) else {
+                return Err(Error::IndexedDb("record is not text".to_string()));
+            };
+            let record: IndexedDbRecord = serde_json::from_str(&json)
+                .map_err(|err| Error::IndexedDb(format!("record of {identity}: {err}")))?;
+            records.insert(identity, record);
+        }
+        transaction.done().await.map_err(indexeddb_error)?;
+        db.close();
+        Ok(Self {
+            db_name: db_name.to_string(),
+            records,
+            queue: Arc::default(),
+        })
+    }
+
+    /// Waits until every write made so far is persisted, failing with
+    /// [`Error::IndexedDb`] if one failed.
+    pub async fn flush(&self) -> Result<(), Error> {
+        poll_fn(|cx| {
+            let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+            if queue.writing {
+                queue.idle.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            Poll::Ready(())
+        })
+        .await;
+        self.check()
+    }
+
+    /// Fails if a background write ever failed.
+    fn check(&self) -> Result<(), Error> {
+        let queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        match &queue.failure {
+            Some(failure) => Err(Error::IndexedDb(failure.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Queues `write`, starting the background writer unless it's running.
+    fn persist(&self, write: PendingWrite) {
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        queue.writes.push_back(write);
+        if !queue.writing {
+            queue.writing = true;
+            wasm_bindgen_futures::spawn_local(write_all(
+                self.db_name.clone(),
+                Arc::clone(&self.queue),
+            ));
+        }
+    }
+
+    fn put(&mut self, id: String, ssi: &Ssi, secret: &EncryptedSecret) -> Result<(), Error> {
+        self.check()?;
+        let record = IndexedDbRecord {
+            ssi: ssi.to_string(),
+            secret: secret.to_string(),
+        };
+        self.records.insert(id.clone(), record.clone());
+        self.persist(PendingWrite::Put(vec![(id, record)]));
+        Ok(())
+    }
+}
+
+async fn open_db(db_name: &str) -> Result<Rexie, Error> {
+    Rexie::builder(db_name)
+        .version(DB_VERSION)
+        .add_object_store(ObjectStore::new(RECORDS_STORE))
+        .build()
+        .await
+        .map_err(indexeddb_error)
+}
+
+/// Drains `queue`, one transaction per write, until it's empty.
+async fn write_all(db_name: String, queue: Arc<Mutex<WriteQueue>>) {
+    let mut db = None;
+    loop {
+        let write = {
+            let mut queue = queue.lock().unwrap_or_else(PoisonError::into_inner);
+            match queue.writes.pop_front() {
+                Some(write) => write,
+                None => {
+                    stop(&mut queue);
+                    break;
+                }
+            }
+        };
+        if db.is_none() {
+            db = match open_db(&db_name).await {
+                Ok(opened) => Some(opened),
+                Err(err) => {
+                    fail(&queue, err);
+                    break;
+                }
+            };
+        }
+        if let Some(db) = &db {
+            if let Err(err) = apply(db, write).await {
+                fail(&queue, err);
+                break;
+            }
+        }
+    }
+    if let Some(db) = db {
+        db.close();
+    }
+}
+
+async fn apply(db: &Rexie, write: PendingWrite) -> Result<(), Error> {
+    let transaction = db
+        .transaction(&[RECORDS_STORE], TransactionMode::ReadWrite)
+        .map_err(indexeddb_error)?;
+    let store = transaction.store(RECORDS_STORE).map_err(indexeddb_error)?;
+    match write {
+        PendingWrite::Put(records) => {
+            for (id, record) in records {
+                let json = serde_json::to_string(&record)
+                    .map_err(|err| Error::IndexedDb(err.to_string()))?;
+                store
+                    .put(&JsValue::from_str(&json), Some(&JsValue::from_str(&id)))
+                    .await
+                    .map_err(indexeddb_error)?;
+            }
+        }
+        PendingWrite::Delete(id) => {
+            store
+                .delete(JsValue::from_str(&id))
+                .await
+                .map_err(indexeddb_error)?;
+        }
+    }
+    transaction.done().await.map_err(indexeddb_error)
+}
+
+/// Records `err` and drops the writes left, which can't be applied in order anymore.
+fn fail(queue: &Mutex<WriteQueue>, err: Error) {
+    let mut queue = queue.lock().unwrap_or_else(PoisonError::into_inner);
+    queue.failure = Some(err.to_string());
+    queue.writes.clear();
+    stop(&mut queue);
+}
+
+/// Marks the writer stopped and wakes those waiting for it.
+fn stop(queue: &mut WriteQueue) {
+    queue.writing = false;
+    queue.idle.drain(..).for_each(Waker::wake);
+}
+
+fn indexeddb_error(err: rexie::Error) -> Error {
+    Error::IndexedDb(err.to_string())
+}
+
+impl SsiStore for SsiIndexedDbStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if self.records.contains_key(&id) {
+            return Err(Error::IdentityExists(id));
+        }
+        self.put(id, &ssi, &secret)
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        self.check()?;
+        let record = self
+            .records
+            .get(id)
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+        Ok(Cow::Owned((
+            Ssi::from_str(&record.ssi)?,
+            EncryptedSecret::from_str(&record.secret)
+                .map_err(|err| Error::SecretParse(err.to_string()))?,
+        )))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        self.check()?;
+        if self.records.remove(id).is_none() {
+            return Ok(false);
+        }
+        self.persist(PendingWrite::Delete(id.to_string()));
+        Ok(true)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        self.check()?;
+        Ok(self.records.contains_key(id))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        self.check()?;
+        for identity in self.records.keys() {
+            f(identity)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default()
+            .with(StoreCapability::AtomicWrites)
+            .with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiIndexedDbStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.put(id, &ssi, &secret)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.records.contains_key(id) {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        self.put(id.to_string(), &ssi, &secret)
+    }
+}
+
+impl JournalCapable for SsiIndexedDbStore {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        self.check()?;
+        let mut seen = HashSet::new();
+        let mut pending = Vec::with_capacity(records.len());
+        for record in records {
+            let exists = !seen.insert(record.identity.clone())
+                || self.records.contains_key(&record.identity);
+            match on_conflict {
+                _ if !exists => {}
+                ConflictPolicy::Skip => continue,
+                ConflictPolicy::Overwrite => {}
+                ConflictPolicy::Error => return Err(Error::IdentityExists(record.identity)),
+            }
+            let indexed = IndexedDbRecord {
+                ssi: record.ssi.to_string(),
+                secret: record.encrypted_secret.to_string(),
+            };
+            pending.push((record.identity, indexed));
+        }
+        let imported = pending.len();
+        for (id, record) in &pending {
+            self.records.insert(id.clone(), record.clone());
+        }
+        if !pending.is_empty() {
+            self.persist(PendingWrite::Put(pending));
+        }
+        Ok(imported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ssi::{Algo, Chain, SsiSecret};
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn record(identity: &str) -> (String, Ssi, EncryptedSecret) {
+        let secret = SsiSecret::new(Algo::Ed25519, Chain::Bitcoin);
+        let uid = format!("{identity} <mailto:{identity}@bitlightlabs.com>");
+        let ssi = Ssi::new(vec![uid.parse().unwrap()], None, &secret);
+        (identity.to_string(), ssi, secret.conceal(""))
+    }
+
+    fn db_name(name: &str) -> String {
+        format!("ssi_man_{name}_{}", js_sys::Date::now())
+    }
+
+    #[wasm_bindgen_test]
+    async fn indexeddb_store_should_reopen_with_its_records() {
+        let db_name = db_name("reopen");
+        let mut store = SsiIndexedDbStore::open(&db_name).await.unwrap();
+        let (identity, ssi, secret) = record("Luna");
+        let ssi_string = ssi.to_string();
+        store.insert(identity, ssi, secret).unwrap();
+        store
+            .insert_many(vec![record("Sol"), record("Terra")])
+            .unwrap();
+        assert!(store.remove("Terra").unwrap());
+        store.flush().await.unwrap();
+
+        let reopened = SsiIndexedDbStore::open(&db_name).await.unwrap();
+        assert_eq!(
+            reopened.all_identities().unwrap(),
+            vec![
+                Cow::Owned("Luna".to_string()),
+                Cow::Owned("Sol".to_string())
+            ]
+        );
+        assert_eq!(reopened.get("Luna").unwrap().0.to_string(), ssi_string);
+    }
+
+    #[wasm_bindgen_test]
+    async fn indexeddb_store_should_keep_failing_after_a_failed_write() {
+        let db_name = db_name("failed_write");
+        let mut store = SsiIndexedDbStore::open(&db_name).await.unwrap();
+        // A newer version of the database makes the writer fail to open it.
+        Rexie::builder(&db_name)
+            .version(DB_VERSION + 1)
+            .add_object_store(ObjectStore::new(RECORDS_STORE))
+            .build()
+            .await
+            .unwrap()
+            .close();
+
+        let (identity, ssi, secret) = record("Luna");
+        store.insert(identity, ssi, secret).unwrap();
+        assert!(matches!(store.flush().await, Err(Error::IndexedDb(_))));
+        assert!(matches!(store.contains("Luna"), Err(Error::IndexedDb(_))));
+        assert!(matches!(store.contains("Luna"), Err(Error::IndexedDb(_))));
+        let (identity, ssi, secret) = record("Sol");
+        assert!(matches!(
+            store.insert(identity, ssi, secret),
+            Err(Error::IndexedDb(_))
+        ));
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Debug, Formatter},
+    str::FromStr,
+};
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+use ssi::{EncryptedSecret, Ssi};
+
+use crate::{
+    creation::CreationRequest,
+    redact::{redact, RedactedList},
+    ConflictPolicy, Error, OpContext, SsiMan, StoredIdentity,
+};
+
+/// `PRAGMA synchronous` level of chunks written between syncs.
+const SYNCHRONOUS_OFF: i32 = 0;
+
+/// One identity to [`SsiMan::ingest`], with its ssi and concealed secret in text form,
+/// parsed as the ingestion reaches it.
+///
+/// The fields are those of [`StoredIdentity`], so the lines of
+/// [`SsiMan::export_all`] deserialize into it.
+#[derive(Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct IngestRecord {
+    pub identity: String,
+    pub ssi: String,
+    pub encrypted_secret: String,
+}
+
+impl Debug for IngestRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IngestRecord")
+            .field("identity", &redact(&self.identity))
+            .field("ssi", &redact(&self.ssi))
+            .finish_non_exhaustive()
+    }
+}
+
+impl IngestRecord {
+    fn parse(self) -> Result<StoredIdentity, Error> {
+        let ssi = Ssi::from_str(&self.ssi)?;
+        let encrypted_secret = EncryptedSecret::from_str(&self.encrypted_secret)
+            .map_err(|err| Error::SecretParse(err.to_string()))?;
+        if encrypted_secret.fp != ssi.pk.fingerprint() {
+            return Err(Error::BackupKeyMismatch(self.identity));
+        }
+        Ok(StoredIdentity {
+            identity: self.identity,
+            ssi,
+            encrypted_secret,
+        })
+    }
+}
+
+/// How [`SsiMan::ingest`] writes its records.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IngestOptions {
+    /// Records written per transaction, 1000 by default.
+    pub chunk_size: usize,
+    /// What to do with identities already stored. Unlike [`SsiMan::import_all`],
+    /// [`ConflictPolicy::Error`] fails the conflicting record only.
+    pub on_conflict: ConflictPolicy,
+    /// Relaxes `PRAGMA synchronous` of sqlite stores for the ingestion, syncing only
+    /// every `n` chunks and once done, or only once done for `Some(0)`. A crash of the
+    /// process loses nothing committed, but losing power may lose the chunks since the
+    /// last sync. `None`, the default, syncs every chunk.
+    pub fsync_every: Option<usize>,
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 1000,
+            on_conflict: ConflictPolicy::Error,
+            fsync_every: None,
+        }
+    }
+}
+
+/// Outcome of [`SsiMan::ingest`], with records numbered by their 0-based position in the
+/// source.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct IngestReport {
+    /// Records skipped at the start of the source, as ingested by an interrupted run.
+    pub resumed_from: usize,
+    /// Chunks committed by this run.
+    pub chunks: usize,
+    /// Records written, new identities or ones replacing a stored identity.
+    pub imported: usize,
+    /// Identities left out as already stored, under [`ConflictPolicy::Skip`].
+    pub skipped: Vec<String>,
+    /// Records that could not be ingested, with the reason.
+    pub failed: Vec<(usize, String)>,
+}
+
+impl Debug for IngestReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IngestReport")
+            .field("resumed_from", &self.resumed_from)
+            .field("chunks", &self.chunks)
+            .field("imported", &self.imported)
+            .field("skipped", &RedactedList(&self.skipped))
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+impl SsiMan {
+    /// Imports a large number of identities in chunks, each written in its own
+    /// transaction.
+    ///
+    /// Records failing to parse or conflicting with a stored identity are reported and
+    /// left out without stopping the ingestion. Stores recording progress, the sqlite
+    /// one, keep the number of records done with every chunk: after an interruption,
+    /// ingesting the same source again skips them and carries on from the last committed
+    /// chunk. Stores without
+    /// [`StoreCapability::AtomicWrites`](crate::StoreCapability::AtomicWrites) fail with
+    /// [`Error::Unsupported`].
+    pub fn ingest(
+        &mut self,
+        source: impl IntoIterator<Item = IngestRecord>,
+        options: IngestOptions,
+    ) -> Result<IngestReport, Error> {
+        self.ingest_with_progress(source, options, |_| {})
+    }
+
+    /// Same as [`SsiMan::ingest`], calling `on_chunk` with the report so far after every
+    /// committed chunk.
+    pub fn ingest_with_progress(
+        &mut self,
+        source: impl IntoIterator<Item = IngestRecord>,
+        options: IngestOptions,
+        on_chunk: impl FnMut(&IngestReport),
+    ) -> Result<IngestReport, Error> {
+        self.ingest_with_ctx(source, options, &OpContext::default(), on_chunk)
+    }
+
+    /// Same as [`SsiMan::ingest_with_progress`], stopping before the next chunk once `ctx`
+    /// is cancelled or past its deadline, with the number of records this run went
+    /// through. Committed chunks stay, so ingesting the source again resumes after them
+    /// like after a crash.
+    pub fn ingest_with_ctx(
+        &mut self,
+        source: impl IntoIterator<Item = IngestRecord>,
+        options: IngestOptions,
+        ctx: &OpContext,
+        mut on_chunk: impl FnMut(&IngestReport),
+    ) -> Result<IngestReport, Error> {
+        let mut report = IngestReport {
+            resumed_from: self.journal()?.ingest_progress()?.unwrap_or(0),
+            ..Default::default()
+        };
+        let relaxed_from = match options.fsync_every {
+            Some(_) => self.set_synchronous(SYNCHRONOUS_OFF)?,
+            None => None,
+        };
+        let result = self.ingest_chunks(
+            source,
+            options,
+            ctx,
+            relaxed_from,
+            &mut report,
+            &mut on_chunk,
+        );
+        let restored = match relaxed_from {
+            Some(level) => self.set_synchronous(level).map(|_| ()),
+            None => Ok(()),
+        };
+        result?;
+        restored?;
+        self.journal()?.finish_ingest()?;
+        Ok(report)
+    }
+
+    fn ingest_chunks(
+        &mut self,
+        source: impl IntoIterator<Item = IngestRecord>,
+        options: IngestOptions,
+        ctx: &OpContext,
+        relaxed_from: Option<i32>,
+        report: &mut IngestReport,
+        on_chunk: &mut impl FnMut(&IngestReport),
+    ) -> Result<(), Error> {
+        let mut source = source.into_iter().skip(report.resumed_from);
+        let mut position = report.resumed_from;
+        loop {
+            let chunk = source
+                .by_ref()
+                .take(options.chunk_size.max(1))
+                .collect::<Vec<_>>();
+            if chunk.is_empty() {
+                return Ok(());
+            }
+            ctx.check(position - report.resumed_from)?;
+            let mut records = Vec::with_capacity(chunk.len());
+            let mut seen = HashSet::new();
+            for record in chunk {
+                match record.parse().and_then(|record| {
+                    self.check_creation(&CreationRequest::from_ssi(
+                        record.identity.clone(),
+                        &record.ssi,
+                    ))
+                    .map(|_| record)
+                }) {
+                    Ok(record) => {
+                        let exists = !seen.insert(record.identity.clone())
+                            || self.store.contains(&record.identity)?;
+                        match options.on_conflict {
+                            _ if !exists => records.push(record),
+                            ConflictPolicy::Skip => report.skipped.push(record.identity),
+                            ConflictPolicy::Overwrite => records.push(record),
+                            ConflictPolicy::Error => report.failed.push((
+                                position,
+                                Error::IdentityExists(record.identity).to_string(),
+                            )),
+                        }
+                    }
+                    Err(err) => report.failed.push((position, err.to_string())),
+                }
+                position += 1;
+            }
+
+            let imported = records.len();
+            let sync = relaxed_from.filter(|_| {
+                options
+                    .fsync_every
+                    .is_some_and(|every| every > 0 && (report.chunks + 1) % every == 0)
+            });
+            if let Some(level) = sync {
+                self.set_synchronous(level)?;
+            }
+            self.journal()?.ingest_chunk(records, position)?;
+            if sync.is_some() {
+                self.set_synchronous(SYNCHRONOUS_OFF)?;
+            }
+            report.chunks += 1;
+            report.imported += imported;
+            on_chunk(report);
+        }
+    }
+
+    /// Sets `PRAGMA synchronous` of sqlite stores to `level`, returning the previous one,
+    /// or `None` for other stores.
+    fn set_synchronous(&mut self, level: i32) -> Result<Option<i32>, Error> {
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = self.sqlite_store() {
+            return store.set_synchronous(level);
+        }
+        let _ = level;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records of `count` new identities, in text form.
+    fn ingest_records(count: usize) -> Vec<IngestRecord> {
+        let mut source = SsiMan::with_memory();
+        (0..count)
+            .map(|n| {
+                let identity = format!("id{n:03}");
+                let ssi = source
+                    .new_ssi(&identity, &format!("{identity}@bitlightlabs.com"), None)
+                    .unwrap();
+                let encrypted_secret = source.store.get(&identity).unwrap().1.to_string();
+                IngestRecord {
+                    identity,
+                    ssi,
+                    encrypted_secret,
+                }
+            })
+            .collect()
+    }
+
+    fn ingest_should_ok(mut ssi_man: SsiMan) {
+        let mut records = ingest_records(5);
+        ssi_man
+            .new_ssi("id001", "other@bitlightlabs.com", None)
+            .unwrap();
+        let stored = ssi_man.get_ssi("id001").unwrap();
+        records[3].encrypted_secret = "not a secret".to_string();
+        records.push(records[0].clone());
+
+        let mut progress = vec![];
+        let report = ssi_man
+            .ingest_with_progress(
+                records.clone(),
+                IngestOptions {
+                    chunk_size: 2,
+                    ..Default::default()
+                },
+                |report| progress.push((report.chunks, report.imported)),
+            )
+            .unwrap();
+        assert_eq!(report.resumed_from, 0);
+        assert_eq!(report.chunks, 3);
+        assert_eq!(report.imported, 3);
+        assert_eq!(progress, [(1, 1), (2, 2), (3, 3)]);
+        assert_eq!(report.skipped, Vec::<String>::new());
+        let failed = report
+            .failed
+            .iter()
+            .map(|(position, _)| *position)
+            .collect::<Vec<_>>();
+        assert_eq!(failed, [1, 3, 5]);
+        assert_eq!(ssi_man.get_ssi("id001"), Ok(stored));
+        assert_eq!(ssi_man.get_ssi("id004"), Ok(records[4].ssi.clone()));
+        assert!(ssi_man.get_ssi("id003").is_err());
+
+        let report = ssi_man
+            .ingest(
+                records.clone(),
+                IngestOptions {
+                    on_conflict: ConflictPolicy::Skip,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(report.imported, 0);
+        assert_eq!(
+            report.skipped,
+            ["id000", "id001", "id002", "id004", "id000"]
+        );
+
+        let report = ssi_man
+            .ingest(
+                records.clone(),
+                IngestOptions {
+                    on_conflict: ConflictPolicy::Overwrite,
+                    fsync_every: Some(1),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(report.imported, 5);
+        assert_eq!(ssi_man.get_ssi("id001"), Ok(records[1].ssi.clone()));
+    }
+
+    #[test]
+    fn ingest_should_report_each_record() {
+        ingest_should_ok(SsiMan::with_memory());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_ingest_should_report_each_record() {
+        ingest_should_ok(SsiMan::with_sqlite(":memory:").unwrap());
+    }
+}
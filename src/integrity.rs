@@ -0,0 +1,109 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+};
+
+use crate::{
+    redact::{redact, RedactedList},
+    Error, SsiMan,
+};
+
+/// Auxiliary data of a store out of step with its identities, see
+/// [`SsiMan::check_referential_integrity`]. Both lists are sorted.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct IntegrityFindings {
+    /// Names with auxiliary data, such as metadata, but no identity.
+    pub orphaned: Vec<String>,
+    /// Identities without the auxiliary data the store keeps for every identity.
+    pub missing: Vec<String>,
+}
+
+impl IntegrityFindings {
+    pub fn is_clean(&self) -> bool {
+        self.orphaned.is_empty() && self.missing.is_empty()
+    }
+}
+
+impl Debug for IntegrityFindings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntegrityFindings")
+            .field("orphaned", &RedactedList(&self.orphaned))
+            .field("missing", &RedactedList(&self.missing))
+            .finish()
+    }
+}
+
+/// What [`SsiMan::repair_referential_integrity`] does with orphaned data.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum RepairPolicy {
+    /// Deletes it.
+    #[default]
+    Delete,
+    /// Moves the data of each orphan to the identity it maps to, in place of that
+    /// identity's own; orphans left out of the map, or mapped to an unknown identity, are
+    /// deleted.
+    Reparent(HashMap<String, String>),
+}
+
+impl RepairPolicy {
+    /// Returns the identity to move the data of `orphan` to, if any.
+    pub(crate) fn parent(&self, orphan: &str) -> Option<&str> {
+        match self {
+            RepairPolicy::Delete => None,
+            RepairPolicy::Reparent(parents) => parents.get(orphan).map(String::as_str),
+        }
+    }
+}
+
+/// Changes made by [`SsiMan::repair_referential_integrity`], each list sorted.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct IntegrityRepair {
+    /// Orphans whose data was deleted.
+    pub deleted: Vec<String>,
+    /// Orphans whose data moved, with the identity it moved to.
+    pub reparented: Vec<(String, String)>,
+    /// Identities given default data in place of the missing one.
+    pub restored: Vec<String>,
+}
+
+impl Debug for IntegrityRepair {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let reparented = self
+            .reparented
+            .iter()
+            .map(|(orphan, parent)| (redact(orphan), redact(parent)))
+            .collect::<Vec<_>>();
+        f.debug_struct("IntegrityRepair")
+            .field("deleted", &RedactedList(&self.deleted))
+            .field("reparented", &reparented)
+            .field("restored", &RedactedList(&self.restored))
+            .finish()
+    }
+}
+
+impl SsiMan {
+    /// Looks for auxiliary data left behind by removed identities, and identities missing
+    /// theirs, e.g. after a bug or an interrupted operation.
+    ///
+    /// Stores keeping everything about an identity in its record, the sqlite one, never
+    /// find any.
+    pub fn check_referential_integrity(&mut self) -> Result<IntegrityFindings, Error> {
+        match self.store.as_ext() {
+            Some(ext) => ext.check_integrity(),
+            None => Ok(IntegrityFindings::default()),
+        }
+    }
+
+    /// Repairs what [`SsiMan::check_referential_integrity`] finds, handling orphans as
+    /// `policy` says and giving identities missing data the defaults of a new identity,
+    /// all at once.
+    pub fn repair_referential_integrity(
+        &mut self,
+        policy: RepairPolicy,
+    ) -> Result<IntegrityRepair, Error> {
+        match self.store.as_ext_mut() {
+            Some(ext) => ext.repair_integrity(&policy),
+            None => Ok(IntegrityRepair::default()),
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use security_framework::{
+    access_control::{ProtectionMode, SecAccessControl},
+    passwords::{
+        delete_generic_password, get_generic_password, set_generic_password,
+        set_generic_password_options, PasswordOptions,
+    },
+};
+use ssi::{EncryptedSecret, Ssi};
+use zeroize::Zeroizing;
+
+use crate::{Error, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability};
+
+/// Keychain service of the items of [`SsiKeychainStore::new`].
+const DEFAULT_SERVICE: &str = "ssi-man";
+/// `errSecItemNotFound`, for items already gone.
+const ITEM_NOT_FOUND: i32 = -25300;
+
+/// When the secrets of a [`SsiKeychainStore`] can be read, the `kSecAttrAccessible`
+/// values of the Keychain.
+///
+/// The `ThisDeviceOnly` ones keep items out of backups and off other devices. Named in
+/// kebab case for the C API, e.g. `when-unlocked-this-device-only`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum KeychainAccessibility {
+    WhenUnlocked,
+    #[default]
+    WhenUnlockedThisDeviceOnly,
+    AfterFirstUnlock,
+    AfterFirstUnlockThisDeviceOnly,
+    /// Only while the device has a passcode; items go away if it's removed.
+    WhenPasscodeSetThisDeviceOnly,
+}
+
+impl KeychainAccessibility {
+    fn protection_mode(self) -> ProtectionMode {
+        match self {
+            Self::WhenUnlocked => ProtectionMode::AccessibleWhenUnlocked,
+            Self::WhenUnlockedThisDeviceOnly => {
+                ProtectionMode::AccessibleWhenUnlockedThisDeviceOnly
+            }
+            Self::AfterFirstUnlock => ProtectionMode::AccessibleAfterFirstUnlock,
+            Self::AfterFirstUnlockThisDeviceOnly => {
+                ProtectionMode::AccessibleAfterFirstUnlockThisDeviceOnly
+            }
+            Self::WhenPasscodeSetThisDeviceOnly => {
+                ProtectionMode::AccessibleWhenPasscodeSetThisDeviceOnly
+            }
+        }
+    }
+}
+
+impl FromStr for KeychainAccessibility {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "when-unlocked" => Ok(Self::WhenUnlocked),
+            "when-unlocked-this-device-only" => Ok(Self::WhenUnlockedThisDeviceOnly),
+            "after-first-unlock" => Ok(Self::AfterFirstUnlock),
+            "after-first-unlock-this-device-only" => Ok(Self::AfterFirstUnlockThisDeviceOnly),
+            "when-passcode-set-this-device-only" => Ok(Self::WhenPasscodeSetThisDeviceOnly),
+            _ => Err(Error::UnknownKeychainAccessibility(name.to_string())),
+        }
+    }
+}
+
+/// A store keeping concealed secrets in the Keychain of macOS or iOS, and only the public
+/// ssis in a local index file.
+///
+/// Each secret is a generic password item of the store's service, named after its
+/// identity, so it's also protected by the Keychain access control, e.g. readable only
+/// while the device is unlocked with [`SsiKeychainStore::accessibility`], and the index
+/// holds `identity<TAB>ssi` lines. For keys bound to the Secure Enclave, create
+/// identities with [`crate::SsiMan::new_ssi_platform`] and a wrapper using it. Secrets are read from the Keychain on every use, which may
+/// ask the user to allow it. Secrets are written before the index and removed after it,
+/// so an interrupted write leaves at worst an unused item behind.
+pub struct SsiKeychainStore {
+    index_path: PathBuf,
+    service: String,
+    accessibility: Option<KeychainAccessibility>,
+    index: BTreeMap<String, Ssi>,
+}
+
+impl SsiKeychainStore {
+    /// Opens the index file at `index_path`, creating it if missing, with secrets in the
+    /// `ssi-man` service.
+    pub fn new(index_path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::with_service(index_path, DEFAULT_SERVICE)
+    }
+
+    /// Opens the index file at `index_path` with secrets in the Keychain service
+    /// `service`, e.g. to keep the identities of several apps apart.
+    pub fn with_service(index_path: impl AsRef<Path>, service: &str) -> Result<Self, Error> {
+        let index_path = index_path.as_ref().to_path_buf();
+        let text = match fs::read_to_string(&index_path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut index = BTreeMap::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (identity, ssi) = line
+                .split_once('\t')
+                .ok_or_else(|| Error::KeychainIndex("line without an ssi".to_string()))?;
+            index.insert(identity.to_string(), Ssi::from_str(ssi)?);
+        }
+        let store = Self {
+            index_path,
+            service: service.to_string(),
+            accessibility: None,
+            index,
+        };
+        store.save(&store.index)?;
+        Ok(store)
+    }
+
+    /// Sets when the secrets written from now on can be read; the Keychain default, like
+    /// [`KeychainAccessibility::WhenUnlocked`], otherwise.
+    pub fn accessibility(mut self, accessibility: KeychainAccessibility) -> Self {
+        self.accessibility = Some(accessibility);
+        self
+    }
+
+    /// Writes `index` through a temporary file renamed over the index file.
+    fn save(&self, index: &BTreeMap<String, Ssi>) -> Result<(), Error> {
+        let mut temporary = self.index_path.clone().into_os_string();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        for (identity, ssi) in index {
+            writeln!(file, "{identity}\t{ssi}")?;
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, &self.index_path)?;
+        Ok(())
+    }
+
+    fn secret(&self, id: &str) -> Result<EncryptedSecret, Error> {
+        let bytes = Zeroizing::new(get_generic_password(&self.service, id)?);
+        let text = std::str::from_utf8(&bytes)
+            .map_err(|err| Error::KeychainIndex(format!("secret of {id}: {err}")))?;
+        EncryptedSecret::from_str(text).map_err(|err| Error::SecretParse(err.to_string()))
+    }
+
+    fn set_secret(&self, id: &str, secret: &EncryptedSecret) -> Result<(), Error> {
+        let secret = Zeroizing::new(secret.to_string());
+        let Some(accessibility) = self.accessibility else {
+            set_generic_password(&self.service, id, secret.as_bytes())?;
+            return Ok(());
+        };
+        let mut options = PasswordOptions::new_generic_password(&self.service, id);
+        options.set_access_control(SecAccessControl::create_with_protection(
+            Some(accessibility.protection_mode()),
+            0,
+        )?);
+        set_generic_password_options(secret.as_bytes(), options)?;
+        Ok(())
+    }
+
+    fn delete_secret(&self, id: &str) -> Result<(), Error> {
+        match delete_generic_password(&self.service, id) {
+            Err(err) if err.code() != ITEM_NOT_FOUND => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the index with `id` set to `ssi`, and its secret before it.
+    fn put(&mut self, id: String, ssi: Ssi, secret: &EncryptedSecret) -> Result<(), Error> {
+        self.set_secret(&id, secret)?;
+        let mut index = self.index.clone();
+        index.insert(id, ssi);
+        self.save(&index)?;
+        self.index = index;
+        Ok(())
+    }
+}
+
+impl SsiStore for SsiKeychainStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if self.index.contains_key(&id) {
+            return Err(Error::IdentityExists(id));
+        }
+        let added = id.clone();
+        let result = self.put(id, ssi, &secret);
+        if result.is_err() {
+            // Not indexed, so not in use: don't leave it behind.
+            let _ = self.delete_secret(&added);
+        }
+        result
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let ssi = self
+            .index
+            .get(id)
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?
+            .clone();
+        Ok(Cow::Owned((ssi, self.secret(id)?)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        if !self.index.contains_key(id) {
+            return Ok(false);
+        }
+        let mut index = self.index.clone();
+        index.remove(id);
+        self.save(&index)?;
+        self.index = index;
+        self.delete_secret(id)?;
+        Ok(true)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.index.contains_key(id))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        for identity in self.index.keys() {
+            f(identity)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default().with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiKeychainStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.put(id, ssi, &secret)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.index.contains_key(id) {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        self.put(id.to_string(), ssi, &secret)
+    }
+}
+
+/// These tests write to the login keychain, so they only run with
+/// `SSI_MAN_KEYCHAIN_TESTS` set, and pass otherwise.
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    #[test]
+    fn keychain_store_should_keep_secrets_out_of_the_index() {
+        if env::var_os("SSI_MAN_KEYCHAIN_TESTS").is_none() {
+            return;
+        }
+        let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        let index_path = env::temp_dir().join(format!("ssi_man_{nanos}.index"));
+        let service = format!("ssi-man-test-{nanos}");
+        let store = SsiKeychainStore::with_service(&index_path, &service).unwrap();
+        let mut ssi_man = SsiMan::with_store(Box::new(store));
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let index = fs::read_to_string(&index_path).unwrap();
+        assert_eq!(index, format!("Luna\t{ssi}\n"));
+        drop(ssi_man);
+
+        let store = SsiKeychainStore::with_service(&index_path, &service).unwrap();
+        let mut ssi_man = SsiMan::with_store(Box::new(store));
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        assert!(get_generic_password(&service, "Luna").is_err());
+        fs::remove_file(index_path).unwrap();
+    }
+
+    #[test]
+    fn keychain_accessibility_should_parse_its_names() {
+        assert_eq!(
+            "when-unlocked-this-device-only".parse(),
+            Ok(KeychainAccessibility::default())
+        );
+        assert_eq!(
+            "After-First-Unlock".parse(),
+            Ok(KeychainAccessibility::AfterFirstUnlock)
+        );
+        assert_eq!(
+            "always".parse::<KeychainAccessibility>(),
+            Err(Error::UnknownKeychainAccessibility("always".to_string()))
+        );
+    }
+}
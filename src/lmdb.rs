@@ -0,0 +1,488 @@
+use std::{borrow::Cow, fs, path::Path, str::FromStr, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use heed::{
+    types::{Bytes, Str},
+    Database, Env, EnvOpenOptions, RoTxn, RwTxn,
+};
+use serde::{Deserialize, Serialize};
+use ssi::{EncryptedSecret, Ssi};
+
+use crate::{
+    clock::system_clock, fingerprint, Clock, ConflictPolicy, Error, IdentityFingerprint,
+    IdentityMetadata, JournalCapable, Page, SsiStore, SsiStoreExt, StoreCapabilities,
+    StoreCapability, StoredIdentity, FORMAT_VERSION,
+};
+
+/// Database of the records, keyed by identity, so keys iterate in byte-wise order.
+const RECORDS_DB: &str = "ssi_secrets";
+const SETTINGS_DB: &str = "settings";
+const FORMAT_VERSION_KEY: &str = "format_version";
+/// Settings key of the number of source records done by an unfinished ingestion.
+const INGEST_PROGRESS_KEY: &str = "ingest_progress";
+/// Largest size the environment of [`SsiLmdbStore::new`] can grow to, far above what
+/// millions of identities take; only the pages in use take disk space.
+const DEFAULT_MAP_SIZE: usize = 1 << 30;
+
+/// An identity as stored, in JSON: the ssi and secret in their text forms, with the
+/// metadata the sqlite store keeps in its columns.
+#[derive(Deserialize, Serialize)]
+struct LmdbRecord {
+    ssi: String,
+    secret: String,
+    created_at: DateTime<Utc>,
+    /// `None` until the ssi or secret is first changed, as in records written before it
+    /// was kept.
+    updated_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    sign_count: u64,
+    needs_rewrap: bool,
+    fingerprint: String,
+    wrapped_key: Option<Vec<u8>>,
+}
+
+impl LmdbRecord {
+    fn new(ssi: &Ssi, secret: &EncryptedSecret, created_at: DateTime<Utc>) -> Self {
+        Self {
+            ssi: ssi.to_string(),
+            secret: secret.to_string(),
+            created_at,
+            updated_at: None,
+            last_used_at: None,
+            sign_count: 0,
+            needs_rewrap: false,
+            fingerprint: fingerprint(ssi),
+            wrapped_key: None,
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(bytes).map_err(|err| Error::LmdbRecord(err.to_string()))
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(self).map_err(|err| Error::LmdbRecord(err.to_string()))
+    }
+
+    fn metadata(&self) -> IdentityMetadata {
+        IdentityMetadata {
+            created_at: self.created_at,
+            updated_at: self.updated_at.unwrap_or(self.created_at),
+            last_used_at: self.last_used_at,
+            sign_count: self.sign_count,
+            needs_rewrap: self.needs_rewrap,
+        }
+    }
+}
+
+/// A store in an [LMDB](http://www.lmdb.tech) environment, memory-mapped for many readers
+/// and one writer.
+///
+/// Reads never block nor wait for the writer: each sees the last committed state, from
+/// any number of threads, through [`SsiLmdbStore::share`], or processes, each opening
+/// the environment. Writes are serialized transactions, committed durably before
+/// returning. Records are kept in key order, so listings and pages come out byte-wise
+/// sorted like with the other stores, with the same metadata.
+pub struct SsiLmdbStore {
+    env: Env,
+    records: Database<Str, Bytes>,
+    settings: Database<Str, Str>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SsiLmdbStore {
+    /// Opens the environment in the directory at `path`, creating it if missing, with a
+    /// map of 1 GiB.
+    ///
+    /// An environment can only be opened once per process; use
+    /// [`SsiLmdbStore::share`] for more stores on it.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        fs::create_dir_all(&path)?;
+        // SAFETY: LMDB requires the environment not to be opened twice by one process,
+        // which heed refuses itself, nor its files to be changed by other means.
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(DEFAULT_MAP_SIZE)
+                .max_dbs(2)
+                .open(path)?
+        };
+        Self::with_env(env)
+    }
+
+    /// Uses an open environment, e.g. one with a larger map, which needs room for two
+    /// named databases.
+    pub fn with_env(env: Env) -> Result<Self, Error> {
+        let mut tx = env.write_txn()?;
+        let records = env.create_database(&mut tx, Some(RECORDS_DB))?;
+        let settings: Database<Str, Str> = env.create_database(&mut tx, Some(SETTINGS_DB))?;
+        // A fresh environment starts at the current format; lmdb ones never had another.
+        if settings.get(&tx, FORMAT_VERSION_KEY)?.is_none() {
+            settings.put(&mut tx, FORMAT_VERSION_KEY, &FORMAT_VERSION.to_string())?;
+        }
+        tx.commit()?;
+        let store = Self {
+            env,
+            records,
+            settings,
+            clock: system_clock(),
+        };
+        let found = store.format_version()?;
+        if found > FORMAT_VERSION {
+            return Err(Error::FormatTooNew {
+                found,
+                supported: FORMAT_VERSION,
+            });
+        }
+        Ok(store)
+    }
+
+    /// Returns another store on the same environment, e.g. for a reader thread.
+    pub fn share(&self) -> Self {
+        Self {
+            env: self.env.clone(),
+            records: self.records,
+            settings: self.settings,
+            clock: self.clock.clone(),
+        }
+    }
+
+    /// Runs `f` in a read transaction, seeing the last committed state throughout.
+    fn read<T>(&self, f: impl FnOnce(&RoTxn) -> Result<T, Error>) -> Result<T, Error> {
+        f(&self.env.read_txn()?)
+    }
+
+    /// Runs `f` in a write transaction, committed if it succeeds and aborted otherwise.
+    fn write<T>(&self, f: impl FnOnce(&mut RwTxn) -> Result<T, Error>) -> Result<T, Error> {
+        let mut tx = self.env.write_txn()?;
+        let value = f(&mut tx)?;
+        tx.commit()?;
+        Ok(value)
+    }
+
+    fn setting(&self, key: &str) -> Result<Option<String>, Error> {
+        self.read(|tx| Ok(self.settings.get(tx, key)?.map(str::to_string)))
+    }
+
+    fn record(&self, id: &str) -> Result<LmdbRecord, Error> {
+        self.read(|tx| {
+            let bytes = self
+                .records
+                .get(tx, id)?
+                .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+            LmdbRecord::decode(bytes)
+        })
+    }
+
+    /// Applies `change` to the record of `id` in one transaction.
+    fn modify(&self, id: &str, change: impl FnOnce(&mut LmdbRecord)) -> Result<(), Error> {
+        self.write(|tx| {
+            let mut record = match self.records.get(tx, id)? {
+                Some(bytes) => LmdbRecord::decode(bytes)?,
+                None => return Err(Error::UnknownIdentity(id.to_string())),
+            };
+            change(&mut record);
+            self.records.put(tx, id, &record.encode()?)?;
+            Ok(())
+        })
+    }
+
+    /// Calls `f` with every record in identity order.
+    fn for_each_record(
+        &self,
+        mut f: impl FnMut(String, LmdbRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.read(|tx| {
+            for entry in self.records.iter(tx)? {
+                let (key, value) = entry?;
+                f(key.to_string(), LmdbRecord::decode(value)?)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Encodes `records` as created now, keyed by identity.
+    fn encode_all(&self, records: &[StoredIdentity]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let created_at = self.clock.now();
+        records
+            .iter()
+            .map(|record| {
+                let value = LmdbRecord::new(&record.ssi, &record.encrypted_secret, created_at);
+                Ok((record.identity.clone(), value.encode()?))
+            })
+            .collect()
+    }
+}
+
+impl SsiStore for SsiLmdbStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let record = LmdbRecord::new(&ssi, &secret, self.clock.now()).encode()?;
+        self.write(|tx| {
+            if self.records.get(tx, &id)?.is_some() {
+                return Err(Error::IdentityExists(id.clone()));
+            }
+            self.records.put(tx, &id, &record)?;
+            Ok(())
+        })
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let record = self.record(id)?;
+        let secret = EncryptedSecret::from_str(&record.secret)
+            .map_err(|err| Error::SecretParse(err.to_string()))?;
+        Ok(Cow::Owned((Ssi::from_str(&record.ssi)?, secret)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        self.write(|tx| Ok(self.records.delete(tx, id)?))
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        self.read(|tx| Ok(self.records.get(tx, id)?.is_some()))
+    }
+
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error> {
+        let offset = Page::offset(page, per_page)?;
+        self.read(|tx| {
+            let identities = self
+                .records
+                .iter(tx)?
+                .skip(offset)
+                .take(per_page)
+                .map(|entry| Ok(entry?.0.to_string()))
+                .collect::<Result<_, Error>>()?;
+            let total = self.records.len(tx)? as usize;
+            Ok(Page::new(identities, total, per_page))
+        })
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        self.read(|tx| {
+            for entry in self.records.iter(tx)? {
+                f(entry?.0)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error> {
+        let mut fingerprints = Vec::new();
+        self.for_each_record(|identity, record| {
+            fingerprints.push(IdentityFingerprint {
+                identity,
+                fingerprint: record.fingerprint,
+            });
+            Ok(())
+        })?;
+        Ok(fingerprints)
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default()
+            .with(StoreCapability::AtomicWrites)
+            .with(StoreCapability::Metadata)
+            .with(StoreCapability::PlatformProtection)
+            .with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiLmdbStore {
+    fn format_version(&self) -> Result<u32, Error> {
+        self.setting(FORMAT_VERSION_KEY)?
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| Error::LmdbRecord("invalid format version".to_string()))
+    }
+
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let record = LmdbRecord::new(&ssi, &secret, self.clock.now()).encode()?;
+        self.write(|tx| Ok(self.records.put(tx, &id, &record)?))
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let now = self.clock.now();
+        self.modify(id, |record| {
+            record.ssi = ssi.to_string();
+            record.fingerprint = fingerprint(&ssi);
+            record.secret = secret.to_string();
+            record.updated_at = Some(now);
+        })
+    }
+
+    fn metadata(&self, id: &str) -> Result<IdentityMetadata, Error> {
+        Ok(self.record(id)?.metadata())
+    }
+
+    fn record_signatures(&mut self, id: &str, count: u64, at: DateTime<Utc>) -> Result<(), Error> {
+        self.modify(id, |record| {
+            record.last_used_at = Some(at);
+            record.sign_count += count;
+        })
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        let mut identities = Vec::new();
+        self.for_each_record(|identity, record| {
+            if record.metadata().last_activity() < cutoff {
+                identities.push(identity);
+            }
+            Ok(())
+        })?;
+        Ok(identities)
+    }
+
+    fn set_needs_rewrap(&mut self, id: &str, needs_rewrap: bool) -> Result<(), Error> {
+        self.modify(id, |record| record.needs_rewrap = needs_rewrap)
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        let mut identities = Vec::new();
+        self.for_each_record(|identity, record| {
+            if record.needs_rewrap {
+                identities.push(identity);
+            }
+            Ok(())
+        })?;
+        Ok(identities)
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn wrapped_key(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.read(|tx| match self.records.get(tx, id)? {
+            Some(bytes) => Ok(LmdbRecord::decode(bytes)?.wrapped_key),
+            None => Ok(None),
+        })
+    }
+
+    fn set_wrapped_key(&mut self, id: &str, wrapped_key: Vec<u8>) -> Result<(), Error> {
+        self.modify(id, |record| record.wrapped_key = Some(wrapped_key))
+    }
+}
+
+impl JournalCapable for SsiLmdbStore {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        let encoded = self.encode_all(&records)?;
+        self.write(|tx| {
+            let mut imported = 0;
+            for (id, value) in &encoded {
+                if self.records.get(tx, id)?.is_some() {
+                    match on_conflict {
+                        ConflictPolicy::Skip => continue,
+                        ConflictPolicy::Overwrite => {}
+                        ConflictPolicy::Error => return Err(Error::IdentityExists(id.clone())),
+                    }
+                }
+                self.records.put(tx, id, value)?;
+                imported += 1;
+            }
+            Ok(imported)
+        })
+    }
+
+    fn ingest_chunk(&mut self, records: Vec<StoredIdentity>, ingested: usize) -> Result<(), Error> {
+        let encoded = self.encode_all(&records)?;
+        self.write(|tx| {
+            for (id, value) in &encoded {
+                self.records.put(tx, id, value)?;
+            }
+            self.settings
+                .put(tx, INGEST_PROGRESS_KEY, &ingested.to_string())?;
+            Ok(())
+        })
+    }
+
+    fn ingest_progress(&mut self) -> Result<Option<usize>, Error> {
+        self.setting(INGEST_PROGRESS_KEY)?
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| Error::LmdbRecord("invalid ingestion progress".to_string()))
+            })
+            .transpose()
+    }
+
+    fn finish_ingest(&mut self) -> Result<(), Error> {
+        self.write(|tx| {
+            self.settings.delete(tx, INGEST_PROGRESS_KEY)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, thread};
+
+    use super::*;
+    use crate::{store_tests, SsiMan};
+
+    fn temp_env_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ssi_man_{name}_{}_lmdb",
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ))
+    }
+
+    #[test]
+    fn lmdb_store_should_reject_duplicates_atomically() {
+        let path = temp_env_dir("duplicates");
+        store_tests::should_reject_duplicates_atomically(&mut SsiLmdbStore::new(&path).unwrap());
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn lmdb_readers_should_see_the_writer_commits() {
+        let path = temp_env_dir("readers");
+        let store = SsiLmdbStore::new(&path).unwrap();
+        let readers = (0..4)
+            .map(|_| SsiMan::with_store(Box::new(store.share())))
+            .collect::<Vec<_>>();
+        let mut writer = SsiMan::with_store(Box::new(store));
+        let ssi = writer
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+
+        let handles = readers
+            .into_iter()
+            .map(|mut reader| {
+                let ssi = ssi.clone();
+                thread::spawn(move || {
+                    assert_eq!(reader.get_ssi("Luna"), Ok(ssi));
+                    let ssi_cert = reader.sign("Luna", "have a good day!", None).unwrap();
+                    crate::ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(writer.identity_info("Luna").unwrap().sign_count, 4);
+        drop(writer);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn lmdb_ingest_should_record_progress() {
+        let path = temp_env_dir("ingest");
+        store_tests::should_record_ingest_progress(SsiMan::with_lmdb(&path).unwrap());
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -1,48 +1,495 @@
-use std::{borrow::Cow, collections::HashMap};
+use std::{borrow::Cow, collections::BTreeMap, sync::Arc};
+#[cfg(feature = "serde")]
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    str::FromStr,
+};
 
-use ssi::{EncryptedSecret, Ssi};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use ssi::{EncryptedSecret, Ssi, SsiPub};
 
-use crate::{Error, SsiStore};
-#[derive(Default)]
+#[cfg(feature = "serde")]
+use crate::FORMAT_VERSION;
+use crate::{
+    clock::system_clock, matches_query, snapshot::import_records, Clock, ConflictPolicy, Error,
+    IdentityMetadata, IntegrityFindings, IntegrityRepair, JournalCapable, RepairPolicy, SsiStore,
+    SsiStoreExt, StoreCapabilities, StoreCapability, StoredIdentity,
+};
+/// A record shared with readers; writes swap in a new one, so readers holding the old one
+/// keep it unchanged.
+type SharedRecord = Arc<(Ssi, EncryptedSecret)>;
+
+/// Store keeping identities in memory, ordered by identity.
 pub struct SsiMemoryStore {
-    records: HashMap<String, (Ssi, EncryptedSecret)>,
+    records: BTreeMap<String, SharedRecord>,
+    metadata: BTreeMap<String, IdentityMetadata>,
+    wrapped_keys: BTreeMap<String, Vec<u8>>,
+    /// The identities written by each open transaction, innermost last, as they were
+    /// before it.
+    journal: Vec<BTreeMap<String, JournalEntry>>,
+    clock: Arc<dyn Clock>,
+}
+
+/// An identity as it was before the first write of a transaction, `None` where it had
+/// nothing.
+struct JournalEntry {
+    record: Option<SharedRecord>,
+    metadata: Option<IdentityMetadata>,
+    wrapped_key: Option<Vec<u8>>,
+}
+
+impl Default for SsiMemoryStore {
+    fn default() -> Self {
+        Self {
+            records: BTreeMap::new(),
+            metadata: BTreeMap::new(),
+            wrapped_keys: BTreeMap::new(),
+            journal: Vec::new(),
+            clock: system_clock(),
+        }
+    }
+}
+
+impl SsiMemoryStore {
+    /// Records `identity` as it is now in the innermost transaction, unless it already
+    /// wrote it; does nothing outside transactions.
+    fn journal(&mut self, identity: &str) {
+        let Some(entries) = self.journal.last_mut() else {
+            return;
+        };
+        if !entries.contains_key(identity) {
+            let entry = JournalEntry {
+                record: self.records.get(identity).cloned(),
+                metadata: self.metadata.get(identity).copied(),
+                wrapped_key: self.wrapped_keys.get(identity).cloned(),
+            };
+            entries.insert(identity.to_string(), entry);
+        }
+    }
+}
+
+/// Sets or removes the value of `key` in `map`.
+fn restore<V>(map: &mut BTreeMap<String, V>, key: String, value: Option<V>) {
+    match value {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
+
+/// An identity of a snapshot: the ssi and secret in their text forms, with the metadata
+/// and the wrapped key of platform-protected identities.
+#[cfg(feature = "serde")]
+#[derive(Deserialize, Serialize)]
+struct SnapshotRecord {
+    ssi: String,
+    secret: String,
+    created_at: DateTime<Utc>,
+    /// Missing from snapshots saved before it was kept.
+    updated_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    sign_count: u64,
+    needs_rewrap: bool,
+    wrapped_key: Option<Vec<u8>>,
+}
+
+/// Everything in a snapshot file, in JSON.
+#[cfg(feature = "serde")]
+#[derive(Deserialize, Serialize)]
+struct MemorySnapshot {
+    format_version: u32,
+    records: BTreeMap<String, SnapshotRecord>,
+}
+
+#[cfg(feature = "serde")]
+impl SsiMemoryStore {
+    /// Writes every identity, with its metadata, to a JSON snapshot file at `path`, for
+    /// [`SsiMemoryStore::load_snapshot`].
+    ///
+    /// Secrets stay concealed with their own passwords. The file is written through a
+    /// temporary one renamed over it, so it's always either the old snapshot or the new.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        write_snapshot(path.as_ref(), &self.snapshot_json())
+    }
+
+    /// Restores a store from the snapshot file at `path`.
+    pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_snapshot_json(&fs::read(path)?)
+    }
+
+    /// Same as [`SsiMemoryStore::save_snapshot`], with the file encrypted with
+    /// `passphrase` as [`SsiEncryptedFileStore`](crate::SsiEncryptedFileStore) files are,
+    /// hiding the identities and public keys too.
+    #[cfg(feature = "encrypted-file")]
+    pub fn save_snapshot_encrypted(
+        &self,
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<(), Error> {
+        let json = zeroize::Zeroizing::new(self.snapshot_json());
+        let sealed = crate::encrypted_file::seal_with_passphrase(passphrase, &json)?;
+        write_snapshot(path.as_ref(), &sealed)
+    }
+
+    /// Restores a store from a snapshot file saved with
+    /// [`SsiMemoryStore::save_snapshot_encrypted`], failing with
+    /// [`Error::EncryptedFilePassphrase`] for a wrong passphrase.
+    #[cfg(feature = "encrypted-file")]
+    pub fn load_snapshot_encrypted(
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<Self, Error> {
+        let sealed = fs::read(path)?;
+        Self::from_snapshot_json(&crate::encrypted_file::unseal_with_passphrase(
+            passphrase, &sealed,
+        )?)
+    }
+
+    fn snapshot_json(&self) -> Vec<u8> {
+        let records = self
+            .records
+            .iter()
+            .map(|(identity, record)| {
+                let metadata = self
+                    .metadata
+                    .get(identity)
+                    .copied()
+                    .unwrap_or_else(|| IdentityMetadata::new(self.clock.now()));
+                let record = SnapshotRecord {
+                    ssi: record.0.to_string(),
+                    secret: record.1.to_string(),
+                    created_at: metadata.created_at,
+                    updated_at: Some(metadata.updated_at),
+                    last_used_at: metadata.last_used_at,
+                    sign_count: metadata.sign_count,
+                    needs_rewrap: metadata.needs_rewrap,
+                    wrapped_key: self.wrapped_keys.get(identity).cloned(),
+                };
+                (identity.clone(), record)
+            })
+            .collect();
+        let snapshot = MemorySnapshot {
+            format_version: FORMAT_VERSION,
+            records,
+        };
+        serde_json::to_vec(&snapshot).expect("snapshot is serializable")
+    }
+
+    fn from_snapshot_json(json: &[u8]) -> Result<Self, Error> {
+        let snapshot: MemorySnapshot =
+            serde_json::from_slice(json).map_err(|err| Error::SnapshotParse {
+                line: err.line(),
+                reason: err.to_string(),
+            })?;
+        if snapshot.format_version > FORMAT_VERSION {
+            return Err(Error::FormatTooNew {
+                found: snapshot.format_version,
+                supported: FORMAT_VERSION,
+            });
+        }
+        let mut store = Self::default();
+        for (identity, record) in snapshot.records {
+            let ssi = Ssi::from_str(&record.ssi)?;
+            let secret = EncryptedSecret::from_str(&record.secret)
+                .map_err(|err| Error::SecretParse(err.to_string()))?;
+            store.metadata.insert(
+                identity.clone(),
+                IdentityMetadata {
+                    created_at: record.created_at,
+                    updated_at: record.updated_at.unwrap_or(record.created_at),
+                    last_used_at: record.last_used_at,
+                    sign_count: record.sign_count,
+                    needs_rewrap: record.needs_rewrap,
+                },
+            );
+            if let Some(wrapped_key) = record.wrapped_key {
+                store.wrapped_keys.insert(identity.clone(), wrapped_key);
+            }
+            store.records.insert(identity, Arc::new((ssi, secret)));
+        }
+        Ok(store)
+    }
+}
+
+/// Writes `bytes` to `path` through a temporary file renamed over it once synced.
+#[cfg(feature = "serde")]
+fn write_snapshot(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    let mut temporary = path.to_path_buf().into_os_string();
+    temporary.push(".tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(())
 }
 
 impl SsiStore for SsiMemoryStore {
     fn insert(&mut self, identity: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
-        self.records.insert(identity, (ssi, secret));
-        Ok(())
+        if self.records.contains_key(&identity) {
+            return Err(Error::IdentityExists(identity));
+        }
+        self.replace(identity, ssi, secret)
     }
 
-    fn get(&mut self, identity: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+    fn get(&self, identity: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
         self.records
             .get(identity)
             .ok_or(Error::UnknownIdentity(identity.to_string()))
-            .map(Cow::Borrowed)
+            .map(|record| Cow::Borrowed(&**record))
+    }
+
+    fn get_shared(&self, identity: &str) -> Result<SharedRecord, Error> {
+        self.records
+            .get(identity)
+            .cloned()
+            .ok_or(Error::UnknownIdentity(identity.to_string()))
     }
 
     fn remove(&mut self, identity: &str) -> Result<bool, Error> {
+        self.journal(identity);
+        self.metadata.remove(identity);
+        self.wrapped_keys.remove(identity);
         Ok(self.records.remove(identity).is_some())
     }
 
-    fn paginated_identities(
+    fn contains(&self, identity: &str) -> Result<bool, Error> {
+        Ok(self.records.contains_key(identity))
+    }
+
+    fn find_by_pubkey(&self, pk: &SsiPub) -> Result<Vec<String>, Error> {
+        Ok(self
+            .records
+            .iter()
+            .filter(|(_, record)| record.0.pk == *pk)
+            .map(|(identity, _)| identity.clone())
+            .collect())
+    }
+
+    fn find_identities(&self, query: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .records
+            .iter()
+            .filter(|(identity, record)| matches_query(identity, &record.0, query))
+            .map(|(identity, _)| identity.clone())
+            .collect())
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        self.records.keys().try_for_each(|identity| f(identity))
+    }
+
+    fn active_identities(&self, now: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        Ok(self
+            .records
+            .iter()
+            .filter(|(_, record)| !record.0.expiry.is_some_and(|expiry| expiry <= now))
+            .map(|(identity, _)| identity.clone())
+            .collect())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default()
+            .with(StoreCapability::AtomicWrites)
+            .with(StoreCapability::Metadata)
+            .with(StoreCapability::PlatformProtection)
+            .with(StoreCapability::Replace)
+            .with(StoreCapability::Transactions)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiMemoryStore {
+    fn replace(
         &mut self,
-        page: usize,
-        per_page: usize,
-    ) -> Result<(Vec<Cow<'_, String>>, usize), Error> {
-        Ok((
-            self.records
+        identity: String,
+        ssi: Ssi,
+        secret: EncryptedSecret,
+    ) -> Result<(), Error> {
+        self.journal(&identity);
+        self.metadata
+            .insert(identity.clone(), IdentityMetadata::new(self.clock.now()));
+        self.wrapped_keys.remove(&identity);
+        self.records.insert(identity, Arc::new((ssi, secret)));
+        Ok(())
+    }
+
+    fn update(&mut self, identity: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.journal(identity);
+        let record = self
+            .records
+            .get_mut(identity)
+            .ok_or(Error::UnknownIdentity(identity.to_string()))?;
+        *record = Arc::new((ssi, secret));
+        if let Some(metadata) = self.metadata.get_mut(identity) {
+            metadata.updated_at = self.clock.now();
+        }
+        Ok(())
+    }
+
+    fn metadata(&self, identity: &str) -> Result<IdentityMetadata, Error> {
+        self.metadata
+            .get(identity)
+            .copied()
+            .ok_or(Error::UnknownIdentity(identity.to_string()))
+    }
+
+    fn record_signatures(
+        &mut self,
+        identity: &str,
+        count: u64,
+        at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.journal(identity);
+        let metadata = self
+            .metadata
+            .get_mut(identity)
+            .ok_or(Error::UnknownIdentity(identity.to_string()))?;
+        metadata.last_used_at = Some(at);
+        metadata.sign_count += count;
+        Ok(())
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        Ok(self
+            .metadata
+            .iter()
+            .filter(|(_, metadata)| metadata.last_activity() < cutoff)
+            .map(|(identity, _)| identity.clone())
+            .collect())
+    }
+
+    fn set_needs_rewrap(&mut self, identity: &str, needs_rewrap: bool) -> Result<(), Error> {
+        self.journal(identity);
+        self.metadata
+            .get_mut(identity)
+            .ok_or(Error::UnknownIdentity(identity.to_string()))?
+            .needs_rewrap = needs_rewrap;
+        Ok(())
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .metadata
+            .iter()
+            .filter(|(_, metadata)| metadata.needs_rewrap)
+            .map(|(identity, _)| identity.clone())
+            .collect())
+    }
+
+    fn check_integrity(&self) -> Result<IntegrityFindings, Error> {
+        Ok(IntegrityFindings {
+            orphaned: self
+                .metadata
                 .keys()
-                .skip((page - 1) * per_page)
-                .take(per_page)
-                .map(Cow::Borrowed)
+                .filter(|identity| !self.records.contains_key(*identity))
+                .cloned()
                 .collect(),
-            self.records.len(),
-        ))
+            missing: self
+                .records
+                .keys()
+                .filter(|identity| !self.metadata.contains_key(*identity))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    fn repair_integrity(&mut self, policy: &RepairPolicy) -> Result<IntegrityRepair, Error> {
+        let mut repair = IntegrityRepair::default();
+        for orphan in self.check_integrity()?.orphaned {
+            self.journal(&orphan);
+            let metadata = self
+                .metadata
+                .remove(&orphan)
+                .expect("orphans have metadata");
+            match policy.parent(&orphan) {
+                Some(parent) if self.records.contains_key(parent) => {
+                    self.journal(parent);
+                    self.metadata.insert(parent.to_string(), metadata);
+                    repair.reparented.push((orphan, parent.to_string()));
+                }
+                _ => repair.deleted.push(orphan),
+            }
+        }
+        let now = self.clock.now();
+        for identity in self.check_integrity()?.missing {
+            self.journal(&identity);
+            self.metadata
+                .insert(identity.clone(), IdentityMetadata::new(now));
+            repair.restored.push(identity);
+        }
+        Ok(repair)
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn wrapped_key(&self, identity: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.wrapped_keys.get(identity).cloned())
+    }
+
+    fn set_wrapped_key(&mut self, identity: &str, wrapped_key: Vec<u8>) -> Result<(), Error> {
+        if !self.records.contains_key(identity) {
+            return Err(Error::UnknownIdentity(identity.to_string()));
+        }
+        self.journal(identity);
+        self.wrapped_keys.insert(identity.to_string(), wrapped_key);
+        Ok(())
+    }
+}
+
+impl JournalCapable for SsiMemoryStore {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        self.begin_transaction()?;
+        let imported = import_records(self, records, on_conflict);
+        match imported {
+            Ok(_) => self.commit_transaction()?,
+            Err(_) => self.rollback_transaction()?,
+        }
+        imported
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), Error> {
+        self.journal.push(BTreeMap::new());
+        Ok(())
     }
 
-    fn all_identities(&mut self) -> Result<Vec<Cow<'_, String>>, Error> {
-        Ok(self.records.keys().map(Cow::Borrowed).collect())
+    fn commit_transaction(&mut self) -> Result<(), Error> {
+        let entries = self.journal.pop().unwrap_or_default();
+        // The enclosing transaction undoes these writes too, back to its own start.
+        if let Some(outer) = self.journal.last_mut() {
+            for (identity, entry) in entries {
+                outer.entry(identity).or_insert(entry);
+            }
+        }
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Error> {
+        for (identity, entry) in self.journal.pop().unwrap_or_default() {
+            restore(&mut self.records, identity.clone(), entry.record);
+            restore(&mut self.metadata, identity.clone(), entry.metadata);
+            restore(&mut self.wrapped_keys, identity, entry.wrapped_key);
+        }
+        Ok(())
     }
 }
 
@@ -75,3 +522,148 @@ impl SsiStore for SsiMemoryStore {
 //         assert_eq!(ssi_man.all_identities(), Ok(vec![]));
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use crate::{ManualClock, SsiMan};
+
+    use super::*;
+
+    /// A store holding `Luna` and `Sol`, with the metadata of `Luna` orphaned under
+    /// `Luna-old` and that of `Sol` missing, as a bug could leave it.
+    fn damaged_store(clock: &ManualClock) -> SsiMemoryStore {
+        let mut ssi_man = SsiMan::with_memory();
+        let mut store = SsiMemoryStore::default();
+        store.set_clock(Arc::new(clock.clone()));
+        for identity in ["Luna", "Sol"] {
+            ssi_man
+                .new_ssi(identity, &format!("{identity}@bitlightlabs.com"), None)
+                .unwrap();
+            let (ssi, secret) = ssi_man.store.get(identity).unwrap().into_owned();
+            store.insert(identity.to_string(), ssi, secret).unwrap();
+        }
+        store.record_signatures("Luna", 3, clock.now()).unwrap();
+        let luna = store.metadata.remove("Luna").unwrap();
+        store.metadata.insert("Luna-old".to_string(), luna);
+        store.metadata.remove("Sol");
+        store
+    }
+
+    #[test]
+    fn integrity_check_should_find_orphans_and_missing_metadata() {
+        let clock = ManualClock::new(DateTime::<Utc>::UNIX_EPOCH);
+        let store = damaged_store(&clock);
+        let findings = store.check_integrity().unwrap();
+        assert_eq!(findings.orphaned, ["Luna-old"]);
+        assert_eq!(findings.missing, ["Luna", "Sol"]);
+        assert!(!findings.is_clean());
+        assert_eq!(
+            store.stale_identities(clock.now() + Duration::days(1)),
+            Ok(vec!["Luna-old".to_string()])
+        );
+    }
+
+    #[test]
+    fn integrity_repair_should_delete_orphans() {
+        let clock = ManualClock::new(DateTime::<Utc>::UNIX_EPOCH);
+        let mut store = damaged_store(&clock);
+        clock.advance(Duration::hours(1));
+        let repair = store.repair_integrity(&RepairPolicy::Delete).unwrap();
+        assert_eq!(repair.deleted, ["Luna-old"]);
+        assert!(repair.reparented.is_empty());
+        assert_eq!(repair.restored, ["Luna", "Sol"]);
+        assert!(store.check_integrity().unwrap().is_clean());
+        assert_eq!(store.metadata("Luna").unwrap().sign_count, 0);
+        assert_eq!(store.metadata("Sol").unwrap().created_at, clock.now());
+    }
+
+    #[test]
+    fn integrity_repair_should_reparent_orphans() {
+        let clock = ManualClock::new(DateTime::<Utc>::UNIX_EPOCH);
+        let reparent = |parent: &str| {
+            RepairPolicy::Reparent([("Luna-old".to_string(), parent.to_string())].into())
+        };
+
+        let mut store = damaged_store(&clock);
+        let repair = store.repair_integrity(&reparent("Luna")).unwrap();
+        assert!(repair.deleted.is_empty());
+        assert_eq!(
+            repair.reparented,
+            [("Luna-old".to_string(), "Luna".to_string())]
+        );
+        assert_eq!(repair.restored, ["Sol"]);
+        assert!(store.check_integrity().unwrap().is_clean());
+        assert_eq!(store.metadata("Luna").unwrap().sign_count, 3);
+
+        let mut store = damaged_store(&clock);
+        let repair = store.repair_integrity(&reparent("nobody")).unwrap();
+        assert_eq!(repair.deleted, ["Luna-old"]);
+        assert_eq!(repair.restored, ["Luna", "Sol"]);
+    }
+
+    #[cfg(feature = "serde")]
+    fn temp_snapshot_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "ssi_man_{name}_{}.json",
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ))
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_should_restore_identities_and_metadata() {
+        let path = temp_snapshot_path("snapshot");
+        let mut ssi_man = SsiMan::with_memory();
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        let store = ssi_man.memory_store().unwrap();
+        store.set_wrapped_key("Luna", vec![1, 2, 3]).unwrap();
+        store.save_snapshot(&path).unwrap();
+        let metadata = store.metadata("Luna").unwrap();
+        let (ssi, secret) = store.get("Luna").unwrap().into_owned();
+
+        let loaded = SsiMemoryStore::load_snapshot(&path).unwrap();
+        assert_eq!(loaded.get("Luna").unwrap().into_owned(), (ssi, secret));
+        assert_eq!(loaded.metadata("Luna"), Ok(metadata));
+        assert_eq!(loaded.wrapped_key("Luna"), Ok(Some(vec![1, 2, 3])));
+        assert!(matches!(
+            SsiMemoryStore::load_snapshot(path.with_extension("missing")),
+            Err(Error::Io(_))
+        ));
+        fs::write(&path, "{\"format_version\": 1,\n\"records\": 3}").unwrap();
+        assert!(matches!(
+            SsiMemoryStore::load_snapshot(&path),
+            Err(Error::SnapshotParse { line: 2, .. })
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "encrypted-file")]
+    #[test]
+    fn encrypted_snapshot_should_need_its_passphrase() {
+        let path = temp_snapshot_path("encrypted_snapshot");
+        let mut ssi_man = SsiMan::with_memory();
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let store = ssi_man.memory_store().unwrap();
+        store
+            .save_snapshot_encrypted(&path, "it's the sun")
+            .unwrap();
+        assert!(!fs::read(&path)
+            .unwrap()
+            .windows(4)
+            .any(|window| window == b"Luna"));
+        assert!(matches!(
+            SsiMemoryStore::load_snapshot_encrypted(&path, "it's the moon"),
+            Err(Error::EncryptedFilePassphrase)
+        ));
+        let loaded = SsiMemoryStore::load_snapshot_encrypted(&path, "it's the sun").unwrap();
+        assert!(loaded.contains("Luna").unwrap());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    borrow::Cow,
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+#[cfg(feature = "stable-output")]
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::Error;
+
+#[cfg(feature = "stable-output")]
+const STABLE_V1_PREFIX: &str = "ssi-man/v1:";
+
+/// How string-producing APIs render their values.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// The value as displayed by the ssi crate, which may change between its versions.
+    #[default]
+    Native,
+    /// The native value framed as `ssi-man/v1:<kind>:<base64 payload>`; the framing never
+    /// changes within a major version of this crate.
+    #[cfg(feature = "stable-output")]
+    StableV1,
+}
+
+/// What a rendered value holds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputKind {
+    Ssi,
+    Cert,
+    Backup,
+}
+
+impl Display for OutputKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OutputKind::Ssi => "ssi",
+            OutputKind::Cert => "cert",
+            OutputKind::Backup => "backup",
+        })
+    }
+}
+
+impl FromStr for OutputKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ssi" => Ok(OutputKind::Ssi),
+            "cert" => Ok(OutputKind::Cert),
+            "backup" => Ok(OutputKind::Backup),
+            _ => Err(Error::StableFormatParse(format!("unknown kind `{s}`"))),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Renders a native value in this format.
+    pub fn format(self, kind: OutputKind, native: String) -> String {
+        match self {
+            OutputFormat::Native => native,
+            #[cfg(feature = "stable-output")]
+            OutputFormat::StableV1 => {
+                format!("{STABLE_V1_PREFIX}{kind}:{}", STANDARD.encode(native))
+            }
+        }
+    }
+}
+
+/// Splits a `StableV1` value into its kind and native value.
+#[cfg(feature = "stable-output")]
+pub fn parse_stable(s: &str) -> Result<(OutputKind, String), Error> {
+    let (kind, payload) = s
+        .trim()
+        .strip_prefix(STABLE_V1_PREFIX)
+        .and_then(|rest| rest.split_once(':'))
+        .ok_or_else(|| Error::StableFormatParse("missing `ssi-man/v1` framing".to_string()))?;
+    let kind = OutputKind::from_str(kind)?;
+    let native = STANDARD
+        .decode(payload)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(|| Error::StableFormatParse("invalid payload".to_string()))?;
+    Ok((kind, native))
+}
+
+/// Accepts a value in either format, returning the native value of the expected kind.
+#[cfg(feature = "stable-output")]
+pub(crate) fn to_native(s: &str, expected: OutputKind) -> Result<Cow<'_, str>, Error> {
+    if !s.trim_start().starts_with(STABLE_V1_PREFIX) {
+        return Ok(Cow::Borrowed(s));
+    }
+    let (kind, native) = parse_stable(s)?;
+    if kind != expected {
+        return Err(Error::StableFormatParse(format!(
+            "expected a {expected}, got a {kind}"
+        )));
+    }
+    Ok(Cow::Owned(native))
+}
+
+/// Without the `stable-output` feature every value is taken as native.
+#[cfg(not(feature = "stable-output"))]
+pub(crate) fn to_native(s: &str, _expected: OutputKind) -> Result<Cow<'_, str>, Error> {
+    Ok(Cow::Borrowed(s))
+}
+
+#[cfg(all(test, feature = "stable-output"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_v1_framing_should_not_change() {
+        assert_eq!(
+            OutputFormat::StableV1.format(OutputKind::Ssi, "ssi:luna".to_string()),
+            "ssi-man/v1:ssi:c3NpOmx1bmE="
+        );
+        assert_eq!(
+            OutputFormat::StableV1.format(OutputKind::Cert, "-----BEGIN".to_string()),
+            "ssi-man/v1:cert:LS0tLS1CRUdJTg=="
+        );
+        assert_eq!(
+            OutputFormat::StableV1.format(OutputKind::Backup, "ssi-man backup v1\n".to_string()),
+            "ssi-man/v1:backup:c3NpLW1hbiBiYWNrdXAgdjEK"
+        );
+        assert_eq!(
+            parse_stable("ssi-man/v1:ssi:c3NpOmx1bmE="),
+            Ok((OutputKind::Ssi, "ssi:luna".to_string()))
+        );
+        assert!(parse_stable("ssi-man/v2:ssi:c3NpOmx1bmE=").is_err());
+        assert!(parse_stable("ssi-man/v1:key:c3NpOmx1bmE=").is_err());
+        assert!(parse_stable("ssi-man/v1:ssi:not base64").is_err());
+    }
+
+    #[test]
+    fn to_native_should_accept_both_formats() {
+        assert_eq!(
+            to_native("ssi:luna", OutputKind::Ssi),
+            Ok(Cow::Borrowed("ssi:luna"))
+        );
+        assert_eq!(
+            to_native("ssi-man/v1:ssi:c3NpOmx1bmE=", OutputKind::Ssi),
+            Ok(Cow::Owned("ssi:luna".to_string()))
+        );
+        assert!(to_native("ssi-man/v1:ssi:c3NpOmx1bmE=", OutputKind::Cert).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+#[cfg(feature = "platform")]
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(feature = "platform")]
+use ssi::{Algo, Chain};
+use zeroize::Zeroizing;
+
+#[cfg(feature = "platform")]
+use crate::{creation::CreationRequest, Identity, StoreCapability};
+use crate::{Error, SsiMan};
+
+/// Bytes of the random key concealing the secret of a platform-protected identity.
+#[cfg(feature = "platform")]
+const PLATFORM_KEY_LEN: usize = 32;
+
+/// How the secret of an identity is protected, see [`SsiMan::protection`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Protection {
+    /// Concealed with a password, possibly the empty one.
+    #[default]
+    Password,
+    /// Concealed with a random key that only the [`SecretWrapper`] can unwrap, e.g. after
+    /// FaceID or a fingerprint; created by [`SsiMan::new_ssi_platform`].
+    Platform,
+}
+
+/// Wraps keys with a key held by the platform, e.g. the Android Keystore or the iOS
+/// Secure Enclave, which the host reaches on our behalf.
+///
+/// Errors are the reason given by the platform, e.g. the user cancelling the unlock.
+#[cfg(feature = "platform")]
+pub trait SecretWrapper: Send + Sync {
+    fn wrap(&self, key: &[u8]) -> Result<Vec<u8>, String>;
+    fn unwrap(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>, String>;
+}
+
+impl SsiMan {
+    /// Sets the wrapper protecting [`Protection::Platform`] identities, which can neither
+    /// be created nor used without one, or removes it.
+    #[cfg(feature = "platform")]
+    pub fn set_secret_wrapper(&mut self, wrapper: Option<Box<dyn SecretWrapper>>) {
+        self.secret_wrapper = wrapper;
+    }
+
+    /// Creates a new identity with [`Protection::Platform`] and returns its ssi.
+    ///
+    /// Its secret is concealed with a random key, which is stored wrapped by the
+    /// [`SecretWrapper`] and unwrapped whenever the identity is used with no password.
+    /// Giving a password, changing it or exporting the identity fail with
+    /// [`Error::PlatformProtected`], and [`SsiMan::export_all`] fails with
+    /// [`Error::DumpPlatformProtected`] while it is stored: the wrapped key can't leave the
+    /// device anyway.
+    #[cfg(feature = "platform")]
+    pub fn new_ssi_platform(
+        &mut self,
+        identity: impl ToString,
+        email: impl AsRef<str>,
+    ) -> Result<String, Error> {
+        self.require(StoreCapability::PlatformProtection)?;
+        let wrapper = self.secret_wrapper.as_ref().ok_or(Error::NoSecretWrapper)?;
+        let mut key = Zeroizing::new([0; PLATFORM_KEY_LEN]);
+        getrandom::getrandom(&mut *key).map_err(|err| Error::SecretWrapper(err.to_string()))?;
+        let wrapped = wrapper.wrap(&*key).map_err(Error::SecretWrapper)?;
+        let passwd = Zeroizing::new(STANDARD.encode(&*key));
+
+        let identity = Identity::try_from(identity.to_string())?;
+        let request = CreationRequest {
+            identity: identity.to_string(),
+            emails: vec![email.as_ref().to_string()],
+            algo: Algo::Ed25519,
+            chain: Chain::Bitcoin,
+        };
+        let ssi = self.create_ssi(request, Some(&passwd), None, false)?;
+        let stored = self
+            .ext_mut(StoreCapability::PlatformProtection)
+            .and_then(|ext| ext.set_wrapped_key(&identity, wrapped));
+        if let Err(err) = stored {
+            // Without its wrapped key the identity could never be used.
+            let _ = self.store.remove(&identity);
+            return Err(err);
+        }
+        Ok(ssi)
+    }
+
+    /// Returns how the secret of an identity is protected.
+    pub fn protection(&mut self, identity: &str) -> Result<Protection, Error> {
+        if !self.store.contains(identity)? {
+            return Err(Error::UnknownIdentity(identity.to_string()));
+        }
+        Ok(match self.wrapped_key(identity)? {
+            Some(_) => Protection::Platform,
+            None => Protection::Password,
+        })
+    }
+
+    /// Fails with [`Error::PlatformProtected`] for [`Protection::Platform`] identities,
+    /// ahead of password-based operations.
+    pub(crate) fn refuse_platform(&mut self, identity: &str) -> Result<(), Error> {
+        match self.wrapped_key(identity)? {
+            Some(_) => Err(Error::PlatformProtected(identity.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the password concealing the secret of a platform-protected identity, from
+    /// its wrapped key.
+    #[cfg(feature = "platform")]
+    pub(crate) fn platform_password(&self, wrapped: &[u8]) -> Result<Zeroizing<String>, Error> {
+        let wrapper = self.secret_wrapper.as_ref().ok_or(Error::NoSecretWrapper)?;
+        let key = wrapper.unwrap(wrapped).map_err(Error::SecretWrapper)?;
+        Ok(Zeroizing::new(STANDARD.encode(&*key)))
+    }
+
+    /// Without the `platform` feature no wrapper can be set, so platform-protected
+    /// identities, e.g. from a store shared with another build, can't be unlocked.
+    #[cfg(not(feature = "platform"))]
+    pub(crate) fn platform_password(&self, _wrapped: &[u8]) -> Result<Zeroizing<String>, Error> {
+        Err(Error::NoSecretWrapper)
+    }
+}
+
+#[cfg(all(test, feature = "platform"))]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::ssi_cert_verify_text;
+
+    /// Software stand-in for a platform keystore, XOR-ing keys with a pad and counting
+    /// unwraps; `deny` makes it refuse like a cancelled unlock.
+    #[derive(Clone, Default)]
+    struct SoftwareWrapper {
+        unwraps: Arc<AtomicUsize>,
+        deny: bool,
+    }
+
+    const PAD: u8 = 0x5c;
+
+    impl SecretWrapper for SoftwareWrapper {
+        fn wrap(&self, key: &[u8]) -> Result<Vec<u8>, String> {
+            Ok(key.iter().map(|byte| byte ^ PAD).collect())
+        }
+
+        fn unwrap(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+            if self.deny {
+                return Err("unlock cancelled".to_string());
+            }
+            self.unwraps.fetch_add(1, Ordering::Relaxed);
+            Ok(Zeroizing::new(
+                wrapped.iter().map(|byte| byte ^ PAD).collect(),
+            ))
+        }
+    }
+
+    fn platform_protection_should_ok(mut ssi_man: SsiMan) {
+        let message = "have a good day!";
+        assert_eq!(
+            ssi_man.new_ssi_platform("Luna", "luna@bitlightlabs.com"),
+            Err(Error::NoSecretWrapper)
+        );
+        assert!(ssi_man.get_ssi("Luna").is_err());
+
+        let wrapper = SoftwareWrapper::default();
+        ssi_man.set_secret_wrapper(Some(Box::new(wrapper.clone())));
+        ssi_man
+            .new_ssi_platform("Luna", "luna@bitlightlabs.com")
+            .unwrap();
+        ssi_man
+            .new_ssi("Sol", "sol@bitlightlabs.com", Some("sun"))
+            .unwrap();
+        assert_eq!(ssi_man.protection("Luna"), Ok(Protection::Platform));
+        assert_eq!(ssi_man.protection("Sol"), Ok(Protection::Password));
+
+        let ssi_cert = ssi_man.sign("Luna", message, None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, message).unwrap();
+        assert_eq!(wrapper.unwraps.load(Ordering::Relaxed), 1);
+        ssi_man
+            .add_uid("Luna", "Luna <mailto:luna@example.com>", None)
+            .unwrap();
+        ssi_man.sign("Luna", message, None).unwrap();
+
+        let refused = Err(Error::PlatformProtected("Luna".to_string()));
+        assert_eq!(ssi_man.sign("Luna", message, Some("")).map(|_| ()), refused);
+        assert_eq!(ssi_man.change_password("Luna", None, Some("moon")), refused);
+        assert_eq!(ssi_man.export("Luna", None).map(|_| ()), refused);
+        #[cfg(feature = "serde")]
+        {
+            assert_eq!(
+                ssi_man.export_all(),
+                Err(Error::DumpPlatformProtected(vec!["Luna".to_string()]))
+            );
+        }
+
+        ssi_man.set_secret_wrapper(Some(Box::new(SoftwareWrapper {
+            deny: true,
+            ..Default::default()
+        })));
+        assert_eq!(
+            ssi_man.sign("Luna", message, None).map(|_| ()),
+            Err(Error::SecretWrapper("unlock cancelled".to_string()))
+        );
+        ssi_man.set_secret_wrapper(None);
+        assert_eq!(
+            ssi_man.sign("Luna", message, None).map(|_| ()),
+            Err(Error::NoSecretWrapper)
+        );
+        ssi_man.sign("Sol", message, Some("sun")).unwrap();
+
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        assert_eq!(ssi_man.protection("Luna"), Ok(Protection::Password));
+    }
+
+    #[test]
+    fn platform_protection_should_unlock_through_the_wrapper() {
+        platform_protection_should_ok(SsiMan::with_memory());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_platform_protection_should_unlock_through_the_wrapper() {
+        platform_protection_should_ok(SsiMan::with_sqlite(":memory:").unwrap());
+    }
+}
@@ -0,0 +1,359 @@
+use std::{borrow::Cow, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use ssi::{EncryptedSecret, Ssi, SsiPub};
+
+use crate::{
+    store_ext, store_journal, Clock, ConflictPolicy, Error, IdentityFingerprint, IdentityMetadata,
+    IntegrityFindings, IntegrityRepair, JournalCapable, Page, RepairPolicy, SnapshotCapable,
+    SsiMan, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability, StoredIdentity,
+    FORMAT_VERSION,
+};
+
+/// The read methods of [`SsiStore`] and [`SsiStoreExt`], given to
+/// [`SsiMan::read_snapshot`] closures, which therefore can't write.
+pub trait SsiStoreRead {
+    fn get(&self, identity: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error>;
+    fn contains(&self, identity: &str) -> Result<bool, Error>;
+    fn find_by_pubkey(&self, pk: &SsiPub) -> Result<Vec<String>, Error>;
+    fn find_identities(&self, query: &str) -> Result<Vec<String>, Error>;
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error>;
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error>;
+    fn all_identities(&self) -> Result<Vec<Cow<'_, String>>, Error>;
+    fn active_identities(&self, now: DateTime<Utc>) -> Result<Vec<String>, Error>;
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error>;
+    fn capabilities(&self) -> StoreCapabilities;
+    fn metadata(&self, identity: &str) -> Result<IdentityMetadata, Error>;
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error>;
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error>;
+}
+
+/// Exposes a store through [`SsiStoreRead`] only.
+struct SnapshotView<'a>(&'a mut dyn SsiStore);
+
+impl SnapshotView<'_> {
+    fn ext(&self) -> Result<&dyn SsiStoreExt, Error> {
+        store_ext(&*self.0, StoreCapability::Metadata)
+    }
+}
+
+impl SsiStoreRead for SnapshotView<'_> {
+    fn get(&self, identity: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        self.0.get(identity)
+    }
+
+    fn contains(&self, identity: &str) -> Result<bool, Error> {
+        self.0.contains(identity)
+    }
+
+    fn find_by_pubkey(&self, pk: &SsiPub) -> Result<Vec<String>, Error> {
+        self.0.find_by_pubkey(pk)
+    }
+
+    fn find_identities(&self, query: &str) -> Result<Vec<String>, Error> {
+        self.0.find_identities(query)
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        self.0.for_each_identity(f)
+    }
+
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error> {
+        self.0.paginated_identities(page, per_page)
+    }
+
+    fn all_identities(&self) -> Result<Vec<Cow<'_, String>>, Error> {
+        self.0.all_identities()
+    }
+
+    fn active_identities(&self, now: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        self.0.active_identities(now)
+    }
+
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error> {
+        self.0.fingerprints()
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        self.0.capabilities()
+    }
+
+    fn metadata(&self, identity: &str) -> Result<IdentityMetadata, Error> {
+        self.ext()?.metadata(identity)
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        self.ext()?.stale_identities(cutoff)
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        self.ext()?.identities_needing_rewrap()
+    }
+}
+
+/// Wraps a store so that nothing can write to it, e.g. for a verification service opening
+/// a production database.
+///
+/// Reads go to the wrapped store and writes fail with [`Error::ReadOnly`], but the
+/// bookkeeping of [`SsiStoreExt::record_signatures`] and [`SsiStoreExt::set_needs_rewrap`],
+/// skipped so that signing still works.
+pub struct ReadOnlyStore<S> {
+    inner: S,
+}
+
+impl<S: SsiStore> ReadOnlyStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    /// Returns the wrapped store, writable again.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: SsiStore> SsiStore for ReadOnlyStore<S> {
+    fn insert(&mut self, _: String, _: Ssi, _: EncryptedSecret) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn get(&self, identity: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        self.inner.get(identity)
+    }
+
+    fn get_shared(&self, identity: &str) -> Result<Arc<(Ssi, EncryptedSecret)>, Error> {
+        self.inner.get_shared(identity)
+    }
+
+    fn remove(&mut self, _: &str) -> Result<bool, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn contains(&self, identity: &str) -> Result<bool, Error> {
+        self.inner.contains(identity)
+    }
+
+    fn find_by_pubkey(&self, pk: &SsiPub) -> Result<Vec<String>, Error> {
+        self.inner.find_by_pubkey(pk)
+    }
+
+    fn find_identities(&self, query: &str) -> Result<Vec<String>, Error> {
+        self.inner.find_identities(query)
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        self.inner.for_each_identity(f)
+    }
+
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error> {
+        self.inner.paginated_identities(page, per_page)
+    }
+
+    fn all_identities(&self) -> Result<Vec<Cow<'_, String>>, Error> {
+        self.inner.all_identities()
+    }
+
+    fn active_identities(&self, now: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        self.inner.active_identities(now)
+    }
+
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error> {
+        self.inner.fingerprints()
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+
+    fn as_snapshot(&mut self) -> Option<&mut dyn SnapshotCapable> {
+        self.inner.as_snapshot()
+    }
+}
+
+impl<S: SsiStore> SsiStoreExt for ReadOnlyStore<S> {
+    fn replace(&mut self, _: String, _: Ssi, _: EncryptedSecret) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn update(&mut self, _: &str, _: Ssi, _: EncryptedSecret) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn warm_fingerprints(&mut self, _: usize) -> Result<(usize, usize), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn metadata(&self, identity: &str) -> Result<IdentityMetadata, Error> {
+        store_ext(&self.inner, StoreCapability::Metadata)?.metadata(identity)
+    }
+
+    fn record_signatures(&mut self, _: &str, _: u64, _: DateTime<Utc>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        store_ext(&self.inner, StoreCapability::Metadata)?.stale_identities(cutoff)
+    }
+
+    fn set_needs_rewrap(&mut self, _: &str, _: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        store_ext(&self.inner, StoreCapability::Metadata)?.identities_needing_rewrap()
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        if let Some(inner) = self.inner.as_ext_mut() {
+            inner.set_clock(clock)
+        }
+    }
+
+    fn check_integrity(&self) -> Result<IntegrityFindings, Error> {
+        match self.inner.as_ext() {
+            Some(inner) => inner.check_integrity(),
+            None => Ok(IntegrityFindings::default()),
+        }
+    }
+
+    fn repair_integrity(&mut self, _: &RepairPolicy) -> Result<IntegrityRepair, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn wrapped_key(&self, identity: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.inner.as_ext() {
+            Some(inner) => inner.wrapped_key(identity),
+            None => Ok(None),
+        }
+    }
+
+    fn set_wrapped_key(&mut self, _: &str, _: Vec<u8>) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn format_version(&self) -> Result<u32, Error> {
+        match self.inner.as_ext() {
+            Some(inner) => inner.format_version(),
+            None => Ok(FORMAT_VERSION),
+        }
+    }
+
+    fn upgrade_format(&mut self) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+}
+
+impl<S: SsiStore> JournalCapable for ReadOnlyStore<S> {
+    fn import_batch(&mut self, _: Vec<StoredIdentity>, _: ConflictPolicy) -> Result<usize, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn ingest_chunk(&mut self, _: Vec<StoredIdentity>, _: usize) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn ingest_progress(&mut self) -> Result<Option<usize>, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn finish_ingest(&mut self) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), Error> {
+        store_journal(&mut self.inner)?.begin_transaction()
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Error> {
+        store_journal(&mut self.inner)?.commit_transaction()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Error> {
+        store_journal(&mut self.inner)?.rollback_transaction()
+    }
+}
+
+impl SsiMan {
+    /// Runs `read` against the store as it is at one instant, e.g. to count and list
+    /// identities with numbers that agree.
+    ///
+    /// Stores implementing [`SnapshotCapable`], like the sqlite one, run `read` in a
+    /// single read transaction, so writes committed meanwhile by other connections stay
+    /// out of sight. Other stores are read as they are, being only written through this
+    /// manager, which `read` borrows.
+    pub fn read_snapshot<R>(
+        &mut self,
+        read: impl FnOnce(&mut dyn SsiStoreRead) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        if let Some(snapshot) = self.store.as_snapshot() {
+            snapshot.begin_read_snapshot()?;
+        }
+        let result = read(&mut SnapshotView(&mut *self.store));
+        let ended = match self.store.as_snapshot() {
+            Some(snapshot) => snapshot.end_read_snapshot(),
+            None => Ok(()),
+        };
+        let value = result?;
+        ended?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssi_cert_verify_text;
+
+    #[test]
+    fn read_snapshot_should_see_the_store() {
+        let mut ssi_man = SsiMan::with_memory();
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let counted = ssi_man.read_snapshot(|store| {
+            let page = store.paginated_identities(1, 10)?;
+            let identities = store.all_identities()?.len();
+            Ok((page.total_items, identities, store.contains("Luna")?))
+        });
+        assert_eq!(counted, Ok((1, 1, true)));
+        assert_eq!(
+            ssi_man.read_snapshot(|store| store.metadata("nobody").map(|_| ())),
+            Err(Error::UnknownIdentity("nobody".to_string()))
+        );
+    }
+
+    #[test]
+    fn read_only_store_should_reject_writes() {
+        let mut ssi_man = SsiMan::with_memory();
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let store = std::mem::take(ssi_man.memory_store().unwrap());
+        let mut ssi_man = SsiMan::with_store(Box::new(ReadOnlyStore::new(store)));
+
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(
+            ssi_man.paginated_identities(1, 10).unwrap().identities,
+            vec!["Luna".to_string()]
+        );
+        assert!(matches!(
+            ssi_man.new_ssi("Sol", "sol@bitlightlabs.com", None),
+            Err(Error::ReadOnly)
+        ));
+        assert_eq!(ssi_man.remove("Luna"), Err(Error::ReadOnly));
+        assert!(ssi_man.store.contains("Luna").unwrap());
+    }
+}
@@ -0,0 +1,213 @@
+#[cfg(feature = "digest")]
+use std::{collections::hash_map::RandomState, hash::BuildHasher, sync::OnceLock};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+#[cfg(feature = "digest")]
+use sha2::{Digest, Sha256};
+
+use crate::SsiMan;
+
+static REDACTION: AtomicU8 = AtomicU8::new(Redaction::Off as u8);
+
+/// How identity names, ssi strings and emails appear in formatted output: error
+/// messages, `Debug` output and FFI error messages.
+///
+/// Fields and accessors always hold the raw values.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Redaction {
+    /// Values appear as they are.
+    #[default]
+    Off,
+    /// Values are replaced with a hash salted per process, e.g. `#3f9a01c2`, so equal
+    /// values can still be matched within one log.
+    #[cfg(feature = "digest")]
+    Hashed,
+    /// Values are cut down to at most their first two characters, e.g. `Lu…`.
+    Truncated,
+}
+
+impl SsiMan {
+    /// Sets the redaction of every formatted output of the crate, for all instances.
+    ///
+    /// Messages already formatted are kept as they are, e.g. the reasons given by
+    /// creation hooks.
+    pub fn set_redaction(redaction: Redaction) {
+        REDACTION.store(redaction as u8, Ordering::Relaxed);
+    }
+
+    pub fn redaction() -> Redaction {
+        match REDACTION.load(Ordering::Relaxed) {
+            #[cfg(feature = "digest")]
+            1 => Redaction::Hashed,
+            2 => Redaction::Truncated,
+            _ => Redaction::Off,
+        }
+    }
+}
+
+/// Formats `value` under the current [`Redaction`].
+pub(crate) fn redact(value: &str) -> Redacted<'_> {
+    Redacted(value)
+}
+
+pub(crate) struct Redacted<'a>(&'a str);
+
+impl Display for Redacted<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match SsiMan::redaction() {
+            Redaction::Off => f.write_str(self.0),
+            #[cfg(feature = "digest")]
+            Redaction::Hashed => {
+                let digest = Sha256::new()
+                    .chain_update(salt().to_le_bytes())
+                    .chain_update(self.0)
+                    .finalize();
+                f.write_str("#")?;
+                digest[..4]
+                    .iter()
+                    .try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+            Redaction::Truncated => {
+                let kept = (self.0.chars().count() / 2).min(2);
+                self.0
+                    .chars()
+                    .take(kept)
+                    .try_for_each(|c| write!(f, "{c}"))?;
+                f.write_str("…")
+            }
+        }
+    }
+}
+
+impl Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.to_string(), f)
+    }
+}
+
+/// Formats a list of values under the current [`Redaction`], for `Debug` output.
+pub(crate) struct RedactedList<'a, T>(pub &'a [T]);
+
+impl<T: AsRef<str>> Debug for RedactedList<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|value| redact(value.as_ref())))
+            .finish()
+    }
+}
+
+/// Random salt of [`Redaction::Hashed`], so hashes can't be matched across processes.
+#[cfg(feature = "digest")]
+fn salt() -> u64 {
+    static SALT: OnceLock<u64> = OnceLock::new();
+    *SALT.get_or_init(|| RandomState::new().hash_one(0u8))
+}
+
+#[cfg(all(test, feature = "digest"))]
+mod tests {
+    use ssi::{Algo, Chain, Ssi, SsiSecret};
+
+    use crate::{
+        CreationRequest, Error, IdentityInfo, Page, RewrapReport, SsiDetails, StoredIdentity,
+        VerifyContext,
+    };
+
+    use super::*;
+
+    const IDENTITY: &str = "Luna Lovegood";
+    const EMAIL: &str = "luna@bitlightlabs.com";
+
+    fn sample_errors() -> Vec<Error> {
+        vec![
+            Error::AuthenticationFailed,
+            Error::BackupKeyMismatch(IDENTITY.to_string()),
+            Error::DumpPlatformProtected(vec![IDENTITY.to_string()]),
+            Error::DuplicateKey {
+                existing_identity: IDENTITY.to_string(),
+            },
+            Error::IdentityExists(IDENTITY.to_string()),
+            Error::IdentityExpired(IDENTITY.to_string()),
+            Error::LastUid(IDENTITY.to_string()),
+            Error::MissingPassword(IDENTITY.to_string()),
+            Error::UnknownIdentity(IDENTITY.to_string()),
+            Error::UnknownUid(format!("{IDENTITY} <mailto:{EMAIL}>")),
+            Error::UnknownSigner,
+        ]
+    }
+
+    #[test]
+    fn redaction_should_hide_identities_in_formatted_output() {
+        let secret = SsiSecret::new(Algo::Ed25519, Chain::Bitcoin);
+        let uid = format!("{IDENTITY} <mailto:{EMAIL}>");
+        let ssi = Ssi::new(vec![uid.parse().unwrap()], None, &secret);
+        let ssi_string = ssi.to_string();
+        let mut context = VerifyContext::new();
+        context.add_contact(IDENTITY, &ssi_string).unwrap();
+        let info = IdentityInfo {
+            identity: IDENTITY.to_string(),
+            uids: vec![uid.clone()],
+            created_at: Default::default(),
+            last_used_at: None,
+            sign_count: 0,
+        };
+        let details = SsiDetails {
+            identity: IDENTITY.to_string(),
+            ssi: ssi_string.clone(),
+            uids: vec![uid.clone()],
+            algo: "ed25519".to_string(),
+            chain: "bitcoin".to_string(),
+            pk: ssi.pk.to_string(),
+            expiry: None,
+        };
+        let report = RewrapReport {
+            failed: vec![(IDENTITY.to_string(), "wrong password".to_string())],
+            ..Default::default()
+        };
+        let stored = StoredIdentity {
+            identity: IDENTITY.to_string(),
+            ssi: ssi.clone(),
+            encrypted_secret: secret.conceal(""),
+        };
+        let request = CreationRequest::from_ssi(IDENTITY.to_string(), &ssi);
+        let outputs = || {
+            let mut outputs = sample_errors()
+                .iter()
+                .flat_map(|err| [err.to_string(), format!("{err:?}")])
+                .collect::<Vec<_>>();
+            outputs.extend([
+                format!("{context:?}"),
+                format!("{info:?}"),
+                format!("{details:?}"),
+                format!("{report:?}"),
+                format!("{stored:?}"),
+                format!("{request:?}"),
+                format!("{:?}", Page::new(vec![IDENTITY.to_string()], 1, 1)),
+            ]);
+            outputs
+        };
+
+        for redaction in [Redaction::Hashed, Redaction::Truncated] {
+            SsiMan::set_redaction(redaction);
+            for output in outputs() {
+                for raw in [IDENTITY, EMAIL, &ssi_string, "Luna"] {
+                    assert!(!output.contains(raw), "{redaction:?}: {output}");
+                }
+            }
+            assert_eq!(info.identity, IDENTITY);
+        }
+        SsiMan::set_redaction(Redaction::Hashed);
+        assert_eq!(redact(IDENTITY).to_string(), redact(IDENTITY).to_string());
+        assert_ne!(redact(IDENTITY).to_string(), redact(EMAIL).to_string());
+        SsiMan::set_redaction(Redaction::Truncated);
+        assert_eq!(redact("Luna").to_string(), "Lu…");
+        assert_eq!(redact("L").to_string(), "…");
+
+        SsiMan::set_redaction(Redaction::Off);
+        let plain = outputs();
+        assert!(plain.iter().all(|output| output.contains("Luna")));
+    }
+}
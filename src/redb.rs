@@ -0,0 +1,478 @@
+use std::{borrow::Cow, path::Path, str::FromStr, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use redb::{
+    CommitError, Database, DatabaseError, ReadOnlyTable, ReadableTable, ReadableTableMetadata,
+    StorageError, Table, TableDefinition, TableError, TransactionError,
+};
+use serde::{Deserialize, Serialize};
+use ssi::{EncryptedSecret, Ssi};
+
+use crate::{
+    clock::system_clock, fingerprint, Clock, ConflictPolicy, Error, IdentityFingerprint,
+    IdentityMetadata, JournalCapable, Page, SsiStore, SsiStoreExt, StoreCapabilities,
+    StoreCapability, StoredIdentity, FORMAT_VERSION,
+};
+
+/// Table of the records, keyed by identity, so keys iterate in byte-wise order.
+const RECORDS: TableDefinition<&str, &[u8]> = TableDefinition::new("ssi_secrets");
+const SETTINGS: TableDefinition<&str, &str> = TableDefinition::new("settings");
+const FORMAT_VERSION_KEY: &str = "format_version";
+/// Settings key of the number of source records done by an unfinished ingestion.
+const INGEST_PROGRESS_KEY: &str = "ingest_progress";
+
+type Records<'txn> = Table<'txn, &'static str, &'static [u8]>;
+type Settings<'txn> = Table<'txn, &'static str, &'static str>;
+
+impl From<DatabaseError> for Error {
+    fn from(err: DatabaseError) -> Self {
+        Error::Redb(err.into())
+    }
+}
+
+impl From<TransactionError> for Error {
+    fn from(err: TransactionError) -> Self {
+        Error::Redb(err.into())
+    }
+}
+
+impl From<TableError> for Error {
+    fn from(err: TableError) -> Self {
+        Error::Redb(err.into())
+    }
+}
+
+impl From<StorageError> for Error {
+    fn from(err: StorageError) -> Self {
+        Error::Redb(err.into())
+    }
+}
+
+impl From<CommitError> for Error {
+    fn from(err: CommitError) -> Self {
+        Error::Redb(err.into())
+    }
+}
+
+/// An identity as stored, in JSON: the ssi and secret in their text forms, with the
+/// metadata the sqlite store keeps in its columns.
+#[derive(Deserialize, Serialize)]
+struct RedbRecord {
+    ssi: String,
+    secret: String,
+    created_at: DateTime<Utc>,
+    /// `None` until the ssi or secret is first changed, as in records written before it
+    /// was kept.
+    updated_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    sign_count: u64,
+    needs_rewrap: bool,
+    fingerprint: String,
+    wrapped_key: Option<Vec<u8>>,
+}
+
+impl RedbRecord {
+    fn new(ssi: &Ssi, secret: &EncryptedSecret, created_at: DateTime<Utc>) -> Self {
+        Self {
+            ssi: ssi.to_string(),
+            secret: secret.to_string(),
+            created_at,
+            updated_at: None,
+            last_used_at: None,
+            sign_count: 0,
+            needs_rewrap: false,
+            fingerprint: fingerprint(ssi),
+            wrapped_key: None,
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(bytes).map_err(|err| Error::RedbRecord(err.to_string()))
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(self).map_err(|err| Error::RedbRecord(err.to_string()))
+    }
+
+    fn metadata(&self) -> IdentityMetadata {
+        IdentityMetadata {
+            created_at: self.created_at,
+            updated_at: self.updated_at.unwrap_or(self.created_at),
+            last_used_at: self.last_used_at,
+            sign_count: self.sign_count,
+            needs_rewrap: self.needs_rewrap,
+        }
+    }
+}
+
+/// A store in a [redb](https://docs.rs/redb) database, in pure Rust with no C
+/// dependencies, e.g. for Android and iOS builds.
+///
+/// Every write is a transaction committed durably before returning, so a crash leaves
+/// either all of it or none. Records are kept in key order, so listings and pages come
+/// out byte-wise sorted like with the other stores, with the same metadata. A database
+/// can only be opened by one process at a time.
+pub struct SsiRedbStore {
+    db: Database,
+    clock: Arc<dyn Clock>,
+}
+
+impl SsiRedbStore {
+    /// Opens the database in the file at `path`, creating it if missing.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::with_database(Database::create(path)?)
+    }
+
+    /// Uses an open database, e.g. one in memory or configured with [`redb::Builder`].
+    pub fn with_database(db: Database) -> Result<Self, Error> {
+        let store = Self {
+            db,
+            clock: system_clock(),
+        };
+        // A fresh database starts at the current format; redb ones never had another.
+        let found = store.write(|_, settings| {
+            let found = settings.get(FORMAT_VERSION_KEY)?.map(|value| {
+                value
+                    .value()
+                    .parse()
+                    .map_err(|_| Error::RedbRecord("invalid format version".to_string()))
+            });
+            match found {
+                Some(found) => found,
+                None => {
+                    settings.insert(FORMAT_VERSION_KEY, FORMAT_VERSION.to_string().as_str())?;
+                    Ok(FORMAT_VERSION)
+                }
+            }
+        })?;
+        if found > FORMAT_VERSION {
+            return Err(Error::FormatTooNew {
+                found,
+                supported: FORMAT_VERSION,
+            });
+        }
+        Ok(store)
+    }
+
+    /// Runs `f` in a write transaction, committed if it succeeds and aborted otherwise.
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&mut Records, &mut Settings) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let tx = self.db.begin_write()?;
+        let value = {
+            let mut records = tx.open_table(RECORDS)?;
+            let mut settings = tx.open_table(SETTINGS)?;
+            f(&mut records, &mut settings)?
+        };
+        tx.commit()?;
+        Ok(value)
+    }
+
+    fn records(&self) -> Result<ReadOnlyTable<&'static str, &'static [u8]>, Error> {
+        Ok(self.db.begin_read()?.open_table(RECORDS)?)
+    }
+
+    fn setting(&self, key: &str) -> Result<Option<String>, Error> {
+        let settings = self.db.begin_read()?.open_table(SETTINGS)?;
+        let value = settings.get(key)?.map(|value| value.value().to_string());
+        Ok(value)
+    }
+
+    fn record(&self, id: &str) -> Result<RedbRecord, Error> {
+        let records = self.records()?;
+        let bytes = records
+            .get(id)?
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+        RedbRecord::decode(bytes.value())
+    }
+
+    /// Applies `change` to the record of `id` in one transaction.
+    fn modify(&self, id: &str, change: impl FnOnce(&mut RedbRecord)) -> Result<(), Error> {
+        self.write(|records, _| {
+            let mut record = match records.get(id)? {
+                Some(bytes) => RedbRecord::decode(bytes.value())?,
+                None => return Err(Error::UnknownIdentity(id.to_string())),
+            };
+            change(&mut record);
+            records.insert(id, record.encode()?.as_slice())?;
+            Ok(())
+        })
+    }
+
+    /// Calls `f` with every record in identity order.
+    fn for_each_record(
+        &self,
+        mut f: impl FnMut(String, RedbRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for entry in self.records()?.iter()? {
+            let (key, value) = entry?;
+            f(key.value().to_string(), RedbRecord::decode(value.value())?)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes `records` as created now, keyed by identity.
+    fn encode_all(&self, records: &[StoredIdentity]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let created_at = self.clock.now();
+        records
+            .iter()
+            .map(|record| {
+                let value = RedbRecord::new(&record.ssi, &record.encrypted_secret, created_at);
+                Ok((record.identity.clone(), value.encode()?))
+            })
+            .collect()
+    }
+}
+
+impl SsiStore for SsiRedbStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let record = RedbRecord::new(&ssi, &secret, self.clock.now()).encode()?;
+        self.write(|records, _| {
+            if records.get(id.as_str())?.is_some() {
+                return Err(Error::IdentityExists(id.clone()));
+            }
+            records.insert(id.as_str(), record.as_slice())?;
+            Ok(())
+        })
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let record = self.record(id)?;
+        let secret = EncryptedSecret::from_str(&record.secret)
+            .map_err(|err| Error::SecretParse(err.to_string()))?;
+        Ok(Cow::Owned((Ssi::from_str(&record.ssi)?, secret)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        self.write(|records, _| {
+            let removed = records.remove(id)?.is_some();
+            Ok(removed)
+        })
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        let found = self.records()?.get(id)?.is_some();
+        Ok(found)
+    }
+
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error> {
+        let offset = Page::offset(page, per_page)?;
+        let records = self.records()?;
+        let identities = records
+            .iter()?
+            .skip(offset)
+            .take(per_page)
+            .map(|entry| Ok(entry?.0.value().to_string()))
+            .collect::<Result<_, Error>>()?;
+        Ok(Page::new(identities, records.len()? as usize, per_page))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        for entry in self.records()?.iter()? {
+            f(entry?.0.value())?;
+        }
+        Ok(())
+    }
+
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error> {
+        let mut fingerprints = Vec::new();
+        self.for_each_record(|identity, record| {
+            fingerprints.push(IdentityFingerprint {
+                identity,
+                fingerprint: record.fingerprint,
+            });
+            Ok(())
+        })?;
+        Ok(fingerprints)
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default()
+            .with(StoreCapability::AtomicWrites)
+            .with(StoreCapability::Metadata)
+            .with(StoreCapability::PlatformProtection)
+            .with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiRedbStore {
+    fn format_version(&self) -> Result<u32, Error> {
+        self.setting(FORMAT_VERSION_KEY)?
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| Error::RedbRecord("invalid format version".to_string()))
+    }
+
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let record = RedbRecord::new(&ssi, &secret, self.clock.now()).encode()?;
+        self.write(|records, _| {
+            records.insert(id.as_str(), record.as_slice())?;
+            Ok(())
+        })
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let now = self.clock.now();
+        self.modify(id, |record| {
+            record.ssi = ssi.to_string();
+            record.fingerprint = fingerprint(&ssi);
+            record.secret = secret.to_string();
+            record.updated_at = Some(now);
+        })
+    }
+
+    fn metadata(&self, id: &str) -> Result<IdentityMetadata, Error> {
+        Ok(self.record(id)?.metadata())
+    }
+
+    fn record_signatures(&mut self, id: &str, count: u64, at: DateTime<Utc>) -> Result<(), Error> {
+        self.modify(id, |record| {
+            record.last_used_at = Some(at);
+            record.sign_count += count;
+        })
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        let mut identities = Vec::new();
+        self.for_each_record(|identity, record| {
+            if record.metadata().last_activity() < cutoff {
+                identities.push(identity);
+            }
+            Ok(())
+        })?;
+        Ok(identities)
+    }
+
+    fn set_needs_rewrap(&mut self, id: &str, needs_rewrap: bool) -> Result<(), Error> {
+        self.modify(id, |record| record.needs_rewrap = needs_rewrap)
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        let mut identities = Vec::new();
+        self.for_each_record(|identity, record| {
+            if record.needs_rewrap {
+                identities.push(identity);
+            }
+            Ok(())
+        })?;
+        Ok(identities)
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn wrapped_key(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+        let records = self.records()?;
+        let wrapped_key = match records.get(id)? {
+            Some(bytes) => RedbRecord::decode(bytes.value())?.wrapped_key,
+            None => None,
+        };
+        Ok(wrapped_key)
+    }
+
+    fn set_wrapped_key(&mut self, id: &str, wrapped_key: Vec<u8>) -> Result<(), Error> {
+        self.modify(id, |record| record.wrapped_key = Some(wrapped_key))
+    }
+}
+
+impl JournalCapable for SsiRedbStore {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        let encoded = self.encode_all(&records)?;
+        self.write(|records, _| {
+            let mut imported = 0;
+            for (id, value) in &encoded {
+                if records.get(id.as_str())?.is_some() {
+                    match on_conflict {
+                        ConflictPolicy::Skip => continue,
+                        ConflictPolicy::Overwrite => {}
+                        ConflictPolicy::Error => return Err(Error::IdentityExists(id.clone())),
+                    }
+                }
+                records.insert(id.as_str(), value.as_slice())?;
+                imported += 1;
+            }
+            Ok(imported)
+        })
+    }
+
+    fn ingest_chunk(&mut self, records: Vec<StoredIdentity>, ingested: usize) -> Result<(), Error> {
+        let encoded = self.encode_all(&records)?;
+        self.write(|records, settings| {
+            for (id, value) in &encoded {
+                records.insert(id.as_str(), value.as_slice())?;
+            }
+            settings.insert(INGEST_PROGRESS_KEY, ingested.to_string().as_str())?;
+            Ok(())
+        })
+    }
+
+    fn ingest_progress(&mut self) -> Result<Option<usize>, Error> {
+        self.setting(INGEST_PROGRESS_KEY)?
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| Error::RedbRecord("invalid ingestion progress".to_string()))
+            })
+            .transpose()
+    }
+
+    fn finish_ingest(&mut self) -> Result<(), Error> {
+        self.write(|_, settings| {
+            settings.remove(INGEST_PROGRESS_KEY)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redb::backends::InMemoryBackend;
+
+    use super::*;
+    use crate::{store_tests, SsiMan};
+
+    fn in_memory() -> SsiRedbStore {
+        let db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        SsiRedbStore::with_database(db).unwrap()
+    }
+
+    #[test]
+    fn redb_store_should_reject_duplicates_atomically() {
+        store_tests::should_reject_duplicates_atomically(&mut in_memory());
+    }
+
+    #[test]
+    fn redb_store_should_reopen_with_its_records() {
+        let path = std::env::temp_dir().join(format!(
+            "ssi_man_{}.redb",
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        store_tests::should_reopen_with_its_records(|| SsiMan::with_redb(&path).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn redb_ingest_should_record_progress() {
+        store_tests::should_record_ingest_progress(SsiMan::with_store(Box::new(in_memory())));
+    }
+}
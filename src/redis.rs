@@ -0,0 +1,257 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use redis::{Client, Commands, Connection};
+use ssi::{EncryptedSecret, Ssi};
+use zeroize::Zeroizing;
+
+use crate::{Error, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability};
+
+/// Key prefix of [`SsiRedisStore::new`].
+const DEFAULT_PREFIX: &str = "ssi-man";
+
+/// A store keeping identities in Redis, so that several signing gateways share them.
+///
+/// Each identity is a hash at `<prefix>:identity:<identity>` with the `ssi` and the
+/// concealed `secret` in their text forms. Writes check and set the hash in one
+/// `WATCH`ed transaction, so two gateways can't create the same identity, and listing
+/// walks the keys with `SCAN`, without blocking the server. Identities can expire, for
+/// ephemeral ones: see [`SsiRedisStore::ttl`] and [`SsiRedisStore::expire`].
+pub struct SsiRedisStore {
+    connection: Mutex<Connection>,
+    prefix: String,
+    ttl: Option<Duration>,
+}
+
+impl SsiRedisStore {
+    /// Connects to the Redis server at `url`, e.g. `redis://127.0.0.1:6379/0`, with
+    /// keys under `ssi-man:`.
+    pub fn new(url: &str) -> Result<Self, Error> {
+        Ok(Self::with_connection(Client::open(url)?.get_connection()?))
+    }
+
+    /// Keeps identities through `connection`, e.g. one set up with TLS or credentials.
+    pub fn with_connection(connection: Connection) -> Self {
+        Self {
+            connection: Mutex::new(connection),
+            prefix: DEFAULT_PREFIX.to_string(),
+            ttl: None,
+        }
+    }
+
+    /// Keeps identities under `<prefix>:` rather than `ssi-man:`, e.g. to share a server.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Makes the identities created from now on expire `ttl` after their creation;
+    /// later writes don't extend it.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Makes `id` expire in `ttl` from now, or never with `None`, returning whether it
+    /// exists.
+    pub fn expire(&mut self, id: &str, ttl: Option<Duration>) -> Result<bool, Error> {
+        let key = self.key(id);
+        let mut connection = self.connection();
+        Ok(match ttl {
+            Some(ttl) => connection.pexpire(&key, millis(ttl))?,
+            None => connection.persist::<_, bool>(&key)? || connection.exists(&key)?,
+        })
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}:identity:{id}", self.prefix)
+    }
+
+    /// Locks the connection, which reads share.
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sets the hash of `id`, first checking that it `exists` unless that's `None`.
+    fn write(
+        &mut self,
+        id: &str,
+        ssi: &Ssi,
+        secret: &EncryptedSecret,
+        exists: Option<bool>,
+    ) -> Result<(), Error> {
+        let key = self.key(id);
+        let fields = [("ssi", ssi.to_string()), ("secret", secret.to_string())];
+        let ttl = self.ttl;
+        redis::transaction(&mut *self.connection(), &[&key], |con, pipe| {
+            let found: bool = con.exists(&key)?;
+            match (exists, found) {
+                (Some(false), true) => return Ok(Some(Err(Error::IdentityExists(id.to_string())))),
+                (Some(true), false) => {
+                    return Ok(Some(Err(Error::UnknownIdentity(id.to_string()))))
+                }
+                _ => {}
+            }
+            pipe.hset_multiple(&key, &fields).ignore();
+            if let (Some(ttl), false) = (ttl, found) {
+                pipe.pexpire(&key, millis(ttl)).ignore();
+            }
+            pipe.query::<Option<()>>(con).map(|done| done.map(Ok))
+        })?
+    }
+}
+
+fn millis(ttl: Duration) -> i64 {
+    i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX)
+}
+
+/// Escapes the glob characters of `MATCH` patterns in `prefix`.
+fn escape_glob(prefix: &str) -> String {
+    let mut escaped = String::with_capacity(prefix.len());
+    for c in prefix.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl SsiStore for SsiRedisStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.write(&id, &ssi, &secret, Some(false))
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let mut fields: HashMap<String, String> = self.connection().hgetall(self.key(id))?;
+        let (Some(ssi), Some(secret)) = (fields.remove("ssi"), fields.remove("secret")) else {
+            return if fields.is_empty() {
+                Err(Error::UnknownIdentity(id.to_string()))
+            } else {
+                Err(Error::RedisRecord(format!("{id} lacks its ssi or secret")))
+            };
+        };
+        let secret = Zeroizing::new(secret);
+        Ok(Cow::Owned((
+            Ssi::from_str(&ssi)?,
+            EncryptedSecret::from_str(&secret)
+                .map_err(|err| Error::SecretParse(err.to_string()))?,
+        )))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        let removed: usize = self.connection().del(self.key(id))?;
+        Ok(removed > 0)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.connection().exists(self.key(id))?)
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        let start = self.key("");
+        let pattern = format!("{}*", escape_glob(&start));
+        // SCAN may return a key more than once, and in any order.
+        let keys: BTreeSet<String> = self.connection().scan_match(&pattern)?.collect();
+        for key in keys {
+            if let Some(identity) = key.strip_prefix(&start) {
+                f(identity)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default().with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiRedisStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.write(&id, &ssi, &secret, None)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.write(id, &ssi, &secret, Some(true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    #[test]
+    fn escape_glob_should_escape_pattern_characters() {
+        assert_eq!(escape_glob("ssi-man:identity:"), "ssi-man:identity:");
+        assert_eq!(escape_glob("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+    }
+
+    /// Needs a Redis server at `SSI_MAN_REDIS_URL`, and passes otherwise.
+    #[test]
+    fn redis_store_should_share_identities() {
+        let Ok(url) = env::var("SSI_MAN_REDIS_URL") else {
+            return;
+        };
+        let prefix = format!("ssi-man-test-{}", Utc::now().timestamp_nanos_opt().unwrap());
+        let store = || SsiRedisStore::new(&url).unwrap().prefix(&prefix);
+        let mut ssi_man = SsiMan::with_store(Box::new(store()));
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let mut other = SsiMan::with_store(Box::new(store()));
+        assert!(matches!(
+            other.new_ssi("Luna", "luna@bitlightlabs.com", None),
+            Err(Error::IdentityExists(_))
+        ));
+        let ssi_cert = other.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(
+            ssi_man.paginated_identities(1, 10).unwrap().identities,
+            vec!["Luna".to_string()]
+        );
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        assert_eq!(other.remove("Luna"), Ok(false));
+    }
+
+    #[test]
+    fn redis_store_should_expire_ephemeral_identities() {
+        let Ok(url) = env::var("SSI_MAN_REDIS_URL") else {
+            return;
+        };
+        let prefix = format!("ssi-man-test-{}", Utc::now().timestamp_nanos_opt().unwrap());
+        let store = SsiRedisStore::new(&url)
+            .unwrap()
+            .prefix(&prefix)
+            .ttl(Duration::from_millis(200));
+        let mut ssi_man = SsiMan::with_store(Box::new(store));
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        assert!(ssi_man.store.contains("Luna").unwrap());
+        std::thread::sleep(Duration::from_millis(400));
+        assert!(!ssi_man.store.contains("Luna").unwrap());
+
+        let mut store = SsiRedisStore::new(&url).unwrap().prefix(&prefix);
+        assert_eq!(store.expire("Luna", None), Ok(false));
+    }
+}
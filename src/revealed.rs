@@ -0,0 +1,127 @@
+use std::{mem::ManuallyDrop, slice};
+
+use chrono::{DateTime, Utc};
+use ssi::{EncryptedSecret, Ssi, SsiCert, SsiPair, SsiPub, SsiSecret, Uid};
+use zeroize::Zeroize;
+
+use crate::Error;
+
+/// Plaintext secret of an identity, revealed for the length of one operation.
+///
+/// The secret never leaves this wrapper: it is only used through the methods below, the
+/// field is private and the type is not `Clone`. Its memory is overwritten with zeros
+/// when the wrapper is dropped.
+pub(crate) struct RevealedSecret(ManuallyDrop<SsiSecret>);
+
+impl RevealedSecret {
+    pub fn new(secret: SsiSecret) -> Self {
+        Self(ManuallyDrop::new(secret))
+    }
+
+    pub fn to_public(&self) -> SsiPub {
+        self.0.to_public()
+    }
+
+    /// Builds an ssi for this secret.
+    pub fn to_ssi(
+        &self,
+        uids: impl IntoIterator<Item = Uid>,
+        expiry: Option<DateTime<Utc>>,
+    ) -> Ssi {
+        Ssi::new(uids.into_iter().collect(), expiry, &self.0)
+    }
+
+    pub fn conceal(&self, passwd: &str) -> EncryptedSecret {
+        self.0.conceal(passwd)
+    }
+
+    /// Signs each of `messages` with a single signer, wiping the copy of the secret it
+    /// holds afterwards.
+    pub fn sign_all(&self, ssi: Ssi, messages: &[&[u8]]) -> Vec<SsiCert> {
+        self.sign_each(ssi, messages, |_| Ok(()))
+            .expect("signing never stops without checks")
+    }
+
+    /// Same as [`RevealedSecret::sign_all`], calling `check` with the number of signatures
+    /// made before each one and stopping at its first error.
+    pub fn sign_each(
+        &self,
+        ssi: Ssi,
+        messages: &[&[u8]],
+        mut check: impl FnMut(usize) -> Result<(), Error>,
+    ) -> Result<Vec<SsiCert>, Error> {
+        let mut signer = ManuallyDrop::new(SsiPair::new(ssi, (*self.0).clone()));
+        let ssi_certs = messages
+            .iter()
+            .enumerate()
+            .map(|(done, message)| {
+                check(done)?;
+                Ok(signer.sign(message))
+            })
+            .collect();
+        // SAFETY: `signer` is not used again.
+        unsafe { wipe(&mut signer) };
+        ssi_certs
+    }
+}
+
+impl Drop for RevealedSecret {
+    fn drop(&mut self) {
+        // SAFETY: the secret is not used again.
+        unsafe { wipe(&mut self.0) };
+    }
+}
+
+/// Drops `value` in place, then overwrites the bytes it occupied with zeros.
+///
+/// Secrets of every supported algorithm keep their key material inline, so this reaches
+/// the key itself.
+///
+/// # Safety
+///
+/// `value` must not be used afterwards.
+unsafe fn wipe<T>(value: &mut ManuallyDrop<T>) {
+    ManuallyDrop::drop(value);
+    slice::from_raw_parts_mut((value as *mut ManuallyDrop<T>).cast::<u8>(), size_of::<T>())
+        .zeroize();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::MaybeUninit;
+
+    use ssi::{Algo, Chain};
+
+    use super::*;
+
+    #[test]
+    fn revealed_secret_should_be_zeroized_on_drop() {
+        for algo in [Algo::Ed25519, Algo::Bip340] {
+            let secret = SsiSecret::new(algo, Chain::Bitcoin);
+            let pk = secret.to_public();
+            let mut slot = MaybeUninit::new(RevealedSecret::new(secret));
+            let ptr = slot.as_ptr().cast::<u8>();
+            let bytes = || unsafe { slice::from_raw_parts(ptr, size_of::<RevealedSecret>()) };
+            assert!(bytes().iter().any(|byte| *byte != 0));
+            assert_eq!(unsafe { slot.assume_init_ref() }.to_public(), pk);
+
+            unsafe { slot.assume_init_drop() };
+            assert!(bytes().iter().all(|byte| *byte == 0));
+        }
+    }
+
+    #[test]
+    fn revealed_secret_should_sign_as_before() {
+        let secret = SsiSecret::new(Algo::Ed25519, Chain::Bitcoin);
+        let revealed = RevealedSecret::new(secret.clone());
+        let ssi = revealed.to_ssi([], None);
+        let message = b"have a good day!";
+        let expected = SsiPair::new(ssi.clone(), secret).sign(message);
+
+        let ssi_certs = revealed.sign_all(ssi, &[message, message]);
+        assert_eq!(ssi_certs.len(), 2);
+        for ssi_cert in ssi_certs {
+            assert_eq!(ssi_cert.to_string(), expected.to_string());
+        }
+    }
+}
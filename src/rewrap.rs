@@ -0,0 +1,185 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+};
+
+use zeroize::Zeroizing;
+
+use crate::{
+    redact::{redact, RedactedList},
+    reveal_secret, Error, SsiMan, StoreCapability, DEFAULT_EMPTY_PASSWORD,
+};
+
+/// Where [`SsiMan::bulk_rewrap`] takes the new password of each identity from.
+pub enum NewPasswordSource {
+    /// The same new password for every identity.
+    Single(String),
+    /// A new password per identity; identities missing from the map fail.
+    PerIdentity(HashMap<String, String>),
+    /// Asked for each identity; `None` makes it fail.
+    Callback(Box<dyn Fn(&str) -> Option<String>>),
+}
+
+impl NewPasswordSource {
+    fn password_for(&self, identity: &str) -> Option<String> {
+        match self {
+            NewPasswordSource::Single(passwd) => Some(passwd.clone()),
+            NewPasswordSource::PerIdentity(passwords) => passwords.get(identity).cloned(),
+            NewPasswordSource::Callback(callback) => callback(identity),
+        }
+    }
+}
+
+/// Outcome of [`SsiMan::bulk_rewrap`] per identity, each list sorted by identity.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct RewrapReport {
+    pub succeeded: Vec<String>,
+    /// Identities whose old password was wrong, left untouched.
+    pub skipped: Vec<String>,
+    /// Identities that could not be rewrapped, with the reason.
+    pub failed: Vec<(String, String)>,
+}
+
+impl Debug for RewrapReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let failed = self
+            .failed
+            .iter()
+            .map(|(identity, reason)| (redact(identity), reason))
+            .collect::<Vec<_>>();
+        f.debug_struct("RewrapReport")
+            .field("succeeded", &RedactedList(&self.succeeded))
+            .field("skipped", &RedactedList(&self.skipped))
+            .field("failed", &failed)
+            .finish()
+    }
+}
+
+impl SsiMan {
+    /// Re-conceals every identity's secret with a new password.
+    ///
+    /// Each identity is revealed with its entry in `old_passwords`, or `default_old` (the
+    /// empty password if `None`), and updated on its own, so a wrong password only skips
+    /// that identity. `on_progress` receives the number of identities done, the total and
+    /// the identity just handled.
+    pub fn bulk_rewrap(
+        &mut self,
+        old_passwords: &HashMap<String, String>,
+        default_old: Option<&str>,
+        new_password_source: NewPasswordSource,
+        mut on_progress: impl FnMut(usize, usize, &str),
+    ) -> Result<RewrapReport, Error> {
+        let mut identities = self
+            .store
+            .all_identities()?
+            .into_iter()
+            .map(Cow::into_owned)
+            .collect::<Vec<_>>();
+        identities.sort();
+
+        let mut report = RewrapReport::default();
+        let total = identities.len();
+        for (done, identity) in identities.into_iter().enumerate() {
+            let old_passwd = old_passwords
+                .get(&identity)
+                .map(String::as_str)
+                .or(default_old);
+            match self.rewrap(&identity, old_passwd, &new_password_source) {
+                Ok(()) => report.succeeded.push(identity.clone()),
+                Err(err) if err.is_wrong_password() => report.skipped.push(identity.clone()),
+                Err(err) => report.failed.push((identity.clone(), err.to_string())),
+            }
+            on_progress(done + 1, total, &identity);
+        }
+        Ok(report)
+    }
+
+    fn rewrap(
+        &mut self,
+        identity: &str,
+        old_passwd: Option<&str>,
+        new_password_source: &NewPasswordSource,
+    ) -> Result<(), Error> {
+        let (ssi, encrypted) = self.store.get(identity)?.into_owned();
+        let secret = reveal_secret(
+            &ssi,
+            &encrypted,
+            old_passwd.unwrap_or(DEFAULT_EMPTY_PASSWORD),
+        )?;
+        let new_passwd = Zeroizing::new(
+            new_password_source
+                .password_for(identity)
+                .ok_or_else(|| Error::MissingPassword(identity.to_string()))?,
+        );
+        self.ext_mut(StoreCapability::Replace)?.update(
+            identity,
+            ssi,
+            secret.conceal(&new_passwd),
+        )?;
+        self.clear_needs_rewrap(identity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssi_cert_verify_text;
+
+    #[test]
+    fn bulk_rewrap_should_report_each_identity() {
+        let message = "have a good day!";
+        let mut ssi_man = SsiMan::with_memory();
+        ssi_man
+            .new_ssi("alice", "alice@bitlightlabs.com", None)
+            .unwrap();
+        ssi_man
+            .new_ssi("bob", "bob@bitlightlabs.com", Some("bob-old"))
+            .unwrap();
+        ssi_man
+            .new_ssi("carol", "carol@bitlightlabs.com", Some("carol-old"))
+            .unwrap();
+        ssi_man
+            .new_ssi("dave", "dave@bitlightlabs.com", None)
+            .unwrap();
+
+        let old_passwords = HashMap::from([
+            ("bob".to_string(), "bob-old".to_string()),
+            ("carol".to_string(), "wrong".to_string()),
+        ]);
+        let new_passwords = NewPasswordSource::Callback(Box::new(|identity| {
+            (identity != "dave").then(|| format!("{identity}-new"))
+        }));
+        let mut progress = vec![];
+        let report = ssi_man
+            .bulk_rewrap(
+                &old_passwords,
+                None,
+                new_passwords,
+                |done, total, identity| progress.push((done, total, identity.to_string())),
+            )
+            .unwrap();
+
+        assert_eq!(report.succeeded, vec!["alice", "bob"]);
+        assert_eq!(report.skipped, vec!["carol"]);
+        assert_eq!(
+            report.failed,
+            vec![(
+                "dave".to_string(),
+                Error::MissingPassword("dave".to_string()).to_string()
+            )]
+        );
+        assert_eq!(progress.len(), 4);
+        assert_eq!(progress[3], (4, 4, "dave".to_string()));
+
+        for (identity, passwd) in [
+            ("alice", Some("alice-new")),
+            ("bob", Some("bob-new")),
+            ("carol", Some("carol-old")),
+            ("dave", None),
+        ] {
+            let ssi_cert = ssi_man.sign(identity, message, passwd).unwrap();
+            ssi_cert_verify_text(&ssi_cert, message).unwrap();
+        }
+    }
+}
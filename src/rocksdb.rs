@@ -0,0 +1,547 @@
+use std::{borrow::Cow, collections::HashSet, path::Path, str::FromStr, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use rocksdb::{ColumnFamily, DBRawIterator, Options, WriteBatch, DB};
+use serde::{Deserialize, Serialize};
+use ssi::{EncryptedSecret, Ssi};
+
+use crate::{
+    clock::system_clock, fingerprint, Clock, ConflictPolicy, Error, IdentityFingerprint,
+    IdentityMetadata, JournalCapable, Page, SsiStore, SsiStoreExt, StoreCapabilities,
+    StoreCapability, StoredIdentity, FORMAT_VERSION,
+};
+
+/// Column family of the ssi of every identity, keyed by identity like the others.
+const SSI_CF: &str = "ssi";
+/// Column family of the concealed secret of every identity.
+const SECRET_CF: &str = "secret";
+const METADATA_CF: &str = "metadata";
+const SETTINGS_CF: &str = "settings";
+const FORMAT_VERSION_KEY: &str = "format_version";
+/// Settings key of the number of identities, kept so pages know their total at once.
+const COUNT_KEY: &str = "identity_count";
+/// Settings key of the number of source records done by an unfinished ingestion.
+const INGEST_PROGRESS_KEY: &str = "ingest_progress";
+
+/// The metadata of an identity as stored, in JSON, with its cached fingerprint and
+/// wrapped key.
+#[derive(Deserialize, Serialize)]
+struct RocksMetadata {
+    created_at: DateTime<Utc>,
+    /// `None` until the ssi or secret is first changed, as in records written before it
+    /// was kept.
+    updated_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    sign_count: u64,
+    needs_rewrap: bool,
+    fingerprint: String,
+    wrapped_key: Option<Vec<u8>>,
+}
+
+impl RocksMetadata {
+    fn new(ssi: &Ssi, created_at: DateTime<Utc>) -> Self {
+        Self {
+            created_at,
+            updated_at: None,
+            last_used_at: None,
+            sign_count: 0,
+            needs_rewrap: false,
+            fingerprint: fingerprint(ssi),
+            wrapped_key: None,
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(bytes).map_err(|err| Error::RocksRecord(err.to_string()))
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(self).map_err(|err| Error::RocksRecord(err.to_string()))
+    }
+
+    fn metadata(&self) -> IdentityMetadata {
+        IdentityMetadata {
+            created_at: self.created_at,
+            updated_at: self.updated_at.unwrap_or(self.created_at),
+            last_used_at: self.last_used_at,
+            sign_count: self.sign_count,
+            needs_rewrap: self.needs_rewrap,
+        }
+    }
+}
+
+fn text(bytes: Vec<u8>) -> Result<String, Error> {
+    String::from_utf8(bytes).map_err(|err| Error::RocksRecord(err.to_string()))
+}
+
+/// A store in a [RocksDB](https://rocksdb.org) database, for hundreds of thousands of
+/// identities and more.
+///
+/// The ssi, secret and metadata of identities are kept in column families keyed by
+/// identity, written together in atomic batches. Keys are sorted byte-wise, so listings
+/// are range scans over the ssi column family and pages skip keys without reading
+/// values; the number of identities is kept up to date for their totals.
+pub struct SsiRocksStore {
+    db: DB,
+    clock: Arc<dyn Clock>,
+}
+
+impl SsiRocksStore {
+    /// Opens the database in the directory at `path`, creating it if missing.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let db = DB::open_cf(
+            &options,
+            path,
+            [SSI_CF, SECRET_CF, METADATA_CF, SETTINGS_CF],
+        )?;
+        let store = Self {
+            db,
+            clock: system_clock(),
+        };
+        let found = match store.setting(FORMAT_VERSION_KEY)? {
+            Some(version) => version,
+            None => {
+                // A fresh database starts at the current format; rocksdb ones never had
+                // another.
+                store.put_setting(FORMAT_VERSION_KEY, FORMAT_VERSION)?;
+                FORMAT_VERSION
+            }
+        };
+        if found > FORMAT_VERSION {
+            return Err(Error::FormatTooNew {
+                found,
+                supported: FORMAT_VERSION,
+            });
+        }
+        Ok(store)
+    }
+
+    fn cf(&self, name: &str) -> Result<&ColumnFamily, Error> {
+        self.db
+            .cf_handle(name)
+            .ok_or_else(|| Error::RocksRecord(format!("missing column family {name}")))
+    }
+
+    fn setting<T: FromStr>(&self, key: &str) -> Result<Option<T>, Error> {
+        self.db
+            .get_cf(self.cf(SETTINGS_CF)?, key)?
+            .map(|value| {
+                text(value)?
+                    .parse()
+                    .map_err(|_| Error::RocksRecord(format!("invalid setting {key}")))
+            })
+            .transpose()
+    }
+
+    fn put_setting(&self, key: &str, value: impl ToString) -> Result<(), Error> {
+        self.db
+            .put_cf(self.cf(SETTINGS_CF)?, key, value.to_string())?;
+        Ok(())
+    }
+
+    /// Whether `id` is stored, only reading when the bloom filters can't rule it out.
+    fn exists(&self, id: &str) -> Result<bool, Error> {
+        let cf = self.cf(SSI_CF)?;
+        Ok(self.db.key_may_exist_cf(cf, id) && self.db.get_pinned_cf(cf, id)?.is_some())
+    }
+
+    fn count(&self) -> Result<usize, Error> {
+        Ok(self.setting(COUNT_KEY)?.unwrap_or(0))
+    }
+
+    fn record_metadata(&self, id: &str) -> Result<RocksMetadata, Error> {
+        let bytes = self
+            .db
+            .get_cf(self.cf(METADATA_CF)?, id)?
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+        RocksMetadata::decode(&bytes)
+    }
+
+    /// Applies `change` to the metadata of `id`; the store is only written through
+    /// `&mut self`, so nothing else changes it meanwhile.
+    fn modify(&mut self, id: &str, change: impl FnOnce(&mut RocksMetadata)) -> Result<(), Error> {
+        let mut metadata = self.record_metadata(id)?;
+        change(&mut metadata);
+        self.db
+            .put_cf(self.cf(METADATA_CF)?, id, metadata.encode()?)?;
+        Ok(())
+    }
+
+    /// Adds writing a record, created at `created_at`, to `batch`.
+    fn put_record(
+        &self,
+        batch: &mut WriteBatch,
+        id: &str,
+        ssi: &Ssi,
+        secret: &EncryptedSecret,
+        created_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        batch.put_cf(self.cf(SSI_CF)?, id, ssi.to_string());
+        batch.put_cf(self.cf(SECRET_CF)?, id, secret.to_string());
+        batch.put_cf(
+            self.cf(METADATA_CF)?,
+            id,
+            RocksMetadata::new(ssi, created_at).encode()?,
+        );
+        Ok(())
+    }
+
+    /// Writes `records`, replacing identities already present, with the new count and
+    /// `settings` in one batch.
+    fn write_records(
+        &self,
+        records: &[StoredIdentity],
+        settings: &[(&str, String)],
+    ) -> Result<(), Error> {
+        let created_at = self.clock.now();
+        let mut batch = WriteBatch::default();
+        let mut added = HashSet::new();
+        for record in records {
+            if !self.exists(&record.identity)? {
+                added.insert(record.identity.as_str());
+            }
+            self.put_record(
+                &mut batch,
+                &record.identity,
+                &record.ssi,
+                &record.encrypted_secret,
+                created_at,
+            )?;
+        }
+        let settings_cf = self.cf(SETTINGS_CF)?;
+        batch.put_cf(
+            settings_cf,
+            COUNT_KEY,
+            (self.count()? + added.len()).to_string(),
+        );
+        for (key, value) in settings {
+            batch.put_cf(settings_cf, key, value);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Iterates over the identities in order, from the first.
+    fn identities(&self) -> Result<DBRawIterator<'_>, Error> {
+        let mut iter = self.db.raw_iterator_cf(self.cf(SSI_CF)?);
+        iter.seek_to_first();
+        Ok(iter)
+    }
+
+    /// Calls `f` with every identity and its metadata in identity order.
+    fn for_each_metadata(
+        &self,
+        mut f: impl FnMut(String, RocksMetadata) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut iter = self.db.raw_iterator_cf(self.cf(METADATA_CF)?);
+        iter.seek_to_first();
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            f(text(key.to_vec())?, RocksMetadata::decode(value)?)?;
+            iter.next();
+        }
+        iter.status()?;
+        Ok(())
+    }
+}
+
+impl SsiStore for SsiRocksStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if self.contains(&id)? {
+            return Err(Error::IdentityExists(id));
+        }
+        let mut batch = WriteBatch::default();
+        self.put_record(&mut batch, &id, &ssi, &secret, self.clock.now())?;
+        batch.put_cf(
+            self.cf(SETTINGS_CF)?,
+            COUNT_KEY,
+            (self.count()? + 1).to_string(),
+        );
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let ssi = self
+            .db
+            .get_cf(self.cf(SSI_CF)?, id)?
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+        let secret = self
+            .db
+            .get_cf(self.cf(SECRET_CF)?, id)?
+            .ok_or_else(|| Error::RocksRecord("identity without a secret".to_string()))?;
+        let secret = EncryptedSecret::from_str(&text(secret)?)
+            .map_err(|err| Error::SecretParse(err.to_string()))?;
+        Ok(Cow::Owned((Ssi::from_str(&text(ssi)?)?, secret)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        if !self.contains(id)? {
+            return Ok(false);
+        }
+        let mut batch = WriteBatch::default();
+        for cf in [SSI_CF, SECRET_CF, METADATA_CF] {
+            batch.delete_cf(self.cf(cf)?, id);
+        }
+        batch.put_cf(
+            self.cf(SETTINGS_CF)?,
+            COUNT_KEY,
+            self.count()?.saturating_sub(1).to_string(),
+        );
+        self.db.write(batch)?;
+        Ok(true)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        self.exists(id)
+    }
+
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error> {
+        let offset = Page::offset(page, per_page)?;
+        let mut iter = self.identities()?;
+        for _ in 0..offset {
+            if !iter.valid() {
+                break;
+            }
+            iter.next();
+        }
+        let mut identities = Vec::new();
+        while let Some(key) = iter.key().filter(|_| identities.len() < per_page) {
+            identities.push(text(key.to_vec())?);
+            iter.next();
+        }
+        iter.status()?;
+        Ok(Page::new(identities, self.count()?, per_page))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        let mut iter = self.identities()?;
+        while let Some(key) = iter.key() {
+            f(&text(key.to_vec())?)?;
+            iter.next();
+        }
+        iter.status()?;
+        Ok(())
+    }
+
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error> {
+        let mut fingerprints = Vec::new();
+        self.for_each_metadata(|identity, metadata| {
+            fingerprints.push(IdentityFingerprint {
+                identity,
+                fingerprint: metadata.fingerprint,
+            });
+            Ok(())
+        })?;
+        Ok(fingerprints)
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default()
+            .with(StoreCapability::AtomicWrites)
+            .with(StoreCapability::Metadata)
+            .with(StoreCapability::PlatformProtection)
+            .with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiRocksStore {
+    fn format_version(&self) -> Result<u32, Error> {
+        Ok(self.setting(FORMAT_VERSION_KEY)?.unwrap_or(FORMAT_VERSION))
+    }
+
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let record = StoredIdentity {
+            identity: id,
+            ssi,
+            encrypted_secret: secret,
+        };
+        self.write_records(&[record], &[])
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let mut metadata = self.record_metadata(id)?;
+        metadata.fingerprint = fingerprint(&ssi);
+        metadata.updated_at = Some(self.clock.now());
+        let mut batch = WriteBatch::default();
+        batch.put_cf(self.cf(SSI_CF)?, id, ssi.to_string());
+        batch.put_cf(self.cf(SECRET_CF)?, id, secret.to_string());
+        batch.put_cf(self.cf(METADATA_CF)?, id, metadata.encode()?);
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    fn metadata(&self, id: &str) -> Result<IdentityMetadata, Error> {
+        Ok(self.record_metadata(id)?.metadata())
+    }
+
+    fn record_signatures(&mut self, id: &str, count: u64, at: DateTime<Utc>) -> Result<(), Error> {
+        self.modify(id, |metadata| {
+            metadata.last_used_at = Some(at);
+            metadata.sign_count += count;
+        })
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        let mut identities = Vec::new();
+        self.for_each_metadata(|identity, metadata| {
+            if metadata.metadata().last_activity() < cutoff {
+                identities.push(identity);
+            }
+            Ok(())
+        })?;
+        Ok(identities)
+    }
+
+    fn set_needs_rewrap(&mut self, id: &str, needs_rewrap: bool) -> Result<(), Error> {
+        self.modify(id, |metadata| metadata.needs_rewrap = needs_rewrap)
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        let mut identities = Vec::new();
+        self.for_each_metadata(|identity, metadata| {
+            if metadata.needs_rewrap {
+                identities.push(identity);
+            }
+            Ok(())
+        })?;
+        Ok(identities)
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn wrapped_key(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.db.get_cf(self.cf(METADATA_CF)?, id)? {
+            Some(bytes) => Ok(RocksMetadata::decode(&bytes)?.wrapped_key),
+            None => Ok(None),
+        }
+    }
+
+    fn set_wrapped_key(&mut self, id: &str, wrapped_key: Vec<u8>) -> Result<(), Error> {
+        self.modify(id, |metadata| metadata.wrapped_key = Some(wrapped_key))
+    }
+}
+
+impl JournalCapable for SsiRocksStore {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        let mut kept = Vec::with_capacity(records.len());
+        for record in records {
+            if self.contains(&record.identity)? {
+                match on_conflict {
+                    ConflictPolicy::Skip => continue,
+                    ConflictPolicy::Overwrite => {}
+                    ConflictPolicy::Error => return Err(Error::IdentityExists(record.identity)),
+                }
+            }
+            kept.push(record);
+        }
+        self.write_records(&kept, &[])?;
+        Ok(kept.len())
+    }
+
+    fn ingest_chunk(&mut self, records: Vec<StoredIdentity>, ingested: usize) -> Result<(), Error> {
+        self.write_records(&records, &[(INGEST_PROGRESS_KEY, ingested.to_string())])
+    }
+
+    fn ingest_progress(&mut self) -> Result<Option<usize>, Error> {
+        self.setting(INGEST_PROGRESS_KEY)
+    }
+
+    fn finish_ingest(&mut self) -> Result<(), Error> {
+        self.db
+            .delete_cf(self.cf(SETTINGS_CF)?, INGEST_PROGRESS_KEY)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::SsiMan;
+
+    fn temp_db_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ssi_man_{name}_{}_rocksdb",
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ))
+    }
+
+    #[test]
+    fn rocks_store_should_page_and_count() {
+        let path = temp_db_dir("pages");
+        let mut source = SsiMan::with_memory();
+        source
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let (ssi, secret) = source.store.get("Luna").unwrap().into_owned();
+        let records = (0..25)
+            .map(|n| StoredIdentity {
+                identity: format!("id{n:02}"),
+                ssi: ssi.clone(),
+                encrypted_secret: secret.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut store = SsiRocksStore::new(&path).unwrap();
+        assert_eq!(
+            store.import_batch(records[..20].to_vec(), ConflictPolicy::Error),
+            Ok(20)
+        );
+        assert_eq!(
+            store.import_batch(records.clone(), ConflictPolicy::Error),
+            Err(Error::IdentityExists("id00".to_string()))
+        );
+        assert_eq!(
+            store.import_batch(records.clone(), ConflictPolicy::Skip),
+            Ok(5)
+        );
+        assert_eq!(
+            store.insert("id00".to_string(), ssi.clone(), secret.clone()),
+            Err(Error::IdentityExists("id00".to_string()))
+        );
+        store
+            .replace("id00".to_string(), ssi.clone(), secret.clone())
+            .unwrap();
+        assert_eq!(store.remove("id24"), Ok(true));
+        assert_eq!(store.remove("id24"), Ok(false));
+
+        let page = store.paginated_identities(3, 10).unwrap();
+        assert_eq!(page.identities, ["id20", "id21", "id22", "id23"]);
+        assert_eq!((page.total_items, page.total_pages), (24, 3));
+        assert!(store
+            .paginated_identities(4, 10)
+            .unwrap()
+            .identities
+            .is_empty());
+        drop(store);
+
+        let mut ssi_man = SsiMan::with_rocksdb(&path).unwrap();
+        assert_eq!(ssi_man.paginated_identities(1, 30).unwrap().total_items, 24);
+        ssi_man.sign("id03", "have a good day!", None).unwrap();
+        assert_eq!(ssi_man.identity_info("id03").unwrap().sign_count, 1);
+        drop(ssi_man);
+        DB::destroy(&Options::default(), &path).unwrap();
+    }
+}
@@ -0,0 +1,328 @@
+use std::{borrow::Cow, collections::BTreeSet, str::FromStr};
+
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::{
+    error::DisplayErrorContext, primitives::ByteStream, types::ServerSideEncryption, Client,
+};
+use serde::{Deserialize, Serialize};
+use ssi::{EncryptedSecret, Ssi};
+use tokio::runtime::{Builder, Runtime};
+use zeroize::Zeroizing;
+
+use crate::{Error, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability};
+
+/// Key prefix of the objects of [`SsiS3Store::new`].
+const DEFAULT_PREFIX: &str = "ssi-man";
+
+/// An identity as stored, in JSON: the ssi and the concealed secret in their text forms.
+#[derive(Deserialize, Serialize)]
+struct S3Record {
+    ssi: String,
+    secret: String,
+}
+
+/// A store keeping one object per identity in an S3 bucket, so that stateless functions
+/// share a set of identities.
+///
+/// Each object is named `<prefix>/<identity>`, with bytes other than letters, digits and
+/// `-_.@` written as `=XX`, and holds the ssi and the concealed secret in JSON. Objects
+/// are created with a conditional write, so two writers can't create the same identity,
+/// and encrypted at rest as [`SsiS3Store::sse_s3`] or [`SsiS3Store::sse_kms`] say, or
+/// as the bucket's default. Credentials and region come from the usual AWS environment,
+/// profile or instance role; other S3-compatible services need a client configured with
+/// their endpoint, see [`SsiS3Store::with_client`].
+pub struct SsiS3Store {
+    runtime: Runtime,
+    client: Client,
+    bucket: String,
+    prefix: String,
+    sse: Option<ServerSideEncryption>,
+    kms_key_id: Option<String>,
+}
+
+impl SsiS3Store {
+    /// Keeps identities in `bucket`, under `ssi-man/`.
+    pub fn new(bucket: &str) -> Result<Self, Error> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let config = runtime.block_on(aws_config::load_defaults(BehaviorVersion::latest()));
+        Ok(Self::with_client(bucket, Client::new(&config), runtime))
+    }
+
+    /// Keeps identities in `bucket` through `client`, which `runtime` drives.
+    pub fn with_client(bucket: &str, client: Client, runtime: Runtime) -> Self {
+        Self {
+            runtime,
+            client,
+            bucket: bucket.to_string(),
+            prefix: DEFAULT_PREFIX.to_string(),
+            sse: None,
+            kms_key_id: None,
+        }
+    }
+
+    /// Names objects `<prefix>/<identity>` rather than `ssi-man/<identity>`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Encrypts the objects written from now on with keys managed by S3.
+    pub fn sse_s3(mut self) -> Self {
+        self.sse = Some(ServerSideEncryption::Aes256);
+        self.kms_key_id = None;
+        self
+    }
+
+    /// Encrypts the objects written from now on with the KMS key `kms_key_id`, an id,
+    /// ARN or alias, or with the account's `aws/s3` key with `None`.
+    pub fn sse_kms(mut self, kms_key_id: Option<&str>) -> Self {
+        self.sse = Some(ServerSideEncryption::AwsKms);
+        self.kms_key_id = kms_key_id.map(str::to_string);
+        self
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}/{}", self.prefix, object_name(id))
+    }
+
+    /// Writes the object of `id`, only if it's missing with `create`.
+    fn put(
+        &self,
+        id: &str,
+        ssi: &Ssi,
+        secret: &EncryptedSecret,
+        create: bool,
+    ) -> Result<(), Error> {
+        let record = S3Record {
+            ssi: ssi.to_string(),
+            secret: secret.to_string(),
+        };
+        let json = serde_json::to_vec(&record).map_err(|err| Error::S3(err.to_string()))?;
+        let mut request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.key(id))
+            .content_type("application/json")
+            .body(ByteStream::from(json))
+            .set_server_side_encryption(self.sse.clone())
+            .set_ssekms_key_id(self.kms_key_id.clone());
+        if create {
+            request = request.if_none_match("*");
+        }
+        match self.runtime.block_on(request.send()) {
+            Ok(_) => Ok(()),
+            Err(err)
+                if create
+                    && err
+                        .raw_response()
+                        .is_some_and(|resp| resp.status().as_u16() == 412) =>
+            {
+                Err(Error::IdentityExists(id.to_string()))
+            }
+            Err(err) => Err(Error::S3(DisplayErrorContext(err).to_string())),
+        }
+    }
+}
+
+/// Escapes `id` into the characters safe in object names.
+fn object_name(id: &str) -> String {
+    let mut name = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.@".contains(&byte) {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("={byte:02X}"));
+        }
+    }
+    name
+}
+
+/// Reverses [`object_name`], returning `None` for names it can't have written.
+fn identity(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'=' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+impl SsiStore for SsiS3Store {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.put(&id, &ssi, &secret, true)
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let request = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(id))
+            .send();
+        let output = match self.runtime.block_on(request) {
+            Ok(output) => output,
+            Err(err)
+                if err
+                    .as_service_error()
+                    .is_some_and(|err| err.is_no_such_key()) =>
+            {
+                return Err(Error::UnknownIdentity(id.to_string()))
+            }
+            Err(err) => return Err(Error::S3(DisplayErrorContext(err).to_string())),
+        };
+        let json = Zeroizing::new(
+            self.runtime
+                .block_on(output.body.collect())
+                .map_err(|err| Error::S3(err.to_string()))?
+                .to_vec(),
+        );
+        let record: S3Record = serde_json::from_slice(&json)
+            .map_err(|err| Error::S3Record(format!("object of {id}: {err}")))?;
+        let secret = Zeroizing::new(record.secret);
+        Ok(Cow::Owned((
+            Ssi::from_str(&record.ssi)?,
+            EncryptedSecret::from_str(&secret)
+                .map_err(|err| Error::SecretParse(err.to_string()))?,
+        )))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        if !self.contains(id)? {
+            return Ok(false);
+        }
+        let request = self
+            .client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.key(id))
+            .send();
+        self.runtime
+            .block_on(request)
+            .map_err(|err| Error::S3(DisplayErrorContext(err).to_string()))?;
+        Ok(true)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        let request = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(id))
+            .send();
+        match self.runtime.block_on(request) {
+            Ok(_) => Ok(true),
+            Err(err) if err.as_service_error().is_some_and(|err| err.is_not_found()) => Ok(false),
+            Err(err) => Err(Error::S3(DisplayErrorContext(err).to_string())),
+        }
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        let start = format!("{}/", self.prefix);
+        // Escaped names don't sort like identities.
+        let mut identities = BTreeSet::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&start)
+            .into_paginator()
+            .send();
+        while let Some(page) = self.runtime.block_on(pages.next()) {
+            let page = page.map_err(|err| Error::S3(DisplayErrorContext(err).to_string()))?;
+            for object in page.contents() {
+                let name = object
+                    .key()
+                    .and_then(|key| key.strip_prefix(&start))
+                    .unwrap_or_default();
+                // Skips objects of deeper prefixes and others not written by this store.
+                if let Some(identity) = identity(name).filter(|id| object_name(id) == name) {
+                    identities.insert(identity);
+                }
+            }
+        }
+        identities.iter().try_for_each(|identity| f(identity))
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default().with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiS3Store {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.put(&id, &ssi, &secret, false)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.contains(id)? {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        self.put(id, &ssi, &secret, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    #[test]
+    fn object_names_should_escape_identities() {
+        assert_eq!(object_name("Luna"), "Luna");
+        assert_eq!(object_name("luna@bitlight.com"), "luna@bitlight.com");
+        assert_eq!(object_name("a/b c"), "a=2Fb=20c");
+        assert_eq!(object_name("ü"), "=C3=BC");
+        for id in ["Luna", "a/b c", "a=b", "ü"] {
+            assert_eq!(identity(&object_name(id)).as_deref(), Some(id));
+        }
+        assert_eq!(identity("a=2"), None);
+    }
+
+    /// Writes to the bucket `SSI_MAN_S3_BUCKET` of the AWS environment, and passes
+    /// without it.
+    #[test]
+    fn s3_store_should_share_identities() {
+        let Ok(bucket) = env::var("SSI_MAN_S3_BUCKET") else {
+            return;
+        };
+        let prefix = format!("ssi-man-test-{}", Utc::now().timestamp_nanos_opt().unwrap());
+        let store = || SsiS3Store::new(&bucket).unwrap().prefix(&prefix).sse_s3();
+        let mut ssi_man = SsiMan::with_store(Box::new(store()));
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let mut other = SsiMan::with_store(Box::new(store()));
+        assert!(matches!(
+            other.new_ssi("Luna", "luna@bitlightlabs.com", None),
+            Err(Error::IdentityExists(_))
+        ));
+        let ssi_cert = other.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(
+            ssi_man.paginated_identities(1, 10).unwrap().identities,
+            vec!["Luna".to_string()]
+        );
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        assert_eq!(other.remove("Luna"), Ok(false));
+    }
+}
@@ -1,9 +1,25 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    settings (key) {
+        key -> Text,
+        value -> Text,
+    }
+}
+
 diesel::table! {
     ssi_secrets (id) {
         id -> Text,
         ssi -> Text,
         secret -> Text,
+        created_at -> Text,
+        last_used_at -> Nullable<Text>,
+        sign_count -> BigInt,
+        needs_rewrap -> Bool,
+        fingerprint -> Nullable<Text>,
+        wrapped_key -> Nullable<Binary>,
+        updated_at -> Nullable<Text>,
     }
 }
+
+diesel::allow_tables_to_appear_in_same_query!(settings, ssi_secrets,);
@@ -0,0 +1,319 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use ::secret_service::{blocking::SecretService, EncryptionType};
+use ssi::{EncryptedSecret, Ssi};
+use zeroize::Zeroizing;
+
+use crate::{Error, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability};
+
+/// Application attribute of the items of [`SsiSecretServiceStore::new`].
+const DEFAULT_APPLICATION: &str = "ssi-man";
+
+/// A store keeping concealed secrets in the freedesktop Secret Service, e.g. GNOME
+/// Keyring or KWallet, and only the public ssis in a local index file.
+///
+/// Each secret is an item of the default collection with the `application` and
+/// `identity` attributes, and the index holds `identity<TAB>ssi` lines. Without a
+/// keyring daemon, e.g. on a headless server, the store falls back to keeping secrets in
+/// the index too, as `identity<TAB>ssi<TAB>secret` lines, still concealed by their
+/// password; see [`SsiSecretServiceStore::uses_keyring`]. Secrets found in the index are
+/// used even once a keyring is available, and moved there on their next write.
+pub struct SsiSecretServiceStore {
+    index_path: PathBuf,
+    application: String,
+    keyring: Option<SecretService<'static>>,
+    index: BTreeMap<String, (Ssi, Option<EncryptedSecret>)>,
+}
+
+impl SsiSecretServiceStore {
+    /// Opens the index file at `index_path`, creating it if missing, with secrets in
+    /// `ssi-man` items.
+    pub fn new(index_path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::with_application(index_path, DEFAULT_APPLICATION)
+    }
+
+    /// Opens the index file at `index_path` with secrets in items of the `application`
+    /// attribute, e.g. to keep the identities of several apps apart.
+    pub fn with_application(
+        index_path: impl AsRef<Path>,
+        application: &str,
+    ) -> Result<Self, Error> {
+        // Any failure here means there is no usable daemon on the session bus.
+        let keyring = SecretService::connect(EncryptionType::Dh).ok();
+        Self::open(index_path.as_ref(), application, keyring)
+    }
+
+    fn open(
+        index_path: &Path,
+        application: &str,
+        keyring: Option<SecretService<'static>>,
+    ) -> Result<Self, Error> {
+        let index_path = index_path.to_path_buf();
+        let text = match fs::read_to_string(&index_path) {
+            Ok(text) => Zeroizing::new(text),
+            Err(err) if err.kind() == ErrorKind::NotFound => Zeroizing::new(String::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut index = BTreeMap::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let mut fields = line.splitn(3, '\t');
+            let (Some(identity), Some(ssi)) = (fields.next(), fields.next()) else {
+                return Err(Error::SecretServiceIndex("line without an ssi".to_string()));
+            };
+            let secret = fields
+                .next()
+                .map(EncryptedSecret::from_str)
+                .transpose()
+                .map_err(|err| Error::SecretParse(err.to_string()))?;
+            index.insert(identity.to_string(), (Ssi::from_str(ssi)?, secret));
+        }
+        let store = Self {
+            index_path,
+            application: application.to_string(),
+            keyring,
+            index,
+        };
+        store.save(&store.index)?;
+        Ok(store)
+    }
+
+    /// Whether secrets are written to the Secret Service, rather than to the index for
+    /// lack of a keyring daemon.
+    pub fn uses_keyring(&self) -> bool {
+        self.keyring.is_some()
+    }
+
+    /// Writes `index` through a temporary file renamed over the index file.
+    fn save(&self, index: &BTreeMap<String, (Ssi, Option<EncryptedSecret>)>) -> Result<(), Error> {
+        let mut temporary = self.index_path.clone().into_os_string();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        for (identity, (ssi, secret)) in index {
+            match secret {
+                Some(secret) => writeln!(file, "{identity}\t{ssi}\t{secret}")?,
+                None => writeln!(file, "{identity}\t{ssi}")?,
+            }
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, &self.index_path)?;
+        Ok(())
+    }
+
+    fn attributes<'a>(&'a self, id: &'a str) -> HashMap<&'a str, &'a str> {
+        HashMap::from([("application", self.application.as_str()), ("identity", id)])
+    }
+
+    fn secret(&self, keyring: &SecretService, id: &str) -> Result<EncryptedSecret, Error> {
+        let collection = keyring.get_default_collection()?;
+        let item = collection
+            .search_items(self.attributes(id))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::SecretServiceIndex(format!("secret of {id} is missing")))?;
+        if item.is_locked()? {
+            item.unlock()?;
+        }
+        let bytes = Zeroizing::new(item.get_secret()?);
+        let text = std::str::from_utf8(&bytes)
+            .map_err(|err| Error::SecretServiceIndex(format!("secret of {id}: {err}")))?;
+        EncryptedSecret::from_str(text).map_err(|err| Error::SecretParse(err.to_string()))
+    }
+
+    fn set_secret(
+        &self,
+        keyring: &SecretService,
+        id: &str,
+        secret: &EncryptedSecret,
+    ) -> Result<(), Error> {
+        let collection = keyring.get_default_collection()?;
+        if collection.is_locked()? {
+            collection.unlock()?;
+        }
+        let secret = Zeroizing::new(secret.to_string());
+        collection.create_item(
+            &format!("{}: {id}", self.application),
+            self.attributes(id),
+            secret.as_bytes(),
+            true,
+            "text/plain",
+        )?;
+        Ok(())
+    }
+
+    fn delete_secret(&self, keyring: &SecretService, id: &str) -> Result<(), Error> {
+        let collection = keyring.get_default_collection()?;
+        for item in collection.search_items(self.attributes(id))? {
+            item.delete()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index with `id` set to `ssi`, and its secret before it when there is
+    /// a keyring.
+    fn put(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let secret = match &self.keyring {
+            Some(keyring) => {
+                self.set_secret(keyring, &id, &secret)?;
+                None
+            }
+            None => Some(secret),
+        };
+        let mut index = self.index.clone();
+        index.insert(id, (ssi, secret));
+        self.save(&index)?;
+        self.index = index;
+        Ok(())
+    }
+}
+
+impl SsiStore for SsiSecretServiceStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if self.index.contains_key(&id) {
+            return Err(Error::IdentityExists(id));
+        }
+        let added = id.clone();
+        let result = self.put(id, ssi, secret);
+        if let (Err(_), Some(keyring)) = (&result, &self.keyring) {
+            // Not indexed, so not in use: don't leave it behind.
+            let _ = self.delete_secret(keyring, &added);
+        }
+        result
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let (ssi, secret) = self
+            .index
+            .get(id)
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+        let secret = match (secret, &self.keyring) {
+            (Some(secret), _) => secret.clone(),
+            (None, Some(keyring)) => self.secret(keyring, id)?,
+            (None, None) => {
+                return Err(Error::SecretServiceIndex(format!(
+                    "secret of {id} is in a keyring that isn't running"
+                )))
+            }
+        };
+        Ok(Cow::Owned((ssi.clone(), secret)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        if !self.index.contains_key(id) {
+            return Ok(false);
+        }
+        let mut index = self.index.clone();
+        index.remove(id);
+        self.save(&index)?;
+        self.index = index;
+        if let Some(keyring) = &self.keyring {
+            self.delete_secret(keyring, id)?;
+        }
+        Ok(true)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.index.contains_key(id))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        for identity in self.index.keys() {
+            f(identity)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default().with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiSecretServiceStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.put(id, ssi, secret)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.index.contains_key(id) {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        self.put(id.to_string(), ssi, secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    fn temp_index_path() -> PathBuf {
+        let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        env::temp_dir().join(format!("ssi_man_{nanos}.index"))
+    }
+
+    #[test]
+    fn secret_service_store_should_fall_back_to_the_index() {
+        let index_path = temp_index_path();
+        let store = SsiSecretServiceStore::open(&index_path, DEFAULT_APPLICATION, None).unwrap();
+        assert!(!store.uses_keyring());
+        let mut ssi_man = SsiMan::with_store(Box::new(store));
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let (_, secret) = ssi_man.store.get("Luna").unwrap().into_owned();
+        let index = fs::read_to_string(&index_path).unwrap();
+        assert_eq!(index, format!("Luna\t{ssi}\t{secret}\n"));
+        drop(ssi_man);
+
+        let store = SsiSecretServiceStore::open(&index_path, DEFAULT_APPLICATION, None).unwrap();
+        let mut ssi_man = SsiMan::with_store(Box::new(store));
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        fs::remove_file(index_path).unwrap();
+    }
+
+    /// Writes to the session keyring, so only runs with `SSI_MAN_SECRET_SERVICE_TESTS`
+    /// set, and passes otherwise.
+    #[test]
+    fn secret_service_store_should_keep_secrets_out_of_the_index() {
+        if env::var_os("SSI_MAN_SECRET_SERVICE_TESTS").is_none() {
+            return;
+        }
+        let index_path = temp_index_path();
+        let application = format!("ssi-man-test-{}", Utc::now().timestamp_nanos_opt().unwrap());
+        let store = SsiSecretServiceStore::with_application(&index_path, &application).unwrap();
+        assert!(store.uses_keyring());
+        let mut ssi_man = SsiMan::with_store(Box::new(store));
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let index = fs::read_to_string(&index_path).unwrap();
+        assert_eq!(index, format!("Luna\t{ssi}\n"));
+        drop(ssi_man);
+
+        let store = SsiSecretServiceStore::with_application(&index_path, &application).unwrap();
+        let mut ssi_man = SsiMan::with_store(Box::new(store));
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        fs::remove_file(index_path).unwrap();
+    }
+}
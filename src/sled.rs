@@ -0,0 +1,436 @@
+use std::{borrow::Cow, path::Path, str::FromStr, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    Db, Transactional, Tree,
+};
+use ssi::{EncryptedSecret, Ssi};
+
+use crate::{
+    clock::system_clock, fingerprint, Clock, ConflictPolicy, Error, IdentityFingerprint,
+    IdentityMetadata, JournalCapable, Page, SsiStore, SsiStoreExt, StoreCapabilities,
+    StoreCapability, StoredIdentity, FORMAT_VERSION,
+};
+
+/// Tree of the records, keyed by identity, so keys iterate in byte-wise order.
+const RECORDS_TREE: &str = "ssi_secrets";
+const SETTINGS_TREE: &str = "settings";
+const FORMAT_VERSION_KEY: &str = "format_version";
+/// Settings key of the number of source records done by an unfinished ingestion.
+const INGEST_PROGRESS_KEY: &str = "ingest_progress";
+
+/// An identity as stored, in JSON: the ssi and secret in their text forms, with the
+/// metadata the sqlite store keeps in its columns.
+#[derive(Deserialize, Serialize)]
+struct SledRecord {
+    ssi: String,
+    secret: String,
+    created_at: DateTime<Utc>,
+    /// `None` until the ssi or secret is first changed, as in records written before it
+    /// was kept.
+    updated_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    sign_count: u64,
+    needs_rewrap: bool,
+    fingerprint: String,
+    wrapped_key: Option<Vec<u8>>,
+}
+
+impl SledRecord {
+    fn new(ssi: &Ssi, secret: &EncryptedSecret, created_at: DateTime<Utc>) -> Self {
+        Self {
+            ssi: ssi.to_string(),
+            secret: secret.to_string(),
+            created_at,
+            updated_at: None,
+            last_used_at: None,
+            sign_count: 0,
+            needs_rewrap: false,
+            fingerprint: fingerprint(ssi),
+            wrapped_key: None,
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(bytes).map_err(|err| Error::SledRecord(err.to_string()))
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(self).map_err(|err| Error::SledRecord(err.to_string()))
+    }
+
+    fn metadata(&self) -> IdentityMetadata {
+        IdentityMetadata {
+            created_at: self.created_at,
+            updated_at: self.updated_at.unwrap_or(self.created_at),
+            last_used_at: self.last_used_at,
+            sign_count: self.sign_count,
+            needs_rewrap: self.needs_rewrap,
+        }
+    }
+}
+
+fn identity(key: &[u8]) -> Result<String, Error> {
+    String::from_utf8(key.to_vec()).map_err(|err| Error::SledRecord(err.to_string()))
+}
+
+fn transaction_error(err: TransactionError<Error>) -> Error {
+    match err {
+        TransactionError::Abort(err) => err,
+        TransactionError::Storage(err) => err.into(),
+    }
+}
+
+/// A store in a [sled](https://docs.rs/sled) database, in pure Rust, for targets where
+/// linking sqlite is a burden, e.g. musl or embedded ones.
+///
+/// Records are kept in key order, so listings and pages come out byte-wise sorted like
+/// with the other stores, with the same metadata. Writes reach the disk within half a
+/// second, or at once with [`SsiSledStore::flush`]. A database can only be opened by one
+/// process at a time.
+pub struct SsiSledStore {
+    db: Db,
+    records: Tree,
+    settings: Tree,
+    clock: Arc<dyn Clock>,
+}
+
+impl SsiSledStore {
+    /// Opens the database in the directory at `path`, creating it if missing.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::with_db(sled::open(path)?)
+    }
+
+    /// Uses an open database, e.g. a temporary one or one configured with
+    /// [`sled::Config`].
+    pub fn with_db(db: Db) -> Result<Self, Error> {
+        let store = Self {
+            records: db.open_tree(RECORDS_TREE)?,
+            settings: db.open_tree(SETTINGS_TREE)?,
+            db,
+            clock: system_clock(),
+        };
+        // A fresh database starts at the current format; sled ones never had another.
+        store.settings.compare_and_swap(
+            FORMAT_VERSION_KEY,
+            None::<&[u8]>,
+            Some(FORMAT_VERSION.to_string().as_bytes()),
+        )?;
+        let found = store.format()?;
+        if found > FORMAT_VERSION {
+            return Err(Error::FormatTooNew {
+                found,
+                supported: FORMAT_VERSION,
+            });
+        }
+        Ok(store)
+    }
+
+    /// Writes every change made so far to disk.
+    pub fn flush(&self) -> Result<(), Error> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn format(&self) -> Result<u32, Error> {
+        let value = self.settings.get(FORMAT_VERSION_KEY)?.unwrap_or_default();
+        String::from_utf8_lossy(&value)
+            .parse()
+            .map_err(|_| Error::SledRecord("invalid format version".to_string()))
+    }
+
+    fn record(&self, id: &str) -> Result<SledRecord, Error> {
+        let bytes = self
+            .records
+            .get(id)?
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+        SledRecord::decode(&bytes)
+    }
+
+    /// Applies `change` to the record of `id`, retrying if another writer got there
+    /// first.
+    fn modify(&self, id: &str, change: impl Fn(&mut SledRecord)) -> Result<(), Error> {
+        loop {
+            let old = self
+                .records
+                .get(id)?
+                .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+            let mut record = SledRecord::decode(&old)?;
+            change(&mut record);
+            let swapped = self
+                .records
+                .compare_and_swap(id, Some(&old), Some(record.encode()?))?;
+            if swapped.is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Calls `f` with every record in identity order.
+    fn for_each_record(
+        &self,
+        mut f: impl FnMut(String, SledRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for entry in self.records.iter() {
+            let (key, value) = entry?;
+            f(identity(&key)?, SledRecord::decode(&value)?)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes `records` as created now, keyed by identity.
+    fn encode_all(&self, records: &[StoredIdentity]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let created_at = self.clock.now();
+        records
+            .iter()
+            .map(|record| {
+                let value = SledRecord::new(&record.ssi, &record.encrypted_secret, created_at);
+                Ok((record.identity.clone(), value.encode()?))
+            })
+            .collect()
+    }
+}
+
+impl SsiStore for SsiSledStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let record = SledRecord::new(&ssi, &secret, self.clock.now()).encode()?;
+        self.records
+            .compare_and_swap(&id, None::<&[u8]>, Some(record))?
+            .map_err(|_| Error::IdentityExists(id))
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let record = self.record(id)?;
+        let secret = EncryptedSecret::from_str(&record.secret)
+            .map_err(|err| Error::SecretParse(err.to_string()))?;
+        Ok(Cow::Owned((Ssi::from_str(&record.ssi)?, secret)))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        Ok(self.records.remove(id)?.is_some())
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.records.contains_key(id)?)
+    }
+
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error> {
+        let offset = Page::offset(page, per_page)?;
+        let identities = self
+            .records
+            .iter()
+            .keys()
+            .skip(offset)
+            .take(per_page)
+            .map(|key| identity(&key?))
+            .collect::<Result<_, _>>()?;
+        Ok(Page::new(identities, self.records.len(), per_page))
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        for key in self.records.iter().keys() {
+            f(&identity(&key?)?)?;
+        }
+        Ok(())
+    }
+
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error> {
+        let mut fingerprints = Vec::new();
+        self.for_each_record(|identity, record| {
+            fingerprints.push(IdentityFingerprint {
+                identity,
+                fingerprint: record.fingerprint,
+            });
+            Ok(())
+        })?;
+        Ok(fingerprints)
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default()
+            .with(StoreCapability::AtomicWrites)
+            .with(StoreCapability::Metadata)
+            .with(StoreCapability::PlatformProtection)
+            .with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiSledStore {
+    fn format_version(&self) -> Result<u32, Error> {
+        self.format()
+    }
+
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let record = SledRecord::new(&ssi, &secret, self.clock.now()).encode()?;
+        self.records.insert(id, record)?;
+        Ok(())
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let (ssi, fingerprint, secret) = (ssi.to_string(), fingerprint(&ssi), secret.to_string());
+        let now = self.clock.now();
+        self.modify(id, |record| {
+            record.ssi.clone_from(&ssi);
+            record.fingerprint.clone_from(&fingerprint);
+            record.secret.clone_from(&secret);
+            record.updated_at = Some(now);
+        })
+    }
+
+    fn metadata(&self, id: &str) -> Result<IdentityMetadata, Error> {
+        Ok(self.record(id)?.metadata())
+    }
+
+    fn record_signatures(&mut self, id: &str, count: u64, at: DateTime<Utc>) -> Result<(), Error> {
+        self.modify(id, |record| {
+            record.last_used_at = Some(at);
+            record.sign_count += count;
+        })
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        let mut identities = Vec::new();
+        self.for_each_record(|identity, record| {
+            if record.metadata().last_activity() < cutoff {
+                identities.push(identity);
+            }
+            Ok(())
+        })?;
+        Ok(identities)
+    }
+
+    fn set_needs_rewrap(&mut self, id: &str, needs_rewrap: bool) -> Result<(), Error> {
+        self.modify(id, |record| record.needs_rewrap = needs_rewrap)
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        let mut identities = Vec::new();
+        self.for_each_record(|identity, record| {
+            if record.needs_rewrap {
+                identities.push(identity);
+            }
+            Ok(())
+        })?;
+        Ok(identities)
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn wrapped_key(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.records.get(id)? {
+            Some(bytes) => Ok(SledRecord::decode(&bytes)?.wrapped_key),
+            None => Ok(None),
+        }
+    }
+
+    fn set_wrapped_key(&mut self, id: &str, wrapped_key: Vec<u8>) -> Result<(), Error> {
+        self.modify(id, |record| record.wrapped_key = Some(wrapped_key.clone()))
+    }
+}
+
+impl JournalCapable for SsiSledStore {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        let encoded = self.encode_all(&records)?;
+        self.records
+            .transaction(|tx| {
+                let mut imported = 0;
+                for (id, value) in &encoded {
+                    if tx.get(id)?.is_some() {
+                        match on_conflict {
+                            ConflictPolicy::Skip => continue,
+                            ConflictPolicy::Overwrite => {}
+                            ConflictPolicy::Error => {
+                                return Err(ConflictableTransactionError::Abort(
+                                    Error::IdentityExists(id.clone()),
+                                ));
+                            }
+                        }
+                    }
+                    tx.insert(id.as_bytes(), value.as_slice())?;
+                    imported += 1;
+                }
+                Ok(imported)
+            })
+            .map_err(transaction_error)
+    }
+
+    fn ingest_chunk(&mut self, records: Vec<StoredIdentity>, ingested: usize) -> Result<(), Error> {
+        let encoded = self.encode_all(&records)?;
+        let progress = ingested.to_string();
+        (&self.records, &self.settings)
+            .transaction(|(records, settings)| {
+                for (id, value) in &encoded {
+                    records.insert(id.as_bytes(), value.as_slice())?;
+                }
+                settings.insert(INGEST_PROGRESS_KEY, progress.as_bytes())?;
+                Ok(())
+            })
+            .map_err(transaction_error)
+    }
+
+    fn ingest_progress(&mut self) -> Result<Option<usize>, Error> {
+        self.settings
+            .get(INGEST_PROGRESS_KEY)?
+            .map(|value| {
+                String::from_utf8_lossy(&value)
+                    .parse()
+                    .map_err(|_| Error::SledRecord("invalid ingestion progress".to_string()))
+            })
+            .transpose()
+    }
+
+    fn finish_ingest(&mut self) -> Result<(), Error> {
+        self.settings.remove(INGEST_PROGRESS_KEY)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store_tests, SsiMan};
+
+    fn temporary() -> SsiSledStore {
+        SsiSledStore::with_db(sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn sled_store_should_reject_duplicates_atomically() {
+        store_tests::should_reject_duplicates_atomically(&mut temporary());
+    }
+
+    #[test]
+    fn sled_store_should_reopen_with_its_records() {
+        let path = std::env::temp_dir().join(format!(
+            "ssi_man_sled_{}",
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        store_tests::should_reopen_with_its_records(|| SsiMan::with_sled(&path).unwrap());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn sled_ingest_should_record_progress() {
+        store_tests::should_record_ingest_progress(SsiMan::with_store(Box::new(temporary())));
+    }
+}
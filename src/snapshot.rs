@@ -0,0 +1,358 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Debug, Formatter},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(all(feature = "serde", feature = "digest"))]
+use sha2::{Digest, Sha256};
+use ssi::{EncryptedSecret, Ssi};
+
+#[cfg(feature = "serde")]
+use crate::{creation::CreationRequest, Compression, OpContext};
+use crate::{redact::redact, Error, SsiMan, SsiStoreExt};
+
+/// One identity of a store snapshot, with its secret still concealed.
+///
+/// The ssi and the secret are serialized in their text forms.
+#[derive(Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StoredIdentity {
+    pub identity: String,
+    #[cfg_attr(feature = "serde", serde(with = "text"))]
+    pub ssi: Ssi,
+    #[cfg_attr(feature = "serde", serde(with = "text"))]
+    pub encrypted_secret: EncryptedSecret,
+}
+
+impl Debug for StoredIdentity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoredIdentity")
+            .field("identity", &redact(&self.identity))
+            .field("ssi", &redact(&self.ssi.to_string()))
+            .finish_non_exhaustive()
+    }
+}
+
+/// What [`SsiMan::import_all`] does with identities already present in the store.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// Keeps the stored identity and leaves out the imported one.
+    Skip,
+    /// Replaces the stored identity with the imported one.
+    Overwrite,
+    /// Aborts the whole import with [`Error::IdentityExists`].
+    #[default]
+    Error,
+}
+
+/// Imports `records` one by one after checking every conflict, so nothing is written if
+/// the import fails with [`ConflictPolicy::Error`].
+///
+/// Used by stores rolling back on their own if this fails, returning the number of
+/// imported identities.
+pub(crate) fn import_records(
+    store: &mut (impl SsiStoreExt + ?Sized),
+    records: Vec<StoredIdentity>,
+    on_conflict: ConflictPolicy,
+) -> Result<usize, Error> {
+    let mut seen = HashSet::new();
+    let mut pending = Vec::with_capacity(records.len());
+    for record in records {
+        let exists = !seen.insert(record.identity.clone()) || store.contains(&record.identity)?;
+        match on_conflict {
+            _ if !exists => pending.push(record),
+            ConflictPolicy::Skip => {}
+            ConflictPolicy::Overwrite => pending.push(record),
+            ConflictPolicy::Error => return Err(Error::IdentityExists(record.identity)),
+        }
+    }
+    let imported = pending.len();
+    for record in pending {
+        store.replace(record.identity, record.ssi, record.encrypted_secret)?;
+    }
+    Ok(imported)
+}
+
+impl SsiMan {
+    /// Returns every identity with its record, sorted by identity, e.g. to back the store
+    /// up or rebuild it on another backend with [`SsiMan::restore`].
+    ///
+    /// Metadata isn't dumped: creation and usage times, signature counts and rewrap flags
+    /// are lost. Fails with [`Error::DumpPlatformProtected`] naming the
+    /// [`Protection::Platform`](crate::Protection::Platform) identities, whose keys can't
+    /// leave the device, if there are any.
+    pub fn dump(&self) -> Result<Vec<StoredIdentity>, Error> {
+        let mut identities = Vec::new();
+        self.store.for_each_identity(&mut |identity| {
+            identities.push(identity.to_string());
+            Ok(())
+        })?;
+        let mut protected = Vec::new();
+        for identity in &identities {
+            if self.wrapped_key(identity)?.is_some() {
+                protected.push(identity.clone());
+            }
+        }
+        if !protected.is_empty() {
+            return Err(Error::DumpPlatformProtected(protected));
+        }
+        let mut records = Vec::with_capacity(identities.len());
+        for identity in identities {
+            let (ssi, encrypted_secret) = self.store.get(&identity)?.into_owned();
+            records.push(StoredIdentity {
+                identity,
+                ssi,
+                encrypted_secret,
+            });
+        }
+        Ok(records)
+    }
+
+    /// Adds the records of [`SsiMan::dump`] to the store, failing with
+    /// [`Error::RestoreTargetNotEmpty`] if it already holds identities.
+    ///
+    /// The records are written all or none through
+    /// [`JournalCapable::import_batch`](crate::JournalCapable::import_batch), so stores
+    /// without [`StoreCapability::AtomicWrites`](crate::StoreCapability::AtomicWrites)
+    /// fail with [`Error::Unsupported`]. Metadata is reset, as if the identities were
+    /// created now: never used, no signatures and no rewrap flag.
+    pub fn restore(&mut self, records: Vec<StoredIdentity>) -> Result<(), Error> {
+        let journal = self.journal()?;
+        if !journal.all_identities()?.is_empty() {
+            return Err(Error::RestoreTargetNotEmpty);
+        }
+        journal
+            .import_batch(records, ConflictPolicy::Error)
+            .map(drop)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl SsiMan {
+    /// Exports every identity as JSON lines of [`StoredIdentity`], sorted by the bytes of
+    /// the identity, with fields in declaration order.
+    ///
+    /// Secrets stay concealed with their own passwords, so no password is needed. Stores
+    /// holding the same records export the same text, whatever their backend or the order
+    /// the records were written in.
+    ///
+    /// Like [`SsiMan::dump`], fails with [`Error::DumpPlatformProtected`] naming the
+    /// [`Protection::Platform`](crate::Protection::Platform) identities, whose keys can't
+    /// leave the device, if there are any.
+    pub fn export_all(&self) -> Result<String, Error> {
+        self.export_all_with_ctx(&OpContext::default())
+    }
+
+    /// Same as [`SsiMan::export_all`], stopping before the next record once `ctx` is
+    /// cancelled or past its deadline. An export only reads, so the store is left as it
+    /// was.
+    pub fn export_all_with_ctx(&self, ctx: &OpContext) -> Result<String, Error> {
+        let mut identities = ctx
+            .interrupting(|| self.store.all_identities(), || 0)?
+            .into_iter()
+            .map(|identity| identity.into_owned())
+            .collect::<Vec<_>>();
+        identities.sort();
+        let mut json = String::new();
+        let mut protected = Vec::new();
+        for (done, identity) in identities.into_iter().enumerate() {
+            ctx.check(done)?;
+            if self.wrapped_key(&identity)?.is_some() {
+                protected.push(identity);
+                continue;
+            }
+            let (ssi, encrypted_secret) = self.store.get(&identity)?.into_owned();
+            let record = StoredIdentity {
+                identity,
+                ssi,
+                encrypted_secret,
+            };
+            json.push_str(&serde_json::to_string(&record).expect("snapshot is serializable"));
+            json.push('\n');
+        }
+        if !protected.is_empty() {
+            return Err(Error::DumpPlatformProtected(protected));
+        }
+        Ok(json)
+    }
+
+    /// Returns the hex SHA-256 digest of [`SsiMan::export_all`], so stores can be compared
+    /// without keeping their exports around.
+    ///
+    /// Usage metadata isn't exported, so signing doesn't change the digest.
+    #[cfg(feature = "digest")]
+    pub fn export_digest(&self) -> Result<String, Error> {
+        Ok(Sha256::digest(self.export_all()?)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect())
+    }
+
+    /// Same as [`SsiMan::export_all`], encoded with `compression` behind a one-byte codec
+    /// header, for [`SsiMan::import_all_bytes`]. Large stores shrink several times with
+    /// zstd, mostly from the repeated text of their records.
+    pub fn export_all_compressed(&self, compression: Compression) -> Result<Vec<u8>, Error> {
+        compression.encode(self.export_all()?.as_bytes())
+    }
+
+    /// Imports the output of [`SsiMan::export_all_compressed`], whatever its codec, or the
+    /// plain text of [`SsiMan::export_all`], like [`SsiMan::import_all`].
+    pub fn import_all_bytes(
+        &mut self,
+        bytes: &[u8],
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        let json = String::from_utf8(Compression::decode(bytes)?)
+            .map_err(|err| Error::Decompression(err.to_string()))?;
+        self.import_all(&json, on_conflict)
+    }
+
+    /// Imports JSON lines produced by [`SsiMan::export_all`], returning the number of
+    /// imported identities.
+    ///
+    /// Every record is parsed and checked before anything is written, then written all
+    /// or none, so a failed import changes nothing. Stores without
+    /// [`StoreCapability::AtomicWrites`](crate::StoreCapability::AtomicWrites) fail with
+    /// [`Error::Unsupported`].
+    pub fn import_all(&mut self, json: &str, on_conflict: ConflictPolicy) -> Result<usize, Error> {
+        let mut records = Vec::new();
+        for (index, line) in json.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str::<StoredIdentity>(line).map_err(|err| {
+                Error::SnapshotParse {
+                    line: index + 1,
+                    reason: err.to_string(),
+                }
+            })?;
+            if record.encrypted_secret.fp != record.ssi.pk.fingerprint() {
+                return Err(Error::BackupKeyMismatch(record.identity));
+            }
+            self.check_creation(&CreationRequest::from_ssi(
+                record.identity.clone(),
+                &record.ssi,
+            ))?;
+            records.push(record);
+        }
+        self.journal()?.import_batch(records, on_conflict)
+    }
+}
+
+/// Serializes a field through its `Display` and `FromStr` text form.
+#[cfg(feature = "serde")]
+mod text {
+    use std::{fmt::Display, str::FromStr};
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        T::from_str(&text).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_identity_should_use_text_forms() {
+        let mut ssi_man = SsiMan::with_memory();
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", Some("secret"))
+            .unwrap();
+        let json = ssi_man.export_all().unwrap();
+        let value: serde_json::Value = serde_json::from_str(json.trim()).unwrap();
+        assert_eq!(value["identity"], "Luna");
+        assert_eq!(value["ssi"], ssi);
+        assert!(value["encrypted_secret"].is_string());
+
+        assert!(matches!(
+            SsiMan::with_memory().import_all("\n{\"identity\": 1}\n", ConflictPolicy::Skip),
+            Err(Error::SnapshotParse { line: 2, .. })
+        ));
+    }
+
+    fn store_of(count: usize) -> SsiMan {
+        let mut ssi_man = SsiMan::with_memory();
+        for n in 0..count {
+            ssi_man
+                .new_ssi(
+                    &format!("id{n:04}"),
+                    &format!("id{n:04}@bitlightlabs.com"),
+                    None,
+                )
+                .unwrap();
+        }
+        ssi_man
+    }
+
+    #[test]
+    fn compressed_snapshot_should_round_trip() {
+        let source = store_of(3);
+        let json = source.export_all().unwrap();
+        let codecs = [
+            Compression::None,
+            #[cfg(feature = "compression")]
+            Compression::Zstd,
+        ];
+        for codec in codecs {
+            let bytes = source.export_all_compressed(codec).unwrap();
+            let mut target = SsiMan::with_memory();
+            assert_eq!(
+                target.import_all_bytes(&bytes, ConflictPolicy::Error),
+                Ok(3)
+            );
+            assert_eq!(target.export_all(), Ok(json.clone()));
+        }
+
+        let mut target = SsiMan::with_memory();
+        assert_eq!(
+            target.import_all_bytes(json.as_bytes(), ConflictPolicy::Error),
+            Ok(3)
+        );
+        assert!(matches!(
+            target.import_all_bytes(&[0, 0xff, 0xfe], ConflictPolicy::Error),
+            Err(Error::Decompression(_))
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn zstd_should_shrink_large_snapshots() {
+        let ssi_man = store_of(1000);
+        let plain = ssi_man.export_all_compressed(Compression::None).unwrap();
+        let compressed = ssi_man.export_all_compressed(Compression::Zstd).unwrap();
+        assert!(
+            compressed.len() * 5 < plain.len() * 4,
+            "{} compressed to {}",
+            plain.len(),
+            compressed.len()
+        );
+
+        let mut corrupted = compressed.clone();
+        let middle = corrupted.len() / 2;
+        corrupted[middle..]
+            .iter_mut()
+            .for_each(|byte| *byte = !*byte);
+        assert!(matches!(
+            SsiMan::with_memory().import_all_bytes(&corrupted, ConflictPolicy::Error),
+            Err(Error::Decompression(_))
+        ));
+    }
+}
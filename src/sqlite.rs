@@ -1,24 +1,74 @@
 use std::{
     borrow::Cow,
+    cell::Cell,
+    env,
+    ffi::{c_char, c_int, c_void},
     fmt::{Debug, Display, Formatter},
+    fs, io,
+    io::{Read, Write},
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    ptr,
     str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
 };
 
+use chrono::{DateTime, SecondsFormat, Utc};
 use diesel::{
+    connection::{AnsiTransactionManager, DefaultLoadingMode, TransactionManager},
     deserialize::{FromSql, FromSqlRow},
-    dsl::count_star,
+    dsl::{count_star, exists},
     expression::AsExpression,
     prelude::*,
+    r2d2::{ConnectionManager, CustomizeConnection, ManageConnection, Pool, PooledConnection},
     serialize::{IsNull, Output, ToSql},
-    sql_types::Text,
+    sql_types::{Nullable, Text},
     sqlite::{Sqlite, SqliteValue},
 };
 use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
-use ssi::{EncryptedSecret, Ssi};
+use libsqlite3_sys::{
+    sqlite3, sqlite3_api_routines, sqlite3_next_stmt, sqlite3_progress_handler, sqlite3_stmt_busy,
+    sqlite3_stmt_readonly, SQLITE_OK,
+};
+use ssi::{EncryptedSecret, Ssi, SsiPub};
 
-use crate::{Error, SsiStore};
+use crate::{
+    clock::system_clock,
+    context::{note_interrupted, stop_requested},
+    fingerprint, matches_query, Clock, ConflictPolicy, Error, IdentityFingerprint,
+    IdentityMetadata, JournalCapable, Page, SnapshotCapable, SsiStore, SsiStoreExt,
+    StoreCapabilities, StoreCapability, StoredIdentity, FORMAT_VERSION,
+};
 
 const DIESEL_MIGRATIONS: EmbeddedMigrations = diesel_migrations::embed_migrations!("./migrations");
+const FORMAT_VERSION_KEY: &str = "format_version";
+/// Settings key of the number of source records done by an unfinished ingestion.
+const INGEST_PROGRESS_KEY: &str = "ingest_progress";
+/// Bytes of the random key of [`SsiSqliteStore::new_platform_keyed`] databases.
+#[cfg(feature = "sqlcipher")]
+const DATABASE_KEY_LEN: usize = 32;
+const DUMP_HEADER: &str = "-- ssi-man sql dump";
+const DUMP_VERSION_PREFIX: &str = "-- format version: ";
+const DUMP_SECRETS_PREFIX: &str = "-- secrets: ";
+/// Tables written by [`SsiSqliteStore::dump_sql`], including diesel's migration records
+/// so a restored database opens without re-running migrations.
+const DUMP_TABLES: [&str; 3] = ["__diesel_schema_migrations", "settings", "ssi_secrets"];
+/// Stands in for concealed secrets in dumps made without them.
+const REDACTED_SECRET: &str = "REDACTED";
+/// Oldest sqlite supported: migrations drop columns and diesel uses `RETURNING`, both
+/// from 3.35.
+const MIN_SQLITE_VERSION: [u32; 3] = [3, 35, 0];
+/// How long opening a database waits by default for another connection to finish
+/// migrating it.
+const MIGRATION_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Virtual machine instructions between two calls of the progress handler, a few
+/// milliseconds of work.
+const PROGRESS_PERIOD: c_int = 10_000;
+
+/// Data transformations, where the step at index `n` upgrades format `n` to `n + 1`.
+const FORMAT_UPGRADES: [fn(&mut SqliteConnection) -> Result<(), Error>; FORMAT_VERSION as usize] =
+    [canonicalize_records];
 
 #[derive(AsExpression, FromSqlRow)]
 #[diesel(sql_type = Text)]
@@ -81,124 +131,2394 @@ pub struct SsiSecret {
     id: String,
     ssi: SqliteTextWrapper<Ssi>,
     secret: SqliteTextWrapper<EncryptedSecret>,
+    created_at: String,
+    /// Fingerprint of the public key, `None` for rows written before it was cached.
+    fingerprint: Option<String>,
+}
+
+type SqlitePool = Pool<SqliteManager>;
+type PooledSqliteConnection = PooledConnection<SqliteManager>;
+/// A row of `ssi_secrets` as written by [`SsiSqliteStore::dump_sql`].
+type DumpedRecord = (
+    String,
+    String,
+    String,
+    String,
+    Option<String>,
+    i64,
+    bool,
+    Option<String>,
+    Option<String>,
+    Option<Vec<u8>>,
+);
+
+enum SqliteSource {
+    /// Locked by every query, reads included, as they only borrow the store.
+    Connection(Mutex<SqliteConnection>),
+    Pool(SqlitePool),
+}
+
+/// A connection of either kind of [`SqliteSource`], or the one pinned by a transaction.
+enum SqliteConn<'a> {
+    Locked(MutexGuard<'a, SqliteConnection>),
+    Pinned(MutexGuard<'a, Option<PooledSqliteConnection>>),
+    Pooled(PooledSqliteConnection),
+}
+
+impl Deref for SqliteConn<'_> {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            SqliteConn::Locked(conn) => conn,
+            SqliteConn::Pinned(conn) => conn.as_deref().expect("only handed out while pinned"),
+            SqliteConn::Pooled(conn) => conn,
+        }
+    }
+}
+
+impl DerefMut for SqliteConn<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            SqliteConn::Locked(conn) => conn,
+            SqliteConn::Pinned(conn) => conn.as_deref_mut().expect("only handed out while pinned"),
+            SqliteConn::Pooled(conn) => conn,
+        }
+    }
+}
+
+/// Opens pooled connections through [`establish`], checking them like diesel's manager.
+struct SqliteManager {
+    db_path: String,
+    diesel: ConnectionManager<SqliteConnection>,
+}
+
+impl SqliteManager {
+    fn new(db_path: String) -> Self {
+        Self {
+            diesel: ConnectionManager::new(&db_path),
+            db_path,
+        }
+    }
+}
+
+impl ManageConnection for SqliteManager {
+    type Connection = SqliteConnection;
+    type Error = diesel::r2d2::Error;
+
+    fn connect(&self) -> Result<SqliteConnection, Self::Error> {
+        establish(&self.db_path).map_err(diesel::r2d2::Error::ConnectionError)
+    }
+
+    fn is_valid(&self, conn: &mut SqliteConnection) -> Result<(), Self::Error> {
+        self.diesel.is_valid(conn)
+    }
+
+    fn has_broken(&self, conn: &mut SqliteConnection) -> bool {
+        self.diesel.has_broken(conn)
+    }
+}
+
+/// Lets pooled connections wait for each other instead of failing on a locked database.
+#[derive(Debug)]
+struct BusyTimeout;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for BusyTimeout {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        diesel::sql_query("PRAGMA busy_timeout = 5000")
+            .execute(conn)
+            .map(drop)
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Entry point of an sqlite extension, with the arguments sqlite calls it with.
+type ExtensionEntry =
+    unsafe extern "C" fn(*mut sqlite3, *mut *mut c_char, *const sqlite3_api_routines) -> c_int;
+
+// Declared with the entry point sqlite calls, where libsqlite3-sys declares one taking
+// nothing.
+extern "C" {
+    #[link_name = "sqlite3_auto_extension"]
+    fn register_extension(entry: ExtensionEntry) -> c_int;
+    #[link_name = "sqlite3_cancel_auto_extension"]
+    fn cancel_extension(entry: ExtensionEntry) -> c_int;
+}
+
+/// Serializes [`establish`], as sqlite keeps one list of auto-extensions per process.
+static ESTABLISHING: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// Handle of the connection [`establish`] is opening on this thread, null until
+    /// [`catch_handle`] sees it, and `None` outside of it.
+    static CAUGHT: Cell<Option<*mut sqlite3>> = const { Cell::new(None) };
+}
+
+/// Opens a connection whose reads stop once the [`crate::OpContext`] of the operation
+/// running them says to, through [`on_progress`].
+///
+/// diesel doesn't hand out the raw connection, so it is caught by an auto-extension,
+/// registered only while the connection opens and acting only on this thread: connections
+/// opened by anyone else are left as they are.
+fn establish(db_path: &str) -> ConnectionResult<SqliteConnection> {
+    let _establishing = ESTABLISHING.lock().unwrap_or_else(PoisonError::into_inner);
+    CAUGHT.with(|caught| caught.set(Some(ptr::null_mut())));
+    // SAFETY: `catch_handle` takes the arguments sqlite calls auto-extensions with.
+    // Registering only fails out of memory, leaving statements to run to completion.
+    unsafe { register_extension(catch_handle) };
+    let connection = SqliteConnection::establish(db_path);
+    // SAFETY: as above.
+    unsafe { cancel_extension(catch_handle) };
+    let db = CAUGHT
+        .with(|caught| caught.take())
+        .unwrap_or(ptr::null_mut());
+    let connection = connection?;
+    if !db.is_null() {
+        // SAFETY: `db` is the handle of `connection`, which outlives its progress handler.
+        unsafe { sqlite3_progress_handler(db, PROGRESS_PERIOD, Some(on_progress), db.cast()) };
+    }
+    Ok(connection)
+}
+
+/// Auto-extension keeping the handle of the connection [`establish`] opens on this thread.
+unsafe extern "C" fn catch_handle(
+    db: *mut sqlite3,
+    _: *mut *mut c_char,
+    _: *const sqlite3_api_routines,
+) -> c_int {
+    CAUGHT.with(|caught| {
+        if caught.get().is_some() {
+            caught.set(Some(db));
+        }
+    });
+    SQLITE_OK
+}
+
+/// Progress handler of the connection `db`, interrupting the running statement with
+/// `SQLITE_INTERRUPT` when not zero.
+///
+/// Only reads are interrupted: sqlite rolls back the whole transaction of an interrupted
+/// write, behind the back of diesel's transaction manager.
+unsafe extern "C" fn on_progress(db: *mut c_void) -> c_int {
+    if !stop_requested() {
+        return 0;
+    }
+    let db = db.cast::<sqlite3>();
+    // SAFETY: sqlite calls the handler with the connection it was installed on, from the
+    // thread running one of its statements.
+    unsafe {
+        let mut stmt = sqlite3_next_stmt(db, ptr::null_mut());
+        while !stmt.is_null() {
+            if sqlite3_stmt_busy(stmt) != 0 && sqlite3_stmt_readonly(stmt) == 0 {
+                return 0;
+            }
+            stmt = sqlite3_next_stmt(db, stmt);
+        }
+    }
+    note_interrupted();
+    1
+}
+
+/// How [`SsiSqliteStore::open`] treats the database path, and how long it waits for
+/// others opening the same database.
+#[derive(Clone, Debug, Default)]
+pub struct SqliteOpenOptions {
+    create_dirs: bool,
+    expand_home: bool,
+    migration_lock_timeout: Option<Duration>,
 }
+
+impl SqliteOpenOptions {
+    /// Creates the missing parent directories of the database.
+    pub fn create_dirs(mut self, create_dirs: bool) -> Self {
+        self.create_dirs = create_dirs;
+        self
+    }
+
+    /// Replaces a leading `~` with the home directory of the user.
+    pub fn expand_home(mut self, expand_home: bool) -> Self {
+        self.expand_home = expand_home;
+        self
+    }
+
+    /// Sets how long to wait for another connection migrating the database before failing
+    /// with [`Error::MigrationLockTimeout`], 30 seconds by default.
+    pub fn migration_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.migration_lock_timeout = Some(timeout);
+        self
+    }
+}
+
 pub struct SsiSqliteStore {
-    connection: SqliteConnection,
+    source: SqliteSource,
+    /// Connection of the pool serving every query during a transaction or read snapshot.
+    pinned: Mutex<Option<PooledSqliteConnection>>,
+    path: Option<PathBuf>,
+    clock: Arc<dyn Clock>,
 }
 
 impl SsiSqliteStore {
     pub fn new(db_path: impl AsRef<str>) -> Result<Self, Error> {
-        let mut connection = SqliteConnection::establish(db_path.as_ref())?;
-        connection
-            .run_pending_migrations(DIESEL_MIGRATIONS)
+        Self::open(db_path, &SqliteOpenOptions::default())
+    }
+
+    /// Opens the database at `db_path`, creating it if missing.
+    ///
+    /// The path is checked before sqlite sees it, failing with
+    /// [`Error::DatabaseParentMissing`], [`Error::DatabaseIsDirectory`] or
+    /// [`Error::DatabasePermissionDenied`], then canonicalized, see
+    /// [`SsiSqliteStore::path`]. `:memory:` and `file:` URIs are passed to sqlite as they
+    /// are.
+    pub fn open(db_path: impl AsRef<str>, options: &SqliteOpenOptions) -> Result<Self, Error> {
+        let (db_path, path) = resolve_path(db_path.as_ref(), options)?;
+        let mut connection = establish(&db_path)?;
+        let lock_timeout = options
+            .migration_lock_timeout
+            .unwrap_or(MIGRATION_LOCK_TIMEOUT);
+        prepare(&mut connection, lock_timeout)?;
+        Ok(Self {
+            source: SqliteSource::Connection(Mutex::new(connection)),
+            pinned: Mutex::new(None),
+            path,
+            clock: system_clock(),
+        })
+    }
+
+    /// Returns the canonical path of the database file, or `None` for in-memory and URI
+    /// databases.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Opens a database encrypted with SQLCipher under `key`, creating it if missing.
+    ///
+    /// Fails with [`Error::BadDatabaseKey`] if `key` doesn't decrypt an existing database.
+    #[cfg(feature = "sqlcipher")]
+    pub fn new_encrypted(db_path: impl AsRef<str>, key: &str) -> Result<Self, Error> {
+        let (db_path, path) = resolve_path(db_path.as_ref(), &SqliteOpenOptions::default())?;
+        let mut connection = establish(&db_path)?;
+        diesel::sql_query(format!("PRAGMA key = {}", sql_text(key))).execute(&mut connection)?;
+        // SQLCipher only checks the key once the database is read.
+        diesel::sql_query("SELECT count(*) FROM sqlite_master")
+            .execute(&mut connection)
+            .map_err(|_| Error::BadDatabaseKey)?;
+        prepare(&mut connection, MIGRATION_LOCK_TIMEOUT)?;
+        Ok(Self {
+            source: SqliteSource::Connection(Mutex::new(connection)),
+            pinned: Mutex::new(None),
+            path,
+            clock: system_clock(),
+        })
+    }
+
+    /// Opens a database encrypted with SQLCipher under a random key that only `wrapper`
+    /// can unwrap, e.g. with a key held by the Android Keystore, creating both if missing.
+    ///
+    /// The key is kept wrapped next to the database, in `<db_path>.key`, so that neither
+    /// file is of any use off the device. Fails with [`Error::BadDatabaseKey`] if the
+    /// database exists without its key file.
+    #[cfg(feature = "sqlcipher")]
+    pub fn new_platform_keyed(
+        db_path: impl AsRef<str>,
+        wrapper: &dyn crate::SecretWrapper,
+    ) -> Result<Self, Error> {
+        let (_, path) = resolve_path(db_path.as_ref(), &SqliteOpenOptions::default())?;
+        let path = path.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "in-memory databases have no key file",
+            )
+        })?;
+        let mut key_path = path.clone().into_os_string();
+        key_path.push(".key");
+        let key = match fs::read(&key_path) {
+            Ok(wrapped) => wrapper.unwrap(&wrapped).map_err(Error::SecretWrapper)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                if path.exists() {
+                    return Err(Error::BadDatabaseKey);
+                }
+                let mut key = zeroize::Zeroizing::new(vec![0; DATABASE_KEY_LEN]);
+                getrandom::getrandom(&mut key)
+                    .map_err(|err| Error::SecretWrapper(err.to_string()))?;
+                fs::write(&key_path, wrapper.wrap(&key).map_err(Error::SecretWrapper)?)?;
+                key
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let key = zeroize::Zeroizing::new(
+            key.iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>(),
+        );
+        Self::new_encrypted(db_path, &key)
+    }
+
+    /// Re-encrypts a database opened with [`SsiSqliteStore::new_encrypted`] under
+    /// `new_key`.
+    #[cfg(feature = "sqlcipher")]
+    pub fn rekey(&mut self, new_key: &str) -> Result<(), Error> {
+        diesel::sql_query(format!("PRAGMA rekey = {}", sql_text(new_key)))
+            .execute(&mut *self.connection()?)?;
+        Ok(())
+    }
+
+    /// Opens the database through a pool of up to `max_connections` connections, so
+    /// stores shared with [`SsiSqliteStore::share`] can run queries concurrently.
+    pub fn with_pool(db_path: impl AsRef<str>, max_connections: u32) -> Result<Self, Error> {
+        let (db_path, path) = resolve_path(db_path.as_ref(), &SqliteOpenOptions::default())?;
+        let pool = Pool::builder()
+            .max_size(max_connections)
+            .connection_timeout(Duration::from_secs(30))
+            .connection_customizer(Box::new(BusyTimeout))
+            .build(SqliteManager::new(db_path))?;
+        prepare(&mut pool.get()?, MIGRATION_LOCK_TIMEOUT)?;
+        Ok(Self {
+            source: SqliteSource::Pool(pool),
+            pinned: Mutex::new(None),
+            path,
+            clock: system_clock(),
+        })
+    }
+
+    /// Returns another store on the same connection pool, or `None` if this store has a
+    /// single connection.
+    pub fn share(&self) -> Option<Self> {
+        match &self.source {
+            SqliteSource::Connection(_) => None,
+            SqliteSource::Pool(pool) => Some(Self {
+                source: SqliteSource::Pool(pool.clone()),
+                pinned: Mutex::new(None),
+                path: self.path.clone(),
+                clock: self.clock.clone(),
+            }),
+        }
+    }
+
+    /// Sets `PRAGMA synchronous` to `level`, returning the previous one, or `None` for
+    /// pools, whose connections come and go.
+    pub(crate) fn set_synchronous(&mut self, level: i32) -> Result<Option<i32>, Error> {
+        let SqliteSource::Connection(conn) = &mut self.source else {
+            return Ok(None);
+        };
+        let conn = conn.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let previous = diesel::sql_query("PRAGMA synchronous")
+            .get_result::<Synchronous>(conn)?
+            .synchronous;
+        diesel::sql_query(format!("PRAGMA synchronous = {level}")).execute(conn)?;
+        Ok(Some(previous))
+    }
+
+    /// Returns the connection for the next query, which holds the store's single
+    /// connection, or the pinned one, until dropped.
+    fn connection(&self) -> Result<SqliteConn<'_>, Error> {
+        let pinned = self.pinned.lock().unwrap_or_else(PoisonError::into_inner);
+        if pinned.is_some() {
+            return Ok(SqliteConn::Pinned(pinned));
+        }
+        drop(pinned);
+        match &self.source {
+            SqliteSource::Connection(conn) => Ok(SqliteConn::Locked(
+                conn.lock().unwrap_or_else(PoisonError::into_inner),
+            )),
+            SqliteSource::Pool(pool) => Ok(SqliteConn::Pooled(pool.get()?)),
+        }
+    }
+
+    /// Begins a transaction, nested in those already open, which pools serve from one
+    /// connection until it ends.
+    fn begin(&mut self) -> Result<(), Error> {
+        let pinned = self
+            .pinned
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        if pinned.is_none() {
+            if let SqliteSource::Pool(pool) = &self.source {
+                *pinned = Some(pool.get()?);
+            }
+        }
+        AnsiTransactionManager::begin_transaction(&mut *self.connection()?)?;
+        Ok(())
+    }
+
+    /// Ends the innermost transaction with `end`, a commit or a rollback, and hands the
+    /// pinned connection back to the pool once none is left, or once it is broken by a
+    /// failed rollback so that the pool discards it.
+    fn end(&mut self, end: fn(&mut SqliteConnection) -> QueryResult<()>) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        let ended = end(&mut *conn);
+        let done = !matches!(
+            AnsiTransactionManager::transaction_manager_status_mut(&mut *conn).transaction_depth(),
+            Ok(Some(_))
+        );
+        drop(conn);
+        if done {
+            *self
+                .pinned
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner) = None;
+        }
+        ended.map_err(Into::into)
+    }
+
+    /// Runs a custom read-only query, binding each of `params` as text in order.
+    ///
+    /// The query runs with `PRAGMA query_only` enabled, so any statement that would write
+    /// fails with [`Error::ReadOnlyQueryViolation`].
+    ///
+    /// The schema visible to these queries is part of the public contract: an
+    /// `ssi_secrets` table with the TEXT columns `id` (the identity), `ssi` (the ssi
+    /// string), `secret` (the concealed secret string), `created_at` and the nullable
+    /// `last_used_at` and `updated_at` (RFC 3339 UTC timestamps with milliseconds,
+    /// `updated_at` NULL until the ssi or secret is first changed), the BIGINT column
+    /// `sign_count`, the BOOLEAN column `needs_rewrap` and the nullable TEXT column
+    /// `fingerprint` (the public key fingerprint, NULL until cached), and a `settings`
+    /// table of TEXT `key`/`value` pairs.
+    pub fn read_query<T>(&mut self, sql: &str, params: &[&str]) -> Result<Vec<T>, Error>
+    where
+        T: QueryableByName<Sqlite> + 'static,
+    {
+        let query = params.iter().fold(
+            diesel::sql_query(sql).into_boxed::<Sqlite>(),
+            |query, param| query.bind::<Text, _>(param.to_string()),
+        );
+        self.connection()?.transaction(|conn| {
+            diesel::sql_query("PRAGMA query_only = ON").execute(conn)?;
+            let result = query.load::<T>(conn);
+            diesel::sql_query("PRAGMA query_only = OFF").execute(conn)?;
+            result.map_err(|err| match err {
+                diesel::result::Error::DatabaseError(_, info)
+                    if info.message().contains("readonly") =>
+                {
+                    Error::ReadOnlyQueryViolation
+                }
+                err => err.into(),
+            })
+        })
+    }
+
+    /// Writes an SQL dump of the database that stock `sqlite3` can restore: a comment
+    /// header with the format version, the schema, and an insert for every row.
+    ///
+    /// Wrapped keys of [`Protection::Platform`](crate::Protection::Platform) identities are
+    /// dumped along with their secrets, so they unlock again once restored on the same
+    /// device. Without `include_secrets`, every concealed secret is replaced with a
+    /// placeholder and wrapped keys are left out; such a dump documents the identities but
+    /// can't be restored.
+    pub fn dump_sql(&mut self, mut writer: impl Write, include_secrets: bool) -> Result<(), Error> {
+        use crate::schema::{settings, ssi_secrets};
+
+        self.connection()?.transaction(|conn| {
+            let version = read_format_version(conn)?.unwrap_or(FORMAT_VERSION);
+            let secrets = if include_secrets {
+                "included"
+            } else {
+                "redacted"
+            };
+            writeln!(writer, "{DUMP_HEADER}")?;
+            writeln!(writer, "{DUMP_VERSION_PREFIX}{version}")?;
+            writeln!(writer, "{DUMP_SECRETS_PREFIX}{secrets}")?;
+            for table in DUMP_TABLES {
+                let schema = diesel::sql_query(
+                    "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?",
+                )
+                .bind::<Text, _>(table)
+                .get_result::<TableSql>(conn)?;
+                let sql = schema
+                    .sql
+                    .replacen("CREATE TABLE", "CREATE TABLE IF NOT EXISTS", 1);
+                writeln!(writer, "{sql};")?;
+            }
+
+            let migrations = diesel::sql_query(
+                "SELECT version FROM __diesel_schema_migrations ORDER BY version",
+            )
+            .load::<MigrationVersion>(conn)?;
+            for migration in migrations {
+                writeln!(
+                    writer,
+                    "INSERT OR IGNORE INTO __diesel_schema_migrations (version) VALUES ({});",
+                    sql_text(&migration.version)
+                )?;
+            }
+            let entries = settings::table
+                .order(settings::key.asc())
+                .load::<(String, String)>(conn)?;
+            for (key, value) in entries {
+                writeln!(
+                    writer,
+                    "INSERT OR REPLACE INTO settings (key, value) VALUES ({}, {});",
+                    sql_text(&key),
+                    sql_text(&value)
+                )?;
+            }
+            let records = ssi_secrets::table
+                .select((
+                    ssi_secrets::id,
+                    ssi_secrets::ssi,
+                    ssi_secrets::secret,
+                    ssi_secrets::created_at,
+                    ssi_secrets::last_used_at,
+                    ssi_secrets::sign_count,
+                    ssi_secrets::needs_rewrap,
+                    ssi_secrets::updated_at,
+                    ssi_secrets::fingerprint,
+                    ssi_secrets::wrapped_key,
+                ))
+                .order(ssi_secrets::id.asc())
+                .load::<DumpedRecord>(conn)?;
+            for (
+                id,
+                ssi,
+                secret,
+                created_at,
+                last_used_at,
+                sign_count,
+                needs_rewrap,
+                updated_at,
+                fingerprint,
+                wrapped_key,
+            ) in records
+            {
+                let (secret, wrapped_key) = if include_secrets {
+                    (secret.as_str(), wrapped_key)
+                } else {
+                    (REDACTED_SECRET, None)
+                };
+                writeln!(
+                    writer,
+                    "INSERT INTO ssi_secrets (id, ssi, secret, created_at, last_used_at, \
+                     sign_count, needs_rewrap, updated_at, fingerprint, wrapped_key) VALUES \
+                     ({}, {}, {}, {}, {}, {sign_count}, {}, {}, {}, {});",
+                    sql_text(&id),
+                    sql_text(&ssi),
+                    sql_text(secret),
+                    sql_text(&created_at),
+                    last_used_at.as_deref().map_or("NULL".to_string(), sql_text),
+                    u8::from(needs_rewrap),
+                    updated_at.as_deref().map_or("NULL".to_string(), sql_text),
+                    fingerprint.as_deref().map_or("NULL".to_string(), sql_text),
+                    wrapped_key.as_deref().map_or("NULL".to_string(), sql_blob)
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Restores a dump written by [`SsiSqliteStore::dump_sql`] with secrets, in a single
+    /// transaction, then upgrades data from an older format.
+    ///
+    /// The dump isn't run as SQL: only the statements `dump_sql` writes are accepted, and
+    /// their rows are inserted with bound parameters. The schema statements are checked
+    /// and skipped, as the database is already migrated. Any other statement fails with
+    /// [`Error::SqlDump`] before anything is written.
+    ///
+    /// Fails with [`Error::RestoreTargetNotEmpty`] if the database already holds
+    /// identities, unless `merge` is set; identities present in both make the restore fail
+    /// without writing anything.
+    pub fn restore_sql(&mut self, mut reader: impl Read, merge: bool) -> Result<(), Error> {
+        use crate::schema::{settings, ssi_secrets, ssi_secrets::dsl};
+
+        let mut dump = String::new();
+        reader.read_to_string(&mut dump)?;
+        let mut header = dump.lines();
+        if header.next() != Some(DUMP_HEADER) {
+            return Err(Error::SqlDump("missing dump header".to_string()));
+        }
+        let found = header
+            .next()
+            .and_then(|line| line.strip_prefix(DUMP_VERSION_PREFIX))
+            .and_then(|version| version.parse::<u32>().ok())
+            .ok_or_else(|| Error::SqlDump("missing format version".to_string()))?;
+        if found > FORMAT_VERSION {
+            return Err(Error::FormatTooNew {
+                found,
+                supported: FORMAT_VERSION,
+            });
+        }
+        match header
+            .next()
+            .and_then(|line| line.strip_prefix(DUMP_SECRETS_PREFIX))
+        {
+            Some("included") => {}
+            Some("redacted") => {
+                return Err(Error::SqlDump("secrets were redacted".to_string()));
+            }
+            _ => return Err(Error::SqlDump("missing secrets marker".to_string())),
+        }
+        let statements = sql_statements(&dump)?
+            .into_iter()
+            .map(DumpStatement::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.connection()?.transaction(|conn| {
+            if !merge && diesel::select(exists(dsl::ssi_secrets)).get_result(conn)? {
+                return Err(Error::RestoreTargetNotEmpty);
+            }
+            for statement in statements {
+                match statement {
+                    DumpStatement::Schema => {}
+                    DumpStatement::Setting(key, value) => {
+                        diesel::replace_into(settings::table)
+                            .values((settings::key.eq(key), settings::value.eq(value)))
+                            .execute(conn)?;
+                    }
+                    DumpStatement::Record(record) => {
+                        diesel::insert_into(ssi_secrets::table)
+                            .values((
+                                ssi_secrets::id.eq(record.0),
+                                ssi_secrets::ssi.eq(record.1),
+                                ssi_secrets::secret.eq(record.2),
+                                ssi_secrets::created_at.eq(record.3),
+                                ssi_secrets::last_used_at.eq(record.4),
+                                ssi_secrets::sign_count.eq(record.5),
+                                ssi_secrets::needs_rewrap.eq(record.6),
+                                ssi_secrets::updated_at.eq(record.7),
+                                ssi_secrets::fingerprint.eq(record.8),
+                                ssi_secrets::wrapped_key.eq(record.9),
+                            ))
+                            .execute(conn)?;
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        self.upgrade_format()
+    }
+}
+
+#[derive(QueryableByName)]
+struct TableSql {
+    #[diesel(sql_type = Text)]
+    sql: String,
+}
+
+#[derive(QueryableByName)]
+struct MigrationVersion {
+    #[diesel(sql_type = Text)]
+    version: String,
+}
+
+#[derive(QueryableByName)]
+struct FingerprintRow {
+    #[diesel(sql_type = Text)]
+    id: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    fingerprint: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    ssi: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct SqliteVersion {
+    #[diesel(sql_type = Text)]
+    version: String,
+}
+
+#[derive(QueryableByName)]
+struct Synchronous {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    synchronous: i32,
+}
+
+#[derive(QueryableByName)]
+struct BusyTimeoutMillis {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    timeout: i32,
+}
+
+/// Checks `db_path` and creates the database file, returning the path to hand to sqlite
+/// and the canonical path of the file, if it is one.
+fn resolve_path(
+    db_path: &str,
+    options: &SqliteOpenOptions,
+) -> Result<(String, Option<PathBuf>), Error> {
+    if db_path.is_empty() || db_path == ":memory:" || db_path.starts_with("file:") {
+        return Ok((db_path.to_string(), None));
+    }
+    let path = match db_path.strip_prefix('~').and_then(home_dir) {
+        Some((home, rest)) if options.expand_home => home.join(rest),
+        _ => PathBuf::from(db_path),
+    };
+    if path.is_dir() {
+        return Err(Error::DatabaseIsDirectory(path));
+    }
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+    if let Some(parent) = parent.filter(|_| options.create_dirs) {
+        fs::create_dir_all(parent).map_err(|err| path_error(err, &path))?;
+    }
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|err| path_error(err, &path))?;
+    let path = fs::canonicalize(&path)?;
+    let db_path = path
+        .to_str()
+        .map_or_else(|| db_path.to_string(), str::to_string);
+    Ok((db_path, Some(path)))
+}
+
+/// Splits the rest of a `~`-prefixed path from the home directory, if it starts with one.
+fn home_dir(rest: &str) -> Option<(PathBuf, &str)> {
+    let rest = match rest.strip_prefix(['/', '\\']) {
+        Some(rest) => rest,
+        None if rest.is_empty() => rest,
+        None => return None,
+    };
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some((PathBuf::from(home), rest))
+}
+
+fn path_error(err: io::Error, path: &Path) -> Error {
+    match err.kind() {
+        io::ErrorKind::NotFound => Error::DatabaseParentMissing(path.to_path_buf()),
+        io::ErrorKind::PermissionDenied => Error::DatabasePermissionDenied(path.to_path_buf()),
+        _ => err.into(),
+    }
+}
+
+/// Quotes `value` as an SQL string literal.
+fn sql_text(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Formats `value` as an SQL blob literal, e.g. `X'00ff'`.
+fn sql_blob(value: &[u8]) -> String {
+    let hex = value
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!("X'{hex}'")
+}
+
+/// Splits a dump into its statements, leaving out `--` comments.
+fn sql_statements(dump: &str) -> Result<Vec<&str>, Error> {
+    let bytes = dump.as_bytes();
+    let mut statements = Vec::new();
+    let (mut start, mut pos, mut quoted) = (0, 0, false);
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'\'' => quoted = !quoted,
+            b'-' if !quoted && bytes.get(pos + 1) == Some(&b'-') => {
+                let line = dump[pos..].find('\n').map_or(dump.len(), |end| pos + end);
+                if dump[start..pos].trim().is_empty() {
+                    start = line;
+                }
+                pos = line;
+                continue;
+            }
+            b';' if !quoted => {
+                statements.push(dump[start..pos].trim());
+                start = pos + 1;
+            }
+            _ => {}
+        }
+        pos += 1;
+    }
+    if !dump[start..].trim().is_empty() {
+        return Err(Error::SqlDump("unterminated statement".to_string()));
+    }
+    Ok(statements)
+}
+
+/// A statement of a dump written by [`SsiSqliteStore::dump_sql`].
+enum DumpStatement {
+    /// Creates a table, or records a migration, which the restored database already has.
+    Schema,
+    Setting(String, String),
+    Record(DumpedRecord),
+}
+
+impl DumpStatement {
+    fn parse(statement: &str) -> Result<Self, Error> {
+        let mut sql = SqlCursor(statement);
+        if sql.keywords("CREATE TABLE IF NOT EXISTS") {
+            let table = sql.identifier()?;
+            if !DUMP_TABLES.contains(&table) {
+                return Err(Error::SqlDump(format!("unexpected table {table}")));
+            }
+            return Ok(Self::Schema);
+        }
+        if sql.keywords("INSERT OR IGNORE INTO __diesel_schema_migrations") {
+            let values = sql.row(&["version"])?;
+            return match values.as_slice() {
+                [SqlLiteral::Text(_)] => Ok(Self::Schema),
+                _ => Err(Error::SqlDump("unexpected migration version".to_string())),
+            };
+        }
+        if sql.keywords("INSERT OR REPLACE INTO settings") {
+            let values = sql.row(&["key", "value"])?;
+            return match <[_; 2]>::try_from(values) {
+                Ok([SqlLiteral::Text(key), SqlLiteral::Text(value)]) => {
+                    Ok(Self::Setting(key, value))
+                }
+                _ => Err(Error::SqlDump("unexpected setting".to_string())),
+            };
+        }
+        if !sql.keywords("INSERT INTO ssi_secrets") {
+            let keywords = statement.split_whitespace().take(2).collect::<Vec<_>>();
+            return Err(Error::SqlDump(format!(
+                "unexpected statement {}",
+                keywords.join(" ")
+            )));
+        }
+        let columns = sql.list(SqlCursor::identifier)?;
+        sql.expect("VALUES")?;
+        let values = sql.list(SqlCursor::literal)?;
+        sql.end()?;
+        if columns.len() != values.len() {
+            return Err(Error::SqlDump("column count mismatch".to_string()));
+        }
+
+        let (mut id, mut ssi, mut secret) = (None, None, None);
+        // Columns missing from older dumps take the defaults of their migrations.
+        let mut record: DumpedRecord = (
+            String::new(),
+            String::new(),
+            String::new(),
+            "1970-01-01T00:00:00.000Z".to_string(),
+            None,
+            0,
+            false,
+            None,
+            None,
+            None,
+        );
+        for (column, value) in columns.into_iter().zip(values) {
+            let unexpected = || Error::SqlDump(format!("unexpected value for {column}"));
+            match (column, value) {
+                ("id", SqlLiteral::Text(value)) => id = Some(value),
+                ("ssi", SqlLiteral::Text(value)) => ssi = Some(value),
+                ("secret", SqlLiteral::Text(value)) => secret = Some(value),
+                ("created_at", SqlLiteral::Text(value)) => record.3 = value,
+                ("last_used_at", value) => {
+                    record.4 = value.nullable_text().ok_or_else(unexpected)?
+                }
+                ("sign_count", SqlLiteral::Integer(value)) => record.5 = value,
+                ("needs_rewrap", SqlLiteral::Integer(value @ (0 | 1))) => record.6 = value == 1,
+                ("updated_at", value) => record.7 = value.nullable_text().ok_or_else(unexpected)?,
+                ("fingerprint", value) => {
+                    record.8 = value.nullable_text().ok_or_else(unexpected)?
+                }
+                ("wrapped_key", SqlLiteral::Null) => record.9 = None,
+                ("wrapped_key", SqlLiteral::Blob(value)) => record.9 = Some(value),
+                (
+                    "id" | "ssi" | "secret" | "created_at" | "sign_count" | "needs_rewrap"
+                    | "wrapped_key",
+                    _,
+                ) => return Err(unexpected()),
+                _ => return Err(Error::SqlDump(format!("unexpected column {column}"))),
+            }
+        }
+        match (id, ssi, secret) {
+            (Some(id), Some(ssi), Some(secret)) => {
+                (record.0, record.1, record.2) = (id, ssi, secret);
+                Ok(Self::Record(record))
+            }
+            _ => Err(Error::SqlDump(
+                "identity without id, ssi or secret".to_string(),
+            )),
+        }
+    }
+}
+
+/// A literal of a dump, as formatted by [`sql_text`] and [`sql_blob`].
+enum SqlLiteral {
+    Null,
+    Integer(i64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl SqlLiteral {
+    fn nullable_text(self) -> Option<Option<String>> {
+        match self {
+            Self::Null => Some(None),
+            Self::Text(value) => Some(Some(value)),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the rest of a dump statement, token by token.
+struct SqlCursor<'a>(&'a str);
+
+impl<'a> SqlCursor<'a> {
+    /// Skips `keywords` if the statement goes on with them, whatever the whitespace.
+    fn keywords(&mut self, keywords: &str) -> bool {
+        let mut rest = self.0;
+        for keyword in keywords.split(' ') {
+            rest = rest.trim_start();
+            match rest.strip_prefix(keyword) {
+                Some(after) if !after.starts_with(|c: char| c.is_alphanumeric() || c == '_') => {
+                    rest = after;
+                }
+                _ => return false,
+            }
+        }
+        self.0 = rest;
+        true
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), Error> {
+        let rest = self.0.trim_start();
+        self.0 = rest
+            .strip_prefix(token)
+            .ok_or_else(|| Error::SqlDump(format!("expected {token}")))?;
+        Ok(())
+    }
+
+    fn end(&self) -> Result<(), Error> {
+        match self.0.trim() {
+            "" => Ok(()),
+            _ => Err(Error::SqlDump(
+                "unexpected text after a statement".to_string(),
+            )),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<&'a str, Error> {
+        let rest = self.0.trim_start();
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return Err(Error::SqlDump("expected a name".to_string()));
+        }
+        self.0 = &rest[end..];
+        Ok(&rest[..end])
+    }
+
+    fn literal(&mut self) -> Result<SqlLiteral, Error> {
+        let rest = self.0.trim_start();
+        if let Some(quoted) = rest.strip_prefix('\'') {
+            let mut text = String::new();
+            let mut chars = quoted.char_indices();
+            while let Some((at, c)) = chars.next() {
+                match c {
+                    // A quote doubled within the text stands for one.
+                    '\'' if quoted[at + 1..].starts_with('\'') => {
+                        text.push(c);
+                        chars.next();
+                    }
+                    '\'' => {
+                        self.0 = &quoted[at + 1..];
+                        return Ok(SqlLiteral::Text(text));
+                    }
+                    c => text.push(c),
+                }
+            }
+            return Err(Error::SqlDump("unterminated text".to_string()));
+        }
+        if let Some(hex) = rest.strip_prefix("X'") {
+            let end = hex
+                .find('\'')
+                .ok_or_else(|| Error::SqlDump("unterminated blob".to_string()))?;
+            let blob = (0..end)
+                .step_by(2)
+                .map(|at| {
+                    hex.get(at..at + 2)
+                        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                })
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| Error::SqlDump("invalid blob".to_string()))?;
+            self.0 = &hex[end + 1..];
+            return Ok(SqlLiteral::Blob(blob));
+        }
+        if self.keywords("NULL") {
+            return Ok(SqlLiteral::Null);
+        }
+        let end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '-'))
+            .unwrap_or(rest.len());
+        let value = rest[..end]
+            .parse()
+            .map_err(|_| Error::SqlDump("expected a value".to_string()))?;
+        self.0 = &rest[end..];
+        Ok(SqlLiteral::Integer(value))
+    }
+
+    /// Reads a parenthesized, comma separated list of `item`.
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, Error>,
+    ) -> Result<Vec<T>, Error> {
+        self.expect("(")?;
+        let mut items = vec![item(self)?];
+        while self.expect(",").is_ok() {
+            items.push(item(self)?);
+        }
+        self.expect(")")?;
+        Ok(items)
+    }
+
+    /// Reads the values inserted into `columns`, which the statement must list as they
+    /// are.
+    fn row(&mut self, columns: &[&str]) -> Result<Vec<SqlLiteral>, Error> {
+        if self.list(SqlCursor::identifier)? != columns {
+            return Err(Error::SqlDump("unexpected columns".to_string()));
+        }
+        self.expect("VALUES")?;
+        let values = self.list(SqlCursor::literal)?;
+        self.end()?;
+        Ok(values)
+    }
+}
+
+/// Formats `at` as stored in the timestamp columns, which then sort chronologically.
+fn timestamp_text(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn parse_timestamp(text: &str) -> Result<DateTime<Utc>, Error> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|at| at.with_timezone(&Utc))
+        .map_err(|err| diesel::result::Error::DeserializationError(Box::new(err)).into())
+}
+
+fn read_format_version(conn: &mut SqliteConnection) -> Result<Option<u32>, Error> {
+    use crate::schema::settings::dsl;
+    dsl::settings
+        .filter(dsl::key.eq(FORMAT_VERSION_KEY))
+        .select(dsl::value)
+        .get_result::<String>(conn)
+        .optional()?
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| Error::DieselMigration(format!("invalid format version: {value}")))
+        })
+        .transpose()
+}
+
+fn write_format_version(conn: &mut SqliteConnection, version: u32) -> Result<(), Error> {
+    use crate::schema::settings::dsl;
+    diesel::replace_into(dsl::settings)
+        .values((
+            dsl::key.eq(FORMAT_VERSION_KEY),
+            dsl::value.eq(version.to_string()),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Runs pending migrations and checks the data format is one this crate can read.
+///
+/// Connections opening the same database at once, from any process, take turns: each
+/// one holds the write lock while it checks for and applies migrations, so the ones
+/// coming later find nothing left to do, or fail with [`Error::MigrationLockTimeout`]
+/// after `lock_timeout`. Migrations are applied in the same transaction, so an
+/// interrupted one leaves no partial schema.
+fn prepare(connection: &mut SqliteConnection, lock_timeout: Duration) -> Result<(), Error> {
+    let sqlite = diesel::sql_query("SELECT sqlite_version() AS version")
+        .get_result::<SqliteVersion>(connection)?;
+    check_sqlite_version(&sqlite.version)?;
+    let busy_timeout = diesel::sql_query("PRAGMA busy_timeout")
+        .get_result::<BusyTimeoutMillis>(connection)?
+        .timeout;
+    set_busy_timeout(connection, lock_timeout.as_millis())?;
+    let found = connection.immediate_transaction(|conn| {
+        conn.run_pending_migrations(DIESEL_MIGRATIONS)
             .map_err(|err| Error::DieselMigration(err.to_string()))?;
-        Ok(Self { connection })
+        init_format_version(conn)
+    });
+    set_busy_timeout(connection, busy_timeout)?;
+    let found = found.map_err(|err| match err {
+        Error::Diesel(diesel::result::Error::DatabaseError(_, info))
+            if info.message().contains("locked") =>
+        {
+            Error::MigrationLockTimeout
+        }
+        err => err,
+    })?;
+    if found > FORMAT_VERSION {
+        return Err(Error::FormatTooNew {
+            found,
+            supported: FORMAT_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// Fails with [`Error::SqliteTooOld`] if the linked sqlite, of version `found`, is older
+/// than [`MIN_SQLITE_VERSION`].
+fn check_sqlite_version(found: &str) -> Result<(), Error> {
+    let parts = found
+        .split('.')
+        .map(|part| part.parse::<u32>().unwrap_or(0))
+        .chain(std::iter::repeat(0))
+        .take(MIN_SQLITE_VERSION.len())
+        .collect::<Vec<_>>();
+    if parts.as_slice() < MIN_SQLITE_VERSION.as_slice() {
+        return Err(Error::SqliteTooOld {
+            found: found.to_string(),
+            required: MIN_SQLITE_VERSION.map(|part| part.to_string()).join("."),
+        });
+    }
+    Ok(())
+}
+
+fn set_busy_timeout(conn: &mut SqliteConnection, millis: impl Display) -> Result<(), Error> {
+    diesel::sql_query(format!("PRAGMA busy_timeout = {millis}")).execute(conn)?;
+    Ok(())
+}
+
+/// Reads the format version, recording one first if the database has none: the current
+/// version for a fresh database, 0 for one created before format versioning.
+fn init_format_version(conn: &mut SqliteConnection) -> Result<u32, Error> {
+    use crate::schema::ssi_secrets::dsl;
+    if let Some(version) = read_format_version(conn)? {
+        return Ok(version);
+    }
+    let legacy = diesel::select(exists(dsl::ssi_secrets)).get_result::<bool>(conn)?;
+    let version = if legacy { 0 } else { FORMAT_VERSION };
+    write_format_version(conn, version)?;
+    Ok(version)
+}
+
+/// Rewrites every record with the canonical text of its ssi and secret.
+fn canonicalize_records(conn: &mut SqliteConnection) -> Result<(), Error> {
+    use crate::schema::ssi_secrets::dsl;
+    let records = dsl::ssi_secrets.select(SsiSecret::as_select()).load(conn)?;
+    for record in records {
+        diesel::update(dsl::ssi_secrets.filter(dsl::id.eq(&record.id)))
+            .set((dsl::ssi.eq(record.ssi), dsl::secret.eq(record.secret)))
+            .execute(conn)?;
     }
+    Ok(())
 }
 
 impl SsiStore for SsiSqliteStore {
     fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
         use crate::schema::ssi_secrets::dsl;
 
-        diesel::insert_into(dsl::ssi_secrets)
-            .values(&SsiSecret {
-                id,
-                ssi: ssi.into(),
-                secret: secret.into(),
-            })
-            .execute(&mut self.connection)
-            .map_err(Into::into)
-            .map(drop)
+        let created_at = timestamp_text(self.clock.now());
+        self.connection()?.transaction(|conn| {
+            if diesel::select(exists(dsl::ssi_secrets.filter(dsl::id.eq(&id)))).get_result(conn)? {
+                return Err(Error::IdentityExists(id));
+            }
+            diesel::insert_into(dsl::ssi_secrets)
+                .values(&SsiSecret {
+                    id,
+                    fingerprint: Some(fingerprint(&ssi)),
+                    ssi: ssi.into(),
+                    secret: secret.into(),
+                    created_at,
+                })
+                .execute(conn)?;
+            Ok(())
+        })
     }
 
-    fn get(&mut self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
         use crate::schema::ssi_secrets::dsl;
         dsl::ssi_secrets
             .filter(dsl::id.eq(id))
-            .get_result::<SsiSecret>(&mut self.connection)
-            .map_err(Into::into)
+            .select(SsiSecret::as_select())
+            .get_result(&mut *self.connection()?)
+            .optional()?
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))
             .map(|record| Cow::Owned((record.ssi.into_inner(), record.secret.into_inner())))
     }
 
     fn remove(&mut self, id: &str) -> Result<bool, Error> {
         use crate::schema::ssi_secrets::dsl;
         diesel::delete(dsl::ssi_secrets.filter(dsl::id.eq(id)))
-            .execute(&mut self.connection)
+            .execute(&mut *self.connection()?)
             .map_err(Into::into)
             .map(|row| row == 1)
     }
 
-    fn paginated_identities(
-        &mut self,
-        page: usize,
-        per_page: usize,
-    ) -> Result<(Vec<Cow<'_, String>>, usize), Error> {
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        use crate::schema::ssi_secrets::dsl;
+        diesel::select(exists(dsl::ssi_secrets.filter(dsl::id.eq(id))))
+            .get_result(&mut *self.connection()?)
+            .map_err(Into::into)
+    }
+
+    fn find_by_pubkey(&self, pk: &SsiPub) -> Result<Vec<String>, Error> {
+        use crate::schema::ssi_secrets::dsl;
+        dsl::ssi_secrets
+            .filter(
+                dsl::fingerprint
+                    .eq(pk.fingerprint().to_string())
+                    .or(dsl::fingerprint.is_null()),
+            )
+            .filter(dsl::ssi.like(format!("%{pk}%")))
+            .order(dsl::id.asc())
+            .select(SsiSecret::as_select())
+            .load(&mut *self.connection()?)
+            .map_err(Into::into)
+            .map(|records| {
+                records
+                    .into_iter()
+                    .filter(|record| record.ssi.0.pk == *pk)
+                    .map(|record| record.id)
+                    .collect()
+            })
+    }
+
+    fn find_identities(&self, query: &str) -> Result<Vec<String>, Error> {
+        use crate::schema::ssi_secrets::dsl;
+        // LIKE narrows down the rows case-insensitively; matching the parsed uids then
+        // drops rows that only matched elsewhere in the ssi, e.g. in the public key.
+        let pattern = format!(
+            "%{}%",
+            query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        dsl::ssi_secrets
+            .filter(
+                dsl::id
+                    .like(&pattern)
+                    .escape('\\')
+                    .or(dsl::ssi.like(&pattern).escape('\\')),
+            )
+            .select((dsl::id, dsl::ssi))
+            .order(dsl::id.asc())
+            .load::<(String, SqliteTextWrapper<Ssi>)>(&mut *self.connection()?)
+            .map_err(Into::into)
+            .map(|records| {
+                records
+                    .into_iter()
+                    .filter(|(id, ssi)| matches_query(id, &ssi.0, query))
+                    .map(|(id, _)| id)
+                    .collect()
+            })
+    }
+
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error> {
         use crate::schema::ssi_secrets::dsl;
-        self.connection.transaction(|conn| {
+        let offset = Page::offset(page, per_page)?;
+        self.connection()?.transaction(|conn| {
             let total = dsl::ssi_secrets
                 .select(count_star())
                 .get_result::<i64>(conn)?;
-            let records = dsl::ssi_secrets
-                .select(SsiSecret::as_select())
-                .offset(((page - 1) * per_page) as i64)
-                .limit(per_page as i64)
-                .load(conn)
-                .map(|records| records.into_iter().map(|ssi| Cow::Owned(ssi.id)).collect())?;
-
-            let total_pages = (total as f64 / per_page as f64).ceil() as usize;
-            Ok((records, total_pages))
+            let identities = dsl::ssi_secrets
+                .select(dsl::id)
+                .order(dsl::id.asc())
+                .offset(i64::try_from(offset).unwrap_or(i64::MAX))
+                .limit(i64::try_from(per_page).unwrap_or(i64::MAX))
+                .load::<String>(conn)?;
+            Ok(Page::new(identities, total as usize, per_page))
         })
     }
 
-    fn all_identities(&mut self) -> Result<Vec<Cow<'_, String>>, Error> {
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        use crate::schema::ssi_secrets::dsl;
+        let mut conn = self.connection()?;
+        let identities = dsl::ssi_secrets
+            .select(dsl::id)
+            .order(dsl::id.asc())
+            .load_iter::<String, DefaultLoadingMode>(&mut *conn)?;
+        for identity in identities {
+            f(&identity?)?;
+        }
+        Ok(())
+    }
+
+    fn active_identities(&self, now: DateTime<Utc>) -> Result<Vec<String>, Error> {
         use crate::schema::ssi_secrets::dsl;
         dsl::ssi_secrets
+            .order(dsl::id.asc())
             .select(SsiSecret::as_select())
-            .load(&mut self.connection)
+            .load(&mut *self.connection()?)
+            .map_err(Into::into)
+            .map(|records| {
+                records
+                    .into_iter()
+                    .filter(|record| !record.ssi.0.expiry.is_some_and(|expiry| expiry <= now))
+                    .map(|record| record.id)
+                    .collect()
+            })
+    }
+
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error> {
+        // The ssi is only loaded, and parsed, for rows without a cached fingerprint.
+        let rows = diesel::sql_query(
+            "SELECT id, fingerprint, CASE WHEN fingerprint IS NULL THEN ssi END AS ssi \
+             FROM ssi_secrets ORDER BY id",
+        )
+        .load::<FingerprintRow>(&mut *self.connection()?)?;
+        rows.into_iter()
+            .map(|row| {
+                let fingerprint = match (row.fingerprint, row.ssi) {
+                    (Some(fingerprint), _) => fingerprint,
+                    (None, Some(ssi)) => fingerprint(&Ssi::from_str(&ssi)?),
+                    (None, None) => unreachable!("the query selects the ssi of uncached rows"),
+                };
+                Ok(IdentityFingerprint {
+                    identity: row.id,
+                    fingerprint,
+                })
+            })
+            .collect()
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default()
+            .with(StoreCapability::AtomicWrites)
+            .with(StoreCapability::Metadata)
+            .with(StoreCapability::PlatformProtection)
+            .with(StoreCapability::Replace)
+            .with(StoreCapability::Transactions)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+
+    fn as_snapshot(&mut self) -> Option<&mut dyn SnapshotCapable> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiSqliteStore {
+    fn format_version(&self) -> Result<u32, Error> {
+        self.connection()?.transaction(init_format_version)
+    }
+
+    fn upgrade_format(&mut self) -> Result<(), Error> {
+        self.connection()?.transaction(|conn| {
+            let found = init_format_version(conn)?;
+            for (version, upgrade) in FORMAT_UPGRADES.iter().enumerate().skip(found as usize) {
+                upgrade(conn)?;
+                write_format_version(conn, version as u32 + 1)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        use crate::schema::ssi_secrets::dsl;
+
+        let created_at = timestamp_text(self.clock.now());
+        self.connection()?.transaction(|conn| {
+            diesel::delete(dsl::ssi_secrets.filter(dsl::id.eq(&id))).execute(conn)?;
+            diesel::insert_into(dsl::ssi_secrets)
+                .values(&SsiSecret {
+                    id,
+                    fingerprint: Some(fingerprint(&ssi)),
+                    ssi: ssi.into(),
+                    secret: secret.into(),
+                    created_at,
+                })
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        use crate::schema::ssi_secrets::dsl;
+        let rows = diesel::update(dsl::ssi_secrets.filter(dsl::id.eq(id)))
+            .set((
+                dsl::fingerprint.eq(fingerprint(&ssi)),
+                dsl::ssi.eq(SqliteTextWrapper::from(ssi)),
+                dsl::secret.eq(SqliteTextWrapper::from(secret)),
+                dsl::updated_at.eq(timestamp_text(self.clock.now())),
+            ))
+            .execute(&mut *self.connection()?)?;
+        if rows == 0 {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        Ok(())
+    }
+
+    fn warm_fingerprints(&mut self, batch_size: usize) -> Result<(usize, usize), Error> {
+        use crate::schema::ssi_secrets::dsl;
+        self.connection()?.transaction(|conn| {
+            let uncached = dsl::ssi_secrets
+                .filter(dsl::fingerprint.is_null())
+                .select((dsl::id, dsl::ssi))
+                .order(dsl::id.asc())
+                .limit(i64::try_from(batch_size).unwrap_or(i64::MAX))
+                .load::<(String, String)>(conn)?;
+            for (id, ssi) in &uncached {
+                diesel::update(dsl::ssi_secrets.filter(dsl::id.eq(id)))
+                    .set(dsl::fingerprint.eq(fingerprint(&Ssi::from_str(ssi)?)))
+                    .execute(conn)?;
+            }
+            let left = dsl::ssi_secrets
+                .filter(dsl::fingerprint.is_null())
+                .select(count_star())
+                .get_result::<i64>(conn)?;
+            Ok((uncached.len(), left as usize))
+        })
+    }
+
+    fn metadata(&self, id: &str) -> Result<IdentityMetadata, Error> {
+        use crate::schema::ssi_secrets::dsl;
+        let (created_at, updated_at, last_used_at, sign_count, needs_rewrap) = dsl::ssi_secrets
+            .filter(dsl::id.eq(id))
+            .select((
+                dsl::created_at,
+                dsl::updated_at,
+                dsl::last_used_at,
+                dsl::sign_count,
+                dsl::needs_rewrap,
+            ))
+            .get_result::<(String, Option<String>, Option<String>, i64, bool)>(
+                &mut *self.connection()?,
+            )
+            .optional()?
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+        Ok(IdentityMetadata {
+            created_at: parse_timestamp(&created_at)?,
+            updated_at: parse_timestamp(updated_at.as_deref().unwrap_or(&created_at))?,
+            last_used_at: last_used_at.as_deref().map(parse_timestamp).transpose()?,
+            sign_count: sign_count as u64,
+            needs_rewrap,
+        })
+    }
+
+    fn record_signatures(&mut self, id: &str, count: u64, at: DateTime<Utc>) -> Result<(), Error> {
+        use crate::schema::ssi_secrets::dsl;
+        let rows = diesel::update(dsl::ssi_secrets.filter(dsl::id.eq(id)))
+            .set((
+                dsl::last_used_at.eq(timestamp_text(at)),
+                dsl::sign_count.eq(dsl::sign_count + count as i64),
+            ))
+            .execute(&mut *self.connection()?)?;
+        if rows == 0 {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        Ok(())
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        use crate::schema::ssi_secrets::dsl;
+        let records = dsl::ssi_secrets
+            .select((dsl::id, dsl::created_at, dsl::last_used_at))
+            .order(dsl::id.asc())
+            .load::<(String, String, Option<String>)>(&mut *self.connection()?)?;
+        let mut identities = Vec::new();
+        for (id, created_at, last_used_at) in records {
+            let last_activity = last_used_at.as_deref().unwrap_or(&created_at);
+            if parse_timestamp(last_activity)? < cutoff {
+                identities.push(id);
+            }
+        }
+        Ok(identities)
+    }
+
+    fn set_needs_rewrap(&mut self, id: &str, needs_rewrap: bool) -> Result<(), Error> {
+        use crate::schema::ssi_secrets::dsl;
+        let rows = diesel::update(dsl::ssi_secrets.filter(dsl::id.eq(id)))
+            .set(dsl::needs_rewrap.eq(needs_rewrap))
+            .execute(&mut *self.connection()?)?;
+        if rows == 0 {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        Ok(())
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        use crate::schema::ssi_secrets::dsl;
+        dsl::ssi_secrets
+            .filter(dsl::needs_rewrap.eq(true))
+            .select(dsl::id)
+            .order(dsl::id.asc())
+            .load(&mut *self.connection()?)
             .map_err(Into::into)
-            .map(|records| records.into_iter().map(|ssi| Cow::Owned(ssi.id)).collect())
-    }
-}
-
-// #[cfg(test)]
-// mod tests {
-//     use time::OffsetDateTime;
-//
-//     use crate::{ssi_cert_verify_text, SsiMan};
-//
-//     use super::*;
-//
-//     const TEST_IDENTITY: &str = "Luna";
-//
-//     #[test]
-//     fn ssi_sqlite_store_should_ok() {
-//         let mut ssi_man = SsiMan::with_sqlite(
-//             std::env::temp_dir()
-//                 .join(format!(
-//                     "ssi_man_{}_sqlite.db",
-//                     OffsetDateTime::now_utc().unix_timestamp()
-//                 ))
-//                 .to_string_lossy(),
-//         )
-//         .unwrap();
-//         let ssi = ssi_man
-//             .new_ssi(TEST_IDENTITY, "luna@bitlightlabs.com", None)
-//             .unwrap();
-//         assert!(Ssi::from_str(&ssi).is_ok());
-//         let message = "have a good day!";
-//         let ssi_cert = ssi_man.sign(TEST_IDENTITY, message, None).unwrap();
-//         ssi_cert_verify_text(&ssi_cert, message).unwrap();
-//         assert_eq!(
-//             ssi_man.paginated_identities(1, 10),
-//             Ok((vec![Cow::Owned(TEST_IDENTITY.to_string())], 1))
-//         );
-//
-//         assert_eq!(ssi_man.paginated_identities(2, 10), Ok((vec![], 1)));
-//         assert_eq!(
-//             ssi_man.all_identities(),
-//             Ok(vec![Cow::Owned(TEST_IDENTITY.to_string())])
-//         );
-//         assert!(ssi_man.remove(TEST_IDENTITY).unwrap());
-//         assert_eq!(ssi_man.all_identities(), Ok(vec![]));
-//     }
-// }
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn wrapped_key(&self, id: &str) -> Result<Option<Vec<u8>>, Error> {
+        use crate::schema::ssi_secrets::dsl;
+        dsl::ssi_secrets
+            .filter(dsl::id.eq(id))
+            .select(dsl::wrapped_key)
+            .get_result::<Option<Vec<u8>>>(&mut *self.connection()?)
+            .optional()
+            .map(Option::flatten)
+            .map_err(Into::into)
+    }
+
+    fn set_wrapped_key(&mut self, id: &str, wrapped_key: Vec<u8>) -> Result<(), Error> {
+        use crate::schema::ssi_secrets::dsl;
+        let rows = diesel::update(dsl::ssi_secrets.filter(dsl::id.eq(id)))
+            .set(dsl::wrapped_key.eq(wrapped_key))
+            .execute(&mut *self.connection()?)?;
+        if rows == 0 {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl JournalCapable for SsiSqliteStore {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        use crate::schema::ssi_secrets::dsl;
+
+        let created_at = timestamp_text(self.clock.now());
+        self.connection()?.transaction(|conn| {
+            let mut imported = 0;
+            for record in records {
+                let filter = dsl::ssi_secrets.filter(dsl::id.eq(&record.identity));
+                if diesel::select(exists(filter)).get_result(conn)? {
+                    match on_conflict {
+                        ConflictPolicy::Skip => continue,
+                        ConflictPolicy::Overwrite => {
+                            diesel::delete(dsl::ssi_secrets.filter(dsl::id.eq(&record.identity)))
+                                .execute(conn)?;
+                        }
+                        ConflictPolicy::Error => {
+                            return Err(Error::IdentityExists(record.identity));
+                        }
+                    }
+                }
+                diesel::insert_into(dsl::ssi_secrets)
+                    .values(&SsiSecret {
+                        id: record.identity,
+                        fingerprint: Some(fingerprint(&record.ssi)),
+                        ssi: record.ssi.into(),
+                        secret: record.encrypted_secret.into(),
+                        created_at: created_at.clone(),
+                    })
+                    .execute(conn)?;
+                imported += 1;
+            }
+            Ok(imported)
+        })
+    }
+
+    fn ingest_chunk(&mut self, records: Vec<StoredIdentity>, ingested: usize) -> Result<(), Error> {
+        use crate::schema::{settings, ssi_secrets::dsl};
+
+        let created_at = timestamp_text(self.clock.now());
+        self.connection()?.transaction(|conn| {
+            for record in records {
+                diesel::delete(dsl::ssi_secrets.filter(dsl::id.eq(&record.identity)))
+                    .execute(conn)?;
+                diesel::insert_into(dsl::ssi_secrets)
+                    .values(&SsiSecret {
+                        id: record.identity,
+                        fingerprint: Some(fingerprint(&record.ssi)),
+                        ssi: record.ssi.into(),
+                        secret: record.encrypted_secret.into(),
+                        created_at: created_at.clone(),
+                    })
+                    .execute(conn)?;
+            }
+            diesel::replace_into(settings::table)
+                .values((
+                    settings::key.eq(INGEST_PROGRESS_KEY),
+                    settings::value.eq(ingested.to_string()),
+                ))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    fn ingest_progress(&mut self) -> Result<Option<usize>, Error> {
+        use crate::schema::settings::dsl;
+        dsl::settings
+            .filter(dsl::key.eq(INGEST_PROGRESS_KEY))
+            .select(dsl::value)
+            .get_result::<String>(&mut *self.connection()?)
+            .optional()?
+            .map(|value| {
+                value.parse().map_err(|err| {
+                    diesel::result::Error::DeserializationError(Box::new(err)).into()
+                })
+            })
+            .transpose()
+    }
+
+    fn finish_ingest(&mut self) -> Result<(), Error> {
+        use crate::schema::settings::dsl;
+        diesel::delete(dsl::settings.filter(dsl::key.eq(INGEST_PROGRESS_KEY)))
+            .execute(&mut *self.connection()?)?;
+        Ok(())
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), Error> {
+        self.begin()
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Error> {
+        self.end(AnsiTransactionManager::commit_transaction)
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Error> {
+        self.end(AnsiTransactionManager::rollback_transaction)
+    }
+}
+
+impl SnapshotCapable for SsiSqliteStore {
+    fn begin_read_snapshot(&mut self) -> Result<(), Error> {
+        // The read transaction sees the database as it is at its first read.
+        self.begin()
+    }
+
+    fn end_read_snapshot(&mut self) -> Result<(), Error> {
+        self.end(AnsiTransactionManager::rollback_transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use diesel::{connection::SimpleConnection, sql_types::BigInt};
+    use time::OffsetDateTime;
+
+    use crate::{
+        backup::SsiBackup, ssi_cert_verify_text, IngestOptions, IngestRecord, OpContext, SsiMan,
+    };
+
+    use super::*;
+
+    const TEST_IDENTITY: &str = "Luna";
+
+    fn temp_db_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "ssi_man_{name}_{}_sqlite.db",
+                OffsetDateTime::now_utc().unix_timestamp_nanos()
+            ))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[derive(QueryableByName)]
+    struct SchemaRow {
+        #[diesel(sql_type = Text)]
+        id: String,
+        #[diesel(sql_type = Text)]
+        ssi: String,
+        #[diesel(sql_type = Text)]
+        secret: String,
+    }
+
+    #[derive(QueryableByName)]
+    struct CountRow {
+        #[diesel(sql_type = BigInt)]
+        count: i64,
+    }
+
+    #[test]
+    fn ssi_sqlite_store_should_ok() {
+        let mut ssi_man = SsiMan::with_sqlite(temp_db_path("store")).unwrap();
+        let ssi = ssi_man
+            .new_ssi(TEST_IDENTITY, "luna@bitlightlabs.com", None)
+            .unwrap();
+        assert!(Ssi::from_str(&ssi).is_ok());
+        let message = "have a good day!";
+        let ssi_cert = ssi_man.sign(TEST_IDENTITY, message, None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, message).unwrap();
+        assert_eq!(
+            ssi_man
+                .paginated_identities(1, 10)
+                .map(|page| page.identities),
+            Ok(vec![TEST_IDENTITY.to_string()])
+        );
+        assert_eq!(
+            ssi_man
+                .paginated_identities(2, 10)
+                .map(|page| page.identities),
+            Ok(vec![])
+        );
+        assert_eq!(
+            ssi_man.all_identities(),
+            Ok(vec![Cow::Owned(TEST_IDENTITY.to_string())])
+        );
+        assert!(ssi_man.remove(TEST_IDENTITY).unwrap());
+        assert_eq!(ssi_man.all_identities(), Ok(vec![]));
+    }
+
+    #[test]
+    fn read_query_should_expose_stable_schema() {
+        let mut ssi_man = SsiMan::with_sqlite(temp_db_path("read_query")).unwrap();
+        let ssi = ssi_man
+            .new_ssi(TEST_IDENTITY, "luna@bitlightlabs.com", None)
+            .unwrap();
+        let store = ssi_man.sqlite_store().unwrap();
+
+        let rows = store
+            .read_query::<SchemaRow>("SELECT id, ssi, secret FROM ssi_secrets", &[])
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].id, TEST_IDENTITY);
+        assert_eq!(rows[0].ssi, ssi);
+        assert!(EncryptedSecret::from_str(&rows[0].secret).is_ok());
+
+        let counts = store
+            .read_query::<CountRow>(
+                "SELECT COUNT(*) AS count FROM ssi_secrets WHERE ssi LIKE ?",
+                &["%bitlightlabs.com%"],
+            )
+            .unwrap();
+        assert_eq!(counts[0].count, 1);
+    }
+
+    #[test]
+    fn read_query_should_reject_writes() {
+        let mut ssi_man = SsiMan::with_sqlite(temp_db_path("read_query_write")).unwrap();
+        ssi_man
+            .new_ssi(TEST_IDENTITY, "luna@bitlightlabs.com", None)
+            .unwrap();
+        let store = ssi_man.sqlite_store().unwrap();
+        assert!(matches!(
+            store.read_query::<SchemaRow>("DELETE FROM ssi_secrets RETURNING id, ssi, secret", &[]),
+            Err(Error::ReadOnlyQueryViolation)
+        ));
+        assert_eq!(
+            ssi_man.all_identities(),
+            Ok(vec![Cow::Owned(TEST_IDENTITY.to_string())])
+        );
+    }
+
+    #[test]
+    fn op_context_should_interrupt_long_reads() {
+        let count_to = |n: u64| {
+            format!(
+                "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < {n}) \
+                 SELECT count(*) AS count FROM n"
+            )
+        };
+        let mut store = SsiSqliteStore::new(":memory:").unwrap();
+        let ctx = OpContext::with_cancel(Arc::new(AtomicBool::new(true)));
+        assert_eq!(
+            ctx.interrupting(
+                || store.read_query::<CountRow>(&count_to(1_000_000_000_000), &[]),
+                || 3
+            )
+            .map(|rows| rows.len()),
+            Err(Error::Cancelled { done: 3 })
+        );
+
+        let rows = store
+            .read_query::<CountRow>(&count_to(100_000), &[])
+            .unwrap();
+        assert_eq!(rows[0].count, 100_000);
+    }
+
+    #[test]
+    fn legacy_database_should_upgrade_format() {
+        let mut memory = SsiMan::with_memory();
+        memory
+            .new_ssi(TEST_IDENTITY, "luna@bitlightlabs.com", None)
+            .unwrap();
+        let backup = SsiBackup::from_str(&memory.export(TEST_IDENTITY, None).unwrap()).unwrap();
+
+        let db_path = temp_db_path("legacy_format");
+        let mut connection = SqliteConnection::establish(&db_path).unwrap();
+        diesel::sql_query(include_str!(
+            "../migrations/2024-10-31-143532_create_identity_ssi_secrets/up.sql"
+        ))
+        .execute(&mut connection)
+        .unwrap();
+        diesel::sql_query("INSERT INTO ssi_secrets (id, ssi, secret) VALUES (?, ?, ?)")
+            .bind::<Text, _>(TEST_IDENTITY)
+            .bind::<Text, _>(backup.ssi.to_string())
+            .bind::<Text, _>(backup.secret.to_string())
+            .execute(&mut connection)
+            .unwrap();
+        drop(connection);
+
+        let mut ssi_man = SsiMan::with_sqlite(&db_path).unwrap();
+        assert_eq!(ssi_man.format_version(), Ok(0));
+        ssi_man.upgrade_format().unwrap();
+        assert_eq!(ssi_man.format_version(), Ok(FORMAT_VERSION));
+        let message = "have a good day!";
+        let ssi_cert = ssi_man.sign(TEST_IDENTITY, message, None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, message).unwrap();
+
+        let ssi_man = SsiMan::with_sqlite(&db_path).unwrap();
+        assert_eq!(ssi_man.format_version(), Ok(FORMAT_VERSION));
+    }
+
+    #[test]
+    fn newer_format_should_be_rejected() {
+        let db_path = temp_db_path("newer_format");
+        let ssi_man = SsiMan::with_sqlite(&db_path).unwrap();
+        assert_eq!(ssi_man.format_version(), Ok(FORMAT_VERSION));
+        drop(ssi_man);
+
+        let mut connection = SqliteConnection::establish(&db_path).unwrap();
+        write_format_version(&mut connection, FORMAT_VERSION + 1).unwrap();
+        drop(connection);
+        assert_eq!(
+            SsiMan::with_sqlite(&db_path).err(),
+            Some(Error::FormatTooNew {
+                found: FORMAT_VERSION + 1,
+                supported: FORMAT_VERSION
+            })
+        );
+    }
+
+    fn dump_records(store: &mut SsiSqliteStore) -> Vec<(String, String, String)> {
+        store
+            .read_query::<SchemaRow>("SELECT id, ssi, secret FROM ssi_secrets ORDER BY id", &[])
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.id, row.ssi, row.secret))
+            .collect()
+    }
+
+    #[test]
+    fn sql_dump_should_restore() {
+        let mut ssi_man = SsiMan::with_sqlite(temp_db_path("dump")).unwrap();
+        for (identity, passwd) in [(TEST_IDENTITY, "moon"), ("O'Brien", "it's")] {
+            ssi_man
+                .new_ssi(identity, "luna@bitlightlabs.com", Some(passwd))
+                .unwrap();
+        }
+        let store = ssi_man.sqlite_store().unwrap();
+        let records = dump_records(store);
+        let mut dump = Vec::new();
+        store.dump_sql(&mut dump, true).unwrap();
+        assert!(dump.starts_with(format!("{DUMP_HEADER}\n").as_bytes()));
+
+        let mut restored = SsiMan::with_sqlite(temp_db_path("restore")).unwrap();
+        let restored_store = restored.sqlite_store().unwrap();
+        restored_store.restore_sql(dump.as_slice(), false).unwrap();
+        assert_eq!(dump_records(restored_store), records);
+        let message = "have a good day!";
+        let ssi_cert = restored.sign("O'Brien", message, Some("it's")).unwrap();
+        ssi_cert_verify_text(&ssi_cert, message).unwrap();
+
+        // A dump restored without this crate, as stock sqlite3 would.
+        let db_path = temp_db_path("restore_raw");
+        let mut conn = SqliteConnection::establish(&db_path).unwrap();
+        conn.batch_execute(std::str::from_utf8(&dump).unwrap())
+            .unwrap();
+        drop(conn);
+        let mut restored = SsiMan::with_sqlite(&db_path).unwrap();
+        assert_eq!(dump_records(restored.sqlite_store().unwrap()), records);
+        assert!(restored.sign(TEST_IDENTITY, message, Some("moon")).is_ok());
+    }
+
+    #[test]
+    fn sql_dump_should_restore_wrapped_keys() {
+        let mut store = SsiSqliteStore::new(temp_db_path("dump_wrapped")).unwrap();
+        let secret = ssi::SsiSecret::new(ssi::Algo::Ed25519, ssi::Chain::Bitcoin);
+        let uid = "Luna <mailto:luna@bitlightlabs.com>".parse().unwrap();
+        let ssi = Ssi::new(vec![uid], None, &secret);
+        store
+            .insert(
+                TEST_IDENTITY.to_string(),
+                ssi,
+                secret.conceal("platform key"),
+            )
+            .unwrap();
+        store
+            .set_wrapped_key(TEST_IDENTITY, vec![0, 0x27, 0xff])
+            .unwrap();
+        let fingerprints = store.fingerprints().unwrap();
+        let mut dump = Vec::new();
+        store.dump_sql(&mut dump, true).unwrap();
+
+        let mut restored = SsiSqliteStore::new(temp_db_path("restore_wrapped")).unwrap();
+        restored.restore_sql(dump.as_slice(), false).unwrap();
+        assert_eq!(
+            restored.wrapped_key(TEST_IDENTITY),
+            Ok(Some(vec![0, 0x27, 0xff]))
+        );
+        assert_eq!(restored.fingerprints(), Ok(fingerprints));
+
+        let mut redacted = Vec::new();
+        store.dump_sql(&mut redacted, false).unwrap();
+        let redacted = String::from_utf8(redacted).unwrap();
+        assert!(!redacted.contains("X'0027ff'"));
+    }
+
+    #[test]
+    fn sql_restore_should_reject_other_statements() {
+        let mut ssi_man = SsiMan::with_sqlite(temp_db_path("dump_foreign")).unwrap();
+        ssi_man
+            .new_ssi(TEST_IDENTITY, "luna@bitlightlabs.com", None)
+            .unwrap();
+        let mut dump = Vec::new();
+        ssi_man
+            .sqlite_store()
+            .unwrap()
+            .dump_sql(&mut dump, true)
+            .unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+
+        let mut target = SsiMan::with_sqlite(temp_db_path("restore_foreign")).unwrap();
+        target.new_ssi("Sol", "sol@bitlightlabs.com", None).unwrap();
+        let store = target.sqlite_store().unwrap();
+        for statement in [
+            "DROP TABLE ssi_secrets;",
+            "ATTACH DATABASE 'other.db' AS other;",
+            "CREATE TABLE IF NOT EXISTS other (id TEXT);",
+            "INSERT INTO ssi_secrets (id, ssi, secret, owner) VALUES ('a', 'b', 'c', 'd');",
+            "INSERT INTO ssi_secrets (id, ssi, secret) VALUES ('a', 'b', lower('c'));",
+            "UPDATE settings SET value = '0'",
+        ] {
+            assert!(
+                matches!(
+                    store.restore_sql(format!("{dump}{statement}\n").as_bytes(), true),
+                    Err(Error::SqlDump(_))
+                ),
+                "{statement}"
+            );
+            assert_eq!(dump_records(store).len(), 1);
+        }
+    }
+
+    #[test]
+    fn sql_restore_should_require_merge_into_non_empty_database() {
+        let mut ssi_man = SsiMan::with_sqlite(temp_db_path("dump_merge")).unwrap();
+        ssi_man
+            .new_ssi(TEST_IDENTITY, "luna@bitlightlabs.com", None)
+            .unwrap();
+        let mut dump = Vec::new();
+        ssi_man
+            .sqlite_store()
+            .unwrap()
+            .dump_sql(&mut dump, true)
+            .unwrap();
+
+        let mut target = SsiMan::with_sqlite(temp_db_path("restore_merge")).unwrap();
+        target.new_ssi("Sol", "sol@bitlightlabs.com", None).unwrap();
+        let store = target.sqlite_store().unwrap();
+        assert_eq!(
+            store.restore_sql(dump.as_slice(), false),
+            Err(Error::RestoreTargetNotEmpty)
+        );
+        store.restore_sql(dump.as_slice(), true).unwrap();
+        assert_eq!(dump_records(store).len(), 2);
+        assert!(store.restore_sql(dump.as_slice(), true).is_err());
+        assert_eq!(dump_records(store).len(), 2);
+
+        let newer = String::from_utf8(dump).unwrap().replacen(
+            &format!("{DUMP_VERSION_PREFIX}{FORMAT_VERSION}"),
+            &format!("{DUMP_VERSION_PREFIX}{}", FORMAT_VERSION + 1),
+            1,
+        );
+        assert!(matches!(
+            store.restore_sql(newer.as_bytes(), true),
+            Err(Error::FormatTooNew { .. })
+        ));
+    }
+
+    #[test]
+    fn redacted_sql_dump_should_not_contain_secrets() {
+        let mut ssi_man = SsiMan::with_sqlite(temp_db_path("dump_redacted")).unwrap();
+        ssi_man
+            .new_ssi(TEST_IDENTITY, "luna@bitlightlabs.com", Some("moon"))
+            .unwrap();
+        let store = ssi_man.sqlite_store().unwrap();
+        let records = dump_records(store);
+        let mut dump = Vec::new();
+        store.dump_sql(&mut dump, false).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+
+        let (_, ssi, secret) = &records[0];
+        assert!(dump.contains(ssi.as_str()));
+        assert!(!dump.contains(secret.as_str()));
+        assert!(dump.contains(REDACTED_SECRET));
+
+        let mut target = SsiMan::with_sqlite(temp_db_path("restore_redacted")).unwrap();
+        assert_eq!(
+            target
+                .sqlite_store()
+                .unwrap()
+                .restore_sql(dump.as_bytes(), false),
+            Err(Error::SqlDump("secrets were redacted".to_string()))
+        );
+    }
+
+    #[test]
+    fn open_should_classify_bad_paths() {
+        let dir = PathBuf::from(temp_db_path("paths"));
+        let nested = dir.join("a").join("b").join("ssi.db");
+        assert_eq!(
+            SsiSqliteStore::new(nested.to_str().unwrap()).err(),
+            Some(Error::DatabaseParentMissing(nested.clone()))
+        );
+
+        let options = SqliteOpenOptions::default().create_dirs(true);
+        let dotted = dir.join("a").join("..").join("a").join("b").join("ssi.db");
+        let store = SsiSqliteStore::open(dotted.to_str().unwrap(), &options).unwrap();
+        assert_eq!(
+            store.path(),
+            Some(fs::canonicalize(&nested).unwrap().as_path())
+        );
+        assert!(SsiSqliteStore::new(":memory:").unwrap().path().is_none());
+
+        let parent = nested.parent().unwrap();
+        assert_eq!(
+            SsiSqliteStore::new(parent.to_str().unwrap()).err(),
+            Some(Error::DatabaseIsDirectory(parent.to_path_buf()))
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let locked = dir.join("locked");
+            fs::create_dir(&locked).unwrap();
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o500)).unwrap();
+            let db_path = locked.join("ssi.db");
+            // Root ignores permissions, so only check where the directory is enforced.
+            if fs::File::create(&db_path).is_err() {
+                assert_eq!(
+                    SsiSqliteStore::new(db_path.to_str().unwrap()).err(),
+                    Some(Error::DatabasePermissionDenied(db_path))
+                );
+            }
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o700)).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fingerprint_cache_should_warm_in_batches() {
+        let mut ssi_man = SsiMan::with_sqlite(temp_db_path("fingerprint_cache")).unwrap();
+        for identity in ["Luna", "Mars", "Sol"] {
+            ssi_man
+                .new_ssi(identity, &format!("{identity}@bitlightlabs.com"), None)
+                .unwrap();
+        }
+        let cached = ssi_man.fingerprints().unwrap();
+
+        // As if stored before fingerprints were cached.
+        let store = ssi_man.sqlite_store().unwrap();
+        diesel::sql_query("UPDATE ssi_secrets SET fingerprint = NULL")
+            .execute(&mut *store.connection().unwrap())
+            .unwrap();
+        assert_eq!(store.fingerprints(), Ok(cached.clone()));
+        let pk = store.get("Mars").unwrap().0.pk;
+        assert_eq!(store.find_by_pubkey(&pk), Ok(vec!["Mars".to_string()]));
+
+        let mut progress = vec![];
+        let warmed = ssi_man.warm_fingerprints(2, |done, total| {
+            progress.push((done, total));
+            false
+        });
+        assert_eq!(warmed, Ok(2));
+        assert_eq!(progress, [(2, 3)]);
+        assert_eq!(ssi_man.fingerprints(), Ok(cached.clone()));
+        let warmed = ssi_man.warm_fingerprints(2, |done, total| {
+            progress.push((done, total));
+            true
+        });
+        assert_eq!(warmed, Ok(1));
+        assert_eq!(progress, [(2, 3), (1, 1)]);
+        assert_eq!(ssi_man.warm_fingerprints(2, |_, _| true), Ok(0));
+
+        let store = ssi_man.sqlite_store().unwrap();
+        let uncached = store
+            .read_query::<CountRow>(
+                "SELECT COUNT(*) AS count FROM ssi_secrets WHERE fingerprint IS NULL",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(uncached[0].count, 0);
+        assert_eq!(store.fingerprints(), Ok(cached));
+        assert_eq!(store.find_by_pubkey(&pk), Ok(vec!["Mars".to_string()]));
+    }
+
+    #[test]
+    fn insert_many_should_add_all_or_nothing() {
+        let mut ssi_man = SsiMan::with_sqlite(temp_db_path("insert_many")).unwrap();
+        ssi_man
+            .new_ssi(TEST_IDENTITY, "luna@bitlightlabs.com", None)
+            .unwrap();
+        let store = ssi_man.sqlite_store().unwrap();
+        let (ssi, secret) = store.get(TEST_IDENTITY).unwrap().into_owned();
+        let records = |identities: &[&str]| {
+            identities
+                .iter()
+                .map(|identity| (identity.to_string(), ssi.clone(), secret.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(store.insert_many(records(&["Mars", "Sol"])), Ok(()));
+        assert_eq!(
+            store.insert_many(records(&["Venus", TEST_IDENTITY])),
+            Err(Error::IdentityExists(TEST_IDENTITY.to_string()))
+        );
+        assert_eq!(store.contains("Venus"), Ok(false));
+        assert_eq!(
+            ssi_man.all_identities(),
+            Ok(["Luna", "Mars", "Sol"]
+                .map(|identity| Cow::Owned(identity.to_string()))
+                .to_vec())
+        );
+    }
+
+    #[test]
+    fn ingest_should_resume_after_crash() {
+        let mut source = SsiMan::with_memory();
+        let records = (0..25)
+            .map(|n| {
+                let identity = format!("id{n:02}");
+                let ssi = source
+                    .new_ssi(&identity, &format!("{identity}@bitlightlabs.com"), None)
+                    .unwrap();
+                let encrypted_secret = source.store.get(&identity).unwrap().1.to_string();
+                IngestRecord {
+                    identity,
+                    ssi,
+                    encrypted_secret,
+                }
+            })
+            .collect::<Vec<_>>();
+        let options = IngestOptions {
+            chunk_size: 10,
+            fsync_every: Some(2),
+            ..Default::default()
+        };
+
+        let path = temp_db_path("ingest_resume");
+        let mut ssi_man = SsiMan::with_sqlite(&path).unwrap();
+        let crashing = records.clone().into_iter().enumerate().map(|(n, record)| {
+            assert_ne!(n, 23, "crash while reading the third chunk");
+            record
+        });
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            ssi_man.ingest(crashing, options)
+        }));
+        assert!(crashed.is_err());
+        drop(ssi_man);
+
+        let mut ssi_man = SsiMan::with_sqlite(&path).unwrap();
+        assert_eq!(ssi_man.all_identities().unwrap().len(), 20);
+        let report = ssi_man.ingest(records.clone(), options).unwrap();
+        assert_eq!(report.resumed_from, 20);
+        assert_eq!(report.chunks, 1);
+        assert_eq!(report.imported, 5);
+        assert!(report.failed.is_empty());
+        assert_eq!(ssi_man.all_identities().unwrap().len(), 25);
+        assert_eq!(ssi_man.sqlite_store().unwrap().ingest_progress(), Ok(None));
+        assert_eq!(ssi_man.get_ssi("id24"), Ok(records[24].ssi.clone()));
+    }
+
+    #[test]
+    fn read_snapshot_should_not_see_concurrent_writes() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let path = temp_db_path("read_snapshot");
+        let mut ssi_man = SsiMan::with_sqlite(&path).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (path, stop) = (path.clone(), stop.clone());
+            std::thread::spawn(move || {
+                let mut ssi_man = SsiMan::with_sqlite(&path).unwrap();
+                let mut written = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    let identity = format!("w{written}");
+                    if ssi_man
+                        .new_ssi(&identity, "w@bitlightlabs.com", None)
+                        .is_ok()
+                    {
+                        written += 1;
+                    }
+                    if written > 5 {
+                        let _ = ssi_man.remove(&format!("w{}", written - 5));
+                    }
+                }
+                written
+            })
+        };
+
+        let mut snapshots = 0;
+        while snapshots < 200 {
+            let snapshot = ssi_man.read_snapshot(|store| {
+                let total = store.paginated_identities(1, 1)?.total_items;
+                let identities = store
+                    .all_identities()?
+                    .into_iter()
+                    .map(Cow::into_owned)
+                    .collect::<Vec<_>>();
+                for identity in &identities {
+                    store.metadata(identity)?;
+                }
+                Ok((total, identities.len(), store.fingerprints()?.len()))
+            });
+            match snapshot {
+                Ok((total, listed, fingerprints)) => {
+                    assert_eq!(total, listed);
+                    assert_eq!(fingerprints, listed);
+                    snapshots += 1;
+                }
+                Err(err) => assert!(err.is_transient(), "{err}"),
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+        assert!(writer.join().unwrap() > 0);
+    }
+
+    #[test]
+    fn old_sqlite_should_be_rejected() {
+        assert_eq!(
+            check_sqlite_version("3.34.1"),
+            Err(Error::SqliteTooOld {
+                found: "3.34.1".to_string(),
+                required: "3.35.0".to_string()
+            })
+        );
+        for version in ["3.35.0", "3.35", "3.47.0", "4.0.0"] {
+            assert_eq!(check_sqlite_version(version), Ok(()));
+        }
+
+        let mut connection = SqliteConnection::establish(":memory:").unwrap();
+        let linked = diesel::sql_query("SELECT sqlite_version() AS version")
+            .get_result::<SqliteVersion>(&mut connection)
+            .unwrap();
+        assert_eq!(check_sqlite_version(&linked.version), Ok(()));
+    }
+
+    #[test]
+    fn concurrent_opens_should_migrate_once() {
+        let migrations =
+            diesel::migration::MigrationSource::<Sqlite>::migrations(&DIESEL_MIGRATIONS)
+                .unwrap()
+                .len();
+        for round in 0..20 {
+            let db_path = temp_db_path(&format!("concurrent_open_{round}"));
+            let barrier = std::sync::Barrier::new(2);
+            std::thread::scope(|scope| {
+                let opens = [(); 2].map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        SsiSqliteStore::new(&db_path).map(drop)
+                    })
+                });
+                for open in opens {
+                    assert_eq!(open.join().unwrap(), Ok(()));
+                }
+            });
+
+            let mut store = SsiSqliteStore::new(&db_path).unwrap();
+            let applied = store
+                .read_query::<MigrationVersion>(
+                    "SELECT version FROM __diesel_schema_migrations",
+                    &[],
+                )
+                .unwrap();
+            assert_eq!(applied.len(), migrations);
+            assert_eq!(store.format_version(), Ok(FORMAT_VERSION));
+            let rows = store
+                .read_query::<CountRow>(
+                    "SELECT count(*) AS count FROM ssi_secrets WHERE needs_rewrap = 0",
+                    &[],
+                )
+                .unwrap();
+            assert_eq!(rows[0].count, 0);
+        }
+    }
+
+    #[test]
+    fn open_should_time_out_while_another_connection_migrates() {
+        let db_path = temp_db_path("migration_lock");
+        drop(SsiSqliteStore::new(&db_path).unwrap());
+        let mut holder = SqliteConnection::establish(&db_path).unwrap();
+        holder.batch_execute("BEGIN IMMEDIATE").unwrap();
+
+        let options =
+            SqliteOpenOptions::default().migration_lock_timeout(Duration::from_millis(50));
+        assert!(matches!(
+            SsiSqliteStore::open(&db_path, &options),
+            Err(Error::MigrationLockTimeout)
+        ));
+        holder.batch_execute("COMMIT").unwrap();
+        assert!(SsiSqliteStore::open(&db_path, &options).is_ok());
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn encrypted_database_should_need_its_key() {
+        let identity = "Luna Lovegood of the encrypted store";
+        let db_path = temp_db_path("encrypted");
+        let mut ssi_man = SsiMan::with_sqlite_encrypted(&db_path, "moon").unwrap();
+        ssi_man
+            .new_ssi(identity, "luna@bitlightlabs.com", None)
+            .unwrap();
+        drop(ssi_man);
+        let file = std::fs::read(&db_path).unwrap();
+        assert!(!file
+            .windows(identity.len())
+            .any(|window| window == identity.as_bytes()));
+
+        assert!(matches!(
+            SsiMan::with_sqlite_encrypted(&db_path, "sun"),
+            Err(Error::BadDatabaseKey)
+        ));
+        assert!(SsiMan::with_sqlite(&db_path).is_err());
+
+        let mut store = SsiSqliteStore::new_encrypted(&db_path, "moon").unwrap();
+        assert!(store.contains(identity).unwrap());
+        store.rekey("it's the sun").unwrap();
+        drop(store);
+        assert!(matches!(
+            SsiSqliteStore::new_encrypted(&db_path, "moon"),
+            Err(Error::BadDatabaseKey)
+        ));
+        let mut ssi_man = SsiMan::with_sqlite_encrypted(&db_path, "it's the sun").unwrap();
+        assert!(ssi_man.sign(identity, "have a good day!", None).is_ok());
+    }
+
+    /// Stand-in for a platform keystore, XOR-ing keys with `pad`.
+    #[cfg(feature = "sqlcipher")]
+    struct PadWrapper {
+        pad: u8,
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    impl crate::SecretWrapper for PadWrapper {
+        fn wrap(&self, key: &[u8]) -> Result<Vec<u8>, String> {
+            Ok(key.iter().map(|byte| byte ^ self.pad).collect())
+        }
+
+        fn unwrap(&self, wrapped: &[u8]) -> Result<zeroize::Zeroizing<Vec<u8>>, String> {
+            Ok(self.wrap(wrapped)?.into())
+        }
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn platform_keyed_database_should_need_the_wrapper() {
+        let db_path = temp_db_path("platform_keyed");
+        let wrapper = PadWrapper { pad: 0x5c };
+        let mut ssi_man =
+            SsiMan::with_sqlite_platform_keyed(&db_path, Box::new(PadWrapper { pad: 0x5c }))
+                .unwrap();
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        drop(ssi_man);
+
+        assert!(SsiMan::with_sqlite(&db_path).is_err());
+        assert!(matches!(
+            SsiSqliteStore::new_platform_keyed(&db_path, &PadWrapper { pad: 0x3a }),
+            Err(Error::BadDatabaseKey)
+        ));
+        let store = SsiSqliteStore::new_platform_keyed(&db_path, &wrapper).unwrap();
+        assert!(store.contains("Luna").unwrap());
+        drop(store);
+
+        fs::remove_file(format!("{db_path}.key")).unwrap();
+        assert!(matches!(
+            SsiSqliteStore::new_platform_keyed(&db_path, &wrapper),
+            Err(Error::BadDatabaseKey)
+        ));
+    }
+}
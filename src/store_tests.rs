@@ -0,0 +1,86 @@
+//! Contract tests shared by the embedded stores, each backend calling them with stores
+//! of its own. Behaviour specific to a backend stays in its module's tests.
+
+use crate::{
+    ConflictPolicy, Error, IngestOptions, IngestRecord, JournalCapable, SsiMan, StoredIdentity,
+    FORMAT_VERSION,
+};
+
+/// Checks that `store`, empty, rejects an identity inserted twice, and imports a batch
+/// holding one all or nothing.
+pub(crate) fn should_reject_duplicates_atomically(store: &mut dyn JournalCapable) {
+    let mut source = SsiMan::with_memory();
+    source
+        .new_ssi("Luna", "luna@bitlightlabs.com", None)
+        .unwrap();
+    let (ssi, secret) = source.store.get("Luna").unwrap().into_owned();
+    store
+        .insert("Luna".to_string(), ssi.clone(), secret.clone())
+        .unwrap();
+    assert_eq!(
+        store.insert("Luna".to_string(), ssi.clone(), secret.clone()),
+        Err(Error::IdentityExists("Luna".to_string()))
+    );
+
+    let records = ["Sol", "Luna"].map(|identity| StoredIdentity {
+        identity: identity.to_string(),
+        ssi: ssi.clone(),
+        encrypted_secret: secret.clone(),
+    });
+    assert_eq!(
+        store.import_batch(records.to_vec(), ConflictPolicy::Error),
+        Err(Error::IdentityExists("Luna".to_string()))
+    );
+    assert_eq!(store.contains("Sol"), Ok(false));
+    assert_eq!(
+        store.import_batch(records.to_vec(), ConflictPolicy::Skip),
+        Ok(1)
+    );
+    assert_eq!(store.contains("Sol"), Ok(true));
+}
+
+/// Checks that a store opened again by `open` keeps the records and metadata written
+/// through the first one.
+pub(crate) fn should_reopen_with_its_records(open: impl Fn() -> SsiMan) {
+    let mut ssi_man = open();
+    let ssi = ssi_man
+        .new_ssi("Luna", "luna@bitlightlabs.com", None)
+        .unwrap();
+    ssi_man.sign("Luna", "have a good day!", None).unwrap();
+    drop(ssi_man);
+
+    let ssi_man = open();
+    assert_eq!(ssi_man.get_ssi("Luna"), Ok(ssi));
+    assert_eq!(ssi_man.identity_info("Luna").unwrap().sign_count, 1);
+    assert_eq!(ssi_man.format_version(), Ok(FORMAT_VERSION));
+}
+
+/// Checks that `ssi_man`, over an empty store, ingests in chunks and forgets its progress
+/// once done.
+pub(crate) fn should_record_ingest_progress(mut ssi_man: SsiMan) {
+    let mut source = SsiMan::with_memory();
+    for identity in ["id0", "id1", "id2"] {
+        source
+            .new_ssi(identity, "luna@bitlightlabs.com", None)
+            .unwrap();
+    }
+    let records = ["id0", "id1", "id2"].map(|identity| {
+        let (ssi, secret) = source.store.get(identity).unwrap().into_owned();
+        IngestRecord {
+            identity: identity.to_string(),
+            ssi: ssi.to_string(),
+            encrypted_secret: secret.to_string(),
+        }
+    });
+    let options = IngestOptions {
+        chunk_size: 2,
+        ..Default::default()
+    };
+    let mut chunks = 0;
+    ssi_man
+        .ingest_with_progress(records, options, |_| chunks += 1)
+        .unwrap();
+    assert_eq!(chunks, 2);
+    assert_eq!(ssi_man.journal().unwrap().ingest_progress(), Ok(None));
+    assert_eq!(ssi_man.export_all(), source.export_all());
+}
@@ -0,0 +1,362 @@
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use chrono::{DateTime, Utc};
+use ssi::{EncryptedSecret, Ssi, SsiPub};
+
+use crate::{
+    store_ext, store_ext_mut, store_journal, Clock, ConflictPolicy, Error, IdentityFingerprint,
+    IdentityMetadata, IntegrityFindings, IntegrityRepair, JournalCapable, Page, RepairPolicy,
+    SnapshotCapable, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability, StoredIdentity,
+    FORMAT_VERSION,
+};
+
+/// Serves records from a fast `hot` store, usually a
+/// [`SsiMemoryStore`](crate::SsiMemoryStore), in front of a persistent `cold` one.
+///
+/// Records read from `cold` are copied to `hot`, so identities used again and again are
+/// read from `cold` once. Writes go to `cold` first, then through to `hot`; listings,
+/// metadata and everything but the records themselves are `cold`'s. Records written to
+/// `cold` behind this store's back aren't seen until [`TieredStore::evict`]ed. `hot` is
+/// locked by every read, as reads cache what they miss.
+pub struct TieredStore<Hot, Cold> {
+    hot: Mutex<Hot>,
+    cold: Cold,
+}
+
+impl<Hot: SsiStoreExt, Cold: SsiStore> TieredStore<Hot, Cold> {
+    /// Puts `hot` in front of `cold`; `hot` should start empty.
+    pub fn new(hot: Hot, cold: Cold) -> Self {
+        Self {
+            hot: Mutex::new(hot),
+            cold,
+        }
+    }
+
+    /// Returns the persistent store.
+    pub fn into_cold(self) -> Cold {
+        self.cold
+    }
+
+    /// Drops the record of `identity` from `hot`, so the next read gets it from `cold`,
+    /// e.g. after another process changed it.
+    pub fn evict(&mut self, identity: &str) -> Result<(), Error> {
+        self.hot().remove(identity).map(drop)
+    }
+
+    /// Drops every record from `hot`.
+    pub fn evict_all(&mut self) -> Result<(), Error> {
+        let mut hot = self.hot();
+        let mut identities = Vec::new();
+        hot.for_each_identity(&mut |identity| {
+            identities.push(identity.to_string());
+            Ok(())
+        })?;
+        identities
+            .iter()
+            .try_for_each(|identity| hot.remove(identity).map(drop))
+    }
+
+    fn hot(&self) -> MutexGuard<'_, Hot> {
+        self.hot.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Copies a record just written to, or read from, `cold` to `hot`.
+    fn cache(&self, identity: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        let mut hot = self.hot();
+        let cached = hot.replace(identity.to_string(), ssi, secret);
+        if cached.is_err() {
+            // A stale record would be worse than none.
+            let _ = hot.remove(identity);
+        }
+        cached
+    }
+
+    /// Reads the record of `identity` from `cold`, and caches it.
+    fn load(&self, identity: &str) -> Result<(Ssi, EncryptedSecret), Error> {
+        let (ssi, secret) = self.cold.get(identity)?.into_owned();
+        self.cache(identity, ssi.clone(), secret.clone())?;
+        Ok((ssi, secret))
+    }
+}
+
+impl<Hot: SsiStoreExt, Cold: SsiStore> SsiStore for TieredStore<Hot, Cold> {
+    fn insert(&mut self, identity: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.cold
+            .insert(identity.clone(), ssi.clone(), secret.clone())?;
+        self.cache(&identity, ssi, secret)
+    }
+
+    fn get(&self, identity: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        {
+            let hot = self.hot();
+            if hot.contains(identity)? {
+                return Ok(Cow::Owned(hot.get(identity)?.into_owned()));
+            }
+        }
+        self.load(identity).map(Cow::Owned)
+    }
+
+    fn get_shared(&self, identity: &str) -> Result<Arc<(Ssi, EncryptedSecret)>, Error> {
+        {
+            let hot = self.hot();
+            if hot.contains(identity)? {
+                return hot.get_shared(identity);
+            }
+        }
+        self.load(identity).map(Arc::new)
+    }
+
+    fn remove(&mut self, identity: &str) -> Result<bool, Error> {
+        let removed = self.cold.remove(identity)?;
+        self.evict(identity)?;
+        Ok(removed)
+    }
+
+    fn contains(&self, identity: &str) -> Result<bool, Error> {
+        Ok(self.hot().contains(identity)? || self.cold.contains(identity)?)
+    }
+
+    fn find_by_pubkey(&self, pk: &SsiPub) -> Result<Vec<String>, Error> {
+        self.cold.find_by_pubkey(pk)
+    }
+
+    fn find_identities(&self, query: &str) -> Result<Vec<String>, Error> {
+        self.cold.find_identities(query)
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        self.cold.for_each_identity(f)
+    }
+
+    fn paginated_identities(&self, page: usize, per_page: usize) -> Result<Page, Error> {
+        self.cold.paginated_identities(page, per_page)
+    }
+
+    fn all_identities(&self) -> Result<Vec<Cow<'_, String>>, Error> {
+        self.cold.all_identities()
+    }
+
+    fn active_identities(&self, now: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        self.cold.active_identities(now)
+    }
+
+    fn fingerprints(&self) -> Result<Vec<IdentityFingerprint>, Error> {
+        self.cold.fingerprints()
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        self.cold.capabilities()
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_journal(&mut self) -> Option<&mut dyn JournalCapable> {
+        Some(self)
+    }
+
+    fn as_snapshot(&mut self) -> Option<&mut dyn SnapshotCapable> {
+        // Records read meanwhile are the snapshot's, as nothing else writes `cold`.
+        self.cold.as_snapshot()
+    }
+}
+
+impl<Hot: SsiStoreExt, Cold: SsiStore> SsiStoreExt for TieredStore<Hot, Cold> {
+    fn replace(
+        &mut self,
+        identity: String,
+        ssi: Ssi,
+        secret: EncryptedSecret,
+    ) -> Result<(), Error> {
+        store_ext_mut(&mut self.cold, StoreCapability::Replace)?.replace(
+            identity.clone(),
+            ssi.clone(),
+            secret.clone(),
+        )?;
+        self.cache(&identity, ssi, secret)
+    }
+
+    fn update(&mut self, identity: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        store_ext_mut(&mut self.cold, StoreCapability::Replace)?.update(
+            identity,
+            ssi.clone(),
+            secret.clone(),
+        )?;
+        self.cache(identity, ssi, secret)
+    }
+
+    fn warm_fingerprints(&mut self, batch_size: usize) -> Result<(usize, usize), Error> {
+        match self.cold.as_ext_mut() {
+            Some(cold) => cold.warm_fingerprints(batch_size),
+            None => Ok((0, 0)),
+        }
+    }
+
+    fn metadata(&self, identity: &str) -> Result<IdentityMetadata, Error> {
+        store_ext(&self.cold, StoreCapability::Metadata)?.metadata(identity)
+    }
+
+    fn record_signatures(
+        &mut self,
+        identity: &str,
+        count: u64,
+        at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        store_ext_mut(&mut self.cold, StoreCapability::Metadata)?
+            .record_signatures(identity, count, at)
+    }
+
+    fn stale_identities(&self, cutoff: DateTime<Utc>) -> Result<Vec<String>, Error> {
+        store_ext(&self.cold, StoreCapability::Metadata)?.stale_identities(cutoff)
+    }
+
+    fn set_needs_rewrap(&mut self, identity: &str, needs_rewrap: bool) -> Result<(), Error> {
+        store_ext_mut(&mut self.cold, StoreCapability::Metadata)?
+            .set_needs_rewrap(identity, needs_rewrap)
+    }
+
+    fn identities_needing_rewrap(&self) -> Result<Vec<String>, Error> {
+        store_ext(&self.cold, StoreCapability::Metadata)?.identities_needing_rewrap()
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        if let Some(cold) = self.cold.as_ext_mut() {
+            cold.set_clock(clock)
+        }
+    }
+
+    fn check_integrity(&self) -> Result<IntegrityFindings, Error> {
+        match self.cold.as_ext() {
+            Some(cold) => cold.check_integrity(),
+            None => Ok(IntegrityFindings::default()),
+        }
+    }
+
+    fn repair_integrity(&mut self, policy: &RepairPolicy) -> Result<IntegrityRepair, Error> {
+        let repair = match self.cold.as_ext_mut() {
+            Some(cold) => cold.repair_integrity(policy)?,
+            None => IntegrityRepair::default(),
+        };
+        self.evict_all()?;
+        Ok(repair)
+    }
+
+    fn wrapped_key(&self, identity: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.cold.as_ext() {
+            Some(cold) => cold.wrapped_key(identity),
+            None => Ok(None),
+        }
+    }
+
+    fn set_wrapped_key(&mut self, identity: &str, wrapped_key: Vec<u8>) -> Result<(), Error> {
+        store_ext_mut(&mut self.cold, StoreCapability::PlatformProtection)?
+            .set_wrapped_key(identity, wrapped_key)
+    }
+
+    fn format_version(&self) -> Result<u32, Error> {
+        match self.cold.as_ext() {
+            Some(cold) => cold.format_version(),
+            None => Ok(FORMAT_VERSION),
+        }
+    }
+
+    fn upgrade_format(&mut self) -> Result<(), Error> {
+        if let Some(cold) = self.cold.as_ext_mut() {
+            cold.upgrade_format()?;
+        }
+        self.evict_all()
+    }
+}
+
+impl<Hot: SsiStoreExt, Cold: SsiStore> JournalCapable for TieredStore<Hot, Cold> {
+    fn import_batch(
+        &mut self,
+        records: Vec<StoredIdentity>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, Error> {
+        let identities: Vec<_> = records.iter().map(|r| r.identity.clone()).collect();
+        let imported = store_journal(&mut self.cold)?.import_batch(records, on_conflict)?;
+        identities
+            .iter()
+            .try_for_each(|identity| self.evict(identity))?;
+        Ok(imported)
+    }
+
+    fn ingest_chunk(&mut self, records: Vec<StoredIdentity>, ingested: usize) -> Result<(), Error> {
+        let identities: Vec<_> = records.iter().map(|r| r.identity.clone()).collect();
+        store_journal(&mut self.cold)?.ingest_chunk(records, ingested)?;
+        identities
+            .iter()
+            .try_for_each(|identity| self.evict(identity))
+    }
+
+    fn ingest_progress(&mut self) -> Result<Option<usize>, Error> {
+        store_journal(&mut self.cold)?.ingest_progress()
+    }
+
+    fn finish_ingest(&mut self) -> Result<(), Error> {
+        store_journal(&mut self.cold)?.finish_ingest()
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), Error> {
+        store_journal(&mut self.cold)?.begin_transaction()
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), Error> {
+        store_journal(&mut self.cold)?.commit_transaction()
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), Error> {
+        store_journal(&mut self.cold)?.rollback_transaction()?;
+        // The cache may hold records written or read since the transaction began.
+        self.evict_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SsiMan, SsiMemoryStore};
+
+    #[test]
+    fn tiered_store_should_cache_reads_and_write_through() {
+        let mut ssi_man = SsiMan::with_memory();
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let cold = std::mem::take(ssi_man.memory_store().unwrap());
+        let mut store = TieredStore::new(SsiMemoryStore::default(), cold);
+
+        assert!(!store.hot().contains("Luna").unwrap());
+        let (ssi, secret) = store.get("Luna").unwrap().into_owned();
+        assert!(store.hot().contains("Luna").unwrap());
+        assert_eq!(store.hot().get("Luna").unwrap().0, ssi);
+
+        store
+            .insert("Sol".to_string(), ssi.clone(), secret.clone())
+            .unwrap();
+        assert!(store.hot().contains("Sol").unwrap());
+        assert!(store.cold.contains("Sol").unwrap());
+        assert!(matches!(
+            store.insert("Sol".to_string(), ssi, secret),
+            Err(Error::IdentityExists(_))
+        ));
+
+        assert_eq!(store.remove("Luna"), Ok(true));
+        assert!(!store.hot().contains("Luna").unwrap());
+        assert!(!store.cold.contains("Luna").unwrap());
+        assert!(matches!(store.get("Luna"), Err(Error::UnknownIdentity(_))));
+
+        store.evict_all().unwrap();
+        assert!(!store.hot().contains("Sol").unwrap());
+        assert_eq!(store.paginated_identities(1, 10).unwrap().total_items, 1);
+    }
+}
@@ -0,0 +1,322 @@
+use std::{borrow::Cow, str::FromStr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use ssi::{EncryptedSecret, Ssi};
+use ureq::{Agent, AgentBuilder, Request};
+use zeroize::Zeroizing;
+
+use crate::{Error, SsiStore, SsiStoreExt, StoreCapabilities, StoreCapability};
+
+/// KV v2 mount of [`SsiVaultStore::new`], the one of Vault dev servers.
+const DEFAULT_MOUNT: &str = "secret";
+/// Path under the mount of the secrets of [`SsiVaultStore::new`].
+const DEFAULT_PREFIX: &str = "ssi-man";
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An identity as stored, the `data` of its Vault secret.
+#[derive(Deserialize, Serialize)]
+struct VaultRecord {
+    ssi: String,
+    secret: String,
+}
+
+#[derive(Deserialize)]
+struct VaultResponse<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct VaultSecret {
+    data: VaultRecord,
+}
+
+#[derive(Deserialize)]
+struct VaultKeys {
+    keys: Vec<String>,
+}
+
+/// A store keeping identities in a HashiCorp Vault KV v2 secrets engine, one Vault
+/// secret per identity at `<mount>/<prefix>/<identity>`, so that nothing is kept on disk.
+///
+/// Secrets hold the `ssi` and the concealed `secret` in their text forms. Creation uses
+/// check-and-set, so two servers can't create the same identity, and removal deletes
+/// every version along with the metadata. `/` and `%` in identities are percent-encoded
+/// in key names, so that every identity is one key.
+pub struct SsiVaultStore {
+    agent: Agent,
+    address: String,
+    token: Zeroizing<String>,
+    namespace: Option<String>,
+    mount: String,
+    prefix: String,
+}
+
+impl SsiVaultStore {
+    /// Talks to the Vault server at `address`, e.g. `https://vault.example.com:8200`,
+    /// with `token`, keeping secrets under `secret/ssi-man`.
+    pub fn new(address: &str, token: &str) -> Self {
+        Self {
+            agent: AgentBuilder::new().timeout(TIMEOUT).build(),
+            address: address.trim_end_matches('/').to_string(),
+            token: Zeroizing::new(token.to_string()),
+            namespace: None,
+            mount: DEFAULT_MOUNT.to_string(),
+            prefix: DEFAULT_PREFIX.to_string(),
+        }
+    }
+
+    /// Sends requests in the Vault Enterprise namespace `namespace`.
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    /// Uses the KV v2 engine mounted at `mount` rather than `secret`.
+    pub fn mount(mut self, mount: &str) -> Self {
+        self.mount = mount.trim_matches('/').to_string();
+        self
+    }
+
+    /// Keeps secrets under `prefix` in the mount rather than `ssi-man`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_matches('/').to_string();
+        self
+    }
+
+    fn request(&self, method: &str, kind: &str, id: Option<&str>) -> Request {
+        let mut url = format!("{}/v1/{}/{kind}/{}", self.address, self.mount, self.prefix);
+        if let Some(id) = id {
+            url.push('/');
+            url.push_str(&url_encode(&key_name(id)));
+        }
+        let request = self
+            .agent
+            .request(method, &url)
+            .set("X-Vault-Token", &self.token);
+        match &self.namespace {
+            Some(namespace) => request.set("X-Vault-Namespace", namespace),
+            None => request,
+        }
+    }
+
+    /// Reads the record of `id`, or `None` if there is none.
+    fn read(&self, id: &str) -> Result<Option<VaultRecord>, Error> {
+        match self.request("GET", "data", Some(id)).call() {
+            Ok(response) => {
+                let response: VaultResponse<VaultSecret> = response
+                    .into_json()
+                    .map_err(|err| Error::Vault(err.to_string()))?;
+                Ok(Some(response.data.data))
+            }
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(err) => Err(vault_error(err)),
+        }
+    }
+
+    /// Writes the record of `id`; with `create`, fails if there already is one.
+    fn write(
+        &self,
+        id: &str,
+        ssi: &Ssi,
+        secret: &EncryptedSecret,
+        create: bool,
+    ) -> Result<(), Error> {
+        let record = VaultRecord {
+            ssi: ssi.to_string(),
+            secret: secret.to_string(),
+        };
+        let mut body = json!({ "data": record });
+        if create {
+            body["options"] = json!({ "cas": 0 });
+        }
+        match self.request("POST", "data", Some(id)).send_json(&body) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(400, response)) if create => {
+                let text = response.into_string().unwrap_or_default();
+                if text.contains("check-and-set") {
+                    Err(Error::IdentityExists(id.to_string()))
+                } else {
+                    Err(Error::Vault(format!("status 400: {text}")))
+                }
+            }
+            Err(err) => Err(vault_error(err)),
+        }
+    }
+}
+
+/// Escapes `id` into a key name without `/`, so that it's never a folder.
+fn key_name(id: &str) -> String {
+    id.replace('%', "%25").replace('/', "%2F")
+}
+
+/// Reverses [`key_name`].
+fn identity(key: &str) -> String {
+    key.replace("%2F", "/").replace("%25", "%")
+}
+
+/// Percent-encodes everything but unreserved characters, for one URL path segment.
+fn url_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+fn vault_error(err: ureq::Error) -> Error {
+    match err {
+        // Sealed, or on standby without a leader.
+        ureq::Error::Status(503, response) => Error::VaultUnreachable(format!(
+            "status 503: {}",
+            response.into_string().unwrap_or_default()
+        )),
+        ureq::Error::Status(status, response) => Error::Vault(format!(
+            "status {status}: {}",
+            response.into_string().unwrap_or_default()
+        )),
+        ureq::Error::Transport(transport) => Error::VaultUnreachable(transport.to_string()),
+    }
+}
+
+impl SsiStore for SsiVaultStore {
+    fn insert(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.write(&id, &ssi, &secret, true)
+    }
+
+    fn get(&self, id: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        let record = self
+            .read(id)?
+            .ok_or_else(|| Error::UnknownIdentity(id.to_string()))?;
+        let secret = Zeroizing::new(record.secret);
+        Ok(Cow::Owned((
+            Ssi::from_str(&record.ssi)?,
+            EncryptedSecret::from_str(&secret)
+                .map_err(|err| Error::SecretParse(err.to_string()))?,
+        )))
+    }
+
+    fn remove(&mut self, id: &str) -> Result<bool, Error> {
+        if !self.contains(id)? {
+            return Ok(false);
+        }
+        self.request("DELETE", "metadata", Some(id))
+            .call()
+            .map_err(vault_error)?;
+        Ok(true)
+    }
+
+    fn contains(&self, id: &str) -> Result<bool, Error> {
+        match self.request("GET", "metadata", Some(id)).call() {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(404, _)) => Ok(false),
+            Err(err) => Err(vault_error(err)),
+        }
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        let response = match self
+            .request("GET", "metadata", None)
+            .query("list", "true")
+            .call()
+        {
+            Ok(response) => response,
+            // Vault answers 404 for an empty listing.
+            Err(ureq::Error::Status(404, _)) => return Ok(()),
+            Err(err) => return Err(vault_error(err)),
+        };
+        let response: VaultResponse<VaultKeys> = response
+            .into_json()
+            .map_err(|err| Error::Vault(err.to_string()))?;
+        let mut identities: Vec<String> = response
+            .data
+            .keys
+            .iter()
+            .filter(|key| !key.ends_with('/'))
+            .map(|key| identity(key))
+            .collect();
+        identities.sort();
+        for identity in identities {
+            f(&identity)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> StoreCapabilities {
+        StoreCapabilities::default().with(StoreCapability::Replace)
+    }
+
+    fn as_ext(&self) -> Option<&dyn SsiStoreExt> {
+        Some(self)
+    }
+
+    fn as_ext_mut(&mut self) -> Option<&mut dyn SsiStoreExt> {
+        Some(self)
+    }
+}
+
+impl SsiStoreExt for SsiVaultStore {
+    fn replace(&mut self, id: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        self.write(&id, &ssi, &secret, false)
+    }
+
+    fn update(&mut self, id: &str, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if !self.contains(id)? {
+            return Err(Error::UnknownIdentity(id.to_string()));
+        }
+        self.write(id, &ssi, &secret, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{ssi_cert_verify_text, SsiMan};
+
+    #[test]
+    fn key_names_should_keep_identities_in_one_segment() {
+        for id in ["Luna", "a/b", "100%", "%2F", "space and ünicode"] {
+            assert!(!key_name(id).contains('/'));
+            assert_eq!(identity(&key_name(id)), id);
+        }
+        assert_eq!(url_encode("a%2Fb c"), "a%252Fb%20c");
+    }
+
+    /// Needs a Vault server, e.g. `vault server -dev`, at `SSI_MAN_VAULT_ADDR` with the
+    /// token in `SSI_MAN_VAULT_TOKEN`, and passes otherwise.
+    #[test]
+    fn vault_store_should_keep_identities() {
+        let (Some(address), Some(token)) = (
+            env::var_os("SSI_MAN_VAULT_ADDR"),
+            env::var_os("SSI_MAN_VAULT_TOKEN"),
+        ) else {
+            return;
+        };
+        let prefix = format!("ssi-man-test-{}", Utc::now().timestamp_nanos_opt().unwrap());
+        let store = || {
+            SsiVaultStore::new(&address.to_string_lossy(), &token.to_string_lossy()).prefix(&prefix)
+        };
+        let mut ssi_man = SsiMan::with_store(Box::new(store()));
+        ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        assert!(matches!(
+            ssi_man.new_ssi("Luna", "luna@bitlightlabs.com", None),
+            Err(Error::IdentityExists(_))
+        ));
+
+        let mut ssi_man = SsiMan::with_store(Box::new(store()));
+        let ssi_cert = ssi_man.sign("Luna", "have a good day!", None).unwrap();
+        ssi_cert_verify_text(&ssi_cert, "have a good day!").unwrap();
+        assert_eq!(ssi_man.remove("Luna"), Ok(true));
+        assert_eq!(ssi_man.remove("Luna"), Ok(false));
+    }
+}
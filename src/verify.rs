@@ -0,0 +1,94 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    str::FromStr,
+};
+
+use ssi::{Ssi, SsiCert};
+
+use crate::{output, redact::redact, Error, OutputKind};
+
+/// Contacts known by their ssi, for verifying certificates without any store.
+#[derive(Clone, Default)]
+pub struct VerifyContext {
+    contacts: BTreeMap<String, Ssi>,
+}
+
+impl Debug for VerifyContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.contacts
+                    .iter()
+                    .map(|(name, ssi)| (redact(name), redact(&ssi.to_string()).to_string())),
+            )
+            .finish()
+    }
+}
+
+impl VerifyContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a contact from its ssi, in either output format, replacing any contact of the
+    /// same name.
+    pub fn add_contact(&mut self, name: impl Into<String>, ssi: &str) -> Result<(), Error> {
+        let ssi = Ssi::from_str(&output::to_native(ssi, OutputKind::Ssi)?)?;
+        self.contacts.insert(name.into(), ssi);
+        Ok(())
+    }
+
+    /// Verifies that `ssi_cert` signs `text` and returns the contact holding the signing
+    /// key, the first one by name if several share it.
+    ///
+    /// Fails with [`Error::UnknownSigner`] if the signature is valid but no contact holds
+    /// the key.
+    pub fn verify(&self, ssi_cert: &str, text: &str) -> Result<&str, Error> {
+        let ssi_cert = SsiCert::from_str(&output::to_native(ssi_cert, OutputKind::Cert)?)?;
+        ssi_cert.verify_text(text)?;
+        let pk = ssi_cert.pk.ok_or(Error::UnknownSigner)?;
+        self.contacts
+            .iter()
+            .find(|(_, ssi)| ssi.pk == pk)
+            .map(|(name, _)| name.as_str())
+            .ok_or(Error::UnknownSigner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SsiMan;
+
+    use super::*;
+
+    #[test]
+    fn verify_context_should_name_contacts() {
+        let mut ssi_man = SsiMan::with_memory();
+        let ssi = ssi_man
+            .new_ssi("Luna", "luna@bitlightlabs.com", None)
+            .unwrap();
+        let other = ssi_man
+            .new_ssi("Sol", "sol@bitlightlabs.com", None)
+            .unwrap();
+        let message = "have a good day!";
+        let ssi_cert = ssi_man.sign("Luna", message, None).unwrap();
+
+        let mut context = VerifyContext::new();
+        assert_eq!(
+            context.verify(&ssi_cert, message),
+            Err(Error::UnknownSigner)
+        );
+        context.add_contact("sol", &other).unwrap();
+        context.add_contact("luna", &ssi).unwrap();
+        assert_eq!(context.verify(&ssi_cert, message), Ok("luna"));
+        assert!(matches!(
+            context.verify(&ssi_cert, "have a bad day!"),
+            Err(Error::VerifyText(_))
+        ));
+        assert!(matches!(
+            context.add_contact("broken", "not an ssi"),
+            Err(Error::SsiParse(_))
+        ));
+    }
+}
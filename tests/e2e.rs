@@ -0,0 +1,176 @@
+//! Multi-step scenarios written against the public API only.
+#![cfg(all(feature = "memory", feature = "serde"))]
+
+use ssi_man::{ssi_cert_verify_text, Algo, Chain, ConflictPolicy, Error, SsiMan};
+
+const LUNA: &str = "Luna";
+const LUNA_EMAIL: &str = "luna@bitlightlabs.com";
+const SOL: &str = "Sol";
+const SOL_EMAIL: &str = "sol@bitlightlabs.com";
+
+/// Certificates produced along a scenario, with the text each one signs.
+#[derive(Default)]
+struct Certs(Vec<(String, String)>);
+
+impl Certs {
+    fn sign(&mut self, ssi_man: &mut SsiMan, identity: &str, text: &str, passwd: Option<&str>) {
+        let ssi_cert = ssi_man.sign(identity, text, passwd).unwrap();
+        assert_eq!(
+            ssi_man.verify_from_known(&ssi_cert, text).as_deref(),
+            Ok(identity)
+        );
+        self.0.push((ssi_cert, text.to_string()));
+    }
+
+    /// Certificates carry their public key, so every one of them must keep verifying
+    /// whatever happens to the store.
+    fn assert_all_verify(&self) {
+        for (ssi_cert, text) in &self.0 {
+            ssi_cert_verify_text(ssi_cert, text).unwrap();
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn temp_db_path(name: &str) -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    std::env::temp_dir()
+        .join(format!("ssi_man_e2e_{name}_{nanos}.db"))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// One manager per backend, the sqlite one on a new database named after `name`.
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn stores(name: &str) -> Vec<SsiMan> {
+    vec![
+        SsiMan::with_memory(),
+        #[cfg(feature = "sqlite")]
+        SsiMan::with_sqlite(temp_db_path(name)).unwrap(),
+    ]
+}
+
+/// create → add uid → sign → verify → change password → rotate key → export → import
+/// on another store → verify again.
+fn full_lifecycle(mut source: SsiMan, mut target: SsiMan) {
+    let mut certs = Certs::default();
+    source.new_ssi(LUNA, LUNA_EMAIL, Some("moon")).unwrap();
+    source
+        .new_ssi_with(SOL, SOL_EMAIL, Some("sun"), Algo::Bip340, Chain::Bitcoin)
+        .unwrap();
+    certs.sign(&mut source, LUNA, "first light", Some("moon"));
+    certs.sign(&mut source, SOL, "noon", Some("sun"));
+
+    let uid = "Luna <mailto:luna@example.com>";
+    source.add_uid(LUNA, uid, Some("moon")).unwrap();
+    assert!(source.list_uids(LUNA).unwrap().contains(&uid.to_string()));
+    assert_eq!(
+        source.find_identities("example.com"),
+        Ok(vec![LUNA.to_string()])
+    );
+    certs.sign(&mut source, LUNA, "after uid", Some("moon"));
+    certs.assert_all_verify();
+
+    source
+        .change_password(LUNA, Some("moon"), Some("full moon"))
+        .unwrap();
+    assert!(source.sign(LUNA, "old password", Some("moon")).is_err());
+    certs.sign(&mut source, LUNA, "new password", Some("full moon"));
+    certs.assert_all_verify();
+
+    let (old_cert, old_text) = certs.0[0].clone();
+    source
+        .new_ssi_overwrite(LUNA, LUNA_EMAIL, Some("new moon"))
+        .unwrap();
+    assert_eq!(
+        source.verify_from_known(&old_cert, &old_text),
+        Err(Error::UnknownSigner)
+    );
+    certs.sign(&mut source, LUNA, "rotated", Some("new moon"));
+    certs.assert_all_verify();
+
+    let snapshot = source.export_all().unwrap();
+    assert_eq!(target.import_all(&snapshot, ConflictPolicy::Error), Ok(2));
+    assert_eq!(
+        target.all_identities().unwrap(),
+        source.all_identities().unwrap()
+    );
+    assert_eq!(target.export_all().unwrap(), snapshot);
+    for (identity, text, passwd) in [(LUNA, "imported", "new moon"), (SOL, "imported", "sun")] {
+        certs.sign(&mut target, identity, text, Some(passwd));
+    }
+    let (rotated_cert, rotated_text) = certs.0[4].clone();
+    assert_eq!(
+        target.verify_from_known(&rotated_cert, &rotated_text),
+        Ok(LUNA.to_string())
+    );
+    certs.assert_all_verify();
+}
+
+#[test]
+fn full_lifecycle_should_ok() {
+    for (source, target) in stores("lifecycle_source")
+        .into_iter()
+        .zip(stores("lifecycle_target").into_iter().rev())
+    {
+        full_lifecycle(source, target);
+    }
+}
+
+/// A wrong password, a duplicate import and a restore from backup in the middle of a
+/// flow leave the store as if they were never attempted, or as restored.
+fn failures_mid_flow(mut ssi_man: SsiMan) {
+    let mut certs = Certs::default();
+    ssi_man.new_ssi(LUNA, LUNA_EMAIL, Some("moon")).unwrap();
+    certs.sign(&mut ssi_man, LUNA, "before", Some("moon"));
+
+    let ssi = ssi_man.get_ssi(LUNA).unwrap();
+    assert!(ssi_man.sign(LUNA, "wrong", Some("sun")).is_err());
+    assert!(ssi_man
+        .change_password(LUNA, Some("sun"), Some("eclipse"))
+        .is_err());
+    assert!(ssi_man
+        .add_uid(LUNA, "Luna <mailto:luna@example.com>", Some("sun"))
+        .is_err());
+    assert_eq!(ssi_man.get_ssi(LUNA), Ok(ssi.clone()));
+    certs.sign(&mut ssi_man, LUNA, "after wrong password", Some("moon"));
+
+    let backup = ssi_man.export(LUNA, Some("moon")).unwrap();
+    assert_eq!(
+        ssi_man.import(&backup),
+        Err(Error::IdentityExists(LUNA.to_string()))
+    );
+    let snapshot = ssi_man.export_all().unwrap();
+    ssi_man.new_ssi(SOL, SOL_EMAIL, None).unwrap();
+    assert_eq!(
+        ssi_man.import_all(&snapshot, ConflictPolicy::Error),
+        Err(Error::IdentityExists(LUNA.to_string()))
+    );
+    assert_eq!(ssi_man.import_all(&snapshot, ConflictPolicy::Skip), Ok(0));
+    assert_eq!(
+        ssi_man.all_identities_ordered(Default::default()),
+        Ok(vec![LUNA.to_string(), SOL.to_string()])
+    );
+
+    assert_eq!(ssi_man.remove(LUNA), Ok(true));
+    assert!(matches!(
+        ssi_man.sign(LUNA, "removed", Some("moon")),
+        Err(Error::UnknownIdentity(_))
+    ));
+    assert_eq!(ssi_man.import(&backup), Ok(LUNA.to_string()));
+    assert_eq!(ssi_man.get_ssi(LUNA), Ok(ssi));
+    certs.sign(&mut ssi_man, LUNA, "restored", Some("moon"));
+    let pk = ssi_man.get_ssi_details(LUNA).unwrap().pk;
+    assert_eq!(ssi_man.find_by_pubkey(&pk), Ok(vec![LUNA.to_string()]));
+    certs.assert_all_verify();
+}
+
+#[test]
+fn failures_mid_flow_should_leave_store_consistent() {
+    for ssi_man in stores("failures") {
+        failures_mid_flow(ssi_man);
+    }
+}
@@ -0,0 +1,86 @@
+//! A store implementing only the required methods of `SsiStore`, as one written
+//! against an older version would.
+//!
+//! This file failing to compile means a change broke third-party stores: new trait
+//! methods need a default implementation, see `SsiStore::capabilities`, or belong to
+//! an extension trait such as `SsiStoreExt` or `JournalCapable`. Operations needing one
+//! fail with `Error::Unsupported` for such a store.
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use ssi::{EncryptedSecret, Ssi};
+use ssi_man::{ssi_cert_verify_text, Error, SsiMan, SsiStore, StoreCapability};
+
+#[derive(Default)]
+struct LegacyStore(BTreeMap<String, (Ssi, EncryptedSecret)>);
+
+impl SsiStore for LegacyStore {
+    fn insert(&mut self, identity: String, ssi: Ssi, secret: EncryptedSecret) -> Result<(), Error> {
+        if self.0.contains_key(&identity) {
+            return Err(Error::IdentityExists(identity));
+        }
+        self.0.insert(identity, (ssi, secret));
+        Ok(())
+    }
+
+    fn get(&self, identity: &str) -> Result<Cow<(Ssi, EncryptedSecret)>, Error> {
+        self.0
+            .get(identity)
+            .map(Cow::Borrowed)
+            .ok_or_else(|| Error::UnknownIdentity(identity.to_string()))
+    }
+
+    fn remove(&mut self, identity: &str) -> Result<bool, Error> {
+        Ok(self.0.remove(identity).is_some())
+    }
+
+    fn for_each_identity(&self, f: &mut dyn FnMut(&str) -> Result<(), Error>) -> Result<(), Error> {
+        self.0.keys().try_for_each(|identity| f(identity))
+    }
+}
+
+#[test]
+fn legacy_store_should_work_without_optional_capabilities() {
+    let message = "have a good day!";
+    let mut ssi_man = SsiMan::with_store(Box::new(LegacyStore::default()));
+    assert_eq!(ssi_man.store_capabilities(), Default::default());
+
+    ssi_man
+        .new_ssi("Luna", "luna@bitlightlabs.com", Some("moon"))
+        .unwrap();
+    ssi_man
+        .new_ssi("Sol", "sol@bitlightlabs.com", None)
+        .unwrap();
+    let ssi_cert = ssi_man.sign("Luna", message, Some("moon")).unwrap();
+    ssi_cert_verify_text(&ssi_cert, message).unwrap();
+    assert_eq!(ssi_man.find_identities("sol"), Ok(vec!["Sol".to_string()]));
+    let pk = ssi_man.get_ssi_details("Luna").unwrap().pk;
+    assert_eq!(ssi_man.find_by_pubkey(&pk), Ok(vec!["Luna".to_string()]));
+    assert_eq!(
+        ssi_man.active_identities(),
+        Ok(vec!["Luna".to_string(), "Sol".to_string()])
+    );
+
+    assert_eq!(
+        ssi_man.change_password("Luna", Some("moon"), Some("full moon")),
+        Err(Error::Unsupported(StoreCapability::Replace))
+    );
+    ssi_man.sign("Luna", message, Some("moon")).unwrap();
+    assert_eq!(
+        ssi_man.restore(vec![]),
+        Err(Error::Unsupported(StoreCapability::AtomicWrites))
+    );
+    assert_eq!(
+        ssi_man.identity_info("Luna"),
+        Err(Error::Unsupported(StoreCapability::Metadata))
+    );
+    assert_eq!(
+        ssi_man.stale_identities(chrono::Duration::zero()),
+        Err(Error::Unsupported(StoreCapability::Metadata))
+    );
+    assert_eq!(ssi_man.remove("Sol"), Ok(true));
+    assert_eq!(
+        ssi_man.all_identities_ordered(Default::default()),
+        Ok(vec!["Luna".to_string()])
+    );
+}
@@ -0,0 +1,190 @@
+//! Property tests of the create → sign → verify and export → import round-trips on every
+//! backend.
+//!
+//! The default run keeps to a few cases and small messages. Setting `PROPTEST_CASES`,
+//! e.g. `PROPTEST_CASES=1000 cargo test --test round_trips`, runs that many cases and adds
+//! multi-megabyte messages. Failing cases are saved under `proptest-regressions/` and
+//! replayed first on later runs; commit those files, and add the minimized case to the
+//! regression tests at the end of this file.
+#![cfg(feature = "memory")]
+
+use proptest::prelude::*;
+use ssi_man::{ssi_cert_verify_text, Error, SsiMan};
+
+/// Cases of a default run.
+const DEFAULT_CASES: u32 = 16;
+
+fn full_run() -> bool {
+    std::env::var_os("PROPTEST_CASES").is_some()
+}
+
+fn config() -> ProptestConfig {
+    let cases = std::env::var("PROPTEST_CASES")
+        .ok()
+        .and_then(|cases| cases.parse().ok())
+        .unwrap_or(DEFAULT_CASES);
+    ProptestConfig::with_cases(cases)
+}
+
+/// One manager per backend.
+fn backends() -> Vec<SsiMan> {
+    vec![
+        SsiMan::with_memory(),
+        #[cfg(feature = "sqlite")]
+        SsiMan::with_sqlite(":memory:").unwrap(),
+    ]
+}
+
+fn identity() -> impl Strategy<Value = String> {
+    "[A-Za-z][A-Za-z0-9._-]{0,254}"
+}
+
+fn email() -> impl Strategy<Value = String> {
+    "[a-z0-9]{1,32}(\\.[a-z0-9]{1,16})?@[a-z0-9]{1,16}\\.[a-z]{2,6}"
+}
+
+/// Any text up to 255 bytes, NULs and emoji included.
+fn password() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just(String::new()),
+        any::<String>(),
+        "[\\x00\u{1F600}-\u{1F64F}a]{1,63}",
+    ]
+    .prop_map(|passwd| truncate(passwd, 255))
+}
+
+fn message() -> BoxedStrategy<String> {
+    let small = prop_oneof![Just(String::new()), any::<String>()];
+    if !full_run() {
+        return small.boxed();
+    }
+    let large = (any::<char>(), (1usize << 20)..(4 << 20))
+        .prop_map(|(c, len)| std::iter::repeat(c).take(len).collect());
+    prop_oneof![4 => small, 1 => large].boxed()
+}
+
+/// Cuts `text` to at most `max` bytes on a char boundary.
+fn truncate(mut text: String, max: usize) -> String {
+    while text.len() > max {
+        text.pop();
+    }
+    text
+}
+
+/// Another message, differing from `message` by one char.
+fn mutated(message: &str) -> String {
+    let mut chars = message.chars().collect::<Vec<_>>();
+    match chars.first_mut() {
+        Some(first) => *first = if *first == 'a' { 'b' } else { 'a' },
+        None => chars.push('a'),
+    }
+    chars.into_iter().collect()
+}
+
+/// Signs `message` with a new identity and checks the certificate verifies it, and only
+/// it, and that only the right password reveals the secret.
+fn sign_round_trip(
+    mut ssi_man: SsiMan,
+    identity: &str,
+    email: &str,
+    passwd: &str,
+    message: &str,
+) -> Result<(), TestCaseError> {
+    ssi_man.new_ssi(identity, email, Some(passwd)).unwrap();
+    let ssi_cert = ssi_man.sign(identity, message, Some(passwd)).unwrap();
+    prop_assert_eq!(ssi_cert_verify_text(&ssi_cert, message), Ok(()));
+    prop_assert_eq!(
+        ssi_man.verify_from_known(&ssi_cert, message),
+        Ok(identity.to_string())
+    );
+    prop_assert!(ssi_cert_verify_text(&ssi_cert, &mutated(message)).is_err());
+    prop_assert!(ssi_man
+        .sign(identity, message, Some(&format!("{passwd}x")))
+        .is_err());
+    Ok(())
+}
+
+/// Moves a new identity to `target` through a backup, which must then sign as before.
+fn backup_round_trip(
+    mut source: SsiMan,
+    mut target: SsiMan,
+    identity: &str,
+    email: &str,
+    passwd: &str,
+    message: &str,
+) -> Result<(), TestCaseError> {
+    let ssi = source.new_ssi(identity, email, Some(passwd)).unwrap();
+    let backup = source.export(identity, Some(passwd)).unwrap();
+    prop_assert_eq!(target.import(&backup), Ok(identity.to_string()));
+    prop_assert_eq!(target.get_ssi(identity), Ok(ssi));
+    let ssi_cert = target.sign(identity, message, Some(passwd)).unwrap();
+    prop_assert_eq!(
+        source.verify_from_known(&ssi_cert, message),
+        Ok(identity.to_string())
+    );
+    prop_assert_eq!(
+        target.import(&backup),
+        Err(Error::IdentityExists(identity.to_string()))
+    );
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(config())]
+
+    #[test]
+    fn sign_should_round_trip(
+        identity in identity(),
+        email in email(),
+        passwd in password(),
+        message in message(),
+    ) {
+        for ssi_man in backends() {
+            sign_round_trip(ssi_man, &identity, &email, &passwd, &message)?;
+        }
+    }
+
+    #[test]
+    fn backup_should_round_trip(
+        identity in identity(),
+        email in email(),
+        passwd in password(),
+        message in message(),
+    ) {
+        for (source, target) in backends().into_iter().zip(backends().into_iter().rev()) {
+            backup_round_trip(source, target, &identity, &email, &passwd, &message)?;
+        }
+    }
+}
+
+#[test]
+fn edge_cases_should_round_trip() {
+    let long_identity = format!("L{}", "u".repeat(254));
+    let emoji_passwd = "🌙".repeat(63);
+    let long_passwd = "p".repeat(255);
+    let cases = [
+        ("Luna", "", ""),
+        ("Luna", "\0", "\0"),
+        ("Luna", "pass\0word", "have a\0good day"),
+        ("Luna", "🌙🌕", "🌑 → 🌕"),
+        ("Luna", emoji_passwd.as_str(), "moon"),
+        ("Luna", long_passwd.as_str(), "moon"),
+        (long_identity.as_str(), "moon", "moon"),
+    ];
+    for (identity, passwd, message) in cases {
+        for ssi_man in backends() {
+            sign_round_trip(ssi_man, identity, "luna@bitlightlabs.com", passwd, message).unwrap();
+        }
+        for (source, target) in backends().into_iter().zip(backends().into_iter().rev()) {
+            backup_round_trip(
+                source,
+                target,
+                identity,
+                "luna@bitlightlabs.com",
+                passwd,
+                message,
+            )
+            .unwrap();
+        }
+    }
+}